@@ -0,0 +1,58 @@
+//! Inspection of the signed packets in the store, shared by the admin HTTP API and the
+//! `iroh-dns-server` subcommands.
+
+use anyhow::Result;
+use pkarr::SignedPacket;
+use serde::{Deserialize, Serialize};
+
+use crate::util::{signed_packet_to_hickory_records_without_origin, PublicKeyBytes};
+
+/// Summary of a stored signed packet.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PacketInfo {
+    /// The z-base-32 encoded public key.
+    pub public_key: String,
+    /// The timestamp of the packet in microseconds since the unix epoch.
+    pub timestamp: u64,
+    /// The size of the signed packet in bytes.
+    pub size: usize,
+}
+
+impl PacketInfo {
+    /// Create the summary of a signed packet.
+    pub fn from_signed_packet(packet: &SignedPacket) -> Self {
+        Self {
+            public_key: PublicKeyBytes::from_signed_packet(packet).to_z32(),
+            timestamp: packet.timestamp(),
+            size: packet.as_bytes().len(),
+        }
+    }
+}
+
+/// A stored signed packet together with its DNS records.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PacketDetails {
+    /// Summary of the packet.
+    #[serde(flatten)]
+    pub info: PacketInfo,
+    /// The DNS records of the packet in zone file format, relative to the zone of the
+    /// public key.
+    pub records: Vec<String>,
+}
+
+impl PacketDetails {
+    /// Decode the records of a signed packet.
+    pub fn from_signed_packet(packet: &SignedPacket) -> Result<Self> {
+        let (_zone, record_sets) =
+            signed_packet_to_hickory_records_without_origin(packet, |_| true)?;
+        let records = record_sets
+            .values()
+            .flat_map(|set| set.records_without_rrsigs())
+            .map(|record| record.to_string())
+            .collect();
+        Ok(Self {
+            info: PacketInfo::from_signed_packet(packet),
+            records,
+        })
+    }
+}
@@ -7,14 +7,16 @@ use std::{
     time::Duration,
 };
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, ensure, Context, Result};
 use serde::{Deserialize, Serialize};
 use tracing::info;
 
 use crate::{
     dns::DnsConfig,
-    http::{CertMode, HttpConfig, HttpsConfig, RateLimitConfig},
-    store::ZoneStoreOptions,
+    http::{AdminConfig, CertMode, HttpConfig, HttpsConfig, RateLimitConfig, SubscribeConfig},
+    replication::ReplicationConfig,
+    republish::RepublishConfig,
+    store::{ZoneStore, ZoneStoreOptions},
 };
 
 const DEFAULT_METRICS_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 9117);
@@ -49,9 +51,29 @@ pub struct Config {
     /// Config for the zone store.
     pub zone_store: Option<StoreConfig>,
 
-    /// Config for pkarr rate limit
+    /// Config for the rate limits of pkarr PUT and GET requests
     #[serde(default)]
     pub pkarr_put_rate_limit: RateLimitConfig,
+
+    /// Config for the pkarr subscription endpoint
+    #[serde(default)]
+    pub pkarr_subscribe: SubscribeConfig,
+
+    /// Config for the replication to peer servers.
+    ///
+    /// If set to `None` packets are not replicated.
+    pub replication: Option<ReplicationConfig>,
+
+    /// Config for republishing stored packets to the mainline DHT.
+    ///
+    /// The DHT is bootstrapped from the nodes in [`MainlineConfig::bootstrap`], also if the
+    /// mainline lookup is disabled.  If set to `None` packets are not republished.
+    pub republish: Option<RepublishConfig>,
+
+    /// Config for the admin API.
+    ///
+    /// If set to `None` the admin routes are not served.
+    pub admin: Option<AdminConfig>,
 }
 
 /// The config for the store.
@@ -71,6 +93,85 @@ pub struct StoreConfig {
     /// Pause between eviction checks.
     #[serde(with = "humantime_serde")]
     eviction_interval: Duration,
+
+    /// Maximum number of packets to keep in the store.
+    ///
+    /// When the store is full, the oldest packets are evicted.  Only supported by the redb
+    /// backend, setting it with another backend is a config error.
+    #[serde(default)]
+    max_packets: Option<u64>,
+
+    /// Maximum total size of the stored packets in bytes.
+    ///
+    /// When the size is exceeded, the oldest packets are evicted.  Only supported by the redb
+    /// backend, setting it with another backend is a config error.
+    #[serde(default)]
+    max_size: Option<u64>,
+
+    /// The storage backend for signed packets.
+    #[serde(default)]
+    backend: StoreBackend,
+
+    /// Path of the database file.
+    ///
+    /// Defaults to [`Config::signed_packet_store_path`] for the redb backend and to
+    /// [`Config::sqlite_store_path`] for the SQLite backend.
+    #[serde(default)]
+    path: Option<PathBuf>,
+}
+
+/// The storage backend for signed packets.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StoreBackend {
+    /// Store packets in a redb database.
+    #[default]
+    Redb,
+    /// Store packets in a SQLite database, which can be shared by several servers.
+    ///
+    /// Changes made by other servers are picked up after at most [`SHARED_CACHE_TTL`].
+    /// Requires the `sqlite` feature.
+    ///
+    /// [`SHARED_CACHE_TTL`]: crate::SHARED_CACHE_TTL
+    Sqlite,
+}
+
+impl StoreConfig {
+    /// Check the config for options the backend does not support.
+    pub fn validate(&self) -> Result<()> {
+        ensure!(
+            self.backend == StoreBackend::Redb
+                || (self.max_packets.is_none() && self.max_size.is_none()),
+            "max_packets and max_size are only supported by the redb backend"
+        );
+        Ok(())
+    }
+
+    /// Open the zone store with the configured backend.
+    pub fn open(&self) -> Result<ZoneStore> {
+        let options = self.clone().into();
+        match self.backend {
+            StoreBackend::Redb => {
+                let path = match &self.path {
+                    Some(path) => path.clone(),
+                    None => Config::signed_packet_store_path()?,
+                };
+                ZoneStore::persistent(path, options)
+            }
+            #[cfg(feature = "sqlite")]
+            StoreBackend::Sqlite => {
+                let path = match &self.path {
+                    Some(path) => path.clone(),
+                    None => Config::sqlite_store_path()?,
+                };
+                ZoneStore::sqlite(path, options)
+            }
+            #[cfg(not(feature = "sqlite"))]
+            StoreBackend::Sqlite => {
+                anyhow::bail!("the sqlite store backend requires the `sqlite` feature")
+            }
+        }
+    }
 }
 
 impl Default for StoreConfig {
@@ -86,6 +187,10 @@ impl From<ZoneStoreOptions> for StoreConfig {
             max_batch_time: value.max_batch_time,
             eviction: value.eviction,
             eviction_interval: value.eviction_interval,
+            max_packets: value.max_packets,
+            max_size: value.max_size,
+            backend: StoreBackend::default(),
+            path: None,
         }
     }
 }
@@ -97,6 +202,8 @@ impl From<StoreConfig> for ZoneStoreOptions {
             max_batch_time: value.max_batch_time,
             eviction: value.eviction,
             eviction_interval: value.eviction_interval,
+            max_packets: value.max_packets,
+            max_size: value.max_size,
         }
     }
 }
@@ -164,9 +271,29 @@ impl Config {
             .await
             .with_context(|| format!("failed to read {}", path.as_ref().to_string_lossy()))?;
         let config: Config = toml::from_str(&s)?;
+        config.validate()?;
         Ok(config)
     }
 
+    /// Check the config for invalid values.
+    pub fn validate(&self) -> Result<()> {
+        if let Some(admin) = &self.admin {
+            admin.validate().context("invalid admin config")?;
+        }
+        if let Some(republish) = &self.republish {
+            republish.validate().context("invalid republish config")?;
+        }
+        if let Some(replication) = &self.replication {
+            replication
+                .validate()
+                .context("invalid replication config")?;
+        }
+        if let Some(zone_store) = &self.zone_store {
+            zone_store.validate().context("invalid zone store config")?;
+        }
+        Ok(())
+    }
+
     /// Get the data directory.
     pub fn data_dir() -> Result<PathBuf> {
         let dir = if let Some(val) = env::var_os("IROH_DNS_DATA_DIR") {
@@ -185,6 +312,11 @@ impl Config {
         Ok(Self::data_dir()?.join("signed-packets-1.db"))
     }
 
+    /// Get the path to the SQLite store database file.
+    pub fn sqlite_store_path() -> Result<PathBuf> {
+        Ok(Self::data_dir()?.join("signed-packets-1.sqlite"))
+    }
+
     /// Get the address where the metrics server should be bound, if set.
     pub(crate) fn metrics_addr(&self) -> Option<SocketAddr> {
         match &self.metrics {
@@ -196,6 +328,19 @@ impl Config {
         }
     }
 
+    /// Get the bootstrap nodes for the mainline DHT, regardless of whether the mainline lookup
+    /// is enabled.
+    pub(crate) fn mainline_bootstrap(&self) -> BootstrapOption {
+        match self
+            .mainline
+            .as_ref()
+            .and_then(|conf| conf.bootstrap.clone())
+        {
+            Some(bootstrap) => BootstrapOption::Custom(bootstrap),
+            None => BootstrapOption::Default,
+        }
+    }
+
     pub(crate) fn mainline_enabled(&self) -> Option<BootstrapOption> {
         match self.mainline.as_ref() {
             None => None,
@@ -217,6 +362,7 @@ impl Default for Config {
             http: Some(HttpConfig {
                 port: 8080,
                 bind_addr: None,
+                proxy_protocol: None,
             }),
             https: Some(HttpsConfig {
                 port: 8443,
@@ -225,10 +371,14 @@ impl Default for Config {
                 cert_mode: CertMode::SelfSigned,
                 letsencrypt_contact: None,
                 letsencrypt_prod: None,
+                proxy_protocol: None,
             }),
             dns: DnsConfig {
                 port: 5300,
                 bind_addr: None,
+                tls_port: None,
+                quic_port: None,
+                proxy_protocol: None,
                 origins: vec!["irohdns.example.".to_string(), ".".to_string()],
 
                 default_soa: "irohdns.example hostmaster.irohdns.example 0 10800 3600 604800 3600"
@@ -238,11 +388,19 @@ impl Default for Config {
                 rr_a: Some(Ipv4Addr::LOCALHOST),
                 rr_aaaa: None,
                 rr_ns: Some("ns1.irohdns.example.".to_string()),
+                static_zones: Vec::new(),
+                node_records: Default::default(),
+                dnssec: None,
+                query_log: None,
             },
             zone_store: None,
             metrics: None,
             mainline: None,
             pkarr_put_rate_limit: RateLimitConfig::default(),
+            pkarr_subscribe: SubscribeConfig::default(),
+            replication: None,
+            republish: None,
+            admin: None,
         }
     }
 }
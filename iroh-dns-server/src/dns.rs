@@ -4,40 +4,59 @@ use std::{
     collections::BTreeMap,
     io,
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
-    sync::Arc,
-    time::Duration,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
 };
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, ensure, Context, Result};
 use async_trait::async_trait;
 use bytes::Bytes;
 use hickory_server::{
-    authority::{Catalog, MessageResponse, ZoneType},
+    authority::{Catalog, MessageRequest, MessageResponse, ZoneType},
     proto::{
         self,
         op::ResponseCode,
         rr::{
-            rdata::{self},
+            rdata::{self, SOA},
             LowerName, Name, RData, Record, RecordSet, RecordType, RrKey,
         },
-        serialize::{binary::BinEncoder, txt::RDataParser},
+        serialize::{
+            binary::{BinDecodable, BinDecoder, BinEncoder},
+            txt::RDataParser,
+        },
         xfer::Protocol,
     },
     server::{Request, RequestHandler, ResponseHandler, ResponseInfo},
     store::in_memory::InMemoryAuthority,
 };
 use iroh_metrics::inc;
+use iroh_relay::proxy_protocol::ProxyProtocolConfig;
+use n0_future::task::AbortOnDropHandle;
 use serde::{Deserialize, Serialize};
 use tokio::{
-    net::{TcpListener, UdpSocket},
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream, UdpSocket},
     sync::broadcast,
+    task::JoinSet,
 };
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 
-use self::node_authority::NodeAuthority;
-use crate::{metrics::Metrics, store::ZoneStore};
+use self::{dnssec::DnssecKey, node_authority::NodeAuthority, node_records::RecordFilter};
+pub use self::{
+    dnssec::{DnssecAlgorithm, DnssecConfig, NxProof, DEFAULT_SIGNATURE_VALIDITY},
+    node_records::NodeRecordPolicy,
+    static_zone::StaticZoneConfig,
+};
+use crate::{
+    metrics::{self, Metrics},
+    query_log::{QueryLog, QueryLogConfig, QueryLogEntry},
+    store::ZoneStore,
+};
 
+mod dnssec;
 mod node_authority;
+mod node_records;
+mod static_zone;
 
 const DEFAULT_NS_TTL: u32 = 60 * 60 * 12; // 12h
 const DEFAULT_SOA_TTL: u32 = 60 * 60 * 24 * 14; // 14d
@@ -51,6 +70,24 @@ pub struct DnsConfig {
     /// The IPv4 or IPv6 address to bind the UDP DNS server.
     /// Uses `0.0.0.0` if unspecified.
     pub bind_addr: Option<IpAddr>,
+    /// The TCP port to serve DNS-over-TLS at, usually 853.
+    ///
+    /// Uses the certificates of the HTTPS server, which must be configured.  If set to `None`,
+    /// DNS-over-TLS is not served.
+    #[serde(default)]
+    pub tls_port: Option<u16>,
+    /// The UDP port to serve DNS-over-QUIC at, usually 853.
+    ///
+    /// Uses the certificates of the HTTPS server, which must be configured.  If set to `None`,
+    /// DNS-over-QUIC is not served.
+    #[serde(default)]
+    pub quic_port: Option<u16>,
+    /// PROXY protocol config for the TCP listener.
+    ///
+    /// If set, connections to the TCP port from the trusted proxies must start with a PROXY
+    /// protocol header, and the client address from the header is used for the queries.
+    #[serde(default)]
+    pub proxy_protocol: Option<ProxyProtocolConfig>,
     /// SOA record data for any authoritative DNS records
     pub default_soa: String,
     /// Default time to live for returned DNS records (TXT & SOA)
@@ -64,36 +101,149 @@ pub struct DnsConfig {
     pub rr_aaaa: Option<Ipv6Addr>,
     /// `NS` record to set for all origins
     pub rr_ns: Option<String>,
+
+    /// Static records to serve for some origins, in addition to the records above.
+    ///
+    /// The static records can be reloaded without restarting the server, see
+    /// [`DnsHandler::reload_static_records`].
+    #[serde(default)]
+    pub static_zones: Vec<StaticZoneConfig>,
+
+    /// Policy for the records of signed packets served under the node names.
+    #[serde(default)]
+    pub node_records: NodeRecordPolicy,
+
+    /// Config for signing all origins with DNSSEC.
+    ///
+    /// If set to `None` responses are not signed.
+    #[serde(default)]
+    pub dnssec: Option<DnssecConfig>,
+
+    /// Config for the structured log of DNS queries.
+    ///
+    /// If set to `None` queries are not logged.
+    #[serde(default)]
+    pub query_log: Option<QueryLogConfig>,
+}
+
+/// The TLS config for the DNS-over-TLS and DNS-over-QUIC listeners.
+#[derive(Debug, Clone)]
+pub struct DnsTlsConfig {
+    /// The rustls server config of the HTTPS server.
+    ///
+    /// The listeners use a copy with their own ALPN protocol identifier.
+    pub server_config: Arc<rustls::ServerConfig>,
 }
 
 /// A DNS server that serves pkarr signed packets.
 pub struct DnsServer {
     local_addr: SocketAddr,
+    tls_addr: Option<SocketAddr>,
+    quic_addr: Option<SocketAddr>,
     server: hickory_server::ServerFuture<DnsHandler>,
+    /// The TCP listener task, if the TCP listener accepts the PROXY protocol.
+    _tcp_task: Option<AbortOnDropHandle<()>>,
+    /// The DNS-over-QUIC listener task, if enabled.
+    _quic_task: Option<AbortOnDropHandle<()>>,
 }
 
 impl DnsServer {
     /// Spawn the server.
-    pub async fn spawn(config: DnsConfig, dns_handler: DnsHandler) -> Result<Self> {
+    ///
+    /// The `tls_config` is required if [`DnsConfig::tls_port`] or [`DnsConfig::quic_port`] are
+    /// set.
+    pub async fn spawn(
+        config: DnsConfig,
+        dns_handler: DnsHandler,
+        tls_config: Option<&DnsTlsConfig>,
+    ) -> Result<Self> {
         const TCP_TIMEOUT: Duration = Duration::from_millis(1000);
-        let mut server = hickory_server::ServerFuture::new(dns_handler);
+        const TLS_TIMEOUT: Duration = Duration::from_secs(5);
+        /// ALPN protocol identifier of DNS-over-TLS, see RFC 7858.
+        const DOT_ALPN: &[u8] = b"dot";
+        /// ALPN protocol identifier of DNS-over-QUIC, see RFC 9250.
+        const DOQ_ALPN: &[u8] = b"doq";
+        let mut server = hickory_server::ServerFuture::new(dns_handler.clone());
 
-        let bind_addr = SocketAddr::new(
-            config.bind_addr.unwrap_or(Ipv4Addr::UNSPECIFIED.into()),
-            config.port,
-        );
+        let bind_ip = config.bind_addr.unwrap_or(Ipv4Addr::UNSPECIFIED.into());
+        let bind_addr = SocketAddr::new(bind_ip, config.port);
 
         let socket = UdpSocket::bind(bind_addr).await?;
 
         let socket_addr = socket.local_addr()?;
 
         server.register_socket(socket);
-        server.register_listener(TcpListener::bind(bind_addr).await?, TCP_TIMEOUT);
+        // Bind TCP to the same port as UDP, also if the configured port is 0.
+        let tcp_listener = TcpListener::bind(socket_addr).await?;
+        let tcp_task = match config.proxy_protocol {
+            Some(proxy_protocol) => Some(AbortOnDropHandle::new(tokio::spawn(
+                serve_tcp_with_proxy_protocol(
+                    tcp_listener,
+                    Arc::new(proxy_protocol),
+                    dns_handler.clone(),
+                    TCP_TIMEOUT,
+                ),
+            ))),
+            None => {
+                server.register_listener(tcp_listener, TCP_TIMEOUT);
+                None
+            }
+        };
         info!("DNS server listening on {}", bind_addr);
 
+        let tls_addr = match config.tls_port {
+            Some(port) => {
+                let tls_config =
+                    tls_config.context("DNS-over-TLS requires the https server config")?;
+                let mut server_config = tls_config.server_config.as_ref().clone();
+                server_config.alpn_protocols = vec![DOT_ALPN.to_vec()];
+                let listener = TcpListener::bind(SocketAddr::new(bind_ip, port)).await?;
+                let tls_addr = listener.local_addr()?;
+                server.register_tls_listener_with_tls_config(
+                    listener,
+                    TLS_TIMEOUT,
+                    Arc::new(server_config),
+                )?;
+                info!("DNS-over-TLS server listening on {}", tls_addr);
+                Some(tls_addr)
+            }
+            None => None,
+        };
+
+        // hickory's QUIC listener only accepts a certificate and key, but not the server config
+        // of the Let's Encrypt cert mode, so DNS-over-QUIC is served here.
+        let (quic_addr, quic_task) = match config.quic_port {
+            Some(port) => {
+                let tls_config =
+                    tls_config.context("DNS-over-QUIC requires the https server config")?;
+                let mut server_config = tls_config.server_config.as_ref().clone();
+                server_config.alpn_protocols = vec![DOQ_ALPN.to_vec()];
+                let crypto = quinn::crypto::rustls::QuicServerConfig::try_from(server_config)
+                    .context("invalid TLS config for DNS-over-QUIC")?;
+                let mut transport_config = quinn::TransportConfig::default();
+                transport_config
+                    .max_concurrent_bidi_streams(MAX_QUIC_STREAMS.into())
+                    .max_concurrent_uni_streams(0u32.into())
+                    .max_idle_timeout(Some(QUIC_IDLE_TIMEOUT.try_into()?));
+                let mut quic_config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+                quic_config.transport_config(Arc::new(transport_config));
+                let endpoint =
+                    quinn::Endpoint::server(quic_config, SocketAddr::new(bind_ip, port))?;
+                let quic_addr = endpoint.local_addr()?;
+                let task = tokio::spawn(serve_quic(endpoint, dns_handler, TLS_TIMEOUT));
+                info!("DNS-over-QUIC server listening on {}", quic_addr);
+                (Some(quic_addr), Some(AbortOnDropHandle::new(task)))
+            }
+            None => (None, None),
+        };
+
         Ok(Self {
             server,
             local_addr: socket_addr,
+            tls_addr,
+            quic_addr,
+            _tcp_task: tcp_task,
+            _quic_task: quic_task,
         })
     }
 
@@ -102,6 +252,16 @@ impl DnsServer {
         self.local_addr
     }
 
+    /// Get the local address of the DNS-over-TLS socket, if enabled.
+    pub fn tls_addr(&self) -> Option<SocketAddr> {
+        self.tls_addr
+    }
+
+    /// Get the local address of the DNS-over-QUIC socket, if enabled.
+    pub fn quic_addr(&self) -> Option<SocketAddr> {
+        self.quic_addr
+    }
+
     /// Shutdown the server an wait for all tasks to complete.
     pub async fn shutdown(mut self) -> Result<()> {
         self.server.shutdown_gracefully().await?;
@@ -117,11 +277,200 @@ impl DnsServer {
     }
 }
 
+/// Maximum number of concurrent DNS TCP connections served with the PROXY protocol.
+const MAX_PROXY_PROTOCOL_CONNECTIONS: usize = 4096;
+
+/// Serves DNS over TCP on connections which may start with a PROXY protocol header.
+///
+/// The TCP listener of hickory can not read the header, so these connections are handled here.
+/// At most [`MAX_PROXY_PROTOCOL_CONNECTIONS`] are served at once, further connections are
+/// closed right away.
+async fn serve_tcp_with_proxy_protocol(
+    listener: TcpListener,
+    proxy_protocol: Arc<ProxyProtocolConfig>,
+    dns_handler: DnsHandler,
+    timeout: Duration,
+) {
+    let mut tasks = JoinSet::new();
+    loop {
+        tokio::select! {
+            Some(_) = tasks.join_next() => {}
+            res = listener.accept() => match res {
+                Ok((stream, peer_addr)) => {
+                    if tasks.len() >= MAX_PROXY_PROTOCOL_CONNECTIONS {
+                        debug!(%peer_addr, "too many DNS TCP connections, closing connection");
+                        continue;
+                    }
+                    let proxy_protocol = proxy_protocol.clone();
+                    let dns_handler = dns_handler.clone();
+                    tasks.spawn(async move {
+                        let res = handle_tcp_connection(
+                            stream,
+                            peer_addr,
+                            &proxy_protocol,
+                            &dns_handler,
+                            timeout,
+                        )
+                        .await;
+                        if let Err(err) = res {
+                            debug!(%peer_addr, "DNS TCP connection failed: {err:#}");
+                        }
+                    });
+                }
+                Err(err) => warn!("failed to accept DNS TCP connection: {err}"),
+            }
+        }
+    }
+}
+
+async fn handle_tcp_connection(
+    mut stream: TcpStream,
+    peer_addr: SocketAddr,
+    proxy_protocol: &ProxyProtocolConfig,
+    dns_handler: &DnsHandler,
+    timeout: Duration,
+) -> Result<()> {
+    // Clients must not hold on to a connection without sending anything, also when they
+    // are expected to send a header.
+    let client_addr =
+        tokio::time::timeout(timeout, proxy_protocol.client_addr(&mut stream, peer_addr))
+            .await
+            .context("timeout reading PROXY protocol header")??;
+    loop {
+        // Each message is prefixed with its length, see RFC 1035, section 4.2.2.
+        let len = match tokio::time::timeout(timeout, stream.read_u16()).await {
+            Ok(Ok(len)) => len,
+            // The client closed the connection or has been idle for too long.
+            _ => return Ok(()),
+        };
+        let mut message = vec![0u8; usize::from(len)];
+        tokio::time::timeout(timeout, stream.read_exact(&mut message)).await??;
+        let message = MessageRequest::read(&mut BinDecoder::new(&message))?;
+        let request = Request::new(message, client_addr, Protocol::Tcp);
+        let response = dns_handler.answer_request(request).await?;
+        stream.write_u16(u16::try_from(response.len())?).await?;
+        stream.write_all(&response).await?;
+    }
+}
+
+/// Maximum number of concurrent DNS-over-QUIC connections.
+const MAX_QUIC_CONNECTIONS: usize = 4096;
+
+/// Maximum number of concurrent queries, i.e. bidirectional streams, of a QUIC connection.
+const MAX_QUIC_STREAMS: u32 = 100;
+
+/// Time after which idle DNS-over-QUIC connections are closed.
+const QUIC_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The `DOQ_PROTOCOL_ERROR` error code of RFC 9250.
+const DOQ_PROTOCOL_ERROR: u32 = 0x2;
+
+/// Serves DNS over QUIC, see RFC 9250.
+///
+/// Each query is sent on its own bidirectional stream, prefixed with its length like over TCP.
+/// At most [`MAX_QUIC_CONNECTIONS`] are served at once, further connections are refused.
+async fn serve_quic(endpoint: quinn::Endpoint, dns_handler: DnsHandler, timeout: Duration) {
+    let mut tasks = JoinSet::new();
+    loop {
+        tokio::select! {
+            Some(_) = tasks.join_next() => {}
+            incoming = endpoint.accept() => {
+                let Some(incoming) = incoming else {
+                    return;
+                };
+                if tasks.len() >= MAX_QUIC_CONNECTIONS {
+                    debug!(peer_addr=%incoming.remote_address(), "too many DNS QUIC connections, refusing connection");
+                    incoming.refuse();
+                    continue;
+                }
+                let dns_handler = dns_handler.clone();
+                tasks.spawn(async move {
+                    let peer_addr = incoming.remote_address();
+                    if let Err(err) = handle_quic_connection(incoming, &dns_handler, timeout).await {
+                        debug!(%peer_addr, "DNS QUIC connection failed: {err:#}");
+                    }
+                });
+            }
+        }
+    }
+}
+
+async fn handle_quic_connection(
+    incoming: quinn::Incoming,
+    dns_handler: &DnsHandler,
+    timeout: Duration,
+) -> Result<()> {
+    let peer_addr = incoming.remote_address();
+    let connection = tokio::time::timeout(timeout, incoming)
+        .await
+        .context("timeout during QUIC handshake")??;
+    let mut streams = JoinSet::new();
+    loop {
+        tokio::select! {
+            Some(_) = streams.join_next() => {}
+            stream = connection.accept_bi() => {
+                let Ok((send, recv)) = stream else {
+                    // The client closed the connection.
+                    return Ok(());
+                };
+                let dns_handler = dns_handler.clone();
+                let connection = connection.clone();
+                streams.spawn(async move {
+                    let res =
+                        handle_quic_stream(send, recv, &connection, &dns_handler, timeout).await;
+                    if let Err(err) = res {
+                        debug!(%peer_addr, "DNS QUIC stream failed: {err:#}");
+                    }
+                });
+            }
+        }
+    }
+}
+
+async fn handle_quic_stream(
+    mut send: quinn::SendStream,
+    mut recv: quinn::RecvStream,
+    connection: &quinn::Connection,
+    dns_handler: &DnsHandler,
+    timeout: Duration,
+) -> Result<()> {
+    let len = tokio::time::timeout(timeout, recv.read_u16()).await??;
+    let mut message = vec![0u8; usize::from(len)];
+    tokio::time::timeout(timeout, recv.read_exact(&mut message)).await??;
+    let message = MessageRequest::read(&mut BinDecoder::new(&message))?;
+    // The message ID must be zero, as streams already identify the queries, see RFC 9250,
+    // section 4.2.1.
+    if message.id() != 0 {
+        connection.close(DOQ_PROTOCOL_ERROR.into(), b"non-zero message ID");
+        bail!("non-zero message ID {}", message.id());
+    }
+    let request = Request::new(message, connection.remote_address(), Protocol::Quic);
+    let response = dns_handler.answer_request(request).await?;
+    send.write_u16(u16::try_from(response.len())?).await?;
+    send.write_all(&response).await?;
+    send.finish()?;
+    Ok(())
+}
+
 /// State for serving DNS
 #[derive(Clone, derive_more::Debug)]
 pub struct DnsHandler {
     #[debug("Catalog")]
     catalog: Arc<Catalog>,
+    static_records: Arc<StaticRecords>,
+    /// The served origins, ordered from the most to the least specific.
+    origins: Arc<Vec<LowerName>>,
+    query_log: Option<Arc<QueryLog>>,
+}
+
+/// The state needed to reload the static records of the origins.
+#[derive(derive_more::Debug)]
+struct StaticRecords {
+    authorities: Vec<Arc<NodeAuthority>>,
+    dnssec_key: Option<DnssecKey>,
+    /// The config the static records were last loaded from.
+    #[debug("DnsConfig")]
+    config: Mutex<DnsConfig>,
 }
 
 impl DnsHandler {
@@ -133,25 +482,117 @@ impl DnsHandler {
             .iter()
             .map(Name::from_utf8)
             .collect::<Result<Vec<_>, _>>()?;
+        ensure!(!origins.is_empty(), "at least one origin is required");
+
+        let dnssec_key = config
+            .dnssec
+            .as_ref()
+            .map(DnssecKey::load_or_generate)
+            .transpose()?;
+
+        for zone in &config.static_zones {
+            let origin = Name::from_utf8(&zone.origin)?;
+            ensure!(
+                origins.contains(&origin),
+                "static zone {origin} is not in the configured origins"
+            );
+        }
 
-        let (static_authority, serial) = create_static_authority(&origins, config)?;
-        let authority = Arc::new(NodeAuthority::new(
-            zone_store,
-            static_authority,
-            origins,
-            serial,
-        )?);
+        let record_filter = RecordFilter::new(&config.node_records)?;
+        let query_log = config
+            .query_log
+            .as_ref()
+            .map(QueryLog::spawn)
+            .transpose()?
+            .map(Arc::new);
 
         let mut catalog = Catalog::new();
-        for origin in authority.origins() {
-            catalog.upsert(LowerName::from(origin), vec![authority.clone()]);
+        let mut authorities = Vec::with_capacity(origins.len());
+        for origin in origins {
+            let (static_authority, soa) =
+                create_static_authority(&origin, config, dnssec_key.as_ref())?;
+            let signer = dnssec_key
+                .as_ref()
+                .map(|key| key.zone_signer(&origin, soa.minimum()))
+                .transpose()?;
+            let authority = Arc::new(NodeAuthority::new(
+                zone_store.clone(),
+                static_authority,
+                origin.clone(),
+                soa.serial(),
+                signer,
+                record_filter.clone(),
+            ));
+            catalog.upsert(LowerName::from(&origin), vec![authority.clone()]);
+            authorities.push(authority);
         }
 
+        let mut origins = authorities
+            .iter()
+            .map(|authority| LowerName::from(authority.origin_name()))
+            .collect::<Vec<_>>();
+        origins.sort_by_key(|origin| std::cmp::Reverse(origin.num_labels()));
+
         Ok(Self {
+            origins: Arc::new(origins),
+            query_log,
             catalog: Arc::new(catalog),
+            static_records: Arc::new(StaticRecords {
+                authorities,
+                dnssec_key,
+                config: Mutex::new(config.clone()),
+            }),
         })
     }
 
+    /// Reload the static records of all origins from the zone files.
+    ///
+    /// The records are loaded with the config the handler was created with, or the config
+    /// last passed to [`Self::update_static_records`].
+    pub fn reload_static_records(&self) -> Result<()> {
+        let config = self.static_records.config.lock().expect("poisoned").clone();
+        self.update_static_records(&config)
+    }
+
+    /// Replace the static records of all origins with the records of `config`.
+    ///
+    /// This applies changes to [`DnsConfig::static_zones`], [`DnsConfig::default_soa`] and the
+    /// `rr_*` records, and reads the zone files again.  Changes to the other settings, in
+    /// particular to the origins, require a restart.
+    ///
+    /// The records of all origins are loaded before any are replaced, so if loading fails the
+    /// previous records are kept.
+    pub fn update_static_records(&self, config: &DnsConfig) -> Result<()> {
+        let static_records = &self.static_records;
+        for zone in &config.static_zones {
+            let origin = Name::from_utf8(&zone.origin)?;
+            ensure!(
+                static_records
+                    .authorities
+                    .iter()
+                    .any(|authority| authority.origin_name() == &origin),
+                "static zone {origin} is not in the served origins"
+            );
+        }
+        let updates = static_records
+            .authorities
+            .iter()
+            .map(|authority| {
+                create_static_authority(
+                    authority.origin_name(),
+                    config,
+                    static_records.dnssec_key.as_ref(),
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+        for (authority, (static_authority, soa)) in static_records.authorities.iter().zip(updates) {
+            authority.set_static_authority(static_authority, soa.serial());
+        }
+        *static_records.config.lock().expect("poisoned") = config.clone();
+        info!("reloaded static records");
+        Ok(())
+    }
+
     /// Handle a DNS request
     pub async fn answer_request(&self, request: Request) -> Result<Bytes> {
         let (tx, mut rx) = broadcast::channel(1);
@@ -159,6 +600,15 @@ impl DnsHandler {
         self.handle_request(&request, response_handle).await;
         Ok(rx.recv().await?)
     }
+
+    /// The most specific served origin which contains `name`, used as a metrics label.
+    fn origin_label(&self, name: &LowerName) -> String {
+        self.origins
+            .iter()
+            .find(|origin| origin.zone_of(name))
+            .map(|origin| origin.to_string())
+            .unwrap_or_else(|| "none".to_string())
+    }
 }
 
 #[async_trait::async_trait]
@@ -171,12 +621,37 @@ impl RequestHandler for DnsHandler {
         inc!(Metrics, dns_requests);
         match request.protocol() {
             Protocol::Udp => inc!(Metrics, dns_requests_udp),
+            Protocol::Tcp => inc!(Metrics, dns_requests_tcp),
+            Protocol::Tls => inc!(Metrics, dns_requests_tls),
             Protocol::Https => inc!(Metrics, dns_requests_https),
+            Protocol::Quic => inc!(Metrics, dns_requests_quic),
             _ => {}
         }
-        debug!(protocol=%request.protocol(), query=%request.query(), "incoming DNS request");
+        debug!(protocol=%request.protocol(), queries=?request.queries(), "incoming DNS request");
 
+        let received = SystemTime::now();
+        let start = Instant::now();
         let res = self.catalog.handle_request(request, response_handle).await;
+        let latency = start.elapsed();
+        let protocol = request.protocol().to_string();
+        let rcode = format!("{:?}", res.response_code());
+        // requests without exactly one query are rejected by the catalog
+        let Some(query) = request.queries().first() else {
+            inc!(Metrics, dns_lookup_error);
+            return res;
+        };
+        metrics::record_dns_request(&self.origin_label(query.name()), &protocol, &rcode, latency);
+        if let Some(query_log) = self.query_log.as_ref().filter(|log| log.sample()) {
+            query_log.log(QueryLogEntry::new(
+                received,
+                request.src().ip(),
+                protocol,
+                query.name().to_string(),
+                query.query_type().to_string(),
+                rcode,
+                latency,
+            ));
+        }
         match &res.response_code() {
             ResponseCode::NoError => match res.answer_count() {
                 0 => inc!(Metrics, dns_lookup_notfound),
@@ -219,10 +694,14 @@ impl ResponseHandler for Handle {
     }
 }
 
+/// Create the authority for the static records of an origin.
+///
+/// If a DNSSEC key is set, the records are signed with the key.
 fn create_static_authority(
-    origins: &[Name],
+    origin: &Name,
     config: &DnsConfig,
-) -> Result<(InMemoryAuthority, u32)> {
+    dnssec_key: Option<&DnssecKey>,
+) -> Result<(InMemoryAuthority, SOA)> {
     let soa = RData::parse(
         RecordType::SOA,
         config.default_soa.split_ascii_whitespace(),
@@ -232,45 +711,60 @@ fn create_static_authority(
     .map_err(|_| anyhow!("Couldn't parse SOA: {}", config.default_soa))?;
     let serial = soa.serial();
     let mut records = BTreeMap::new();
-    for name in origins {
+    push_record(
+        &mut records,
+        serial,
+        Record::from_rdata(origin.clone(), DEFAULT_SOA_TTL, RData::SOA(soa.clone())),
+    );
+    if let Some(addr) = config.rr_a {
         push_record(
             &mut records,
             serial,
-            Record::from_rdata(name.clone(), DEFAULT_SOA_TTL, RData::SOA(soa.clone())),
+            Record::from_rdata(origin.clone(), DEFAULT_A_TTL, RData::A(addr.into())),
         );
-        if let Some(addr) = config.rr_a {
-            push_record(
-                &mut records,
-                serial,
-                Record::from_rdata(name.clone(), DEFAULT_A_TTL, RData::A(addr.into())),
-            );
-        }
-        if let Some(addr) = config.rr_aaaa {
-            push_record(
-                &mut records,
-                serial,
-                Record::from_rdata(name.clone(), DEFAULT_A_TTL, RData::AAAA(addr.into())),
-            );
+    }
+    if let Some(addr) = config.rr_aaaa {
+        push_record(
+            &mut records,
+            serial,
+            Record::from_rdata(origin.clone(), DEFAULT_A_TTL, RData::AAAA(addr.into())),
+        );
+    }
+    if let Some(ns) = &config.rr_ns {
+        let ns = Name::parse(ns, Some(&Name::root()))?;
+        push_record(
+            &mut records,
+            serial,
+            Record::from_rdata(origin.clone(), DEFAULT_NS_TTL, RData::NS(rdata::NS(ns))),
+        );
+    }
+    for zone in &config.static_zones {
+        if Name::from_utf8(&zone.origin)? != *origin {
+            continue;
         }
-        if let Some(ns) = &config.rr_ns {
-            let ns = Name::parse(ns, Some(&Name::root()))?;
-            push_record(
-                &mut records,
-                serial,
-                Record::from_rdata(name.clone(), DEFAULT_NS_TTL, RData::NS(rdata::NS(ns))),
-            );
+        for record in zone.load(origin, config.default_ttl)? {
+            push_record(&mut records, serial, record);
         }
     }
 
-    let static_authority = InMemoryAuthority::new(Name::root(), records, ZoneType::Primary, false)
-        .map_err(|e| anyhow!(e))?;
+    // The static authority does not serve NSEC or NSEC3 records: a chain over the static
+    // records would deny the existence of all pkarr names.  Non-existence is instead proven
+    // by the `NodeAuthority`.
+    let mut static_authority =
+        InMemoryAuthority::new(origin.clone(), records, ZoneType::Primary, false, None)
+            .map_err(|e| anyhow!(e))?;
+    if let Some(key) = dnssec_key {
+        static_authority.add_zone_signing_key_mut(key.signer(origin)?)?;
+        static_authority.secure_zone_mut()?;
+    }
 
-    Ok((static_authority, serial))
+    Ok((static_authority, soa))
 }
 
 fn push_record(records: &mut BTreeMap<RrKey, RecordSet>, serial: u32, record: Record) {
     let key = RrKey::new(record.name().clone().into(), record.record_type());
-    let mut record_set = RecordSet::new(record.name().clone(), record.record_type(), serial);
-    record_set.insert(record, serial);
-    records.insert(key, record_set);
+    records
+        .entry(key)
+        .or_insert_with(|| RecordSet::new(record.name().clone(), record.record_type(), serial))
+        .insert(record, serial);
 }
@@ -0,0 +1,294 @@
+//! DNSSEC signing of the zones served by the DNS server.
+//!
+//! The static records of each origin are signed when the server starts and re-signed before
+//! their signatures expire, while the records from pkarr signed packets are signed when they
+//! are served.
+//!
+//! As the names in the pkarr zones are not known in advance, the server cannot serve a chain
+//! of NSEC or NSEC3 records that proves a name does not exist.  Instead, it uses compact denial
+//! of existence ("black lies"): for a name or record type that does not exist, the server
+//! answers with `NOERROR` and no records, together with a signed NSEC or NSEC3 record for
+//! exactly the queried name which lists the record types that do exist at the name.
+
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use anyhow::{Context, Result};
+use hickory_server::{
+    dnssec::NxProofKind,
+    proto::{
+        dnssec::{
+            rdata::{DNSSECRData, DNSKEY, NSEC, NSEC3, RRSIG},
+            ring::{signing_key_from_der, EcdsaSigningKey, Ed25519SigningKey},
+            Algorithm, DigestType, Nsec3HashAlgorithm, SigSigner, SigningKey, TBS,
+        },
+        rr::{DNSClass, Name, RData, Record, RecordSet, RecordType},
+    },
+};
+use rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tracing::info;
+
+use crate::config::Config;
+
+/// The default validity of signatures.
+pub const DEFAULT_SIGNATURE_VALIDITY: Duration = Duration::from_secs(60 * 60 * 24 * 7); // 7d
+
+/// Signatures are valid from this long before they are created, to allow for clock skew.
+const INCEPTION_OFFSET: Duration = Duration::from_secs(60 * 60); // 1h
+
+/// DNSSEC settings
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DnssecConfig {
+    /// Path to the PKCS#8 encoded private key used to sign all zones.
+    ///
+    /// If the file does not exist, a new key is generated and written to the path.
+    /// Defaults to `dnssec-key.pk8` in the [`Config::data_dir`].
+    #[serde(default)]
+    pub key_file: Option<PathBuf>,
+    /// The signing algorithm of the key.
+    #[serde(default)]
+    pub algorithm: DnssecAlgorithm,
+    /// How long signatures are valid after they were created.
+    #[serde(default = "default_signature_validity", with = "humantime_serde")]
+    pub signature_validity: Duration,
+    /// The kind of record which proves that a name or record type does not exist.
+    #[serde(default)]
+    pub nx_proof: NxProof,
+}
+
+fn default_signature_validity() -> Duration {
+    DEFAULT_SIGNATURE_VALIDITY
+}
+
+/// DNSSEC signing algorithm
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DnssecAlgorithm {
+    /// ECDSA with curve P-256 and SHA-256 (algorithm 13)
+    #[default]
+    EcdsaP256Sha256,
+    /// Ed25519 (algorithm 15)
+    Ed25519,
+}
+
+impl DnssecAlgorithm {
+    fn to_hickory(self) -> Algorithm {
+        match self {
+            Self::EcdsaP256Sha256 => Algorithm::ECDSAP256SHA256,
+            Self::Ed25519 => Algorithm::ED25519,
+        }
+    }
+}
+
+/// Proof of non-existence served for names and record types which do not exist
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NxProof {
+    /// Serve NSEC records
+    #[default]
+    Nsec,
+    /// Serve NSEC3 records, with an empty salt and no additional iterations as recommended
+    /// by RFC 9276.
+    Nsec3,
+}
+
+/// The private key used to sign the zones.
+#[derive(Clone, derive_more::Debug)]
+pub(crate) struct DnssecKey {
+    #[debug("{} bytes", pkcs8.len())]
+    pkcs8: Arc<Vec<u8>>,
+    algorithm: Algorithm,
+    signature_validity: Duration,
+    nx_proof: NxProof,
+}
+
+impl DnssecKey {
+    /// Load the key configured in `config`, or generate and save a new key.
+    pub(crate) fn load_or_generate(config: &DnssecConfig) -> Result<Self> {
+        let path = match &config.key_file {
+            Some(path) => path.clone(),
+            None => Config::data_dir()?.join("dnssec-key.pk8"),
+        };
+        let algorithm = config.algorithm.to_hickory();
+        let pkcs8 = if path.exists() {
+            info!("loading DNSSEC key from {}", path.display());
+            std::fs::read(&path)
+                .with_context(|| format!("failed to read DNSSEC key from {}", path.display()))?
+        } else {
+            info!("generating new DNSSEC key at {}", path.display());
+            let pkcs8 = match config.algorithm {
+                DnssecAlgorithm::EcdsaP256Sha256 => EcdsaSigningKey::generate_pkcs8(algorithm)?,
+                DnssecAlgorithm::Ed25519 => Ed25519SigningKey::generate_pkcs8()?,
+            }
+            .secret_pkcs8_der()
+            .to_vec();
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(&path, &pkcs8)
+                .with_context(|| format!("failed to write DNSSEC key to {}", path.display()))?;
+            pkcs8
+        };
+        let key = Self {
+            pkcs8: Arc::new(pkcs8),
+            algorithm,
+            signature_validity: config.signature_validity,
+            nx_proof: config.nx_proof,
+        };
+        // fail early if the key does not match the algorithm
+        key.signing_key()
+            .with_context(|| format!("invalid DNSSEC key in {}", path.display()))?;
+        Ok(key)
+    }
+
+    fn signing_key(&self) -> Result<Box<dyn SigningKey>> {
+        let der = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(self.pkcs8.as_slice()));
+        Ok(signing_key_from_der(&der, self.algorithm)?)
+    }
+
+    /// Create a signer for the zone at `origin`.
+    pub(crate) fn signer(&self, origin: &Name) -> Result<SigSigner> {
+        let key = self.signing_key()?;
+        let dnskey = DNSKEY::from_key(&key.to_public_key()?);
+        Ok(SigSigner::dnssec(
+            dnskey,
+            key,
+            origin.clone(),
+            self.signature_validity,
+        ))
+    }
+
+    /// Create a [`ZoneSigner`] for signing records of the zone at `origin` when serving them.
+    pub(crate) fn zone_signer(&self, origin: &Name, negative_ttl: u32) -> Result<ZoneSigner> {
+        let signer = self.signer(origin)?;
+        let dnskey = signer.to_dnskey()?;
+        let key_tag = signer.calculate_key_tag()?;
+        let digest = dnskey.to_digest(origin, DigestType::SHA256)?;
+        info!(
+            "DNSSEC enabled for {origin}, DS record: {origin} IN DS {key_tag} {} 2 {}",
+            u8::from(self.algorithm),
+            data_encoding::HEXUPPER.encode(digest.as_ref())
+        );
+        let nx_proof_kind = match self.nx_proof {
+            NxProof::Nsec => NxProofKind::Nsec,
+            NxProof::Nsec3 => NxProofKind::Nsec3 {
+                algorithm: Nsec3HashAlgorithm::SHA1,
+                salt: Arc::new([]),
+                iterations: 0,
+            },
+        };
+        Ok(ZoneSigner {
+            signer,
+            key_tag,
+            nx_proof_kind,
+            negative_ttl,
+            origin: origin.clone(),
+        })
+    }
+}
+
+/// Signs the records of a zone when serving them.
+#[derive(derive_more::Debug)]
+pub(crate) struct ZoneSigner {
+    #[debug("SigSigner")]
+    signer: SigSigner,
+    key_tag: u16,
+    nx_proof_kind: NxProofKind,
+    /// TTL of the NSEC and NSEC3 records.
+    negative_ttl: u32,
+    origin: Name,
+}
+
+impl ZoneSigner {
+    pub(crate) fn nx_proof_kind(&self) -> &NxProofKind {
+        &self.nx_proof_kind
+    }
+
+    /// Interval after which the static records of the zone need to be signed again.
+    pub(crate) fn resign_interval(&self) -> Duration {
+        self.signer.sig_duration() / 2
+    }
+
+    /// Sign a record set, replacing any existing signatures.
+    pub(crate) fn sign(&self, record_set: &mut RecordSet) -> Result<()> {
+        let inception = OffsetDateTime::now_utc() - INCEPTION_OFFSET;
+        let expiration = inception + INCEPTION_OFFSET + self.signer.sig_duration();
+        let tbs = TBS::from_rrset(
+            record_set,
+            DNSClass::IN,
+            inception,
+            expiration,
+            &self.signer,
+        )?;
+        let signature = self.signer.sign(&tbs)?;
+        let rrsig = RRSIG::new(
+            record_set.record_type(),
+            self.signer.key().algorithm(),
+            record_set.name().num_labels(),
+            record_set.ttl(),
+            expiration.unix_timestamp() as u32,
+            inception.unix_timestamp() as u32,
+            self.key_tag,
+            self.signer.signer_name().clone(),
+            signature,
+        );
+        record_set.clear_rrsigs();
+        record_set.insert_rrsig(Record::from_rdata(
+            record_set.name().clone(),
+            record_set.ttl(),
+            RData::DNSSEC(DNSSECRData::RRSIG(rrsig)),
+        ));
+        Ok(())
+    }
+
+    /// Create the signed NSEC or NSEC3 record set which proves that only the record types in
+    /// `types` exist at `name`.
+    pub(crate) fn deny(&self, name: &Name, mut types: Vec<RecordType>) -> Result<RecordSet> {
+        types.push(RecordType::RRSIG);
+        let record = match &self.nx_proof_kind {
+            NxProofKind::Nsec => {
+                // the next name is the immediate successor of `name` in the canonical order
+                let next = Name::from_labels([&b"\0"[..]])?.append_name(name)?;
+                Record::from_rdata(
+                    name.clone(),
+                    self.negative_ttl,
+                    RData::DNSSEC(DNSSECRData::NSEC(NSEC::new_cover_self(next, types))),
+                )
+            }
+            NxProofKind::Nsec3 {
+                algorithm,
+                salt,
+                iterations,
+            } => {
+                let hash = algorithm.hash(salt, name, *iterations)?;
+                let owner = self
+                    .origin
+                    .prepend_label(data_encoding::BASE32_DNSSEC.encode(hash.as_ref()))?;
+                // the next hashed name is the immediate successor of the hash of `name`
+                let mut next = hash.as_ref().to_vec();
+                for byte in next.iter_mut().rev() {
+                    *byte = byte.wrapping_add(1);
+                    if *byte != 0 {
+                        break;
+                    }
+                }
+                Record::from_rdata(
+                    owner,
+                    self.negative_ttl,
+                    RData::DNSSEC(DNSSECRData::NSEC3(NSEC3::new(
+                        *algorithm,
+                        false,
+                        *iterations,
+                        salt.to_vec(),
+                        next,
+                        types,
+                    ))),
+                )
+            }
+        };
+        let mut record_set = RecordSet::from(record);
+        self.sign(&mut record_set)?;
+        Ok(record_set)
+    }
+}
@@ -1,12 +1,20 @@
-use std::{fmt, sync::Arc};
+use std::{
+    fmt, slice,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+    time::{Duration, Instant},
+};
 
-use anyhow::{bail, ensure, Context, Result};
+use anyhow::{bail, Context, Result};
 use async_trait::async_trait;
 use hickory_server::{
     authority::{
-        AuthLookup, Authority, LookupControlFlow, LookupError, LookupOptions, LookupRecords,
-        MessageRequest, UpdateResult, ZoneType,
+        AuthLookup, Authority, DnssecAuthority, LookupControlFlow, LookupError, LookupOptions,
+        LookupRecords, MessageRequest, Nsec3QueryInfo, UpdateResult, ZoneType,
     },
+    dnssec::NxProofKind,
     proto::{
         op::ResponseCode,
         rr::{LowerName, Name, RecordType},
@@ -14,8 +22,10 @@ use hickory_server::{
     server::RequestInfo,
     store::in_memory::InMemoryAuthority,
 };
-use tracing::{debug, trace};
+use tokio::sync::Mutex;
+use tracing::{debug, trace, warn};
 
+use super::{dnssec::ZoneSigner, node_records::RecordFilter};
 use crate::{
     store::ZoneStore,
     util::{record_set_append_origin, PublicKeyBytes},
@@ -23,51 +33,86 @@ use crate::{
 
 #[derive(derive_more::Debug)]
 pub struct NodeAuthority {
-    serial: u32,
-    origins: Vec<Name>,
+    /// The serial of the SOA of the static records.
+    serial: AtomicU32,
+    origin: Name,
+    lower_origin: LowerName,
+    /// The static records, which are replaced when they are reloaded.
     #[debug("InMemoryAuthority")]
-    static_authority: InMemoryAuthority,
+    static_authority: RwLock<Arc<InMemoryAuthority>>,
     zones: ZoneStore,
-    // TODO: This is used by Authority::origin
-    // Find out what exactly this is used for - we don't have a primary origin.
-    first_origin: LowerName,
+    /// Signs the responses if DNSSEC is enabled.
+    signer: Option<ZoneSigner>,
+    /// Reference point for `static_signed_at`.
+    created: Instant,
+    /// When the static records were last signed, in milliseconds since `created`.
+    static_signed_at: AtomicU64,
+    /// Held while the static records are signed.
+    static_signing: Mutex<()>,
+    /// Selects the records of signed packets which are served.
+    record_filter: RecordFilter,
 }
 
 impl NodeAuthority {
     pub fn new(
         zones: ZoneStore,
         static_authority: InMemoryAuthority,
-        origins: Vec<Name>,
+        origin: Name,
         serial: u32,
-    ) -> Result<Self> {
-        ensure!(!origins.is_empty(), "at least one origin is required");
-        let first_origin = LowerName::from(&origins[0]);
-        Ok(Self {
-            static_authority,
-            origins,
-            serial,
+        signer: Option<ZoneSigner>,
+        record_filter: RecordFilter,
+    ) -> Self {
+        Self {
+            lower_origin: LowerName::from(&origin),
+            origin,
+            static_authority: RwLock::new(Arc::new(static_authority)),
+            serial: AtomicU32::new(serial),
             zones,
-            first_origin,
-        })
+            signer,
+            created: Instant::now(),
+            static_signed_at: AtomicU64::new(0),
+            static_signing: Mutex::new(()),
+            record_filter,
+        }
     }
 
-    pub fn origins(&self) -> impl Iterator<Item = &Name> {
-        self.origins.iter()
+    pub fn serial(&self) -> u32 {
+        self.serial.load(Ordering::Relaxed)
     }
 
-    pub fn serial(&self) -> u32 {
-        self.serial
+    pub fn origin_name(&self) -> &Name {
+        &self.origin
+    }
+
+    /// Replace the static records.
+    ///
+    /// The new records must already be signed if DNSSEC is enabled.
+    pub fn set_static_authority(&self, static_authority: InMemoryAuthority, serial: u32) {
+        *self.static_authority.write().expect("poisoned") = Arc::new(static_authority);
+        self.serial.store(serial, Ordering::Relaxed);
+    }
+
+    fn static_authority(&self) -> Arc<InMemoryAuthority> {
+        self.static_authority.read().expect("poisoned").clone()
+    }
+
+    /// Returns the signer if DNSSEC is enabled and was requested.
+    fn signer_for(&self, lookup_options: LookupOptions) -> Option<&ZoneSigner> {
+        self.signer.as_ref().filter(|_| lookup_options.dnssec_ok())
     }
 
     async fn resolve_pkarr(
         &self,
         name: Name,
         pubkey: PublicKeyBytes,
-        origin: Name,
         record_type: RecordType,
         lookup_options: LookupOptions,
     ) -> Result<AuthLookup, LookupError> {
+        let origin = &self.origin;
         debug!(%origin, %pubkey, %name, "resolve in pkarr zones");
+        if !self.record_filter.allows(&name, record_type) {
+            return Err(err_nx_domain("not allowed by record policy"));
+        }
         match self
             .zones
             .resolve(&pubkey, &name, record_type)
@@ -77,9 +122,13 @@ impl NodeAuthority {
             Some(pkarr_set) => {
                 debug!(%origin, %pubkey, %name, "found {} records in pkarr zone", pkarr_set.records_without_rrsigs().count());
                 let new_origin =
-                    Name::parse(&pubkey.to_z32(), Some(&origin)).map_err(err_refused)?;
+                    Name::parse(&pubkey.to_z32(), Some(origin)).map_err(err_refused)?;
                 let record_set = record_set_append_origin(&pkarr_set, &new_origin, self.serial())
                     .map_err(err_refused)?;
+                let mut record_set = self.record_filter.apply(record_set);
+                if let Some(signer) = self.signer_for(lookup_options) {
+                    signer.sign(&mut record_set).map_err(err_servfail)?;
+                }
                 let records = LookupRecords::new(lookup_options, Arc::new(record_set));
                 let answers = AuthLookup::answers(records, None);
                 Ok(answers)
@@ -87,6 +136,102 @@ impl NodeAuthority {
             None => Err(err_nx_domain("not found")),
         }
     }
+
+    /// Sign the static records again if their signatures are about to expire.
+    ///
+    /// Lookups during a re-sign do not wait for it and are answered with the previous
+    /// signatures, which are still valid.
+    async fn refresh_static_signatures(&self) {
+        let Some(signer) = &self.signer else {
+            return;
+        };
+        if !self.static_signatures_due(signer) {
+            return;
+        }
+        let Ok(_signing) = self.static_signing.try_lock() else {
+            return;
+        };
+        // another lookup may have signed the records since the check above
+        if !self.static_signatures_due(signer) {
+            return;
+        }
+        debug!(origin=%self.origin, "sign static records");
+        if let Err(err) = self.static_authority().secure_zone().await {
+            warn!(origin=%self.origin, "failed to sign static records: {err}");
+        }
+        self.static_signed_at
+            .store(self.millis_since_created(), Ordering::Relaxed);
+    }
+
+    /// Returns whether the resign interval passed since the static records were last signed.
+    fn static_signatures_due(&self, signer: &ZoneSigner) -> bool {
+        let signed_at = self.static_signed_at.load(Ordering::Relaxed);
+        let elapsed = Duration::from_millis(self.millis_since_created().saturating_sub(signed_at));
+        elapsed >= signer.resign_interval()
+    }
+
+    fn millis_since_created(&self) -> u64 {
+        self.created.elapsed().as_millis() as u64
+    }
+
+    /// Returns the record types which exist at `name`.
+    async fn record_types(&self, name: &LowerName) -> Result<Vec<RecordType>, LookupError> {
+        match parse_name_as_pkarr_with_origin(name, slice::from_ref(&self.origin)) {
+            Ok((name, pubkey, _origin)) => Ok(self
+                .zones
+                .record_types(&pubkey, &name)
+                .await
+                .map_err(err_refused)?
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|record_type| self.record_filter.allows(&name, *record_type))
+                .collect()),
+            Err(_) => {
+                let lookup = self
+                    .static_authority()
+                    .lookup(name, RecordType::ANY, LookupOptions::default())
+                    .await;
+                let mut types = match lookup.map_result() {
+                    Some(Ok(lookup)) => lookup
+                        .iter()
+                        .map(|record| record.record_type())
+                        .collect::<Vec<_>>(),
+                    _ => Vec::new(),
+                };
+                types.sort();
+                types.dedup();
+                Ok(types)
+            }
+        }
+    }
+
+    /// Returns the signed NSEC or NSEC3 record which proves that `record_type` does not exist
+    /// at `name`.
+    ///
+    /// The record is generated for exactly the queried name, see the [`super::dnssec`] module.
+    async fn deny_existence(
+        &self,
+        name: &LowerName,
+        record_type: Option<RecordType>,
+        lookup_options: LookupOptions,
+    ) -> LookupControlFlow<AuthLookup> {
+        let Some(signer) = self.signer_for(lookup_options) else {
+            return LookupControlFlow::Continue(Ok(AuthLookup::default()));
+        };
+        let res = async {
+            let types = self.record_types(name).await?;
+            // NSEC3 records are requested for positive answers too, which need no proof
+            if record_type.is_some_and(|record_type| types.contains(&record_type)) {
+                return Ok(AuthLookup::default());
+            }
+            let record_set = signer
+                .deny(&Name::from(name), types)
+                .map_err(err_servfail)?;
+            let records = LookupRecords::new(lookup_options, Arc::new(record_set));
+            Ok(AuthLookup::answers(records, None))
+        };
+        LookupControlFlow::Continue(res.await)
+    }
 }
 
 #[async_trait]
@@ -106,7 +251,7 @@ impl Authority for NodeAuthority {
     }
 
     fn origin(&self) -> &LowerName {
-        &self.first_origin
+        &self.lower_origin
     }
 
     async fn lookup(
@@ -116,26 +261,36 @@ impl Authority for NodeAuthority {
         lookup_options: LookupOptions,
     ) -> LookupControlFlow<Self::Lookup> {
         debug!(name=%name, "lookup in node authority");
-        match record_type {
+        self.refresh_static_signatures().await;
+        let res = match record_type {
             RecordType::SOA | RecordType::NS => {
-                self.static_authority
+                self.static_authority()
                     .lookup(name, record_type, lookup_options)
                     .await
             }
-            _ => match parse_name_as_pkarr_with_origin(name, &self.origins) {
-                Ok((name, pubkey, origin)) => {
+            _ => match parse_name_as_pkarr_with_origin(name, slice::from_ref(&self.origin)) {
+                Ok((name, pubkey, _origin)) => {
                     let res = self
-                        .resolve_pkarr(name, pubkey, origin, record_type, lookup_options)
+                        .resolve_pkarr(name, pubkey, record_type, lookup_options)
                         .await;
                     LookupControlFlow::Continue(res)
                 }
                 Err(err) => {
                     debug!(%name, failed_with=%err, "not a pkarr name, resolve in static authority");
-                    self.static_authority
+                    self.static_authority()
                         .lookup(name, record_type, lookup_options)
                         .await
                 }
             },
+        };
+        match self.signer_for(lookup_options) {
+            // With compact denial of existence, a name which does not exist is answered like a
+            // name without records of the queried type.
+            Some(_) => res.map_err(|err| match err.is_nx_domain() {
+                true => LookupError::NameExists,
+                false => err,
+            }),
+            None => res,
         }
     }
 
@@ -149,7 +304,7 @@ impl Authority for NodeAuthority {
         let record_type: RecordType = request_info.query.query_type();
         match record_type {
             RecordType::SOA => {
-                self.static_authority
+                self.static_authority()
                     .lookup(self.origin(), record_type, lookup_options)
                     .await
             }
@@ -162,10 +317,23 @@ impl Authority for NodeAuthority {
 
     async fn get_nsec_records(
         &self,
-        _name: &LowerName,
-        _lookup_options: LookupOptions,
+        name: &LowerName,
+        lookup_options: LookupOptions,
     ) -> LookupControlFlow<Self::Lookup> {
-        LookupControlFlow::Skip
+        self.deny_existence(name, None, lookup_options).await
+    }
+
+    async fn get_nsec3_records(
+        &self,
+        info: Nsec3QueryInfo<'_>,
+        lookup_options: LookupOptions,
+    ) -> LookupControlFlow<Self::Lookup> {
+        self.deny_existence(info.qname, Some(info.qtype), lookup_options)
+            .await
+    }
+
+    fn nx_proof_kind(&self) -> Option<&NxProofKind> {
+        self.signer.as_ref().map(ZoneSigner::nx_proof_kind)
     }
 }
 
@@ -204,3 +372,8 @@ fn err_nx_domain(e: impl fmt::Debug) -> LookupError {
     trace!("lookup failed (nxdomain): {e:?}");
     LookupError::from(ResponseCode::NXDomain)
 }
+
+fn err_servfail(e: impl fmt::Debug) -> LookupError {
+    trace!("lookup failed (servfail): {e:?}");
+    LookupError::from(ResponseCode::ServFail)
+}
@@ -0,0 +1,166 @@
+//! Policy for the records from signed packets which are served under the node names.
+
+use anyhow::{ensure, Result};
+use hickory_server::proto::rr::{Name, RecordSet, RecordType};
+use serde::{Deserialize, Serialize};
+
+/// Policy for the records of signed packets served under `<z32>.<origin>`.
+///
+/// By default, all records of a signed packet are served, except for `SOA` and `NS` records
+/// which are never served from signed packets.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NodeRecordPolicy {
+    /// The record types which are served.
+    ///
+    /// If `None`, all record types are served.
+    pub record_types: Option<Vec<RecordType>>,
+    /// The names which are served, relative to the node name.
+    ///
+    /// `@` is the node name itself, and a `*` label matches any single label, e.g. `_iroh`,
+    /// `@` or `*._udp`.  If `None`, all names are served.
+    pub names: Option<Vec<String>>,
+    /// Maximum number of records served for a name and record type.
+    ///
+    /// Additional records of a signed packet are not served.
+    pub max_records: Option<usize>,
+    /// Minimum TTL of served records, in seconds.
+    pub min_ttl: Option<u32>,
+    /// Maximum TTL of served records, in seconds.
+    pub max_ttl: Option<u32>,
+}
+
+/// A [`NodeRecordPolicy`] with parsed name patterns.
+#[derive(Debug, Clone, Default)]
+pub(super) struct RecordFilter {
+    record_types: Option<Vec<RecordType>>,
+    names: Option<Vec<Name>>,
+    max_records: Option<usize>,
+    min_ttl: u32,
+    max_ttl: u32,
+}
+
+impl RecordFilter {
+    pub(super) fn new(policy: &NodeRecordPolicy) -> Result<Self> {
+        let names = policy
+            .names
+            .as_ref()
+            .map(|names| {
+                names
+                    .iter()
+                    .map(|name| match name.as_str() {
+                        "@" => Ok(Name::new()),
+                        name => Name::from_utf8(name.trim_end_matches('.')),
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .transpose()?;
+        let min_ttl = policy.min_ttl.unwrap_or(0);
+        let max_ttl = policy.max_ttl.unwrap_or(u32::MAX);
+        ensure!(
+            min_ttl <= max_ttl,
+            "the minimum TTL of node records must not exceed the maximum TTL"
+        );
+        Ok(Self {
+            record_types: policy.record_types.clone(),
+            names,
+            max_records: policy.max_records,
+            min_ttl,
+            max_ttl,
+        })
+    }
+
+    /// Returns whether records of `record_type` at `name`, relative to the node name, are
+    /// served.
+    pub(super) fn allows(&self, name: &Name, record_type: RecordType) -> bool {
+        let type_allowed = match &self.record_types {
+            Some(types) => types.contains(&record_type),
+            None => true,
+        };
+        let name_allowed = match &self.names {
+            Some(patterns) => patterns.iter().any(|pattern| name_matches(pattern, name)),
+            None => true,
+        };
+        type_allowed && name_allowed
+    }
+
+    /// Limit the number of records and clamp their TTLs.
+    pub(super) fn apply(&self, record_set: RecordSet) -> RecordSet {
+        let max_records = self.max_records.unwrap_or(usize::MAX);
+        let needs_clamp = record_set
+            .records_without_rrsigs()
+            .any(|record| record.ttl() < self.min_ttl || record.ttl() > self.max_ttl);
+        if !needs_clamp && record_set.records_without_rrsigs().count() <= max_records {
+            return record_set;
+        }
+        let serial = record_set.serial();
+        let mut output =
+            RecordSet::new(record_set.name().clone(), record_set.record_type(), serial);
+        for record in record_set.records_without_rrsigs().take(max_records) {
+            let mut record = record.clone();
+            record.set_ttl(record.ttl().clamp(self.min_ttl, self.max_ttl));
+            output.insert(record, serial);
+        }
+        output
+    }
+}
+
+/// Returns whether `name` matches `pattern`, where a `*` label in the pattern matches any label.
+fn name_matches(pattern: &Name, name: &Name) -> bool {
+    // `Name::num_labels` does not count a leading `*` label
+    pattern.iter().len() == name.iter().len()
+        && pattern
+            .iter()
+            .zip(name.iter())
+            .all(|(pattern, label)| pattern == b"*" || pattern.eq_ignore_ascii_case(label))
+}
+
+#[cfg(test)]
+mod tests {
+    use hickory_server::proto::rr::{rdata, RData, Record};
+
+    use super::*;
+
+    #[test]
+    fn record_filter() -> Result<()> {
+        let filter = RecordFilter::new(&NodeRecordPolicy {
+            record_types: Some(vec![RecordType::TXT, RecordType::SRV]),
+            names: Some(vec!["_iroh".into(), "@".into(), "*._udp".into()]),
+            max_records: Some(2),
+            min_ttl: Some(30),
+            max_ttl: Some(3600),
+        })?;
+        let name = |s: &str| Name::from_utf8(s).unwrap();
+        assert!(filter.allows(&name("_iroh"), RecordType::TXT));
+        assert!(filter.allows(&name("_IROH"), RecordType::TXT));
+        assert!(filter.allows(&Name::new(), RecordType::TXT));
+        assert!(filter.allows(&name("_gateway._udp"), RecordType::SRV));
+        assert!(!filter.allows(&name("_iroh"), RecordType::A));
+        assert!(!filter.allows(&name("_gateway._tcp"), RecordType::SRV));
+        assert!(!filter.allows(&name("foo._gateway._udp"), RecordType::SRV));
+
+        let mut record_set = RecordSet::new(name("_iroh"), RecordType::TXT, 0);
+        for (i, ttl) in [10, 600, 86400].into_iter().enumerate() {
+            let txt = RData::TXT(rdata::TXT::new(vec![format!("record {i}")]));
+            record_set.insert(Record::from_rdata(name("_iroh"), ttl, txt), 0);
+        }
+        let record_set = filter.apply(record_set);
+        let ttls = record_set
+            .records_without_rrsigs()
+            .map(|record| record.ttl())
+            .collect::<Vec<_>>();
+        assert_eq!(ttls.len(), 2);
+        assert!(ttls.iter().all(|ttl| (30..=3600).contains(ttl)));
+
+        let filter = RecordFilter::new(&NodeRecordPolicy::default())?;
+        assert!(filter.allows(&name("foo.bar"), RecordType::A));
+
+        let res = RecordFilter::new(&NodeRecordPolicy {
+            min_ttl: Some(3600),
+            max_ttl: Some(30),
+            ..Default::default()
+        });
+        assert!(res.is_err());
+        Ok(())
+    }
+}
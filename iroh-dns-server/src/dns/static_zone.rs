@@ -0,0 +1,133 @@
+//! Static records of an origin, loaded from a zone file or from a list of records in the config.
+
+use std::path::PathBuf;
+
+use anyhow::{ensure, Context, Result};
+use hickory_server::proto::{
+    rr::{Name, Record, RecordType},
+    serialize::txt::Parser,
+};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+/// Static records served for an origin, in addition to the pkarr records.
+///
+/// The records are written in the zone file format of RFC 1035.  Names are relative to the
+/// origin, and records without a TTL use [`super::DnsConfig::default_ttl`].  SOA records are
+/// ignored, the SOA of the origin is always [`super::DnsConfig::default_soa`].
+///
+/// Records at names below the origin which start with a z-base-32 encoded public key are not
+/// served, because these names are resolved from the pkarr records.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StaticZoneConfig {
+    /// The origin of the records, which must be one of [`super::DnsConfig::origins`].
+    pub origin: String,
+    /// Path of a zone file with records for the origin.
+    ///
+    /// Relative paths are resolved from the working directory of the server.
+    #[serde(default)]
+    pub zone_file: Option<PathBuf>,
+    /// Records for the origin, one record per entry.
+    #[serde(default)]
+    pub records: Vec<String>,
+}
+
+impl StaticZoneConfig {
+    /// Read and parse the records from the zone file and the record list.
+    pub(super) fn load(&self, origin: &Name, default_ttl: u32) -> Result<Vec<Record>> {
+        let mut records = Vec::new();
+        if let Some(path) = &self.zone_file {
+            let zone = std::fs::read_to_string(path)
+                .with_context(|| format!("failed to read zone file {}", path.display()))?;
+            let zone_records = parse(&zone, Some(path.clone()), origin, default_ttl)
+                .with_context(|| format!("failed to parse zone file {}", path.display()))?;
+            records.extend(zone_records);
+        }
+        // Each record is parsed on its own, so that records without a TTL do not inherit the
+        // TTL of the previous record.
+        for record in &self.records {
+            let list_records = parse(record, None, origin, default_ttl)
+                .with_context(|| format!("failed to parse record `{record}`"))?;
+            records.extend(list_records);
+        }
+        Ok(records)
+    }
+}
+
+fn parse(
+    zone: &str,
+    path: Option<PathBuf>,
+    origin: &Name,
+    default_ttl: u32,
+) -> Result<Vec<Record>> {
+    // A `$TTL` directive in the zone overrides the default.
+    let zone = format!("$TTL {default_ttl}\n{zone}");
+    let (_origin, record_sets) = Parser::new(zone, path, Some(origin.clone())).parse()?;
+    let mut records = Vec::new();
+    for record in record_sets
+        .values()
+        .flat_map(|set| set.records_without_rrsigs())
+    {
+        if record.record_type() == RecordType::SOA {
+            warn!(%origin, "ignoring SOA record of static zone, the SOA is set with `default_soa`");
+            continue;
+        }
+        ensure!(
+            origin.zone_of(record.name()),
+            "record {} is not in zone {origin}",
+            record.name()
+        );
+        records.push(record.clone());
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use hickory_server::proto::rr::RData;
+
+    use super::*;
+
+    #[test]
+    fn parse_records() -> Result<()> {
+        let origin = Name::from_utf8("irohdns.example.")?;
+        let config = StaticZoneConfig {
+            origin: origin.to_string(),
+            zone_file: None,
+            records: vec![
+                "@ IN MX 10 mail".to_string(),
+                "mail 60 IN A 192.0.2.1".to_string(),
+                "_acme-challenge IN TXT \"token\"".to_string(),
+                "@ IN SOA ns1 hostmaster 1 10800 3600 604800 3600".to_string(),
+            ],
+        };
+        let records = config.load(&origin, 900)?;
+        assert_eq!(records.len(), 3);
+        let mx = records
+            .iter()
+            .find_map(|r| r.data().as_mx())
+            .expect("MX record");
+        assert_eq!(mx.exchange(), &Name::from_utf8("mail.irohdns.example.")?);
+        let a = records
+            .iter()
+            .find(|r| matches!(r.data(), RData::A(_)))
+            .expect("A record");
+        assert_eq!(a.ttl(), 60);
+        let txt = records
+            .iter()
+            .find(|r| r.record_type() == RecordType::TXT)
+            .expect("TXT record");
+        assert_eq!(
+            txt.name(),
+            &Name::from_utf8("_acme-challenge.irohdns.example.")?
+        );
+        assert_eq!(txt.ttl(), 900);
+
+        let config = StaticZoneConfig {
+            records: vec!["relay.other.example. IN A 192.0.2.1".to_string()],
+            ..config
+        };
+        assert!(config.load(&origin, 900).is_err());
+        Ok(())
+    }
+}
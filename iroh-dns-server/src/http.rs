@@ -2,20 +2,22 @@
 
 use std::{
     net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
     time::Instant,
 };
 
-use anyhow::{bail, Context, Result};
+use anyhow::{bail, ensure, Context, Result};
 use axum::{
-    extract::{ConnectInfo, Request},
-    handler::Handler,
+    extract::{ConnectInfo, MatchedPath, Request},
     http::Method,
     middleware::{self, Next},
     response::IntoResponse,
-    routing::get,
-    Router,
+    routing::{get, put},
+    Extension, Router,
 };
+use axum_server::accept::DefaultAcceptor;
 use iroh_metrics::{inc, inc_by};
+use iroh_relay::proxy_protocol::ProxyProtocolConfig;
 use serde::{Deserialize, Serialize};
 use tokio::{net::TcpListener, task::JoinSet};
 use tower_http::{
@@ -24,14 +26,29 @@ use tower_http::{
 };
 use tracing::{info, span, warn, Level};
 
+mod admin;
 mod doh;
 mod error;
 mod pkarr;
+mod proxy_protocol;
 mod rate_limiting;
+mod replication;
+mod subscribe;
 mod tls;
 
-pub use self::{rate_limiting::RateLimitConfig, tls::CertMode};
-use crate::{config::Config, metrics::Metrics, state::AppState};
+use self::proxy_protocol::ClientAddrAcceptor;
+pub use self::{
+    admin::AdminConfig,
+    rate_limiting::{RateLimit, RateLimitConfig, RateLimitKey, RateLimits},
+    subscribe::SubscribeConfig,
+    tls::CertMode,
+};
+use crate::{
+    config::Config,
+    dns::DnsTlsConfig,
+    metrics::{self, Metrics},
+    state::AppState,
+};
 
 /// Config for the HTTP server
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -40,6 +57,12 @@ pub struct HttpConfig {
     pub port: u16,
     /// Optionally set a custom bind address (will use 0.0.0.0 if unset)
     pub bind_addr: Option<IpAddr>,
+    /// PROXY protocol config, for running behind a TCP load balancer.
+    ///
+    /// If set, connections from the trusted proxies must start with a PROXY protocol header,
+    /// and the client address from the header is used for rate limits and DoH queries.
+    #[serde(default)]
+    pub proxy_protocol: Option<ProxyProtocolConfig>,
 }
 
 /// Config for the HTTPS server
@@ -57,6 +80,11 @@ pub struct HttpsConfig {
     pub letsencrypt_contact: Option<String>,
     /// Whether to use the letsenrypt production servers (only applies to [`CertMode::LetsEncrypt`])
     pub letsencrypt_prod: Option<bool>,
+    /// PROXY protocol config, for running behind a TCP load balancer.
+    ///
+    /// See [`HttpConfig::proxy_protocol`].
+    #[serde(default)]
+    pub proxy_protocol: Option<ProxyProtocolConfig>,
 }
 
 /// The HTTP(S) server part of iroh-dns-server
@@ -64,6 +92,7 @@ pub struct HttpServer {
     tasks: JoinSet<std::io::Result<()>>,
     http_addr: Option<SocketAddr>,
     https_addr: Option<SocketAddr>,
+    dns_tls_config: Option<DnsTlsConfig>,
 }
 
 impl HttpServer {
@@ -72,13 +101,24 @@ impl HttpServer {
         http_config: Option<HttpConfig>,
         https_config: Option<HttpsConfig>,
         rate_limit_config: RateLimitConfig,
+        subscribe_config: SubscribeConfig,
+        admin_config: Option<AdminConfig>,
+        replication_token: Option<String>,
         state: AppState,
     ) -> Result<HttpServer> {
         if http_config.is_none() && https_config.is_none() {
             bail!("Either http or https config is required");
         }
 
-        let app = create_app(state, &rate_limit_config);
+        let app = router(
+            state,
+            RouterOptions {
+                rate_limit: rate_limit_config,
+                subscribe: subscribe_config,
+                admin: admin_config,
+                replication_token,
+            },
+        )?;
 
         let mut tasks = JoinSet::new();
 
@@ -91,8 +131,10 @@ impl HttpServer {
             let app = app.clone();
             let listener = TcpListener::bind(bind_addr).await?.into_std()?;
             let bound_addr = listener.local_addr()?;
+            let acceptor = ClientAddrAcceptor::new(DefaultAcceptor, config.proxy_protocol);
             let fut = axum_server::from_tcp(listener)
-                .serve(app.into_make_service_with_connect_info::<SocketAddr>());
+                .acceptor(acceptor)
+                .serve(app.into_make_service());
             info!("HTTP server listening on {bind_addr}");
             tasks.spawn(fut);
             Some(bound_addr)
@@ -101,12 +143,13 @@ impl HttpServer {
         };
 
         // launch https
+        let mut dns_tls_config = None;
         let https_addr = if let Some(config) = https_config {
             let bind_addr = SocketAddr::new(
                 config.bind_addr.unwrap_or(Ipv4Addr::UNSPECIFIED.into()),
                 config.port,
             );
-            let acceptor = {
+            let (acceptor, tls_config) = {
                 let cache_path = Config::data_dir()?
                     .join("cert_cache")
                     .join(config.cert_mode.to_string());
@@ -125,11 +168,13 @@ impl HttpServer {
                     )
                     .await?
             };
+            dns_tls_config = Some(tls_config);
             let listener = TcpListener::bind(bind_addr).await?.into_std()?;
             let bound_addr = listener.local_addr()?;
+            let acceptor = ClientAddrAcceptor::new(acceptor, config.proxy_protocol);
             let fut = axum_server::from_tcp(listener)
                 .acceptor(acceptor)
-                .serve(app.into_make_service_with_connect_info::<SocketAddr>());
+                .serve(app.into_make_service());
             info!("HTTPS server listening on {bind_addr}");
             tasks.spawn(fut);
             Some(bound_addr)
@@ -141,6 +186,7 @@ impl HttpServer {
             tasks,
             http_addr,
             https_addr,
+            dns_tls_config,
         })
     }
 
@@ -154,6 +200,11 @@ impl HttpServer {
         self.https_addr
     }
 
+    /// Get the TLS config for encrypted DNS listeners, with the certificates of the HTTPS server.
+    pub fn dns_tls_config(&self) -> Option<&DnsTlsConfig> {
+        self.dns_tls_config.as_ref()
+    }
+
     /// Shutdown the server and wait for all tasks to complete.
     pub async fn shutdown(mut self) -> Result<()> {
         // TODO: Graceful cancellation.
@@ -185,7 +236,32 @@ impl HttpServer {
     }
 }
 
-pub(crate) fn create_app(state: AppState, rate_limit_config: &RateLimitConfig) -> Router {
+/// Options for the routes created by [`router`].
+#[derive(Debug, Clone, Default)]
+pub struct RouterOptions {
+    /// Rate limits for the pkarr PUT and GET routes.
+    pub rate_limit: RateLimitConfig,
+    /// Limits of the pkarr subscription route.
+    pub subscribe: SubscribeConfig,
+    /// Config for the admin routes, which are nested under `/admin`.
+    ///
+    /// If set to `None` the admin routes are not served.
+    pub admin: Option<AdminConfig>,
+    /// The token peer servers authenticate with on the replication route.
+    ///
+    /// If set to `None` the replication route is not served.
+    pub replication_token: Option<String>,
+}
+
+/// Create the router with the pkarr relay, DoH and other routes of the HTTP server.
+///
+/// This allows to serve the routes from an existing axum app, without spawning a
+/// [`HttpServer`].  The router must be served with
+/// [`Router::into_make_service_with_connect_info`] with a [`SocketAddr`] as connect info,
+/// because the DoH handlers and the rate limits use the address of the client.
+///
+/// Fails if the rate limits are invalid, or if the admin or replication token is empty.
+pub fn router(state: AppState, options: RouterOptions) -> Result<Router> {
     // configure cors middleware
     let cors = CorsLayer::new()
         // allow `GET` and `POST` when accessing the resource
@@ -195,59 +271,90 @@ pub(crate) fn create_app(state: AppState, rate_limit_config: &RateLimitConfig) -
 
     // configure tracing middleware
     let trace = TraceLayer::new_for_http().make_span_with(|request: &http::Request<_>| {
-        let conn_info = request
+        let src = request
             .extensions()
             .get::<ConnectInfo<SocketAddr>>()
-            .expect("connectinfo extension to be present");
+            .map(|conn_info| conn_info.0);
         let span = span!(
         Level::DEBUG,
             "http_request",
             method = ?request.method(),
             uri = ?request.uri(),
-            src = %conn_info.0,
+            src = ?src,
             );
         span
     });
 
     // configure rate limiting middleware
-    let rate_limit = rate_limiting::create(rate_limit_config);
+    //
+    // only the pkarr routes get a rate limit
+    let rate_limits = rate_limiting::create(&options.rate_limit)?;
+    let mut pkarr_put = put(pkarr::put);
+    for layer in rate_limits.put {
+        pkarr_put = pkarr_put.layer(layer);
+    }
+    let mut pkarr_get = get(pkarr::get);
+    for layer in rate_limits.get {
+        pkarr_get = pkarr_get.layer(layer);
+    }
 
     // configure routes
-    //
-    // only the pkarr::put route gets a rate limit
-    let router = Router::new()
+    let mut router = Router::new()
         .route("/dns-query", get(doh::get).post(doh::post))
         .route(
-            "/pkarr/:key",
-            if let Some(rate_limit) = rate_limit {
-                get(pkarr::get).put(pkarr::put.layer(rate_limit))
-            } else {
-                get(pkarr::get).put(pkarr::put)
-            },
+            "/pkarr/subscribe",
+            get(subscribe::subscribe)
+                .layer(Extension(subscribe::Subscriptions::new(&options.subscribe))),
         )
+        .route("/pkarr/:key", pkarr_get.merge(pkarr_put))
         .route("/healthcheck", get(|| async { "OK" }))
-        .route("/", get(|| async { "Hi!" }))
-        .with_state(state);
+        .route("/", get(|| async { "Hi!" }));
+
+    // the replication endpoint is only served to peers if replication is configured
+    if let Some(token) = &options.replication_token {
+        ensure!(!token.is_empty(), "the replication token must not be empty");
+        let token: Arc<str> = token.as_str().into();
+        router = router.route(
+            crate::replication::REPLICATION_PATH,
+            get(replication::get)
+                .post(replication::post)
+                .layer(Extension(rate_limits.replication))
+                .route_layer(middleware::from_fn_with_state(token, admin::authenticate)),
+        );
+    }
+
+    // the admin routes are only served if a token is configured
+    if let Some(admin_config) = &options.admin {
+        admin_config.validate().context("invalid admin config")?;
+        router = router.nest("/admin", admin::router(admin_config));
+    }
+    let router = router.with_state(state);
 
     // configure app
-    router
+    let router = router
         .layer(cors)
         .layer(trace)
-        .route_layer(middleware::from_fn(metrics_middleware))
+        .route_layer(middleware::from_fn(metrics_middleware));
+    Ok(router)
 }
 
 /// Record request metrics.
 // TODO:
-// * Request duration would be much better tracked as a histogram.
-// * It would be great to attach labels to the metrics, so that the recorded metrics
+// * It would be great to attach labels to the counters, so that the recorded metrics
 // can filter by method etc.
 //
 // See also
 // https://github.com/tokio-rs/axum/blob/main/examples/prometheus-metrics/src/main.rs#L114
 async fn metrics_middleware(req: Request, next: Next) -> impl IntoResponse {
     let start = Instant::now();
+    let method = req.method().clone();
+    let path = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string());
     let response = next.run(req).await;
-    let latency = start.elapsed().as_millis();
+    let elapsed = start.elapsed();
+    let latency = elapsed.as_millis();
     let status = response.status();
     inc_by!(Metrics, http_requests_duration_ms, latency as u64);
     inc!(Metrics, http_requests);
@@ -256,5 +363,8 @@ async fn metrics_middleware(req: Request, next: Next) -> impl IntoResponse {
     } else {
         inc!(Metrics, http_requests_error);
     }
+    if let Some(path) = path {
+        metrics::record_http_request(method.as_str(), &path, elapsed);
+    }
     response
 }
@@ -0,0 +1,218 @@
+//! Authenticated routes to manage the signed packet store.
+//!
+//! All routes require an `Authorization: Bearer <token>` header with the token configured in
+//! [`AdminConfig::token`]:
+//!
+//! * `GET /admin/packets?after_time=<us>&after_key=<z32>&limit=<n>` lists the stored packets,
+//!   ordered by timestamp and public key.
+//! * `GET /admin/packets/:key` returns a stored packet with its DNS records.
+//! * `DELETE /admin/packets/:key` removes a stored packet.
+//! * `GET /admin/blocklist` lists the blocked public keys.
+//! * `PUT /admin/blocklist/:key` blocks a public key and removes its packet.
+//! * `DELETE /admin/blocklist/:key` unblocks a public key.
+//! * `GET /admin/archive` exports the store as an archive.
+//! * `POST /admin/archive` imports an archive into the store.
+//! * `POST /admin/dns/reload` reloads the static DNS records from the zone files.
+
+use std::{io, sync::Arc};
+
+use anyhow::{ensure, Result};
+use axum::{
+    body::Body,
+    extract::{DefaultBodyLimit, Path, Query, Request, State},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post, put},
+    Json, Router,
+};
+use http::{header, StatusCode};
+use n0_future::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio_util::io::StreamReader;
+use tracing::info;
+
+use super::error::AppError;
+use crate::{
+    admin::{PacketDetails, PacketInfo},
+    state::AppState,
+    util::PublicKeyBytes,
+};
+
+/// The default number of packets returned by `GET /admin/packets`.
+const DEFAULT_LIST_LIMIT: usize = 100;
+
+/// The maximum number of packets returned by `GET /admin/packets`.
+const MAX_LIST_LIMIT: usize = 10_000;
+
+/// Config for the admin API.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AdminConfig {
+    /// The bearer token required for all admin requests.
+    pub token: String,
+}
+
+impl AdminConfig {
+    /// Check the config for invalid values.
+    pub fn validate(&self) -> Result<()> {
+        ensure!(!self.token.is_empty(), "the admin token must not be empty");
+        Ok(())
+    }
+}
+
+/// Create the router for the admin routes, to be nested at `/admin`.
+pub fn router(config: &AdminConfig) -> Router<AppState> {
+    let token: Arc<str> = config.token.as_str().into();
+    Router::new()
+        .route("/packets", get(list_packets))
+        .route("/packets/:key", get(get_packet).delete(delete_packet))
+        .route("/blocklist", get(blocklist))
+        .route("/blocklist/:key", put(block).delete(unblock))
+        .route(
+            "/archive",
+            get(export).post(import).layer(DefaultBodyLimit::disable()),
+        )
+        .route("/dns/reload", post(reload_static_records))
+        .route_layer(middleware::from_fn_with_state(token, authenticate))
+}
+
+/// Reject requests without the bearer `token`.
+pub(super) async fn authenticate(
+    State(token): State<Arc<str>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let authorized = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|provided| constant_time_eq(provided.as_bytes(), token.as_bytes()));
+    if !authorized {
+        return AppError::with_status(StatusCode::UNAUTHORIZED).into_response();
+    }
+    next.run(req).await
+}
+
+/// Compare two byte strings in time independent of their contents.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+fn parse_key(key: &str) -> Result<PublicKeyBytes, AppError> {
+    PublicKeyBytes::from_z32(key)
+        .map_err(|e| AppError::new(StatusCode::BAD_REQUEST, Some(format!("invalid key: {e}"))))
+}
+
+#[derive(Debug, Deserialize)]
+struct ListQuery {
+    /// Only return packets with a timestamp after this one, in microseconds.
+    after_time: Option<u64>,
+    /// The z-base-32 encoded public key of the last packet with `after_time` already listed.
+    after_key: Option<String>,
+    /// Maximum number of packets to return.
+    limit: Option<usize>,
+}
+
+async fn list_packets(
+    State(state): State<AppState>,
+    Query(query): Query<ListQuery>,
+) -> Result<Json<Vec<PacketInfo>>, AppError> {
+    let after_key = match query.after_key {
+        Some(key) => parse_key(&key)?,
+        None => PublicKeyBytes::new([0; 32]),
+    };
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_LIST_LIMIT)
+        .min(MAX_LIST_LIMIT);
+    let packets = state
+        .store
+        .packets_after((query.after_time.unwrap_or(0), after_key), limit)
+        .await?;
+    Ok(Json(
+        packets.iter().map(PacketInfo::from_signed_packet).collect(),
+    ))
+}
+
+async fn get_packet(
+    State(state): State<AppState>,
+    Path(key): Path<String>,
+) -> Result<Json<PacketDetails>, AppError> {
+    let key = parse_key(&key)?;
+    let packet = state
+        .store
+        .get_signed_packet(&key)
+        .await?
+        .ok_or_else(|| AppError::with_status(StatusCode::NOT_FOUND))?;
+    Ok(Json(PacketDetails::from_signed_packet(&packet)?))
+}
+
+async fn delete_packet(
+    State(state): State<AppState>,
+    Path(key): Path<String>,
+) -> Result<StatusCode, AppError> {
+    let key = parse_key(&key)?;
+    if !state.store.remove(&key).await? {
+        return Err(AppError::with_status(StatusCode::NOT_FOUND));
+    }
+    info!(%key, "admin: removed packet");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn blocklist(State(state): State<AppState>) -> Result<Json<Vec<String>>, AppError> {
+    let keys = state.store.blocked().await?;
+    Ok(Json(keys.into_iter().map(PublicKeyBytes::to_z32).collect()))
+}
+
+async fn block(
+    State(state): State<AppState>,
+    Path(key): Path<String>,
+) -> Result<StatusCode, AppError> {
+    let key = parse_key(&key)?;
+    state.store.block(&key).await?;
+    info!(%key, "admin: blocked key");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn unblock(
+    State(state): State<AppState>,
+    Path(key): Path<String>,
+) -> Result<StatusCode, AppError> {
+    let key = parse_key(&key)?;
+    if !state.store.unblock(&key).await? {
+        return Err(AppError::with_status(StatusCode::NOT_FOUND));
+    }
+    info!(%key, "admin: unblocked key");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn export(State(state): State<AppState>) -> impl IntoResponse {
+    let headers = [(header::CONTENT_TYPE, "application/octet-stream")];
+    (headers, Body::from_stream(state.store.export()))
+}
+
+async fn import(State(state): State<AppState>, body: Body) -> Result<impl IntoResponse, AppError> {
+    let reader = StreamReader::new(
+        body.into_data_stream()
+            .map(|chunk| chunk.map_err(io::Error::other)),
+    );
+    let stats = state.store.import(reader).await.map_err(|e| {
+        AppError::new(
+            StatusCode::BAD_REQUEST,
+            Some(format!("failed to import archive: {e:#}")),
+        )
+    })?;
+    info!(?stats, "admin: imported archive");
+    Ok(Json(stats))
+}
+
+async fn reload_static_records(State(state): State<AppState>) -> Result<StatusCode, AppError> {
+    state.dns_handler.reload_static_records().map_err(|e| {
+        AppError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Some(format!("failed to reload static records: {e:#}")),
+        )
+    })?;
+    info!("admin: reloaded static DNS records");
+    Ok(StatusCode::NO_CONTENT)
+}
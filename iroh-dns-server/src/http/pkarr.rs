@@ -8,7 +8,11 @@ use http::{header, StatusCode};
 use tracing::info;
 
 use super::error::AppError;
-use crate::{state::AppState, store::PacketSource, util::PublicKeyBytes};
+use crate::{
+    state::AppState,
+    store::{self, PacketSource},
+    util::PublicKeyBytes,
+};
 
 pub async fn put(
     State(state): State<AppState>,
@@ -18,12 +22,24 @@ pub async fn put(
     let key = pkarr::PublicKey::try_from(key.as_str())
         .map_err(|e| AppError::new(StatusCode::BAD_REQUEST, Some(format!("invalid key: {e}"))))?;
     let label = &key.to_z32()[..10];
+    if state.store.is_blocked(&key.clone().into()).await? {
+        return Err(AppError::new(
+            StatusCode::FORBIDDEN,
+            Some("public key is blocked"),
+        ));
+    }
     let signed_packet = pkarr::SignedPacket::from_relay_payload(&key, &body).map_err(|e| {
         AppError::new(
             StatusCode::BAD_REQUEST,
             Some(format!("invalid body payload: {e}")),
         )
     })?;
+    if store::is_too_new(&signed_packet) {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            Some("packet timestamp is too far in the future"),
+        ));
+    }
 
     let updated = state
         .store
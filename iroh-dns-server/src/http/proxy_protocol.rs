@@ -0,0 +1,60 @@
+use std::{io, net::SocketAddr, sync::Arc};
+
+use axum::extract::ConnectInfo;
+use axum_server::accept::Accept;
+use iroh_relay::proxy_protocol::ProxyProtocolConfig;
+use n0_future::{future::Boxed as BoxFuture, FutureExt};
+use tokio::net::TcpStream;
+use tower_http::add_extension::AddExtension;
+
+/// Acceptor which provides the client address of a connection as [`ConnectInfo`].
+///
+/// If a [`ProxyProtocolConfig`] is set, the client address is read from the PROXY protocol
+/// header of connections from trusted proxies.  Otherwise, and for all other connections, the
+/// peer address is used.  The stream is then passed on to the inner acceptor.
+///
+/// Because this sets the [`ConnectInfo`], the router must be served with
+/// [`axum::Router::into_make_service`].
+#[derive(Debug, Clone)]
+pub(crate) struct ClientAddrAcceptor<A> {
+    inner: A,
+    proxy_protocol: Option<Arc<ProxyProtocolConfig>>,
+}
+
+impl<A> ClientAddrAcceptor<A> {
+    pub(crate) fn new(inner: A, proxy_protocol: Option<ProxyProtocolConfig>) -> Self {
+        Self {
+            inner,
+            proxy_protocol: proxy_protocol.map(Arc::new),
+        }
+    }
+}
+
+impl<A, S> Accept<TcpStream, S> for ClientAddrAcceptor<A>
+where
+    A: Accept<TcpStream, AddExtension<S, ConnectInfo<SocketAddr>>> + Clone + Send + 'static,
+    A::Future: Send,
+    S: Send + 'static,
+{
+    type Stream = A::Stream;
+    type Service = A::Service;
+    type Future = BoxFuture<io::Result<(Self::Stream, Self::Service)>>;
+
+    fn accept(&self, mut stream: TcpStream, service: S) -> Self::Future {
+        let inner = self.inner.clone();
+        let proxy_protocol = self.proxy_protocol.clone();
+        async move {
+            let peer_addr = stream.peer_addr()?;
+            let client_addr = match proxy_protocol {
+                Some(config) => config
+                    .client_addr(&mut stream, peer_addr)
+                    .await
+                    .map_err(io::Error::other)?,
+                None => peer_addr,
+            };
+            let service = AddExtension::new(service, ConnectInfo(client_addr));
+            inner.accept(stream, service).await
+        }
+        .boxed()
+    }
+}
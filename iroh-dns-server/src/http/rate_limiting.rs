@@ -1,11 +1,31 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    net::IpAddr,
+    sync::{Arc, Weak},
+    time::Duration,
+};
 
+use anyhow::{Context, Result};
+use axum::{body::Body, response::IntoResponse};
 use governor::{clock::QuantaInstant, middleware::NoOpMiddleware};
+use http::{header, HeaderValue, Request, Response, StatusCode};
+use iroh_metrics::inc;
 use serde::{Deserialize, Serialize};
 use tower_governor::{
-    governor::GovernorConfigBuilder,
-    key_extractor::{PeerIpKeyExtractor, SmartIpKeyExtractor},
-    GovernorLayer,
+    governor::{GovernorConfigBuilder, SharedRateLimiter},
+    key_extractor::{KeyExtractor, PeerIpKeyExtractor, SmartIpKeyExtractor},
+    GovernorError, GovernorLayer,
+};
+
+use super::error::AppError;
+use crate::{metrics::Metrics, util::PublicKeyBytes};
+
+/// The limit for pkarr PUT requests per IP address used by [`RateLimitConfig::Simple`] and
+/// [`RateLimitConfig::Smart`]: bursts of up to two requests, one request replenished every
+/// four seconds.
+const DEFAULT_PUT_LIMIT: RateLimit = RateLimit {
+    by: RateLimitKey::Ip,
+    period: Duration::from_secs(4),
+    burst_size: 2,
 };
 
 /// Config for http server rate limit.
@@ -14,17 +34,20 @@ use tower_governor::{
 pub enum RateLimitConfig {
     /// Disable rate limit.
     Disabled,
-    /// Enable rate limit based on the connection's peer IP address.
+    /// Enable rate limit for pkarr PUT requests based on the connection's peer IP address.
     ///
     /// <https://docs.rs/tower_governor/latest/tower_governor/key_extractor/struct.PeerIpKeyExtractor.html>
     #[default]
     Simple,
-    /// Enable rate limit based on headers commonly used by reverse proxies.
+    /// Enable rate limit for pkarr PUT requests based on headers commonly used by reverse
+    /// proxies.
     ///
     /// Uses headers commonly used by reverse proxies to extract the original IP address,
     /// falling back to the connection's peer IP address.
     /// <https://docs.rs/tower_governor/latest/tower_governor/key_extractor/struct.SmartIpKeyExtractor.html>
     Smart,
+    /// Enable custom rate limits for pkarr PUT and GET requests.
+    Limits(RateLimits),
 }
 
 impl Default for &RateLimitConfig {
@@ -33,51 +56,243 @@ impl Default for &RateLimitConfig {
     }
 }
 
-/// Create the default rate-limiting layer.
-///
-/// This spawns a background thread to clean up the rate limiting cache.
-pub fn create(
-    rate_limit_config: &RateLimitConfig,
-) -> Option<GovernorLayer<PeerIpKeyExtractor, NoOpMiddleware<QuantaInstant>>> {
-    let use_smart_extractor = match rate_limit_config {
-        RateLimitConfig::Disabled => {
-            tracing::info!("Rate limiting disabled");
-            return None;
+impl RateLimitConfig {
+    fn to_limits(&self) -> Option<RateLimits> {
+        match self {
+            RateLimitConfig::Disabled => None,
+            RateLimitConfig::Simple => Some(RateLimits {
+                smart_ip: false,
+                put: vec![DEFAULT_PUT_LIMIT],
+                get: vec![],
+            }),
+            RateLimitConfig::Smart => Some(RateLimits {
+                smart_ip: true,
+                put: vec![DEFAULT_PUT_LIMIT],
+                get: vec![],
+            }),
+            RateLimitConfig::Limits(limits) => Some(limits.clone()),
         }
-        RateLimitConfig::Simple => false,
-        RateLimitConfig::Smart => true,
-    };
+    }
+}
 
-    tracing::info!("Rate limiting enabled ({rate_limit_config:?})");
+/// Rate limits for the pkarr requests.
+///
+/// A request is rejected with `429 Too Many Requests` if it exceeds any of the limits.
+#[derive(Debug, Deserialize, Default, Serialize, Clone)]
+pub struct RateLimits {
+    /// Use headers commonly set by reverse proxies to determine the client IP address.
+    ///
+    /// If false, the connection's peer IP address is used.
+    #[serde(default)]
+    pub smart_ip: bool,
+    /// Limits for publishing signed packets with `PUT /pkarr/:key`.
+    #[serde(default)]
+    pub put: Vec<RateLimit>,
+    /// Limits for fetching signed packets with `GET /pkarr/:key`.
+    #[serde(default)]
+    pub get: Vec<RateLimit>,
+}
+
+/// A single rate limit.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RateLimit {
+    /// What the requests are counted by.
+    pub by: RateLimitKey,
+    /// Interval after which one request of the burst is replenished.  Must not be zero.
+    #[serde(with = "humantime_serde")]
+    pub period: Duration,
+    /// Maximum number of requests in a burst.  Must not be zero.
+    pub burst_size: u32,
+}
+
+/// What the requests of a [`RateLimit`] are counted by.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitKey {
+    /// Count the requests by client IP address.
+    Ip,
+    /// Count the requests by the public key of the signed packet.
+    PublicKey,
+    /// Count the requests by the combination of public key and client IP address.
+    PublicKeyAndIp,
+}
 
-    // Configure rate limiting:
-    // * allow bursts with up to five requests per IP address
-    // * replenish one element every two seconds
-    let mut governor_conf_builder = GovernorConfigBuilder::default();
-    // governor_conf_builder.use_headers()
-    governor_conf_builder.per_second(4);
-    governor_conf_builder.burst_size(2);
+type PkarrRateLimitLayer = GovernorLayer<PkarrKeyExtractor, NoOpMiddleware<QuantaInstant>>;
+
+type PkarrRateLimiter = SharedRateLimiter<PkarrRateLimitKey, NoOpMiddleware<QuantaInstant>>;
+
+/// The rate-limiting layers for the pkarr routes.
+#[derive(Default)]
+pub struct PkarrRateLimits {
+    pub put: Vec<PkarrRateLimitLayer>,
+    pub get: Vec<PkarrRateLimitLayer>,
+    pub replication: PacketRateLimits,
+}
+
+/// The per-public-key limits of pkarr PUT requests, applied to each replicated packet.
+///
+/// The limiters are shared with the PUT route, so a public key can not exceed its limit by
+/// publishing to several replicating servers.  Limits by IP address are not applied, as all
+/// replicated packets come from the few peer servers.
+#[derive(Clone, Default)]
+pub struct PacketRateLimits(Arc<Vec<PkarrRateLimiter>>);
 
-    if use_smart_extractor {
-        governor_conf_builder.key_extractor(SmartIpKeyExtractor);
+impl PacketRateLimits {
+    /// Returns whether a packet of `public_key` is within the limits.
+    pub fn check(&self, public_key: PublicKeyBytes) -> bool {
+        self.0.iter().all(|limiter| {
+            limiter
+                .check_key(&PkarrRateLimitKey::PublicKey(public_key))
+                .is_ok()
+        })
     }
+}
+
+/// Create the rate-limiting layers for the pkarr routes.
+///
+/// This spawns a background thread to clean up the rate limiting cache.  Fails if a limit
+/// has a zero `period` or `burst_size`.
+pub fn create(rate_limit_config: &RateLimitConfig) -> Result<PkarrRateLimits> {
+    let Some(limits) = rate_limit_config.to_limits() else {
+        tracing::info!("Rate limiting disabled");
+        return Ok(PkarrRateLimits::default());
+    };
 
-    let governor_conf = governor_conf_builder
-        .finish()
-        .expect("failed to build rate-limiting governor");
+    tracing::info!("Rate limiting enabled ({rate_limit_config:?})");
 
-    let governor_conf = Arc::new(governor_conf);
+    let mut limiters = Vec::new();
+    let mut create_layers = |rate_limits: &[RateLimit]| {
+        rate_limits
+            .iter()
+            .map(|limit| {
+                let governor_conf = GovernorConfigBuilder::default()
+                    .period(limit.period)
+                    .burst_size(limit.burst_size)
+                    .key_extractor(PkarrKeyExtractor {
+                        by: limit.by,
+                        smart_ip: limits.smart_ip,
+                    })
+                    .error_handler(error_response)
+                    .finish()
+                    .with_context(|| {
+                        format!(
+                            "invalid rate limit {limit:?}: period and burst_size must not be zero"
+                        )
+                    })?;
+                let governor_conf = Arc::new(governor_conf);
+                limiters.push(Arc::downgrade(governor_conf.limiter()));
+                Ok(GovernorLayer {
+                    config: governor_conf,
+                })
+            })
+            .collect::<Result<Vec<_>>>()
+    };
+    let put = create_layers(&limits.put)?;
+    let get = create_layers(&limits.get)?;
+    let replication = put
+        .iter()
+        .zip(&limits.put)
+        .filter(|(_, limit)| limit.by == RateLimitKey::PublicKey)
+        .map(|(layer, _)| layer.config.limiter().clone())
+        .collect();
 
-    // The governor needs a background task for garbage collection (to clear expired records)
+    // The governor needs a background task for garbage collection (to clear expired records).
+    // It only holds weak references, and stops once the layers of all limiters are dropped.
     let gc_interval = Duration::from_secs(60);
-    let governor_limiter = governor_conf.limiter().clone();
     std::thread::spawn(move || loop {
         std::thread::sleep(gc_interval);
-        tracing::debug!("rate limiting storage size: {}", governor_limiter.len());
-        governor_limiter.retain_recent();
+        limiters.retain(|limiter| limiter.strong_count() > 0);
+        if limiters.is_empty() {
+            break;
+        }
+        for limiter in limiters.iter().filter_map(Weak::upgrade) {
+            tracing::debug!("rate limiting storage size: {}", limiter.len());
+            limiter.retain_recent();
+        }
     });
 
-    Some(GovernorLayer {
-        config: governor_conf,
+    Ok(PkarrRateLimits {
+        put,
+        get,
+        replication: PacketRateLimits(Arc::new(replication)),
     })
 }
+
+/// Extracts the key of a [`RateLimit`] from a request to `/pkarr/:key`.
+#[derive(Debug, Clone)]
+pub struct PkarrKeyExtractor {
+    by: RateLimitKey,
+    smart_ip: bool,
+}
+
+/// The key by which requests are counted.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PkarrRateLimitKey {
+    Ip(IpAddr),
+    PublicKey(PublicKeyBytes),
+    PublicKeyAndIp(PublicKeyBytes, IpAddr),
+}
+
+impl KeyExtractor for PkarrKeyExtractor {
+    type Key = PkarrRateLimitKey;
+
+    fn extract<T>(&self, req: &Request<T>) -> Result<Self::Key, GovernorError> {
+        // the peer and smart IP extractors only fail with `UnableToExtractKey`
+        let ip = || match self.smart_ip {
+            true => SmartIpKeyExtractor.extract(req).ok(),
+            false => PeerIpKeyExtractor.extract(req).ok(),
+        };
+        let public_key = || {
+            let key = req.uri().path().rsplit('/').next().unwrap_or_default();
+            PublicKeyBytes::from_z32(key).ok()
+        };
+        let invalid_key = || GovernorError::Other {
+            code: StatusCode::BAD_REQUEST,
+            msg: Some("invalid public key".to_string()),
+            headers: None,
+        };
+        let key = match self.by {
+            RateLimitKey::Ip => ip().map(PkarrRateLimitKey::Ip),
+            RateLimitKey::PublicKey => {
+                let key = public_key().ok_or_else(invalid_key)?;
+                Some(PkarrRateLimitKey::PublicKey(key))
+            }
+            RateLimitKey::PublicKeyAndIp => {
+                let key = public_key().ok_or_else(invalid_key)?;
+                ip().map(|ip| PkarrRateLimitKey::PublicKeyAndIp(key, ip))
+            }
+        };
+        key.ok_or(GovernorError::UnableToExtractKey)
+    }
+}
+
+/// Turn rate limiting errors into responses, with a `Retry-After` header for rejected requests.
+fn error_response(error: GovernorError) -> Response<Body> {
+    match error {
+        GovernorError::TooManyRequests { wait_time, headers } => {
+            inc!(Metrics, pkarr_rate_limited);
+            // the wait time is rounded down to full seconds
+            let retry_after = wait_time + 1;
+            let mut response = AppError::new(
+                StatusCode::TOO_MANY_REQUESTS,
+                Some(format!("too many requests, retry after {retry_after}s")),
+            )
+            .into_response();
+            response.headers_mut().extend(headers.unwrap_or_default());
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+            response
+        }
+        GovernorError::UnableToExtractKey => AppError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Some("unable to extract rate limiting key"),
+        )
+        .into_response(),
+        GovernorError::Other { code, msg, headers } => {
+            let mut response = AppError::new(code, msg).into_response();
+            response.headers_mut().extend(headers.unwrap_or_default());
+            response
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use anyhow::Result;
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Extension,
+};
+use bytes::Bytes;
+use http::{header, StatusCode};
+use iroh_metrics::inc;
+use serde::Deserialize;
+use tracing::debug;
+
+use super::{error::AppError, rate_limiting::PacketRateLimits};
+use crate::{
+    metrics::Metrics,
+    replication::{decode_packets, encode_packets, MAX_BATCH_SIZE},
+    state::AppState,
+    store::PacketSource,
+    util::PublicKeyBytes,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct PacketsQuery {
+    /// Only return packets with a timestamp after this one, in microseconds.
+    after_time: Option<u64>,
+    /// The z-base-32 encoded public key of the last packet with `after_time` already received.
+    after_key: Option<String>,
+    /// Maximum number of packets to return.
+    limit: Option<usize>,
+}
+
+pub async fn get(
+    State(state): State<AppState>,
+    Query(query): Query<PacketsQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let after_key = match query.after_key {
+        Some(key) => PublicKeyBytes::from_z32(&key).map_err(|e| {
+            AppError::new(StatusCode::BAD_REQUEST, Some(format!("invalid key: {e}")))
+        })?,
+        None => PublicKeyBytes::new([0; 32]),
+    };
+    let limit = query.limit.unwrap_or(MAX_BATCH_SIZE).min(MAX_BATCH_SIZE);
+    let packets = state
+        .store
+        .packets_after((query.after_time.unwrap_or(0), after_key), limit)
+        .await?;
+    let headers = [(header::CONTENT_TYPE, "application/octet-stream")];
+    Ok((headers, encode_packets(&packets)))
+}
+
+pub async fn post(
+    State(state): State<AppState>,
+    Extension(rate_limits): Extension<PacketRateLimits>,
+    body: Bytes,
+) -> Result<impl IntoResponse, AppError> {
+    let packets = decode_packets(&body).map_err(|e| {
+        AppError::new(
+            StatusCode::BAD_REQUEST,
+            Some(format!("invalid packets: {e}")),
+        )
+    })?;
+    if packets.len() > MAX_BATCH_SIZE {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            Some(format!(
+                "too many packets, at most {MAX_BATCH_SIZE} allowed"
+            )),
+        ));
+    }
+    debug!("received {} replicated packets", packets.len());
+    for packet in packets {
+        if !rate_limits.check(PublicKeyBytes::from_signed_packet(&packet)) {
+            debug!("dropped rate limited replicated packet");
+            inc!(Metrics, pkarr_rate_limited);
+            continue;
+        }
+        state
+            .store
+            .insert(packet, PacketSource::Replication)
+            .await?;
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
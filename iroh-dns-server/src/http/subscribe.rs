@@ -0,0 +1,222 @@
+//! Server-sent events endpoint to subscribe to pkarr packet updates.
+//!
+//! A client connects to `GET /pkarr/subscribe?keys=<z32>,<z32>` and receives a `packet` event
+//! for each requested public key: first with the currently stored signed packet, if any, and
+//! then whenever the store accepts a newer packet for the key.  The data of each event is the
+//! z-base-32 encoded public key and the base64url encoded relay payload of the signed packet,
+//! separated by a space.
+
+use std::{
+    collections::{HashSet, VecDeque},
+    convert::Infallible,
+    sync::Arc,
+};
+
+use anyhow::Result;
+use axum::{
+    extract::{Query, State},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse,
+    },
+    Extension,
+};
+use http::StatusCode;
+use iroh_metrics::{dec, inc};
+use n0_future::{stream, Stream};
+use pkarr::SignedPacket;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, OwnedSemaphorePermit, Semaphore};
+use tracing::{debug, warn};
+
+use super::error::AppError;
+use crate::{
+    metrics::Metrics,
+    state::AppState,
+    store::{PacketSource, ZoneStore},
+    util::PublicKeyBytes,
+};
+
+/// The default maximum number of public keys a single connection may subscribe to.
+pub const DEFAULT_MAX_KEYS_PER_CONNECTION: usize = 64;
+
+/// The default maximum number of concurrent subscription connections.
+pub const DEFAULT_MAX_CONNECTIONS: usize = 1024;
+
+/// Config for the pkarr subscription endpoint.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct SubscribeConfig {
+    /// Maximum number of public keys a single connection may subscribe to.
+    pub max_keys_per_connection: usize,
+    /// Maximum number of concurrent subscription connections.
+    ///
+    /// Further connections are rejected with `503 Service Unavailable`.
+    pub max_connections: usize,
+}
+
+impl Default for SubscribeConfig {
+    fn default() -> Self {
+        Self {
+            max_keys_per_connection: DEFAULT_MAX_KEYS_PER_CONNECTION,
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+        }
+    }
+}
+
+/// Shared limits of the subscription endpoint.
+#[derive(Debug, Clone)]
+pub struct Subscriptions {
+    max_keys: usize,
+    connections: Arc<Semaphore>,
+}
+
+impl Subscriptions {
+    pub fn new(config: &SubscribeConfig) -> Self {
+        Self {
+            max_keys: config.max_keys_per_connection,
+            connections: Arc::new(Semaphore::new(config.max_connections)),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SubscribeQuery {
+    /// Comma-separated list of z-base-32 encoded public keys.
+    keys: String,
+}
+
+pub async fn subscribe(
+    State(state): State<AppState>,
+    Extension(subscriptions): Extension<Subscriptions>,
+    Query(query): Query<SubscribeQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let keys = parse_keys(&query.keys, subscriptions.max_keys).inspect_err(|_| {
+        inc!(Metrics, pkarr_subscribe_rejected);
+    })?;
+    let Ok(permit) = subscriptions.connections.try_acquire_owned() else {
+        inc!(Metrics, pkarr_subscribe_rejected);
+        return Err(AppError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            Some("too many subscriptions"),
+        ));
+    };
+    inc!(Metrics, pkarr_subscribe_connections);
+    debug!(keys = keys.len(), "pkarr subscribe");
+
+    // subscribe before reading the current packets so that no update is missed
+    let updates = state.store.subscribe();
+    let pending = current_packets(&state.store, &keys).await?;
+    // decremented when the subscription is dropped
+    inc!(Metrics, pkarr_subscribe_active);
+    let subscription = Subscription {
+        store: state.store,
+        keys,
+        updates,
+        pending,
+        _permit: permit,
+    };
+    Ok(Sse::new(subscription.into_stream()).keep_alive(KeepAlive::default()))
+}
+
+fn parse_keys(keys: &str, max_keys: usize) -> Result<HashSet<PublicKeyBytes>, AppError> {
+    let keys = keys
+        .split(',')
+        .filter(|key| !key.is_empty())
+        .map(|key| {
+            PublicKeyBytes::from_z32(key).map_err(|e| {
+                AppError::new(StatusCode::BAD_REQUEST, Some(format!("invalid key: {e}")))
+            })
+        })
+        .collect::<Result<HashSet<_>, _>>()?;
+    if keys.is_empty() {
+        return Err(AppError::new(StatusCode::BAD_REQUEST, Some("no keys")));
+    }
+    if keys.len() > max_keys {
+        return Err(AppError::new(
+            StatusCode::BAD_REQUEST,
+            Some(format!("too many keys, at most {max_keys} allowed")),
+        ));
+    }
+    Ok(keys)
+}
+
+async fn current_packets(
+    store: &ZoneStore,
+    keys: &HashSet<PublicKeyBytes>,
+) -> Result<VecDeque<SignedPacket>> {
+    let mut packets = VecDeque::new();
+    for key in keys {
+        if let Some(packet) = store.get_signed_packet(key).await? {
+            packets.push_back(packet);
+        }
+    }
+    Ok(packets)
+}
+
+/// The state of a single subscription connection.
+struct Subscription {
+    store: ZoneStore,
+    keys: HashSet<PublicKeyBytes>,
+    updates: broadcast::Receiver<(SignedPacket, PacketSource)>,
+    /// Packets to send before waiting for further updates.
+    pending: VecDeque<SignedPacket>,
+    /// Counts the connection towards [`SubscribeConfig::max_connections`].
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        dec!(Metrics, pkarr_subscribe_active);
+    }
+}
+
+impl Subscription {
+    fn into_stream(self) -> impl Stream<Item = Result<Event, Infallible>> {
+        stream::unfold(self, |mut this| async move {
+            let packet = this.next_packet().await?;
+            inc!(Metrics, pkarr_subscribe_packets_sent);
+            Some((Ok(packet_event(&packet)), this))
+        })
+    }
+
+    /// Returns the next packet to send, or `None` if the store is gone.
+    async fn next_packet(&mut self) -> Option<SignedPacket> {
+        loop {
+            if let Some(packet) = self.pending.pop_front() {
+                return Some(packet);
+            }
+            match self.updates.recv().await {
+                Ok((packet, _source)) => {
+                    if self
+                        .keys
+                        .contains(&PublicKeyBytes::from_signed_packet(&packet))
+                    {
+                        return Some(packet);
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    // we missed updates, so resend the current packets of all keys
+                    debug!("pkarr subscriber lagged by {n} updates");
+                    match current_packets(&self.store, &self.keys).await {
+                        Ok(packets) => self.pending = packets,
+                        Err(err) => {
+                            warn!("failed to read packets for pkarr subscriber: {err:#}");
+                            return None;
+                        }
+                    }
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+fn packet_event(packet: &SignedPacket) -> Event {
+    let data = format!(
+        "{} {}",
+        packet.public_key().to_z32(),
+        base64_url::encode(&packet.to_relay_payload())
+    );
+    Event::default().event("packet").data(data)
+}
@@ -17,6 +17,8 @@ use tokio_rustls_acme::{axum::AxumAcceptor, caches::DirCache, AcmeConfig};
 use tokio_stream::StreamExt;
 use tracing::{debug, error, info_span, Instrument};
 
+use crate::dns::DnsTlsConfig;
+
 /// The mode how SSL certificates should be created.
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, strum::Display)]
 #[serde(rename_all = "snake_case")]
@@ -30,14 +32,15 @@ pub enum CertMode {
 }
 
 impl CertMode {
-    /// Build the [`TlsAcceptor`] for this mode.
+    /// Build the [`TlsAcceptor`] for this mode, and the TLS config for the encrypted DNS
+    /// listeners which use the same certificates.
     pub(crate) async fn build(
         &self,
         domains: Vec<String>,
         cert_cache: PathBuf,
         letsencrypt_contact: Option<String>,
         letsencrypt_prod: bool,
-    ) -> Result<TlsAcceptor> {
+    ) -> Result<(TlsAcceptor, DnsTlsConfig)> {
         Ok(match self {
             CertMode::Manual => TlsAcceptor::manual(domains, cert_cache).await?,
             CertMode::SelfSigned => TlsAcceptor::self_signed(domains).await?,
@@ -73,15 +76,18 @@ impl<I: AsyncRead + AsyncWrite + Unpin + Send + 'static, S: Send + 'static> Acce
 }
 
 impl TlsAcceptor {
-    async fn self_signed(domains: Vec<String>) -> Result<Self> {
+    async fn self_signed(domains: Vec<String>) -> Result<(Self, DnsTlsConfig)> {
         let rcgen::CertifiedKey { cert, key_pair } = rcgen::generate_simple_self_signed(domains)?;
         let config =
             RustlsConfig::from_der(vec![cert.der().to_vec()], key_pair.serialize_der()).await?;
+        let dns_config = DnsTlsConfig {
+            server_config: config.get_inner(),
+        };
         let acceptor = RustlsAcceptor::new(config);
-        Ok(Self::Manual(acceptor))
+        Ok((Self::Manual(acceptor), dns_config))
     }
 
-    async fn manual(domains: Vec<String>, dir: PathBuf) -> Result<Self> {
+    async fn manual(domains: Vec<String>, dir: PathBuf) -> Result<(Self, DnsTlsConfig)> {
         let config = rustls::ServerConfig::builder().with_no_client_auth();
         if domains.len() != 1 {
             bail!("Multiple domains in manual mode are not supported");
@@ -93,10 +99,13 @@ impl TlsAcceptor {
         let certs = load_certs(cert_path).await?;
         let secret_key = load_secret_key(key_path).await?;
 
-        let config = config.with_single_cert(certs, secret_key)?;
-        let config = RustlsConfig::from_config(Arc::new(config));
+        let config = Arc::new(config.with_single_cert(certs, secret_key)?);
+        let dns_config = DnsTlsConfig {
+            server_config: config.clone(),
+        };
+        let config = RustlsConfig::from_config(config);
         let acceptor = RustlsAcceptor::new(config);
-        Ok(Self::Manual(acceptor))
+        Ok((Self::Manual(acceptor), dns_config))
     }
 
     fn letsencrypt(
@@ -104,7 +113,7 @@ impl TlsAcceptor {
         contact: &str,
         is_production: bool,
         dir: PathBuf,
-    ) -> Result<Self> {
+    ) -> Result<(Self, DnsTlsConfig)> {
         let config = rustls::ServerConfig::builder().with_no_client_auth();
         let mut state = AcmeConfig::new(domains)
             .contact([format!("mailto:{contact}")])
@@ -127,8 +136,11 @@ impl TlsAcceptor {
             .instrument(info_span!("acme")),
         );
         let config = Arc::new(config);
+        let dns_config = DnsTlsConfig {
+            server_config: config.clone(),
+        };
         let acceptor = AxumAcceptor::new(acceptor, config);
-        Ok(Self::LetsEncrypt(acceptor))
+        Ok((Self::LetsEncrypt(acceptor), dns_config))
     }
 }
 
@@ -2,42 +2,86 @@
 
 #![deny(missing_docs, rustdoc::broken_intra_doc_links)]
 
+pub mod admin;
 pub mod config;
 pub mod dns;
 pub mod http;
 pub mod metrics;
+pub mod query_log;
+pub mod replication;
+pub mod republish;
 pub mod server;
 pub mod state;
 mod store;
 mod util;
 
 // Re-export to be able to construct your own dns-server
-pub use store::ZoneStore;
+#[cfg(feature = "sqlite")]
+pub use store::SqliteStorage;
+pub use store::{
+    ImportStats, MemoryStorage, PacketSource, PacketStorage, SignedPacketStore, UpsertOutcome,
+    ZoneStore, ZoneStoreOptions, MAX_TIMESTAMP_DRIFT, SHARED_CACHE_TTL,
+};
+pub use util::PublicKeyBytes;
 
 #[cfg(test)]
 mod tests {
     use std::{
+        future::IntoFuture,
         net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+        sync::Arc,
         time::Duration,
     };
 
-    use anyhow::Result;
+    use anyhow::{Context, Result};
+    use axum_server::tls_rustls::RustlsConfig;
+    use hickory_server::{
+        authority::MessageRequest,
+        proto::{
+            dnssec::{
+                rdata::{DNSSECRData, DNSKEY},
+                Verifier,
+            },
+            op::{Edns, Message, Query, ResponseCode},
+            rr::{DNSClass, Name, Record, RecordType},
+            serialize::binary::{BinDecodable, BinDecoder},
+            xfer::Protocol,
+        },
+        server::Request,
+    };
+    use http::{header, Method, StatusCode};
     use iroh::{
         discovery::pkarr::PkarrRelayClient,
-        dns::{node_info::NodeInfo, DnsResolver},
+        dns::{node_info::NodeInfo, DnsProtocol, DnsResolver},
         SecretKey,
     };
+    use iroh_relay::proxy_protocol::ProxyProtocolConfig;
     use pkarr::{PkarrClient, SignedPacket};
     use testresult::TestResult;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
     use tracing_test::traced_test;
     use url::Url;
 
     use crate::{
-        config::BootstrapOption,
+        admin::{PacketDetails, PacketInfo},
+        config::{BootstrapOption, Config, StoreConfig},
+        dns::{
+            DnsHandler, DnsServer, DnssecAlgorithm, DnssecConfig, NodeRecordPolicy, NxProof,
+            StaticZoneConfig, DEFAULT_SIGNATURE_VALIDITY,
+        },
+        http::{
+            router, AdminConfig, CertMode, HttpConfig, HttpServer, RateLimit, RateLimitConfig,
+            RateLimitKey, RateLimits, RouterOptions,
+        },
+        metrics::Metrics,
+        query_log::{QueryLogConfig, QueryLogEntry},
+        replication::ReplicationConfig,
+        republish::{RepublishConfig, Republisher},
         server::Server,
-        store::{PacketSource, ZoneStoreOptions},
+        state::AppState,
+        store::{PacketSource, SignedPacketStore, ZoneStoreOptions},
         util::PublicKeyBytes,
-        ZoneStore,
+        ImportStats, ZoneStore,
     };
 
     const DNS_TIMEOUT: Duration = Duration::from_secs(1);
@@ -111,7 +155,6 @@ mod tests {
         })?;
         pkarr_client.as_async().publish(&signed_packet).await?;
 
-        use hickory_server::proto::rr::Name;
         let pubkey = signed_packet.public_key().to_z32();
         let resolver = test_resolver(nameserver);
 
@@ -187,6 +230,184 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    #[traced_test]
+    async fn pkarr_subscribe() -> Result<()> {
+        let (server, _nameserver, http_url) = Server::spawn_for_tests().await?;
+        let http_addr = (
+            http_url.host_str().unwrap().to_string(),
+            http_url.port().unwrap(),
+        );
+        let pkarr_relay = {
+            let mut url = http_url.clone();
+            url.set_path("/pkarr");
+            url
+        };
+        let pkarr = PkarrRelayClient::new(pkarr_relay);
+
+        let secret_key = SecretKey::generate(rand::thread_rng());
+        let node_id = secret_key.public();
+        let relay_url: Url = "https://relay.example.".parse()?;
+        let node_info = NodeInfo::new(node_id, Some(relay_url.clone()), Default::default());
+        let first = node_info.to_pkarr_signed_packet(&secret_key, 30)?;
+        pkarr.publish(&first).await?;
+        let z32 = first.public_key().to_z32();
+
+        // too many keys are rejected
+        let keys = (0..65)
+            .map(|_| pkarr::Keypair::random().public_key().to_z32())
+            .collect::<Vec<_>>()
+            .join(",");
+        let mut stream = tokio::net::TcpStream::connect(http_addr.clone()).await?;
+        write_get(&mut stream, &format!("/pkarr/subscribe?keys={keys}")).await?;
+        read_until(&mut stream, "400 Bad Request").await?;
+
+        // the current packet is sent first, followed by updates
+        let mut stream = tokio::net::TcpStream::connect(http_addr).await?;
+        write_get(&mut stream, &format!("/pkarr/subscribe?keys={z32}")).await?;
+        let event = |packet: &SignedPacket| {
+            format!(
+                "event: packet\ndata: {z32} {}\n",
+                base64_url::encode(&packet.to_relay_payload())
+            )
+        };
+        read_until(&mut stream, &event(&first)).await?;
+
+        let relay_url: Url = "https://relay2.example.".parse()?;
+        let node_info = NodeInfo::new(node_id, Some(relay_url), Default::default());
+        let second = node_info.to_pkarr_signed_packet(&secret_key, 30)?;
+        pkarr.publish(&second).await?;
+        read_until(&mut stream, &event(&second)).await?;
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    async fn write_get(stream: &mut tokio::net::TcpStream, path: &str) -> Result<()> {
+        let request = format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        stream.write_all(request.as_bytes()).await?;
+        Ok(())
+    }
+
+    /// Reads from the stream until the data received so far contains `needle`.
+    async fn read_until(stream: &mut tokio::net::TcpStream, needle: &str) -> Result<()> {
+        let mut received = Vec::new();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            let mut buf = [0u8; 4096];
+            while !String::from_utf8_lossy(&received).contains(needle) {
+                let n = stream.read(&mut buf).await?;
+                anyhow::ensure!(n > 0, "connection closed");
+                received.extend_from_slice(&buf[..n]);
+            }
+            Ok(())
+        })
+        .await
+        .map_err(|_| {
+            anyhow::anyhow!(
+                "timeout waiting for {needle:?}, received {:?}",
+                String::from_utf8_lossy(&received)
+            )
+        })?
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn replication() -> Result<()> {
+        let replication = |peers: Vec<Url>| {
+            Some(ReplicationConfig {
+                peers,
+                token: "secret".to_string(),
+                skip_startup_sync: false,
+            })
+        };
+        // an empty token is rejected
+        let server_config = Config {
+            replication: replication(vec![]).map(|config| ReplicationConfig {
+                token: String::new(),
+                ..config
+            }),
+            ..Default::default()
+        };
+        assert!(server_config.validate().is_err());
+
+        let publish = |http_url: &Url, packet: SignedPacket| {
+            let mut url = http_url.clone();
+            url.set_path("/pkarr");
+            async move { PkarrRelayClient::new(url).publish(&packet).await }
+        };
+
+        // a has no peers, but serves the replication endpoint
+        let (a, _, a_url) =
+            Server::spawn_for_tests_with_options(None, None, replication(vec![])).await?;
+        let first = random_signed_packet()?;
+        publish(&a_url, first.clone()).await?;
+
+        // the replication endpoint requires the token
+        let mut replication_url = a_url.clone();
+        replication_url.set_path(crate::replication::REPLICATION_PATH);
+        let client = reqwest::Client::new();
+        let res = client.get(replication_url.clone()).send().await?;
+        assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+        let res = client
+            .post(replication_url)
+            .bearer_auth("wrong")
+            .body(crate::replication::encode_packets(&[
+                random_signed_packet()?
+            ]))
+            .send()
+            .await?;
+        assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+        // b fetches the packets of a on startup
+        let (b, _, b_url) =
+            Server::spawn_for_tests_with_options(None, None, replication(vec![a_url.clone()]))
+                .await?;
+        wait_for_packet(&b_url, &first).await?;
+
+        // packets published to b are pushed to a
+        let second = random_signed_packet()?;
+        publish(&b_url, second.clone()).await?;
+        wait_for_packet(&a_url, &second).await?;
+
+        // packets b receives from c are not forwarded to a
+        let (c, _, c_url) =
+            Server::spawn_for_tests_with_options(None, None, replication(vec![b_url.clone()]))
+                .await?;
+        let third = random_signed_packet()?;
+        publish(&c_url, third.clone()).await?;
+        wait_for_packet(&b_url, &third).await?;
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(get_packet(&a_url, &third).await?.is_none());
+
+        a.shutdown().await?;
+        b.shutdown().await?;
+        c.shutdown().await?;
+        Ok(())
+    }
+
+    /// Get the relay payload of the packet stored for the public key of `packet`.
+    async fn get_packet(http_url: &Url, packet: &SignedPacket) -> Result<Option<bytes::Bytes>> {
+        let mut url = http_url.clone();
+        url.set_path(&format!("/pkarr/{}", packet.public_key().to_z32()));
+        let res = reqwest::get(url).await?;
+        if res.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        Ok(Some(res.error_for_status()?.bytes().await?))
+    }
+
+    /// Wait until the server at `http_url` stores `packet`.
+    async fn wait_for_packet(http_url: &Url, packet: &SignedPacket) -> Result<()> {
+        let payload = packet.to_relay_payload();
+        for _ in 0..50 {
+            if get_packet(http_url, packet).await?.as_ref() == Some(&payload) {
+                return Ok(());
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        anyhow::bail!("packet was not replicated to {http_url}")
+    }
+
     #[tokio::test]
     #[traced_test]
     async fn store_eviction() -> TestResult<()> {
@@ -217,6 +438,110 @@ mod tests {
         panic!("store did not evict packet");
     }
 
+    #[tokio::test]
+    #[traced_test]
+    async fn future_timestamp() -> Result<()> {
+        let (server, _nameserver, http_url) = Server::spawn_for_tests().await?;
+        let store = ZoneStore::in_memory(Default::default())?;
+
+        // packets within the allowed drift are accepted
+        let packet = signed_packet_at(pkarr::system_time() + 60_000_000)?;
+        assert!(store.insert(packet, PacketSource::PkarrPublish).await?);
+
+        // packets from the far future are rejected, both by the store and the pkarr relay
+        let packet = signed_packet_at(pkarr::system_time() + 3600 * 1_000_000)?;
+        let key = PublicKeyBytes::from_signed_packet(&packet);
+        let inserted = store
+            .insert(packet.clone(), PacketSource::Replication)
+            .await?;
+        assert!(!inserted);
+        assert!(store.get_signed_packet(&key).await?.is_none());
+        let url = http_url.join(&format!("/pkarr/{}", packet.public_key().to_z32()))?;
+        let res = reqwest::Client::new()
+            .put(url)
+            .body(packet.to_relay_payload())
+            .send()
+            .await?;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        // bounding the store is only supported by the redb backend
+        let bounded = ZoneStoreOptions {
+            max_packets: Some(10),
+            ..Default::default()
+        };
+        assert!(ZoneStore::in_memory(bounded).is_err());
+        let config: StoreConfig = bounded.into();
+        assert!(config.validate().is_ok());
+        let mut table = toml::Table::try_from(&config)?;
+        table.insert("backend".into(), "sqlite".into());
+        let config: StoreConfig = table.try_into()?;
+        assert!(config.validate().is_err());
+
+        server.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn bounded_store() -> Result<()> {
+        let options = ZoneStoreOptions {
+            max_packets: Some(1),
+            ..Default::default()
+        };
+        let store = ZoneStore::new(SignedPacketStore::in_memory(options)?, options);
+        let now = pkarr::system_time();
+        let older = signed_packet_at(now - 1_000_000)?;
+        let newer = signed_packet_at(now)?;
+        let older_key = PublicKeyBytes::from_signed_packet(&older);
+        let newer_key = PublicKeyBytes::from_signed_packet(&newer);
+
+        // a packet which is evicted right away is not reported as inserted
+        assert!(store.insert(newer, PacketSource::PkarrPublish).await?);
+        assert!(
+            !store
+                .insert(older.clone(), PacketSource::PkarrPublish)
+                .await?
+        );
+        assert!(store.get_signed_packet(&older_key).await?.is_none());
+
+        // evicted packets are removed from the cache
+        assert!(store
+            .record_types(&newer_key, &Name::root())
+            .await?
+            .is_some());
+        let newest = signed_packet_at(now + 1_000_000)?;
+        assert!(store.insert(newest, PacketSource::PkarrPublish).await?);
+        assert!(store.get_signed_packet(&newer_key).await?.is_none());
+        assert!(store
+            .record_types(&newer_key, &Name::root())
+            .await?
+            .is_none());
+        Ok(())
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    #[traced_test]
+    async fn shared_sqlite_store() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("signed-packets.sqlite");
+        let store_a = ZoneStore::sqlite(&path, Default::default())?;
+        let store_b = ZoneStore::sqlite(&path, Default::default())?
+            .with_cache_ttl(Duration::from_millis(100));
+
+        let packet = random_signed_packet()?;
+        let key = PublicKeyBytes::from_signed_packet(&packet);
+        assert!(store_a.insert(packet, PacketSource::PkarrPublish).await?);
+        assert!(store_b.record_types(&key, &Name::root()).await?.is_some());
+
+        // the removal is only picked up by the other store once its cached zone expired
+        assert!(store_a.remove(&key).await?);
+        assert!(store_b.record_types(&key, &Name::root()).await?.is_some());
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(store_b.record_types(&key, &Name::root()).await?.is_none());
+        Ok(())
+    }
+
     #[tokio::test]
     #[traced_test]
     async fn integration_mainline() -> Result<()> {
@@ -225,9 +550,12 @@ mod tests {
         let bootstrap = testnet.bootstrap.clone();
 
         // spawn our server with mainline support
-        let (server, nameserver, _http_url) =
-            Server::spawn_for_tests_with_options(Some(BootstrapOption::Custom(bootstrap)), None)
-                .await?;
+        let (server, nameserver, _http_url) = Server::spawn_for_tests_with_options(
+            Some(BootstrapOption::Custom(bootstrap)),
+            None,
+            None,
+        )
+        .await?;
 
         let origin = "irohdns.example.";
 
@@ -261,10 +589,1000 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    #[traced_test]
+    async fn dht_republish() -> Result<()> {
+        // run a mainline testnet
+        let testnet = pkarr::mainline::dht::Testnet::new(5);
+        let bootstrap = testnet.bootstrap.clone();
+
+        // a packet which was only published to our server
+        let store = ZoneStore::in_memory(Default::default())?;
+        let signed_packet = random_signed_packet()?;
+        store
+            .insert(signed_packet.clone(), PacketSource::PkarrPublish)
+            .await?;
+
+        // a zero interval, concurrency or rate limit is rejected
+        let server_config = Config {
+            republish: Some(RepublishConfig {
+                interval: Duration::ZERO,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert!(server_config.validate().is_err());
+        let config = RepublishConfig {
+            concurrency: 0,
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+        let config = RepublishConfig {
+            max_bytes_per_second: Some(0),
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+
+        let config = RepublishConfig {
+            concurrency: 2,
+            max_bytes_per_second: Some(64 * 1024),
+            ..Default::default()
+        };
+        let republisher = Republisher::spawn(
+            config,
+            store.clone(),
+            BootstrapOption::Custom(bootstrap.clone()),
+        )?;
+
+        // resolve the packet from the DHT
+        let pkarr = PkarrClient::builder()
+            .dht_settings(pkarr::mainline::dht::DhtSettings {
+                bootstrap: Some(bootstrap),
+                ..Default::default()
+            })
+            .build()?
+            .as_async();
+        let resolved = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                if let Some(packet) = pkarr.resolve(&signed_packet.public_key()).await? {
+                    return anyhow::Ok(packet);
+                }
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        })
+        .await??;
+        assert!(resolved.is_same_as(&signed_packet));
+
+        drop(republisher);
+        for mut node in testnet.nodes {
+            node.shutdown()?;
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn integration_doh_fallback() -> Result<()> {
+        let store = ZoneStore::in_memory(Default::default())?;
+        let dns_handler = DnsHandler::new(store.clone(), &Config::default().dns)?;
+        let app = router(
+            AppState {
+                store: store.clone(),
+                dns_handler,
+            },
+            RouterOptions {
+                rate_limit: RateLimitConfig::Disabled,
+                ..Default::default()
+            },
+        )?;
+
+        // serve DNS-over-HTTPS with a self-signed certificate
+        let rcgen::CertifiedKey { cert, key_pair } =
+            rcgen::generate_simple_self_signed(vec!["localhost".to_string()])?;
+        let tls_config =
+            RustlsConfig::from_der(vec![cert.der().to_vec()], key_pair.serialize_der()).await?;
+        let listener = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await?
+            .into_std()?;
+        let doh_addr = listener.local_addr()?;
+        let server = tokio::task::spawn(
+            axum_server::from_tcp_rustls(listener, tls_config)
+                .serve(app.into_make_service_with_connect_info::<SocketAddr>()),
+        );
+
+        let origin = "irohdns.example.";
+        let secret_key = SecretKey::generate(rand::thread_rng());
+        let node_id = secret_key.public();
+        let relay_url: Url = "https://relay.example.".parse()?;
+        let node_info = NodeInfo::new(node_id, Some(relay_url.clone()), Default::default());
+        let signed_packet = node_info.to_pkarr_signed_packet(&secret_key, 30)?;
+        store
+            .insert(signed_packet, PacketSource::PkarrPublish)
+            .await?;
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(cert.der().clone())?;
+        let client_config = rustls::ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+        // the first nameserver is unreachable, the resolver falls back to DoH
+        let resolver = DnsResolver::builder()
+            .nameserver("127.0.0.1:1".parse()?, DnsProtocol::Udp)
+            .nameserver(doh_addr, DnsProtocol::https("localhost"))
+            .timeout(Duration::from_millis(200))
+            .tls_client_config(Arc::new(client_config))
+            .build();
+        let res = resolver.lookup_node_by_id(&node_id, origin).await?;
+
+        assert_eq!(res.node_id, node_id);
+        assert_eq!(res.relay_url.map(Url::from), Some(relay_url));
+
+        server.abort();
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn embedded_router() -> Result<()> {
+        let store = ZoneStore::in_memory(Default::default())?;
+        let state = AppState::new(store, &Config::default().dns)?;
+        let app = axum::Router::new()
+            .route("/hello", axum::routing::get(|| async { "hello" }))
+            .nest("/dns", router(state, Default::default())?);
+        let listener = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        let base_url: Url = format!("http://{}/", listener.local_addr()?).parse()?;
+        let server = tokio::task::spawn(
+            axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .into_future(),
+        );
+
+        // the routes of the app are served next to the nested pkarr relay and DoH routes
+        let res = reqwest::get(base_url.join("hello")?).await?;
+        assert_eq!(res.text().await?, "hello");
+        let secret_key = SecretKey::generate(rand::thread_rng());
+        let node_id = secret_key.public();
+        let relay_url: Url = "https://relay.example.".parse()?;
+        let signed_packet = NodeInfo::new(node_id, Some(relay_url.clone()), Default::default())
+            .to_pkarr_signed_packet(&secret_key, 30)?;
+        PkarrRelayClient::new(base_url.join("dns/pkarr")?)
+            .publish(&signed_packet)
+            .await?;
+        let z32 = signed_packet.public_key().to_z32();
+        let res = reqwest::get(base_url.join(&format!("dns/pkarr/{z32}"))?).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = reqwest::Client::new()
+            .get(base_url.join("dns/dns-query?name=irohdns.example.&type=A")?)
+            .header(header::ACCEPT, "application/dns-json")
+            .send()
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.text().await?.contains("127.0.0.1"));
+        server.abort();
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn pkarr_rate_limit() -> Result<()> {
+        let store = ZoneStore::in_memory(Default::default())?;
+        let dns_handler = DnsHandler::new(store.clone(), &Config::default().dns)?;
+        let limit = |by| RateLimit {
+            by,
+            period: Duration::from_secs(60),
+            burst_size: 1,
+        };
+        let rate_limit = RateLimitConfig::Limits(RateLimits {
+            smart_ip: false,
+            put: vec![limit(RateLimitKey::PublicKey)],
+            get: vec![limit(RateLimitKey::PublicKeyAndIp)],
+        });
+        let state = AppState { store, dns_handler };
+        let invalid = RateLimitConfig::Limits(RateLimits {
+            put: vec![RateLimit {
+                burst_size: 0,
+                ..limit(RateLimitKey::Ip)
+            }],
+            ..Default::default()
+        });
+        let options = RouterOptions {
+            rate_limit: invalid,
+            ..Default::default()
+        };
+        assert!(router(state.clone(), options).is_err());
+
+        let app = router(
+            state,
+            RouterOptions {
+                rate_limit,
+                ..Default::default()
+            },
+        )?;
+        let listener = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        let base_url: Url = format!("http://{}/pkarr/", listener.local_addr()?).parse()?;
+        let server = tokio::task::spawn(
+            axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .into_future(),
+        );
+
+        let client = reqwest::Client::new();
+        let put = |packet: SignedPacket| {
+            let url = base_url.join(&packet.public_key().to_z32()).unwrap();
+            client.put(url).body(packet.to_relay_payload()).send()
+        };
+        let get = |packet: &SignedPacket| {
+            let url = base_url.join(&packet.public_key().to_z32()).unwrap();
+            client.get(url).send()
+        };
+
+        // publishing is limited per public key
+        let packet = random_signed_packet()?;
+        let res = put(packet.clone()).await?;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        let res = put(packet.clone()).await?;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: u64 = res.headers()[header::RETRY_AFTER].to_str()?.parse()?;
+        assert!(retry_after > 0 && retry_after <= 60);
+        let res = put(random_signed_packet()?).await?;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+
+        // fetching is limited separately per public key and IP address
+        let res = get(&packet).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = get(&packet).await?;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(res.headers().contains_key(header::RETRY_AFTER));
+
+        server.abort();
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn admin_api() -> Result<()> {
+        let store = ZoneStore::in_memory(Default::default())?;
+        let dns_handler = DnsHandler::new(store.clone(), &Config::default().dns)?;
+        let state = AppState { store, dns_handler };
+
+        // an empty token is rejected, both in the config and by the router
+        let empty = AdminConfig {
+            token: String::new(),
+        };
+        let config = Config {
+            admin: Some(empty.clone()),
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+        let options = RouterOptions {
+            admin: Some(empty),
+            ..Default::default()
+        };
+        assert!(router(state.clone(), options).is_err());
+        let options = RouterOptions {
+            replication_token: Some(String::new()),
+            ..Default::default()
+        };
+        assert!(router(state.clone(), options).is_err());
+
+        let admin_config = AdminConfig {
+            token: "secret".to_string(),
+        };
+        let app = router(
+            state,
+            RouterOptions {
+                rate_limit: RateLimitConfig::Disabled,
+                admin: Some(admin_config),
+                ..Default::default()
+            },
+        )?;
+        let listener = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        let base_url: Url = format!("http://{}/", listener.local_addr()?).parse()?;
+        let server = tokio::task::spawn(
+            axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .into_future(),
+        );
+
+        let client = reqwest::Client::new();
+        let admin = |method, path: &str| {
+            client
+                .request(method, base_url.join(path).unwrap())
+                .bearer_auth("secret")
+        };
+        let packet = random_signed_packet()?;
+        let key = packet.public_key().to_z32();
+        let pkarr_url = base_url.join(&format!("pkarr/{key}"))?;
+        let publish = || {
+            client
+                .put(pkarr_url.clone())
+                .body(packet.to_relay_payload())
+                .send()
+        };
+        assert_eq!(publish().await?.status(), StatusCode::NO_CONTENT);
+
+        // requests without the token are rejected
+        let url = base_url.join("admin/packets")?;
+        let res = client.get(url.clone()).send().await?;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let res = client.get(url).bearer_auth("wrong").send().await?;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        // list and look up packets
+        let list: Vec<PacketInfo> = admin(Method::GET, "admin/packets")
+            .send()
+            .await?
+            .json()
+            .await?;
+        assert_eq!(list, vec![PacketInfo::from_signed_packet(&packet)]);
+        let details: PacketDetails = admin(Method::GET, &format!("admin/packets/{key}"))
+            .send()
+            .await?
+            .json()
+            .await?;
+        assert_eq!(details.info.public_key, key);
+        assert!(details.records.iter().any(|r| r.contains("relay.example.")));
+
+        let archive = admin(Method::GET, "admin/archive")
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+
+        // blocked keys cannot publish
+        let res = admin(Method::PUT, &format!("admin/blocklist/{key}"))
+            .send()
+            .await?;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            client.get(pkarr_url.clone()).send().await?.status(),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(publish().await?.status(), StatusCode::FORBIDDEN);
+        let blocklist: Vec<String> = admin(Method::GET, "admin/blocklist")
+            .send()
+            .await?
+            .json()
+            .await?;
+        assert_eq!(blocklist, vec![key.clone()]);
+        let res = admin(Method::DELETE, &format!("admin/blocklist/{key}"))
+            .send()
+            .await?;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert_eq!(publish().await?.status(), StatusCode::NO_CONTENT);
+
+        // delete a packet
+        let path = format!("admin/packets/{key}");
+        let res = admin(Method::DELETE, &path).send().await?;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        let res = admin(Method::DELETE, &path).send().await?;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        // import the archive created before
+        let stats: ImportStats = admin(Method::POST, "admin/archive")
+            .body(archive)
+            .send()
+            .await?
+            .json()
+            .await?;
+        assert_eq!(
+            stats,
+            ImportStats {
+                packets: 1,
+                stored: 1,
+                blocked: 0
+            }
+        );
+        assert_eq!(client.get(pkarr_url).send().await?.status(), StatusCode::OK);
+
+        server.abort();
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn dnssec() -> Result<()> {
+        for (algorithm, nx_proof) in [
+            (DnssecAlgorithm::EcdsaP256Sha256, NxProof::Nsec),
+            (DnssecAlgorithm::Ed25519, NxProof::Nsec3),
+        ] {
+            let dir = tempfile::tempdir()?;
+            let key_file = dir.path().join("dnssec-key.pk8");
+            let mut config = Config::default().dns;
+            config.dnssec = Some(DnssecConfig {
+                key_file: Some(key_file),
+                algorithm,
+                signature_validity: DEFAULT_SIGNATURE_VALIDITY,
+                nx_proof,
+            });
+            let store = ZoneStore::in_memory(Default::default())?;
+            let dns_handler = DnsHandler::new(store.clone(), &config)?;
+
+            let signed_packet = random_signed_packet()?;
+            let z32 = signed_packet.public_key().to_z32();
+            store
+                .insert(signed_packet, PacketSource::PkarrPublish)
+                .await?;
+
+            // the key is served at the origin and self-signed
+            let origin = Name::from_utf8("irohdns.example.")?;
+            let res = dns_query(&dns_handler, &origin, RecordType::DNSKEY, true).await?;
+            let dnskey = res
+                .answers()
+                .iter()
+                .find_map(|r| r.data().as_dnssec()?.as_dnskey())
+                .expect("DNSKEY")
+                .clone();
+            verify_rrsigs(&dnskey, res.answers(), RecordType::DNSKEY)?;
+
+            // pkarr records are signed
+            let name = Name::from_utf8(format!("_iroh.{z32}.irohdns.example."))?;
+            let res = dns_query(&dns_handler, &name, RecordType::TXT, true).await?;
+            verify_rrsigs(&dnskey, res.answers(), RecordType::TXT)?;
+
+            // but only if requested
+            let res = dns_query(&dns_handler, &name, RecordType::TXT, false).await?;
+            assert!(!res.answers().is_empty());
+            assert!(res
+                .answers()
+                .iter()
+                .all(|r| r.record_type() != RecordType::RRSIG));
+
+            // the non-existence of records and names is proven for exactly the queried name
+            for name in [
+                name.clone(),
+                Name::from_utf8(format!("_foo.{z32}.irohdns.example."))?,
+            ] {
+                let res = dns_query(&dns_handler, &name, RecordType::A, true).await?;
+                assert_eq!(res.response_code(), ResponseCode::NoError);
+                assert!(res.answers().is_empty());
+                let proof_type = match nx_proof {
+                    NxProof::Nsec => RecordType::NSEC,
+                    NxProof::Nsec3 => RecordType::NSEC3,
+                };
+                let proof = res
+                    .name_servers()
+                    .iter()
+                    .find(|r| r.record_type() == proof_type)
+                    .expect("proof of non-existence");
+                let types = match proof.data().as_dnssec().expect("dnssec record") {
+                    DNSSECRData::NSEC(nsec) => {
+                        assert_eq!(proof.name(), &name);
+                        nsec.type_bit_maps().to_vec()
+                    }
+                    DNSSECRData::NSEC3(nsec3) => nsec3.type_bit_maps().to_vec(),
+                    _ => unreachable!(),
+                };
+                assert!(!types.contains(&RecordType::A));
+                assert_eq!(
+                    types.contains(&RecordType::TXT),
+                    name.to_string().starts_with("_iroh")
+                );
+                verify_rrsigs(&dnskey, res.name_servers(), proof_type)?;
+                verify_rrsigs(&dnskey, res.name_servers(), RecordType::SOA)?;
+            }
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn static_zones() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let zone_file = dir.path().join("irohdns.example.zone");
+        std::fs::write(
+            &zone_file,
+            "relay IN CNAME relay-eu\nrelay-eu IN A 192.0.2.10\n",
+        )?;
+        let mut config = Config::default().dns;
+        config.static_zones = vec![StaticZoneConfig {
+            origin: "irohdns.example.".to_string(),
+            zone_file: Some(zone_file.clone()),
+            records: vec![
+                "@ IN MX 10 mail".to_string(),
+                "_acme-challenge 60 IN TXT \"token\"".to_string(),
+            ],
+        }];
+        let store = ZoneStore::in_memory(Default::default())?;
+        let dns_handler = DnsHandler::new(store.clone(), &config)?;
+
+        let signed_packet = random_signed_packet()?;
+        let z32 = signed_packet.public_key().to_z32();
+        store
+            .insert(signed_packet, PacketSource::PkarrPublish)
+            .await?;
+
+        // the target of a CNAME is resolved in the additional section
+        let a_records = |res: &Message| {
+            res.answers()
+                .iter()
+                .chain(res.additionals())
+                .filter_map(|r| r.data().as_a().map(|a| a.0))
+                .collect::<Vec<_>>()
+        };
+        let origin = Name::from_utf8("irohdns.example.")?;
+        let relay = Name::from_utf8("relay.irohdns.example.")?;
+
+        // the static records are merged with the records from the config and the pkarr records
+        let res = dns_query(&dns_handler, &origin, RecordType::MX, false).await?;
+        assert_eq!(res.answers().len(), 1);
+        let res = dns_query(&dns_handler, &origin, RecordType::A, false).await?;
+        assert_eq!(a_records(&res), vec![Ipv4Addr::LOCALHOST]);
+        let name = Name::from_utf8("_acme-challenge.irohdns.example.")?;
+        let res = dns_query(&dns_handler, &name, RecordType::TXT, false).await?;
+        assert_eq!(res.answers().len(), 1);
+        assert_eq!(res.answers()[0].ttl(), 60);
+        let res = dns_query(&dns_handler, &relay, RecordType::A, false).await?;
+        assert_eq!(a_records(&res), vec![Ipv4Addr::new(192, 0, 2, 10)]);
+        let name = Name::from_utf8(format!("_iroh.{z32}.irohdns.example."))?;
+        let res = dns_query(&dns_handler, &name, RecordType::TXT, false).await?;
+        assert!(!res.answers().is_empty());
+
+        // changes to the zone file are served after a reload
+        std::fs::write(
+            &zone_file,
+            "relay IN CNAME relay-eu\nrelay-eu IN A 192.0.2.20\n",
+        )?;
+        let res = dns_query(&dns_handler, &relay, RecordType::A, false).await?;
+        assert_eq!(a_records(&res), vec![Ipv4Addr::new(192, 0, 2, 10)]);
+        dns_handler.reload_static_records()?;
+        let res = dns_query(&dns_handler, &relay, RecordType::A, false).await?;
+        assert_eq!(a_records(&res), vec![Ipv4Addr::new(192, 0, 2, 20)]);
+
+        // invalid records are rejected and the previous records are kept
+        std::fs::write(&zone_file, "relay.other.example. IN A 192.0.2.30\n")?;
+        assert!(dns_handler.reload_static_records().is_err());
+        let res = dns_query(&dns_handler, &relay, RecordType::A, false).await?;
+        assert_eq!(a_records(&res), vec![Ipv4Addr::new(192, 0, 2, 20)]);
+
+        // the records can be replaced with a new config
+        config.static_zones[0].zone_file = None;
+        dns_handler.update_static_records(&config)?;
+        let res = dns_query(&dns_handler, &relay, RecordType::A, false).await?;
+        assert!(res.answers().is_empty());
+        let res = dns_query(&dns_handler, &origin, RecordType::MX, false).await?;
+        assert_eq!(res.answers().len(), 1);
+
+        // static zones must be in the served origins
+        config.static_zones[0].origin = "other.example.".to_string();
+        assert!(DnsHandler::new(store.clone(), &config).is_err());
+        assert!(dns_handler.update_static_records(&config).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn node_record_policy() -> Result<()> {
+        let mut config = Config::default().dns;
+        config.node_records = NodeRecordPolicy {
+            record_types: Some(vec![RecordType::TXT, RecordType::SRV, RecordType::A]),
+            names: Some(vec![
+                "_iroh".to_string(),
+                "@".to_string(),
+                "*._udp".to_string(),
+            ]),
+            max_records: Some(2),
+            min_ttl: Some(30),
+            max_ttl: Some(3600),
+        };
+        let store = ZoneStore::in_memory(Default::default())?;
+        let dns_handler = DnsHandler::new(store.clone(), &config)?;
+
+        let signed_packet = {
+            use pkarr::dns;
+            let keypair = pkarr::Keypair::random();
+            let mut packet = dns::Packet::new_reply(0);
+            packet.answers.push(dns::ResourceRecord::new(
+                dns::Name::new("_gateway._udp").unwrap(),
+                dns::CLASS::IN,
+                86400,
+                dns::rdata::RData::SRV(dns::rdata::SRV {
+                    priority: 0,
+                    weight: 0,
+                    port: 8080,
+                    target: dns::Name::new("gateway.example.").unwrap(),
+                }),
+            ));
+            let txts = (0..3).map(|i| format!("txt{i}")).collect::<Vec<_>>();
+            for txt in &txts {
+                packet.answers.push(dns::ResourceRecord::new(
+                    dns::Name::new("_gateway._udp").unwrap(),
+                    dns::CLASS::IN,
+                    10,
+                    dns::rdata::RData::TXT(txt.as_str().try_into()?),
+                ));
+            }
+            for name in ["", "gateway"] {
+                packet.answers.push(dns::ResourceRecord::new(
+                    dns::Name::new(name).unwrap(),
+                    dns::CLASS::IN,
+                    30,
+                    dns::rdata::RData::A(Ipv4Addr::new(192, 0, 2, 1).into()),
+                ));
+            }
+            SignedPacket::from_packet(&keypair, &packet)?
+        };
+        let z32 = signed_packet.public_key().to_z32();
+        store
+            .insert(signed_packet, PacketSource::PkarrPublish)
+            .await?;
+        let name = |prefix: &str| Name::from_utf8(format!("{prefix}{z32}.irohdns.example."));
+
+        // allowed records are served with clamped TTLs
+        let res = dns_query(
+            &dns_handler,
+            &name("_gateway._udp.")?,
+            RecordType::SRV,
+            false,
+        )
+        .await?;
+        assert_eq!(res.answers().len(), 1);
+        assert_eq!(res.answers()[0].ttl(), 3600);
+        let res = dns_query(
+            &dns_handler,
+            &name("_gateway._udp.")?,
+            RecordType::TXT,
+            false,
+        )
+        .await?;
+        assert_eq!(res.answers().len(), 2);
+        assert!(res.answers().iter().all(|r| r.ttl() == 30));
+        let res = dns_query(&dns_handler, &name("")?, RecordType::A, false).await?;
+        assert_eq!(res.answers().len(), 1);
+
+        // records at names which are not allowed are not served
+        let res = dns_query(&dns_handler, &name("gateway.")?, RecordType::A, false).await?;
+        assert!(res.answers().is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn query_log() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("queries.log");
+        let mut config = Config::default().dns;
+        config.query_log = Some(QueryLogConfig {
+            path: path.clone(),
+            sample_rate: 0.5,
+            max_file_size: crate::query_log::DEFAULT_MAX_FILE_SIZE,
+            max_files: crate::query_log::DEFAULT_MAX_FILES,
+        });
+        let store = ZoneStore::in_memory(Default::default())?;
+        let dns_handler = DnsHandler::new(store, &config)?;
+
+        let origin = Name::from_utf8("irohdns.example.")?;
+        let missing = Name::from_utf8("missing.irohdns.example.")?;
+        for name in [&origin, &missing, &origin, &missing] {
+            dns_query(&dns_handler, name, RecordType::A, false).await?;
+        }
+
+        // every second query is logged, by the writer thread
+        let entries = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let entries = std::fs::read_to_string(&path)?
+                    .lines()
+                    .map(serde_json::from_str::<QueryLogEntry>)
+                    .collect::<Result<Vec<_>, _>>()?;
+                if entries.len() == 2 {
+                    return anyhow::Ok(entries);
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await??;
+        for entry in entries {
+            assert_eq!(entry.qname, "missing.irohdns.example.");
+            assert_eq!(entry.qtype, "A");
+            assert_eq!(entry.rcode, "NXDomain");
+            assert_eq!(entry.protocol, "udp");
+            assert_eq!(entry.client_ip, Ipv4Addr::LOCALHOST);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn proxy_protocol() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("queries.log");
+        let proxy_protocol = ProxyProtocolConfig::new(["127.0.0.0/8".parse()?]);
+        let mut config = Config::default();
+        config.dns.port = 0;
+        config.dns.bind_addr = Some(Ipv4Addr::LOCALHOST.into());
+        config.dns.proxy_protocol = Some(proxy_protocol.clone());
+        config.dns.query_log = Some(QueryLogConfig {
+            path: path.clone(),
+            sample_rate: 1.0,
+            max_file_size: crate::query_log::DEFAULT_MAX_FILE_SIZE,
+            max_files: crate::query_log::DEFAULT_MAX_FILES,
+        });
+        let http_config = HttpConfig {
+            port: 0,
+            bind_addr: Some(Ipv4Addr::LOCALHOST.into()),
+            proxy_protocol: Some(proxy_protocol),
+        };
+        let store = ZoneStore::in_memory(Default::default())?;
+        let dns_handler = DnsHandler::new(store.clone(), &config.dns)?;
+        let http_server = HttpServer::spawn(
+            Some(http_config),
+            None,
+            RateLimitConfig::Disabled,
+            Default::default(),
+            None,
+            None,
+            AppState {
+                store,
+                dns_handler: dns_handler.clone(),
+            },
+        )
+        .await?;
+        let dns_server = DnsServer::spawn(config.dns, dns_handler, None).await?;
+
+        // DNS over TCP from a client behind the proxy
+        let mut stream = tokio::net::TcpStream::connect(dns_server.local_addr()).await?;
+        stream
+            .write_all(b"PROXY TCP4 192.0.2.1 127.0.0.1 56324 53\r\n")
+            .await?;
+        let mut message = Message::new();
+        message.add_query(Query::query(
+            Name::from_utf8("irohdns.example.")?,
+            RecordType::A,
+        ));
+        let bytes = message.to_vec()?;
+        stream.write_u16(bytes.len() as u16).await?;
+        stream.write_all(&bytes).await?;
+        let len = stream.read_u16().await?;
+        let mut response = vec![0u8; usize::from(len)];
+        stream.read_exact(&mut response).await?;
+        let response = Message::from_vec(&response)?;
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert_eq!(response.answers().len(), 1);
+
+        // DNS over HTTP from another client behind the proxy
+        let mut stream = tokio::net::TcpStream::connect(http_server.http_addr().unwrap()).await?;
+        stream
+            .write_all(b"PROXY TCP4 192.0.2.2 127.0.0.1 56325 80\r\n")
+            .await?;
+        stream
+            .write_all(
+                b"GET /dns-query?name=irohdns.example.&type=A HTTP/1.1\r\n\
+                  Host: localhost\r\nAccept: application/dns-json\r\nConnection: close\r\n\r\n",
+            )
+            .await?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+
+        let entries = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let entries = std::fs::read_to_string(&path)?
+                    .lines()
+                    .map(serde_json::from_str::<QueryLogEntry>)
+                    .collect::<Result<Vec<_>, _>>()?;
+                if entries.len() == 2 {
+                    return anyhow::Ok(entries);
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await??;
+        assert_eq!(entries[0].protocol, "tcp");
+        assert_eq!(entries[0].client_ip, Ipv4Addr::new(192, 0, 2, 1));
+        assert_eq!(entries[1].protocol, "https");
+        assert_eq!(entries[1].client_ip, Ipv4Addr::new(192, 0, 2, 2));
+
+        // the trusted proxy must send a header
+        let mut stream = tokio::net::TcpStream::connect(dns_server.local_addr()).await?;
+        stream.write_u16(bytes.len() as u16).await?;
+        stream.write_all(&bytes).await?;
+        assert!(stream.read_u16().await.is_err());
+
+        // connections which do not send a header are closed after the TCP timeout
+        let mut stream = tokio::net::TcpStream::connect(dns_server.local_addr()).await?;
+        let res = tokio::time::timeout(Duration::from_secs(3), stream.read_u16()).await?;
+        assert!(res.is_err());
+
+        dns_server.shutdown().await?;
+        http_server.shutdown().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn encrypted_dns() -> Result<()> {
+        use hickory_server::proto::{
+            quic::QuicClientStream,
+            runtime::{TokioRuntimeProvider, TokioTime},
+            rustls::tls_client_connect,
+            xfer::{DnsExchange, DnsHandle, DnsMultiplexer, DnsRequestOptions},
+        };
+        use iroh_metrics::core::{Core, Metric};
+        use n0_future::StreamExt;
+
+        // the protocol counters are only recorded once the metrics are initialized
+        Core::try_init(|reg, metrics| {
+            metrics.insert(Metrics::new(reg));
+        })
+        .ok();
+        let metrics = Core::get()
+            .and_then(|core| core.get_collector::<Metrics>())
+            .context("metrics not initialized")?;
+
+        let mut config = Config::default();
+        config.dns.port = 0;
+        config.dns.bind_addr = Some(Ipv4Addr::LOCALHOST.into());
+        config.dns.tls_port = Some(0);
+        config.dns.quic_port = Some(0);
+        // the certificate is loaded in the manual cert mode, so that the client can trust it
+        let dir = tempfile::tempdir()?;
+        let rcgen::CertifiedKey { cert, key_pair } =
+            rcgen::generate_simple_self_signed(vec!["localhost".to_string()])?;
+        std::fs::write(dir.path().join("localhost.crt"), cert.pem())?;
+        std::fs::write(dir.path().join("localhost.key"), key_pair.serialize_pem())?;
+        let (_acceptor, tls_config) = CertMode::Manual
+            .build(
+                vec!["localhost".to_string()],
+                dir.path().to_path_buf(),
+                None,
+                false,
+            )
+            .await?;
+        let store = ZoneStore::in_memory(Default::default())?;
+        let dns_handler = DnsHandler::new(store, &config.dns)?;
+        let dns_server = DnsServer::spawn(config.dns, dns_handler, Some(&tls_config)).await?;
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(cert.der().clone())?;
+        let client_config = |alpn: &[u8]| -> Result<rustls::ClientConfig> {
+            let mut config = rustls::ClientConfig::builder_with_provider(Arc::new(
+                rustls::crypto::ring::default_provider(),
+            ))
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(roots.clone())
+            .with_no_client_auth();
+            config.alpn_protocols = vec![alpn.to_vec()];
+            Ok(config)
+        };
+        let query = |client: DnsExchange| async move {
+            let query = Query::query(Name::from_utf8("irohdns.example.")?, RecordType::A);
+            let response = client
+                .lookup(query, DnsRequestOptions::default())
+                .next()
+                .await
+                .context("no response")??;
+            assert_eq!(response.response_code(), ResponseCode::NoError);
+            assert_eq!(response.answers().len(), 1);
+            anyhow::Ok(())
+        };
+
+        // DNS-over-TLS
+        let tls_requests = metrics.dns_requests_tls.get();
+        let (stream, handle) = tls_client_connect(
+            dns_server.tls_addr().unwrap(),
+            "localhost".to_string(),
+            Arc::new(client_config(b"dot")?),
+            TokioRuntimeProvider::default(),
+        );
+        let multiplexer = DnsMultiplexer::new(stream, handle, None);
+        let (client, background) = DnsExchange::connect::<_, _, TokioTime>(multiplexer).await?;
+        let background = tokio::spawn(background);
+        query(client).await?;
+        background.abort();
+        assert!(metrics.dns_requests_tls.get() > tls_requests);
+
+        // DNS-over-QUIC
+        let quic_requests = metrics.dns_requests_quic.get();
+        let mut builder = QuicClientStream::builder();
+        builder.crypto_config(client_config(b"doq")?);
+        let stream = builder.build(dns_server.quic_addr().unwrap(), "localhost".to_string());
+        let (client, background) = DnsExchange::connect::<_, _, TokioTime>(stream).await?;
+        let background = tokio::spawn(background);
+        query(client).await?;
+        background.abort();
+        assert!(metrics.dns_requests_quic.get() > quic_requests);
+
+        // DNS-over-QUIC connections are closed on queries with a non-zero message ID
+        let crypto = quinn::crypto::rustls::QuicClientConfig::try_from(client_config(b"doq")?)?;
+        let mut endpoint = quinn::Endpoint::client((Ipv4Addr::LOCALHOST, 0).into())?;
+        endpoint.set_default_client_config(quinn::ClientConfig::new(Arc::new(crypto)));
+        let connection = endpoint
+            .connect(dns_server.quic_addr().unwrap(), "localhost")?
+            .await?;
+        let mut message = Message::new();
+        message.set_id(1);
+        message.add_query(Query::query(
+            Name::from_utf8("irohdns.example.")?,
+            RecordType::A,
+        ));
+        let message = message.to_vec()?;
+        let (mut send, _recv) = connection.open_bi().await?;
+        send.write_u16(u16::try_from(message.len())?).await?;
+        send.write_all(&message).await?;
+        send.finish()?;
+        match connection.closed().await {
+            quinn::ConnectionError::ApplicationClosed(close) => {
+                assert_eq!(close.error_code, quinn::VarInt::from_u32(0x2))
+            }
+            err => panic!("unexpected connection error: {err}"),
+        }
+
+        dns_server.shutdown().await?;
+        Ok(())
+    }
+
+    async fn dns_query(
+        dns_handler: &DnsHandler,
+        name: &Name,
+        record_type: RecordType,
+        dnssec_ok: bool,
+    ) -> Result<Message> {
+        let mut message = Message::new();
+        message.add_query(Query::query(name.clone(), record_type));
+        let mut edns = Edns::new();
+        edns.set_dnssec_ok(dnssec_ok);
+        message.set_edns(edns);
+        let bytes = message.to_vec()?;
+        let request = MessageRequest::read(&mut BinDecoder::new(&bytes))?;
+        let request = Request::new(request, "127.0.0.1:0".parse()?, Protocol::Udp);
+        let res = dns_handler.answer_request(request).await?;
+        Ok(Message::from_vec(&res)?)
+    }
+
+    /// Verify the signatures of the records of `record_type`, which must be signed.
+    fn verify_rrsigs(dnskey: &DNSKEY, records: &[Record], record_type: RecordType) -> Result<()> {
+        let covered = records
+            .iter()
+            .filter(|r| r.record_type() == record_type)
+            .collect::<Vec<_>>();
+        let rrsig = records
+            .iter()
+            .filter_map(|r| r.data().as_dnssec()?.as_rrsig())
+            .find(|rrsig| rrsig.type_covered() == record_type)
+            .ok_or_else(|| anyhow::anyhow!("no RRSIG for {record_type}"))?;
+        dnskey.verify_rrsig(covered[0].name(), DNSClass::IN, rrsig, covered.into_iter())?;
+        Ok(())
+    }
+
     fn test_resolver(nameserver: SocketAddr) -> DnsResolver {
         DnsResolver::with_nameserver(nameserver)
     }
 
+    /// Creates a signed packet for a random key with the given timestamp in microseconds.
+    fn signed_packet_at(timestamp: u64) -> Result<SignedPacket> {
+        use pkarr::dns;
+        let keypair = pkarr::Keypair::random();
+        let mut packet = dns::Packet::new_reply(0);
+        packet.answers.push(dns::ResourceRecord::new(
+            dns::Name::new("_hello").unwrap(),
+            dns::CLASS::IN,
+            30,
+            dns::rdata::RData::TXT("hi".try_into()?),
+        ));
+        let encoded = packet.build_bytes_vec_compressed()?;
+        // the signed message as specified by BEP44
+        let mut signable = format!("3:seqi{timestamp}e1:v{}:", encoded.len()).into_bytes();
+        signable.extend(&encoded);
+        let signature = keypair.sign(&signable);
+        let mut bytes = Vec::new();
+        bytes.extend(keypair.public_key().to_bytes());
+        bytes.extend(signature.to_bytes());
+        bytes.extend(timestamp.to_be_bytes());
+        bytes.extend(encoded);
+        Ok(SignedPacket::from_bytes(&bytes.into())?)
+    }
+
     fn random_signed_packet() -> Result<SignedPacket> {
         let secret_key = SecretKey::generate(rand::thread_rng());
         let node_id = secret_key.public();
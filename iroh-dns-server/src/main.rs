@@ -1,17 +1,75 @@
-use std::path::PathBuf;
+use std::{
+    path::PathBuf,
+    pin::pin,
+    time::{Duration, UNIX_EPOCH},
+};
 
-use anyhow::Result;
-use clap::Parser;
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
 use iroh_dns_server::{
-    config::Config, metrics::init_metrics, server::run_with_config_until_ctrl_c,
+    admin::{PacketDetails, PacketInfo},
+    config::Config,
+    metrics::init_metrics,
+    server::{run_with_config_file_until_ctrl_c, run_with_config_until_ctrl_c},
+    PublicKeyBytes,
 };
+use n0_future::StreamExt;
+use tokio::io::{AsyncWriteExt, BufReader, BufWriter};
 use tracing::debug;
 
+/// Number of packets read from the store at once when listing packets.
+const LIST_BATCH_SIZE: usize = 1024;
+
 #[derive(Parser, Debug)]
 struct Cli {
     /// Path to config file
     #[clap(short, long)]
     config: Option<PathBuf>,
+    /// Manage the store instead of running the server.
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+/// Commands to manage the signed packet store.
+///
+/// The commands open the store configured in the config file, so they can only be used while
+/// the server is not running.  Use the admin HTTP API to manage the store of a running server.
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// List the stored signed packets with their timestamp and size.
+    List,
+    /// Print the DNS records of the signed packet of a public key.
+    Get {
+        /// The z-base-32 encoded public key.
+        key: PublicKeyBytes,
+    },
+    /// Remove the signed packet of a public key.
+    Delete {
+        /// The z-base-32 encoded public key.
+        key: PublicKeyBytes,
+    },
+    /// Block a public key from being published, and remove its signed packet.
+    Block {
+        /// The z-base-32 encoded public key.
+        key: PublicKeyBytes,
+    },
+    /// Remove a public key from the blocklist.
+    Unblock {
+        /// The z-base-32 encoded public key.
+        key: PublicKeyBytes,
+    },
+    /// List the blocked public keys.
+    Blocklist,
+    /// Export the blocklist and all signed packets to an archive file.
+    Export {
+        /// Path of the archive file to create.
+        path: PathBuf,
+    },
+    /// Import the blocklist and the signed packets of an archive file.
+    Import {
+        /// Path of the archive file to import.
+        path: PathBuf,
+    },
 }
 
 #[tokio::main]
@@ -19,14 +77,108 @@ async fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
     let args = Cli::parse();
 
-    let config = if let Some(path) = args.config {
-        debug!("loading config from {:?}", path);
-        Config::load(path).await?
-    } else {
-        debug!("using default config");
-        Config::default()
-    };
+    match (args.command, args.config) {
+        (None, Some(path)) => {
+            debug!("loading config from {:?}", path);
+            init_metrics();
+            run_with_config_file_until_ctrl_c(path).await
+        }
+        (None, None) => {
+            debug!("using default config");
+            init_metrics();
+            run_with_config_until_ctrl_c(Config::default()).await
+        }
+        (Some(command), path) => {
+            let config = match path {
+                Some(path) => Config::load(path).await?,
+                None => Config::default(),
+            };
+            run_command(config, command).await
+        }
+    }
+}
+
+async fn run_command(config: Config, command: Command) -> Result<()> {
+    let store = config.zone_store.unwrap_or_default().open()?;
+    match command {
+        Command::List => {
+            let mut after = (0, PublicKeyBytes::new([0; 32]));
+            loop {
+                let packets = store.packets_after(after, LIST_BATCH_SIZE).await?;
+                for packet in &packets {
+                    let info = PacketInfo::from_signed_packet(packet);
+                    println!(
+                        "{}\t{}\t{}",
+                        info.public_key,
+                        format_timestamp(info.timestamp),
+                        info.size
+                    );
+                }
+                match packets.last() {
+                    Some(last) if packets.len() == LIST_BATCH_SIZE => {
+                        after = (last.timestamp(), PublicKeyBytes::from_signed_packet(last));
+                    }
+                    _ => break,
+                }
+            }
+        }
+        Command::Get { key } => {
+            let packet = store
+                .get_signed_packet(&key)
+                .await?
+                .with_context(|| format!("no signed packet stored for {key}"))?;
+            let details = PacketDetails::from_signed_packet(&packet)?;
+            println!("; {}", details.info.public_key);
+            println!("; timestamp {}", format_timestamp(details.info.timestamp));
+            println!("; size {}", details.info.size);
+            for record in details.records {
+                println!("{record}");
+            }
+        }
+        Command::Delete { key } => match store.remove(&key).await? {
+            true => println!("removed signed packet for {key}"),
+            false => println!("no signed packet stored for {key}"),
+        },
+        Command::Block { key } => match store.block(&key).await? {
+            true => println!("blocked {key}"),
+            false => println!("{key} was already blocked"),
+        },
+        Command::Unblock { key } => match store.unblock(&key).await? {
+            true => println!("unblocked {key}"),
+            false => println!("{key} was not blocked"),
+        },
+        Command::Blocklist => {
+            for key in store.blocked().await? {
+                println!("{key}");
+            }
+        }
+        Command::Export { path } => {
+            let file = tokio::fs::File::create_new(&path)
+                .await
+                .with_context(|| format!("failed to create {}", path.display()))?;
+            let mut writer = BufWriter::new(file);
+            let mut chunks = pin!(store.export());
+            while let Some(chunk) = chunks.next().await {
+                writer.write_all(&chunk?).await?;
+            }
+            writer.flush().await?;
+            println!("exported store to {}", path.display());
+        }
+        Command::Import { path } => {
+            let file = tokio::fs::File::open(&path)
+                .await
+                .with_context(|| format!("failed to open {}", path.display()))?;
+            let stats = store.import(BufReader::new(file)).await?;
+            println!(
+                "imported {} signed packets ({} stored) and {} blocked keys",
+                stats.packets, stats.stored, stats.blocked
+            );
+        }
+    }
+    Ok(())
+}
 
-    init_metrics();
-    run_with_config_until_ctrl_c(config).await
+fn format_timestamp(timestamp: u64) -> String {
+    let time = UNIX_EPOCH + Duration::from_micros(timestamp);
+    humantime::format_rfc3339_micros(time).to_string()
 }
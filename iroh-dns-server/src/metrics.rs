@@ -1,6 +1,17 @@
 //! Metrics support for the server
 
-use iroh_metrics::core::{Core, Counter, Metric};
+use std::{sync::OnceLock, time::Duration};
+
+use iroh_metrics::core::{Core, Counter, Gauge, Metric};
+use prometheus_client::{
+    encoding::EncodeLabelSet,
+    metrics::{
+        counter,
+        family::Family,
+        histogram::{exponential_buckets, Histogram},
+    },
+    registry::Registry,
+};
 use struct_iterable::Iterable;
 
 /// Metrics for iroh-dns-server
@@ -9,12 +20,29 @@ use struct_iterable::Iterable;
 pub struct Metrics {
     pub pkarr_publish_update: Counter,
     pub pkarr_publish_noop: Counter,
+    pub pkarr_rate_limited: Counter,
+    pub pkarr_subscribe_connections: Counter,
+    pub pkarr_subscribe_active: Gauge,
+    pub pkarr_subscribe_rejected: Counter,
+    pub pkarr_subscribe_packets_sent: Counter,
+    pub replication_update: Counter,
+    pub replication_noop: Counter,
+    pub replication_packets_sent: Counter,
+    pub replication_packets_dropped: Counter,
+    pub replication_sync_packets: Counter,
+    pub dht_republish_rounds: Counter,
+    pub dht_republish_success: Counter,
+    pub dht_republish_error: Counter,
     pub dns_requests: Counter,
     pub dns_requests_udp: Counter,
+    pub dns_requests_tcp: Counter,
+    pub dns_requests_tls: Counter,
     pub dns_requests_https: Counter,
+    pub dns_requests_quic: Counter,
     pub dns_lookup_success: Counter,
     pub dns_lookup_notfound: Counter,
     pub dns_lookup_error: Counter,
+    pub query_log_dropped: Counter,
     pub http_requests: Counter,
     pub http_requests_success: Counter,
     pub http_requests_error: Counter,
@@ -23,6 +51,9 @@ pub struct Metrics {
     pub store_packets_removed: Counter,
     pub store_packets_updated: Counter,
     pub store_packets_expired: Counter,
+    pub store_packets_evicted: Counter,
+    pub store_packets_blocked: Counter,
+    pub store_packets_future: Counter,
 }
 
 impl Default for Metrics {
@@ -32,12 +63,45 @@ impl Default for Metrics {
             pkarr_publish_noop: Counter::new(
                 "Number of pkarr relay puts that did not update the state",
             ),
+            pkarr_rate_limited: Counter::new("Number of pkarr requests rejected by a rate limit"),
+            pkarr_subscribe_connections: Counter::new("Number of pkarr subscription connections"),
+            pkarr_subscribe_active: Gauge::new("Number of open pkarr subscription connections"),
+            pkarr_subscribe_rejected: Counter::new(
+                "Number of rejected pkarr subscription connections",
+            ),
+            pkarr_subscribe_packets_sent: Counter::new(
+                "Number of signed packets sent to pkarr subscribers",
+            ),
+            replication_update: Counter::new(
+                "Number of packets received from peers via replication that updated the state",
+            ),
+            replication_noop: Counter::new(
+                "Number of packets received from peers via replication that did not update the state",
+            ),
+            replication_packets_sent: Counter::new("Number of packets replicated to peers"),
+            replication_packets_dropped: Counter::new(
+                "Number of packets which could not be replicated to peers",
+            ),
+            replication_sync_packets: Counter::new(
+                "Number of packets fetched from peers during anti-entropy sync",
+            ),
+            dht_republish_rounds: Counter::new("Number of rounds of republishing packets to the DHT"),
+            dht_republish_success: Counter::new("Number of packets republished to the DHT"),
+            dht_republish_error: Counter::new(
+                "Number of packets which could not be republished to the DHT",
+            ),
             dns_requests: Counter::new("DNS requests (total)"),
             dns_requests_udp: Counter::new("DNS requests via UDP"),
+            dns_requests_tcp: Counter::new("DNS requests via TCP"),
+            dns_requests_tls: Counter::new("DNS requests via TLS (DoT)"),
             dns_requests_https: Counter::new("DNS requests via HTTPS (DoH)"),
+            dns_requests_quic: Counter::new("DNS requests via QUIC (DoQ)"),
             dns_lookup_success: Counter::new("DNS lookup responses with at least one answer"),
             dns_lookup_notfound: Counter::new("DNS lookup responses with no answers"),
             dns_lookup_error: Counter::new("DNS lookup responses which failed"),
+            query_log_dropped: Counter::new(
+                "Number of sampled DNS queries which could not be written to the query log",
+            ),
             http_requests: Counter::new("Number of HTTP requests"),
             http_requests_success: Counter::new("Number of HTTP requests with a 2xx status code"),
             http_requests_error: Counter::new("Number of HTTP requests with a non-2xx status code"),
@@ -46,6 +110,15 @@ impl Default for Metrics {
             store_packets_removed: Counter::new("Signed packets removed from the store"),
             store_packets_updated: Counter::new("Number of updates to existing packets"),
             store_packets_expired: Counter::new("Number of expired packets"),
+            store_packets_evicted: Counter::new(
+                "Number of packets evicted because the store was full",
+            ),
+            store_packets_blocked: Counter::new(
+                "Number of packets rejected because their public key is blocked",
+            ),
+            store_packets_future: Counter::new(
+                "Number of packets rejected because their timestamp is too far in the future",
+            ),
         }
     }
 }
@@ -60,5 +133,114 @@ impl Metric for Metrics {
 pub fn init_metrics() {
     Core::init(|reg, metrics| {
         metrics.insert(Metrics::new(reg));
+        LABELED_METRICS.get_or_init(|| LabeledMetrics::new(reg));
     });
 }
+
+static LABELED_METRICS: OnceLock<LabeledMetrics> = OnceLock::new();
+
+type HistogramFamily<L> = Family<L, Histogram, fn() -> Histogram>;
+
+/// Metrics with labels, broken down per origin and per HTTP route.
+///
+/// These are not supported by [`Metric`], so they are registered separately in the same
+/// registry by [`init_metrics`].  Nothing is recorded if [`init_metrics`] was not called.
+#[derive(Debug)]
+struct LabeledMetrics {
+    dns_requests: Family<DnsLabels, counter::Counter>,
+    dns_responses: Family<DnsResponseLabels, counter::Counter>,
+    dns_request_duration: HistogramFamily<DnsLabels>,
+    http_request_duration: HistogramFamily<HttpLabels>,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct DnsLabels {
+    origin: String,
+    protocol: String,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct DnsResponseLabels {
+    origin: String,
+    rcode: String,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct HttpLabels {
+    method: String,
+    path: String,
+}
+
+/// Buckets from 0.1ms to about 3.3s.
+fn duration_histogram() -> Histogram {
+    Histogram::new(exponential_buckets(0.0001, 2.0, 16))
+}
+
+impl LabeledMetrics {
+    fn new(registry: &mut Registry) -> Self {
+        let this = Self {
+            dns_requests: Default::default(),
+            dns_responses: Default::default(),
+            dns_request_duration: Family::new_with_constructor(duration_histogram),
+            http_request_duration: Family::new_with_constructor(duration_histogram),
+        };
+        let sub_registry = registry.sub_registry_with_prefix(Metrics::name());
+        sub_registry.register(
+            "origin_dns_requests",
+            "DNS requests per origin and protocol",
+            this.dns_requests.clone(),
+        );
+        sub_registry.register(
+            "origin_dns_responses",
+            "DNS responses per origin and response code",
+            this.dns_responses.clone(),
+        );
+        sub_registry.register(
+            "origin_dns_request_duration_seconds",
+            "Time to answer DNS requests per origin and protocol",
+            this.dns_request_duration.clone(),
+        );
+        sub_registry.register(
+            "http_request_duration_seconds",
+            "Time to answer HTTP requests per method and route",
+            this.http_request_duration.clone(),
+        );
+        this
+    }
+}
+
+/// Record an answered DNS request of the `origin` label.
+pub(crate) fn record_dns_request(origin: &str, protocol: &str, rcode: &str, latency: Duration) {
+    let Some(metrics) = LABELED_METRICS.get() else {
+        return;
+    };
+    let labels = DnsLabels {
+        origin: origin.to_string(),
+        protocol: protocol.to_string(),
+    };
+    metrics.dns_requests.get_or_create(&labels).inc();
+    metrics
+        .dns_request_duration
+        .get_or_create(&labels)
+        .observe(latency.as_secs_f64());
+    let labels = DnsResponseLabels {
+        origin: origin.to_string(),
+        rcode: rcode.to_string(),
+    };
+    metrics.dns_responses.get_or_create(&labels).inc();
+}
+
+/// Record the duration of an HTTP request to the route `path`.
+pub(crate) fn record_http_request(method: &str, path: &str, latency: Duration) {
+    let Some(metrics) = LABELED_METRICS.get() else {
+        return;
+    };
+    let labels = HttpLabels {
+        method: method.to_string(),
+        path: path.to_string(),
+    };
+    metrics
+        .http_request_duration
+        .get_or_create(&labels)
+        .observe(latency.as_secs_f64());
+}
@@ -0,0 +1,300 @@
+//! Structured log of DNS queries.
+//!
+//! When configured, the DNS handler writes an entry for each answered query as a line of JSON
+//! to a log file.  This is intended for the analysis of abusive traffic, so each entry contains
+//! the address of the client, the query and the outcome of the lookup.
+//!
+//! Only a fraction of the queries is logged if [`QueryLogConfig::sample_rate`] is below 1.  The
+//! log file is rotated when it exceeds [`QueryLogConfig::max_file_size`], keeping at most
+//! [`QueryLogConfig::max_files`] old files next to it with the suffixes `.1`, `.2` and so on.
+//!
+//! Entries are written by a dedicated thread.  If the thread cannot keep up, entries are
+//! dropped instead of slowing down the DNS responses.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, SyncSender},
+    },
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{ensure, Result};
+use iroh_metrics::inc;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::metrics::Metrics;
+
+/// The default maximum size of the log file before it is rotated.
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 100 * 1024 * 1024; // 100MiB
+
+/// The default number of rotated log files to keep.
+pub const DEFAULT_MAX_FILES: usize = 5;
+
+/// Number of entries which can be queued for the writer thread.
+const QUEUE_SIZE: usize = 4096;
+
+/// Config for the structured query log.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct QueryLogConfig {
+    /// Path of the log file.
+    pub path: PathBuf,
+    /// Fraction of the queries to log, between 0 and 1.
+    ///
+    /// The logged queries are evenly spread: with a rate of `0.1`, every tenth query is logged.
+    #[serde(default = "default_sample_rate")]
+    pub sample_rate: f64,
+    /// Maximum size of the log file in bytes before it is rotated.
+    #[serde(default = "default_max_file_size")]
+    pub max_file_size: u64,
+    /// Maximum number of rotated log files to keep.
+    #[serde(default = "default_max_files")]
+    pub max_files: usize,
+}
+
+fn default_sample_rate() -> f64 {
+    1.0
+}
+
+fn default_max_file_size() -> u64 {
+    DEFAULT_MAX_FILE_SIZE
+}
+
+fn default_max_files() -> usize {
+    DEFAULT_MAX_FILES
+}
+
+/// An entry of the query log.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct QueryLogEntry {
+    /// Time the query was received, in RFC 3339 format.
+    pub timestamp: String,
+    /// IP address of the client.
+    pub client_ip: IpAddr,
+    /// The protocol the query was received over.
+    pub protocol: String,
+    /// The queried name.
+    pub qname: String,
+    /// The queried record type.
+    pub qtype: String,
+    /// The response code of the answer.
+    pub rcode: String,
+    /// Time to answer the query, in microseconds.
+    pub latency_us: u64,
+}
+
+impl QueryLogEntry {
+    pub(crate) fn new(
+        time: SystemTime,
+        client_ip: IpAddr,
+        protocol: String,
+        qname: String,
+        qtype: String,
+        rcode: String,
+        latency: Duration,
+    ) -> Self {
+        Self {
+            timestamp: humantime::format_rfc3339_micros(time).to_string(),
+            client_ip,
+            protocol,
+            qname,
+            qtype,
+            rcode,
+            latency_us: latency.as_micros() as u64,
+        }
+    }
+}
+
+/// The writer of the query log.
+///
+/// The writer thread stops when this is dropped.
+#[derive(Debug)]
+pub(crate) struct QueryLog {
+    sender: SyncSender<QueryLogEntry>,
+    sample_rate: f64,
+    /// Number of queries seen, used for sampling.
+    queries: AtomicU64,
+}
+
+impl QueryLog {
+    /// Open the log file and spawn the writer thread.
+    pub(crate) fn spawn(config: &QueryLogConfig) -> Result<Self> {
+        ensure!(
+            (0.0..=1.0).contains(&config.sample_rate),
+            "query log sample rate must be between 0 and 1"
+        );
+        let file = RotatingFile::open(config.path.clone(), config.max_file_size, config.max_files)?;
+        let (sender, receiver) = mpsc::sync_channel(QUEUE_SIZE);
+        std::thread::Builder::new()
+            .name("dns-query-log".to_string())
+            .spawn(move || write_loop(file, receiver))?;
+        Ok(Self {
+            sender,
+            sample_rate: config.sample_rate,
+            queries: AtomicU64::new(0),
+        })
+    }
+
+    /// Whether the next query should be logged.
+    pub(crate) fn sample(&self) -> bool {
+        let n = self.queries.fetch_add(1, Ordering::Relaxed);
+        // Log a query whenever the expected number of logged queries crosses an integer.
+        ((n + 1) as f64 * self.sample_rate).floor() > (n as f64 * self.sample_rate).floor()
+    }
+
+    /// Queue an entry to be written to the log.
+    pub(crate) fn log(&self, entry: QueryLogEntry) {
+        if self.sender.try_send(entry).is_err() {
+            inc!(Metrics, query_log_dropped);
+        }
+    }
+}
+
+fn write_loop(mut file: RotatingFile, receiver: Receiver<QueryLogEntry>) {
+    while let Ok(entry) = receiver.recv() {
+        // Write all queued entries before flushing the file.
+        let res = std::iter::once(entry)
+            .chain(receiver.try_iter())
+            .try_for_each(|entry| file.write_entry(&entry))
+            .and_then(|()| file.flush());
+        if let Err(err) = res {
+            warn!("failed to write to the query log: {err:#}");
+        }
+    }
+}
+
+/// A log file which is rotated when it exceeds a maximum size.
+#[derive(Debug)]
+struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    max_files: usize,
+    writer: BufWriter<File>,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_size: u64, max_files: usize) -> io::Result<Self> {
+        let file = open_append(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            max_size,
+            max_files,
+            writer: BufWriter::new(file),
+            size,
+        })
+    }
+
+    fn write_entry(&mut self, entry: &QueryLogEntry) -> io::Result<()> {
+        if self.size >= self.max_size {
+            self.rotate()?;
+        }
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        self.writer.write_all(&line)?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// Move the current file to `<path>.1`, shifting the older files, and start a new file.
+    fn rotate(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        if self.max_files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let _ = fs::remove_file(rotated_path(&self.path, self.max_files));
+            for i in (1..self.max_files).rev() {
+                let from = rotated_path(&self.path, i);
+                if from.exists() {
+                    fs::rename(from, rotated_path(&self.path, i + 1))?;
+                }
+            }
+            fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        }
+        self.writer = BufWriter::new(open_append(&self.path)?);
+        self.size = 0;
+        Ok(())
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(format!(".{index}"));
+    path.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(qname: &str) -> QueryLogEntry {
+        QueryLogEntry::new(
+            SystemTime::now(),
+            "127.0.0.1".parse().unwrap(),
+            "udp".to_string(),
+            qname.to_string(),
+            "TXT".to_string(),
+            "NoError".to_string(),
+            Duration::from_micros(150),
+        )
+    }
+
+    #[test]
+    fn sampling() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let config = QueryLogConfig {
+            path: dir.path().join("queries.log"),
+            sample_rate: 0.25,
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+            max_files: DEFAULT_MAX_FILES,
+        };
+        let log = QueryLog::spawn(&config)?;
+        let sampled = (0..100).filter(|_| log.sample()).count();
+        assert_eq!(sampled, 25);
+        Ok(())
+    }
+
+    #[test]
+    fn rotation() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("queries.log");
+        let line_len = serde_json::to_vec(&entry("a.example."))?.len() as u64 + 1;
+        let mut file = RotatingFile::open(path.clone(), 2 * line_len, 2)?;
+        for name in ["a", "b", "c", "d", "e", "f", "g"] {
+            file.write_entry(&entry(&format!("{name}.example.")))?;
+        }
+        file.flush()?;
+
+        let read = |path: &Path| -> Result<Vec<String>> {
+            fs::read_to_string(path)?
+                .lines()
+                .map(|line| Ok(serde_json::from_str::<QueryLogEntry>(line)?.qname))
+                .collect()
+        };
+        assert_eq!(read(&path)?, vec!["g.example."]);
+        assert_eq!(
+            read(&rotated_path(&path, 1))?,
+            vec!["e.example.", "f.example."]
+        );
+        assert_eq!(
+            read(&rotated_path(&path, 2))?,
+            vec!["c.example.", "d.example."]
+        );
+        assert!(!rotated_path(&path, 3).exists());
+        Ok(())
+    }
+}
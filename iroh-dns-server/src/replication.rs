@@ -0,0 +1,303 @@
+//! Replication of signed packets between iroh-dns-server instances.
+//!
+//! When replication is configured, every signed packet which is published to this server
+//! and accepted by the [`ZoneStore`] is pushed to all configured peer servers.  As signed
+//! packets carry their own signature, the peers verify them like any other published packet,
+//! so no further trust between the servers is needed.
+//!
+//! Packets received from a peer are stored with [`PacketSource::Replication`] and are never
+//! forwarded again, which prevents replication loops between the servers.  Each server thus
+//! has to list all other servers as peers.
+//!
+//! On startup, the server fetches all packets stored by its peers to catch up on the packets
+//! published while it was not running.
+//!
+//! The peers exchange packets through the `/replication/packets` HTTP endpoint, which is only
+//! served if replication is configured.  All servers share a [`ReplicationConfig::token`],
+//! which the peers send as bearer token.  Packets are encoded as a sequence of the
+//! [`SignedPacket::as_bytes`] encoding, each prefixed with its length as big-endian `u16`.
+//!
+//! Packets pushed by a peer count against the per-public-key limits of pkarr PUT requests,
+//! packets exceeding them are dropped.
+
+use std::time::Duration;
+
+use anyhow::{ensure, Context, Result};
+use bytes::{BufMut, Bytes, BytesMut};
+use iroh_metrics::{inc, inc_by};
+use n0_future::task::{self, AbortOnDropHandle};
+use pkarr::SignedPacket;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, mpsc};
+use tracing::{debug, info, warn, Instrument};
+use url::Url;
+
+use crate::{
+    metrics::Metrics,
+    store::{PacketSource, ZoneStore},
+    util::PublicKeyBytes,
+};
+
+/// The path of the replication endpoint, relative to the base URL of a server.
+pub const REPLICATION_PATH: &str = "/replication/packets";
+
+/// Maximum number of packets sent or fetched in a single request.
+pub(crate) const MAX_BATCH_SIZE: usize = 256;
+
+/// Number of packets queued for each peer before new packets are dropped.
+const PEER_QUEUE_CAPACITY: usize = 4096;
+
+/// Timeout for a single request to a peer.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Number of attempts to send a batch of packets or to sync with a peer.
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Delay before the first retry, doubled for each further attempt.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Config for the replication between servers.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ReplicationConfig {
+    /// Base URLs of the HTTP(S) servers of the peer iroh-dns-server instances.
+    pub peers: Vec<Url>,
+    /// The token authenticating the servers to each other.
+    ///
+    /// All peers must be configured with the same token.
+    pub token: String,
+    /// Set to true to not fetch the packets stored by the peers on startup.
+    #[serde(default)]
+    pub skip_startup_sync: bool,
+}
+
+impl ReplicationConfig {
+    /// Check the config for invalid values.
+    pub fn validate(&self) -> Result<()> {
+        ensure!(
+            !self.token.is_empty(),
+            "the replication token must not be empty"
+        );
+        Ok(())
+    }
+}
+
+/// The replication of packets to and from peer servers.
+///
+/// The replication tasks are stopped when this is dropped.
+#[derive(Debug)]
+pub struct Replication {
+    _tasks: Vec<AbortOnDropHandle<()>>,
+}
+
+impl Replication {
+    /// Spawn the replication tasks for the zone store.
+    pub fn spawn(config: ReplicationConfig, store: ZoneStore) -> Result<Self> {
+        config.validate()?;
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .context("failed to build replication http client")?;
+        // subscribe before the sync so that no packet published in between is missed
+        let updates = store.subscribe();
+        let mut tasks = Vec::new();
+        let mut queues = Vec::new();
+        for peer in config.peers {
+            let url = replication_url(&peer);
+            info!(%peer, "replicating packets to peer");
+            let span = tracing::error_span!("replication", %peer);
+            if !config.skip_startup_sync {
+                let task = sync_task(
+                    client.clone(),
+                    url.clone(),
+                    config.token.clone(),
+                    store.clone(),
+                );
+                tasks.push(task::spawn(task.instrument(span.clone())));
+            }
+            let (send, recv) = mpsc::channel(PEER_QUEUE_CAPACITY);
+            queues.push(send);
+            tasks.push(task::spawn(
+                push_task(client.clone(), url, config.token.clone(), recv).instrument(span),
+            ));
+        }
+        tasks.push(task::spawn(forward_task(updates, queues)));
+        Ok(Self {
+            _tasks: tasks.into_iter().map(AbortOnDropHandle::new).collect(),
+        })
+    }
+}
+
+/// Forward the packets published to this server to the queues of all peers.
+async fn forward_task(
+    mut updates: broadcast::Receiver<(SignedPacket, PacketSource)>,
+    queues: Vec<mpsc::Sender<SignedPacket>>,
+) {
+    loop {
+        match updates.recv().await {
+            Ok((packet, PacketSource::PkarrPublish)) => {
+                for queue in &queues {
+                    if queue.try_send(packet.clone()).is_err() {
+                        inc!(Metrics, replication_packets_dropped);
+                    }
+                }
+            }
+            // packets from peers are not forwarded again, to prevent replication loops
+            Ok((_packet, PacketSource::Replication)) => {}
+            // imported packets are not forwarded, each server imports the archive itself
+            Ok((_packet, PacketSource::Import)) => {}
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                warn!("replication lagged behind, dropped {n} packets");
+                inc_by!(
+                    Metrics,
+                    replication_packets_dropped,
+                    n * queues.len() as u64
+                );
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+/// Send the queued packets to a peer in batches.
+async fn push_task(
+    client: reqwest::Client,
+    url: Url,
+    token: String,
+    mut queue: mpsc::Receiver<SignedPacket>,
+) {
+    let mut batch = Vec::new();
+    while let Some(packet) = queue.recv().await {
+        batch.push(packet);
+        while batch.len() < MAX_BATCH_SIZE {
+            match queue.try_recv() {
+                Ok(packet) => batch.push(packet),
+                Err(_) => break,
+            }
+        }
+        let body = encode_packets(&batch);
+        let res = with_retries(|| async {
+            let res = client
+                .post(url.clone())
+                .bearer_auth(&token)
+                .body(body.clone())
+                .send()
+                .await?;
+            res.error_for_status()?;
+            Ok(())
+        })
+        .await;
+        match res {
+            Ok(()) => {
+                debug!("replicated {} packets", batch.len());
+                inc_by!(Metrics, replication_packets_sent, batch.len() as u64);
+            }
+            Err(err) => {
+                warn!("failed to replicate {} packets: {err:#}", batch.len());
+                inc_by!(Metrics, replication_packets_dropped, batch.len() as u64);
+            }
+        }
+        batch.clear();
+    }
+}
+
+/// Fetch all packets stored by a peer.
+async fn sync_task(client: reqwest::Client, url: Url, token: String, store: ZoneStore) {
+    match with_retries(|| sync(&client, &url, &token, &store)).await {
+        Ok(count) => info!("synced {count} packets from peer"),
+        Err(err) => warn!("failed to sync packets from peer: {err:#}"),
+    }
+}
+
+async fn sync(
+    client: &reqwest::Client,
+    url: &Url,
+    token: &str,
+    store: &ZoneStore,
+) -> Result<usize> {
+    let mut after = (0u64, PublicKeyBytes::new([0; 32]));
+    let mut count = 0;
+    loop {
+        let res = client
+            .get(url.clone())
+            .bearer_auth(token)
+            .query(&[
+                ("after_time", after.0.to_string()),
+                ("after_key", after.1.to_z32()),
+                ("limit", MAX_BATCH_SIZE.to_string()),
+            ])
+            .send()
+            .await?
+            .error_for_status()?;
+        let packets = decode_packets(&res.bytes().await?)?;
+        let len = packets.len();
+        for packet in packets {
+            after = (
+                packet.timestamp(),
+                PublicKeyBytes::from_signed_packet(&packet),
+            );
+            store.insert(packet, PacketSource::Replication).await?;
+        }
+        count += len;
+        inc_by!(Metrics, replication_sync_packets, len as u64);
+        if len < MAX_BATCH_SIZE {
+            return Ok(count);
+        }
+    }
+}
+
+async fn with_retries<T, F, Fut>(mut f: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<T>>,
+{
+    let mut backoff = INITIAL_BACKOFF;
+    let mut attempt = 1;
+    loop {
+        match f().await {
+            Ok(res) => return Ok(res),
+            Err(err) if attempt < MAX_ATTEMPTS => {
+                debug!("replication request failed (attempt {attempt}): {err:#}");
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+                attempt += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// The URL of the replication endpoint of a peer.
+fn replication_url(peer: &Url) -> Url {
+    let mut url = peer.clone();
+    url.set_path(&format!(
+        "{}{REPLICATION_PATH}",
+        peer.path().trim_end_matches('/')
+    ));
+    url
+}
+
+/// Encode signed packets for the replication endpoint.
+pub(crate) fn encode_packets(packets: &[SignedPacket]) -> Bytes {
+    let mut buf = BytesMut::new();
+    for packet in packets {
+        let bytes = packet.as_bytes();
+        buf.put_u16(bytes.len() as u16);
+        buf.put_slice(bytes);
+    }
+    buf.freeze()
+}
+
+/// Decode signed packets received from the replication endpoint, verifying their signatures.
+pub(crate) fn decode_packets(mut bytes: &[u8]) -> Result<Vec<SignedPacket>> {
+    let mut packets = Vec::new();
+    while !bytes.is_empty() {
+        ensure!(bytes.len() >= 2, "truncated packet length");
+        let len = u16::from_be_bytes([bytes[0], bytes[1]]) as usize;
+        bytes = &bytes[2..];
+        ensure!(bytes.len() >= len, "truncated packet");
+        let packet = SignedPacket::from_bytes(&Bytes::copy_from_slice(&bytes[..len]))?;
+        packets.push(packet);
+        bytes = &bytes[len..];
+    }
+    Ok(packets)
+}
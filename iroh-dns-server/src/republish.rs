@@ -0,0 +1,225 @@
+//! Republishing of stored signed packets to the bittorrent mainline DHT.
+//!
+//! Nodes which only publish their signed packets to this server via HTTP cannot be found by
+//! clients resolving from the mainline DHT.  When republishing is configured, the server
+//! periodically walks the packets in its store, ordered by their timestamp, and publishes all
+//! packets which are not older than [`RepublishConfig::max_age`] to the DHT.
+//!
+//! Records in the DHT expire after a few hours, so the [`RepublishConfig::interval`] should be
+//! well below that.  The load on the DHT and on the network is bounded by the number of
+//! concurrent publish operations and by a limit on the republished bytes per second.
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{ensure, Result};
+use iroh_metrics::inc;
+use n0_future::task::{self, AbortOnDropHandle};
+use pkarr::{system_time, PkarrClient, SignedPacket};
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinSet;
+use tracing::{debug, info, warn, Instrument};
+
+use crate::{config::BootstrapOption, metrics::Metrics, store::ZoneStore, util::PublicKeyBytes};
+
+/// The default interval between two republishing rounds.
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(60 * 60); // 1h
+
+/// The default maximum age of republished packets.
+pub const DEFAULT_MAX_AGE: Duration = Duration::from_secs(60 * 60 * 24); // 24h
+
+/// The default maximum number of concurrent publish operations.
+pub const DEFAULT_CONCURRENCY: usize = 16;
+
+/// Number of packets read from the store at once.
+const BATCH_SIZE: usize = 256;
+
+/// Config for republishing stored packets to the mainline DHT.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct RepublishConfig {
+    /// Interval between the start of two republishing rounds.  Must not be zero.
+    ///
+    /// If a round takes longer than the interval, the next round starts right after it.
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+    /// Only packets with a timestamp within this duration before the start of a round are
+    /// republished.
+    #[serde(with = "humantime_serde")]
+    pub max_age: Duration,
+    /// Maximum number of concurrent publish operations.  Must not be zero.
+    pub concurrency: usize,
+    /// Maximum number of bytes of signed packets republished per second.
+    ///
+    /// Each publish operation sends the packet to several DHT nodes, so the resulting traffic
+    /// is a multiple of this.  If `None`, the rate is only limited by the concurrency.  Must not
+    /// be zero.
+    pub max_bytes_per_second: Option<u64>,
+}
+
+impl Default for RepublishConfig {
+    fn default() -> Self {
+        Self {
+            interval: DEFAULT_INTERVAL,
+            max_age: DEFAULT_MAX_AGE,
+            concurrency: DEFAULT_CONCURRENCY,
+            max_bytes_per_second: None,
+        }
+    }
+}
+
+impl RepublishConfig {
+    /// Check the config for invalid values.
+    pub fn validate(&self) -> Result<()> {
+        ensure!(
+            !self.interval.is_zero(),
+            "the republish interval must not be zero"
+        );
+        ensure!(
+            self.concurrency > 0,
+            "the republish concurrency must not be zero"
+        );
+        ensure!(
+            self.max_bytes_per_second != Some(0),
+            "the republish rate limit must not be zero"
+        );
+        Ok(())
+    }
+}
+
+/// The republishing of stored packets to the mainline DHT.
+///
+/// The republishing task is stopped when this is dropped.
+#[derive(Debug)]
+pub struct Republisher {
+    _task: AbortOnDropHandle<()>,
+}
+
+impl Republisher {
+    /// Spawn the republishing task for the zone store.
+    pub fn spawn(
+        config: RepublishConfig,
+        store: ZoneStore,
+        bootstrap: BootstrapOption,
+    ) -> Result<Self> {
+        config.validate()?;
+        let client = crate::store::mainline_client(bootstrap)?;
+        info!(
+            interval = ?config.interval,
+            max_age = ?config.max_age,
+            "republishing packets to the mainline DHT"
+        );
+        let task =
+            republish_task(config, store, client).instrument(tracing::error_span!("republish"));
+        Ok(Self {
+            _task: AbortOnDropHandle::new(task::spawn(task)),
+        })
+    }
+}
+
+async fn republish_task(config: RepublishConfig, store: ZoneStore, client: PkarrClient) {
+    let client = Arc::new(client);
+    let mut interval = tokio::time::interval(config.interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        let start = Instant::now();
+        match republish(&config, &store, &client).await {
+            Ok(count) => info!("republished {count} packets in {:?}", start.elapsed()),
+            Err(err) => warn!("failed to republish packets: {err:#}"),
+        }
+        inc!(Metrics, dht_republish_rounds);
+    }
+}
+
+/// Publish all packets not older than [`RepublishConfig::max_age`] to the DHT.
+///
+/// Returns the number of packets which were published.
+async fn republish(
+    config: &RepublishConfig,
+    store: &ZoneStore,
+    client: &Arc<PkarrClient>,
+) -> Result<usize> {
+    let min_time = system_time().saturating_sub(config.max_age.as_micros() as u64);
+    let mut after = (min_time, PublicKeyBytes::new([0; 32]));
+    let mut pacer = Pacer::new(config.max_bytes_per_second);
+    let mut tasks = JoinSet::new();
+    let mut count = 0;
+    loop {
+        let packets = store.packets_after(after, BATCH_SIZE).await?;
+        let done = packets.len() < BATCH_SIZE;
+        for packet in packets {
+            after = (
+                packet.timestamp(),
+                PublicKeyBytes::from_signed_packet(&packet),
+            );
+            while tasks.len() >= config.concurrency {
+                count += join_next(&mut tasks).await;
+            }
+            pacer.wait(packet.as_bytes().len()).await;
+            tasks.spawn(publish(client.clone(), packet));
+        }
+        if done {
+            break;
+        }
+    }
+    while !tasks.is_empty() {
+        count += join_next(&mut tasks).await;
+    }
+    Ok(count)
+}
+
+/// Wait for the next publish operation and return 1 if it succeeded.
+async fn join_next(tasks: &mut JoinSet<bool>) -> usize {
+    match tasks.join_next().await {
+        Some(Ok(true)) => 1,
+        _ => 0,
+    }
+}
+
+async fn publish(client: Arc<PkarrClient>, packet: SignedPacket) -> bool {
+    let key = PublicKeyBytes::from_signed_packet(&packet);
+    let client = client.as_ref().clone().as_async();
+    match client.publish(&packet).await {
+        Ok(()) => {
+            debug!(%key, "republished packet");
+            inc!(Metrics, dht_republish_success);
+            true
+        }
+        Err(err) => {
+            debug!(%key, "failed to republish packet: {err}");
+            inc!(Metrics, dht_republish_error);
+            false
+        }
+    }
+}
+
+/// Limits the rate of republished bytes.
+#[derive(Debug)]
+struct Pacer {
+    max_bytes_per_second: Option<u64>,
+    start: Instant,
+    bytes: u64,
+}
+
+impl Pacer {
+    fn new(max_bytes_per_second: Option<u64>) -> Self {
+        Self {
+            max_bytes_per_second,
+            start: Instant::now(),
+            bytes: 0,
+        }
+    }
+
+    /// Wait until `len` more bytes can be sent without exceeding the rate.
+    async fn wait(&mut self, len: usize) {
+        let Some(limit) = self.max_bytes_per_second else {
+            return;
+        };
+        let due = self.start + Duration::from_secs_f64(self.bytes as f64 / limit as f64);
+        tokio::time::sleep_until(due.into()).await;
+        self.bytes += len as u64;
+    }
+}
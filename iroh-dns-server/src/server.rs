@@ -1,40 +1,88 @@
 //! The main server which combines the DNS and HTTP(S) servers.
 
+use std::path::{Path, PathBuf};
+
 use anyhow::Result;
 use iroh_metrics::metrics::start_metrics_server;
-use tracing::info;
+use tracing::{info, warn};
 
 use crate::{
     config::Config,
     dns::{DnsHandler, DnsServer},
     http::HttpServer,
+    replication::Replication,
+    republish::Republisher,
     state::AppState,
     store::ZoneStore,
 };
 
 /// Spawn the server and run until the `Ctrl-C` signal is received, then shutdown.
+///
+/// On unix, the static DNS records are reloaded when the `SIGHUP` signal is received.
 pub async fn run_with_config_until_ctrl_c(config: Config) -> Result<()> {
-    let zone_store_options = config.zone_store.clone().unwrap_or_default();
-    let mut store = ZoneStore::persistent(
-        Config::signed_packet_store_path()?,
-        zone_store_options.into(),
-    )?;
+    run_until_ctrl_c(config, None).await
+}
+
+/// Spawn the server with the config loaded from `path` and run until the `Ctrl-C` signal is
+/// received, then shutdown.
+///
+/// On unix, the config file is read again and the static DNS records are reloaded when the
+/// `SIGHUP` signal is received.
+pub async fn run_with_config_file_until_ctrl_c(path: PathBuf) -> Result<()> {
+    let config = Config::load(&path).await?;
+    run_until_ctrl_c(config, Some(path)).await
+}
+
+async fn run_until_ctrl_c(config: Config, config_path: Option<PathBuf>) -> Result<()> {
+    let mut store = config.zone_store.clone().unwrap_or_default().open()?;
     if let Some(bootstrap) = config.mainline_enabled() {
         info!("mainline fallback enabled");
         store = store.with_mainline_fallback(bootstrap);
     };
     let server = Server::spawn(config, store).await?;
-    tokio::signal::ctrl_c().await?;
+    #[cfg(unix)]
+    let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
+    loop {
+        #[cfg(unix)]
+        let reload = hangup.recv();
+        #[cfg(not(unix))]
+        let reload = std::future::pending::<Option<()>>();
+        tokio::select! {
+            res = tokio::signal::ctrl_c() => {
+                res?;
+                break;
+            }
+            _ = reload => reload_static_records(&server.dns_handler, config_path.as_deref()).await,
+        }
+    }
     info!("shutdown");
     server.shutdown().await?;
     Ok(())
 }
 
+/// Reload the static DNS records, with the DNS config from the config file if set.
+async fn reload_static_records(dns_handler: &DnsHandler, config_path: Option<&Path>) {
+    info!("reloading static DNS records");
+    let res = match config_path {
+        Some(path) => match Config::load(path).await {
+            Ok(config) => dns_handler.update_static_records(&config.dns),
+            Err(err) => Err(err),
+        },
+        None => dns_handler.reload_static_records(),
+    };
+    if let Err(err) = res {
+        warn!("failed to reload static DNS records: {err:#}");
+    }
+}
+
 /// The iroh-dns server.
 pub struct Server {
     http_server: HttpServer,
     dns_server: DnsServer,
+    dns_handler: DnsHandler,
     metrics_task: tokio::task::JoinHandle<anyhow::Result<()>>,
+    replication: Option<Replication>,
+    republisher: Option<Republisher>,
 }
 
 impl Server {
@@ -44,10 +92,13 @@ impl Server {
     /// * A DNS server task
     /// * A HTTP server task, if `config.http` is not empty
     /// * A HTTPS server task, if `config.https` is not empty
+    /// * DNS-over-TLS and DNS-over-QUIC listeners, if `config.dns.tls_port` or
+    ///   `config.dns.quic_port` are set
+    /// * Replication tasks, if `config.replication` is not empty
+    /// * A task to republish packets to the mainline DHT, if `config.republish` is not empty
     pub async fn spawn(config: Config, store: ZoneStore) -> Result<Self> {
-        let dns_handler = DnsHandler::new(store.clone(), &config.dns)?;
-
-        let state = AppState { store, dns_handler };
+        let mainline_bootstrap = config.mainline_bootstrap();
+        let state = AppState::new(store, &config.dns)?;
 
         let metrics_addr = config.metrics_addr();
         let metrics_task = tokio::task::spawn(async move {
@@ -60,20 +111,44 @@ impl Server {
             config.http,
             config.https,
             config.pkarr_put_rate_limit,
+            config.pkarr_subscribe,
+            config.admin,
+            config
+                .replication
+                .as_ref()
+                .map(|config| config.token.clone()),
             state.clone(),
         )
         .await?;
-        let dns_server = DnsServer::spawn(config.dns, state.dns_handler.clone()).await?;
+        let dns_server = DnsServer::spawn(
+            config.dns,
+            state.dns_handler.clone(),
+            http_server.dns_tls_config(),
+        )
+        .await?;
+        let replication = config
+            .replication
+            .map(|config| Replication::spawn(config, state.store.clone()))
+            .transpose()?;
+        let republisher = config
+            .republish
+            .map(|config| Republisher::spawn(config, state.store.clone(), mainline_bootstrap))
+            .transpose()?;
         Ok(Self {
             http_server,
             dns_server,
+            dns_handler: state.dns_handler,
             metrics_task,
+            replication,
+            republisher,
         })
     }
 
     /// Cancel the server tasks and wait for all tasks to complete.
     pub async fn shutdown(self) -> Result<()> {
         self.metrics_task.abort();
+        drop(self.replication);
+        drop(self.republisher);
         let (res1, res2) = tokio::join!(self.dns_server.shutdown(), self.http_server.shutdown(),);
         res1?;
         res2?;
@@ -100,15 +175,16 @@ impl Server {
     /// HTTP server.
     #[cfg(test)]
     pub async fn spawn_for_tests() -> Result<(Self, std::net::SocketAddr, url::Url)> {
-        Self::spawn_for_tests_with_options(None, None).await
+        Self::spawn_for_tests_with_options(None, None, None).await
     }
 
     /// Spawn a server suitable for testing, while optionally enabling mainline with custom
-    /// bootstrap addresses.
+    /// bootstrap addresses and replication to peers.
     #[cfg(test)]
     pub async fn spawn_for_tests_with_options(
         mainline: Option<crate::config::BootstrapOption>,
         options: Option<crate::store::ZoneStoreOptions>,
+        replication: Option<crate::replication::ReplicationConfig>,
     ) -> Result<(Self, std::net::SocketAddr, url::Url)> {
         use std::net::{IpAddr, Ipv4Addr};
 
@@ -121,6 +197,7 @@ impl Server {
         config.http.as_mut().unwrap().bind_addr = Some(IpAddr::V4(Ipv4Addr::LOCALHOST));
         config.https = None;
         config.metrics = Some(MetricsConfig::disabled());
+        config.replication = replication;
 
         let mut store = ZoneStore::in_memory(options.unwrap_or_default())?;
         if let Some(bootstrap) = mainline {
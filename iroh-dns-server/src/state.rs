@@ -1,6 +1,11 @@
 //! Shared state and store for the iroh-dns-server
 
-use crate::{dns::DnsHandler, store::ZoneStore};
+use anyhow::Result;
+
+use crate::{
+    dns::{DnsConfig, DnsHandler},
+    store::ZoneStore,
+};
 
 /// The shared app state.
 #[derive(Clone)]
@@ -10,3 +15,14 @@ pub struct AppState {
     /// Handler for DNS requests
     pub dns_handler: DnsHandler,
 }
+
+impl AppState {
+    /// Create the state with a [`DnsHandler`] serving the `store` under the origins of `config`.
+    ///
+    /// No DNS server is started, so the state can be used to serve the routes of
+    /// [`crate::http::router`] from another application.
+    pub fn new(store: ZoneStore, config: &DnsConfig) -> Result<Self> {
+        let dns_handler = DnsHandler::new(store.clone(), config)?;
+        Ok(Self { store, dns_handler })
+    }
+}
@@ -1,59 +1,142 @@
 //! Pkarr packet store used to resolve DNS queries.
 
-use std::{collections::BTreeMap, num::NonZeroUsize, path::Path, sync::Arc, time::Duration};
+use std::{
+    collections::BTreeMap,
+    num::NonZeroUsize,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use anyhow::Result;
-use hickory_server::proto::rr::{Name, RecordSet, RecordType, RrKey};
+use anyhow::{ensure, Result};
+use bytes::Bytes;
+use hickory_server::proto::rr::{LowerName, Name, RecordSet, RecordType, RrKey};
 use iroh_metrics::inc;
 use lru::LruCache;
+use n0_future::{
+    task::{self, AbortOnDropHandle},
+    Stream,
+};
 use pkarr::{mainline::dht::DhtSettings, PkarrClient, SignedPacket};
-use tokio::sync::Mutex;
+use tokio::{
+    io::AsyncRead,
+    sync::{broadcast, Mutex},
+};
 use tracing::{debug, trace};
 use ttl_cache::TtlCache;
 
-use self::signed_packets::SignedPacketStore;
+#[cfg(feature = "sqlite")]
+pub use self::sqlite::SqliteStorage;
+pub use self::{
+    archive::ImportStats,
+    memory::MemoryStorage,
+    signed_packets::SignedPacketStore,
+    storage::{PacketStorage, UpsertOutcome},
+};
 use crate::{
     config::BootstrapOption,
     metrics::Metrics,
     util::{signed_packet_to_hickory_records_without_origin, PublicKeyBytes},
 };
 
+mod archive;
+mod memory;
 mod signed_packets;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod storage;
 pub use signed_packets::Options as ZoneStoreOptions;
 
 /// Cache up to 1 million pkarr zones by default
 pub const DEFAULT_CACHE_CAPACITY: usize = 1024 * 1024;
 /// Default TTL for DHT cache entries
 pub const DHT_CACHE_TTL: Duration = Duration::from_secs(300);
+/// Default TTL for cache entries of stores which can be shared by several servers
+pub const SHARED_CACHE_TTL: Duration = Duration::from_secs(5);
+/// Number of accepted packet updates buffered for each subscriber
+pub const UPDATES_CAPACITY: usize = 1024;
+/// How far in the future the timestamp of an inserted packet may be.
+///
+/// Packets are expired and evicted by their timestamp, which is chosen by the publisher, so
+/// packets from the far future would never be evicted.
+pub const MAX_TIMESTAMP_DRIFT: Duration = Duration::from_secs(300);
 
 /// Where a new pkarr packet comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PacketSource {
     /// Received via HTTPS relay PUT
     PkarrPublish,
+    /// Received from a peer server via replication
+    Replication,
+    /// Imported from an archive with [`ZoneStore::import`]
+    Import,
 }
 
 /// A store for pkarr signed packets.
 ///
-/// Packets are stored in the persistent `SignedPacketStore`, and cached on-demand in an in-memory LRU
-/// cache used for resolving DNS queries.
+/// Packets are stored in a [`PacketStorage`], and cached on-demand in an in-memory LRU cache used
+/// for resolving DNS queries.  Expired packets are evicted from the storage by a background task,
+/// so a store can only be created from within a tokio runtime.
 #[derive(Debug, Clone)]
 pub struct ZoneStore {
     cache: Arc<Mutex<ZoneCache>>,
-    store: Arc<SignedPacketStore>,
+    /// The storage backend.
+    ///
+    /// This is a trait object rather than a type parameter, because the backend is selected by
+    /// the config at runtime, and the store is shared by the DNS and HTTP servers which would
+    /// otherwise all have to be generic over it.
+    store: Arc<dyn PacketStorage>,
     pkarr: Option<Arc<PkarrClient>>,
+    updates: broadcast::Sender<(SignedPacket, PacketSource)>,
+    _evict_task: Arc<AbortOnDropHandle<()>>,
 }
 
 impl ZoneStore {
-    /// Create a persistent store
+    /// Create a persistent store backed by a redb database.
     pub fn persistent(path: impl AsRef<Path>, options: ZoneStoreOptions) -> Result<Self> {
         let packet_store = SignedPacketStore::persistent(path, options)?;
-        Ok(Self::new(packet_store))
+        Ok(Self::new(packet_store, options))
     }
 
     /// Create an in-memory store.
+    ///
+    /// Fails if [`ZoneStoreOptions::max_packets`] or [`ZoneStoreOptions::max_size`] is set.
     pub fn in_memory(options: ZoneStoreOptions) -> Result<Self> {
-        let packet_store = SignedPacketStore::in_memory(options)?;
-        Ok(Self::new(packet_store))
+        ensure!(
+            !options.is_bounded(),
+            "max_packets and max_size are not supported by the in-memory store"
+        );
+        Ok(Self::new(MemoryStorage::new(), options))
+    }
+
+    /// Create a persistent store backed by a SQLite database.
+    ///
+    /// As the database can be shared by several servers, cached zones expire after
+    /// [`SHARED_CACHE_TTL`] to pick up changes made by the other servers.
+    ///
+    /// Fails if [`ZoneStoreOptions::max_packets`] or [`ZoneStoreOptions::max_size`] is set.
+    #[cfg(feature = "sqlite")]
+    pub fn sqlite(path: impl AsRef<Path>, options: ZoneStoreOptions) -> Result<Self> {
+        ensure!(
+            !options.is_bounded(),
+            "max_packets and max_size are not supported by the sqlite store"
+        );
+        let packet_store = SqliteStorage::persistent(path)?;
+        Ok(Self::new(packet_store, options).with_cache_ttl(SHARED_CACHE_TTL))
+    }
+
+    /// Expire zones cached from the packet storage after `ttl`.
+    ///
+    /// By default cached zones are only invalidated by changes made through this store.  If the
+    /// packet storage is shared with other servers, a TTL bounds how long changes made by the
+    /// other servers may go unnoticed.
+    pub fn with_cache_ttl(self, ttl: Duration) -> Self {
+        Self {
+            cache: Arc::new(Mutex::new(
+                ZoneCache::new(DEFAULT_CACHE_CAPACITY).with_ttl(ttl),
+            )),
+            ..self
+        }
     }
 
     /// Configure a pkarr client for resolution of packets from the bittorrent mainline DHT.
@@ -63,32 +146,56 @@ impl ZoneStore {
     /// Optionally set custom bootstrap nodes. If `bootstrap` is empty it will use the default
     /// mainline bootstrap nodes.
     pub fn with_mainline_fallback(self, bootstrap: BootstrapOption) -> Self {
-        let pkarr_client = match bootstrap {
-            BootstrapOption::Default => PkarrClient::builder().build().unwrap(),
-            BootstrapOption::Custom(bootstrap) => PkarrClient::builder()
-                .dht_settings(DhtSettings {
-                    bootstrap: Some(bootstrap),
-                    ..Default::default()
-                })
-                .build()
-                .unwrap(),
-        };
+        let pkarr_client = mainline_client(bootstrap).unwrap();
         Self {
             pkarr: Some(Arc::new(pkarr_client)),
             ..self
         }
     }
 
-    /// Create a new zone store.
-    pub fn new(store: SignedPacketStore) -> Self {
+    /// Create a new zone store on top of a packet storage.
+    ///
+    /// This spawns a task on the current tokio runtime to evict expired packets according to
+    /// [`ZoneStoreOptions::eviction`], which is stopped when the last clone of the store is
+    /// dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a tokio runtime.
+    pub fn new(store: impl PacketStorage, options: ZoneStoreOptions) -> Self {
+        let store: Arc<dyn PacketStorage> = Arc::new(store);
         let zone_cache = ZoneCache::new(DEFAULT_CACHE_CAPACITY);
+        let evict_task = task::spawn(storage::evict_task(store.clone(), options));
         Self {
-            store: Arc::new(store),
+            store,
             cache: Arc::new(Mutex::new(zone_cache)),
             pkarr: None,
+            updates: broadcast::channel(UPDATES_CAPACITY).0,
+            _evict_task: Arc::new(AbortOnDropHandle::new(evict_task)),
         }
     }
 
+    /// Subscribe to the signed packets accepted by [`Self::insert`].
+    ///
+    /// Each packet which updated the store is sent to all subscribers, together with its
+    /// source.  A subscriber which falls behind by more than `UPDATES_CAPACITY` packets
+    /// receives a [`broadcast::error::RecvError::Lagged`] error.
+    pub fn subscribe(&self) -> broadcast::Receiver<(SignedPacket, PacketSource)> {
+        self.updates.subscribe()
+    }
+
+    /// Get at most `limit` signed packets ordered by timestamp and pubkey, starting after
+    /// `after`.
+    ///
+    /// See [`PacketStorage::packets_after`].
+    pub async fn packets_after(
+        &self,
+        after: (u64, PublicKeyBytes),
+        limit: usize,
+    ) -> Result<Vec<SignedPacket>> {
+        self.store.packets_after(after, limit).await
+    }
+
     /// Resolve a DNS query.
     #[allow(clippy::unused_async)]
     pub async fn resolve(
@@ -98,16 +205,36 @@ impl ZoneStore {
         record_type: RecordType,
     ) -> Result<Option<Arc<RecordSet>>> {
         tracing::info!("{} {}", name, record_type);
-        if let Some(rset) = self.cache.lock().await.resolve(pubkey, name, record_type) {
-            return Ok(Some(rset));
+        let res = self
+            .with_zone(pubkey, |zone| zone.resolve(name, record_type))
+            .await?;
+        Ok(res.flatten())
+    }
+
+    /// Get the record types which exist at `name` in the zone of `pubkey`.
+    ///
+    /// Returns `None` if no signed packet is found for `pubkey`.
+    pub async fn record_types(
+        &self,
+        pubkey: &PublicKeyBytes,
+        name: &Name,
+    ) -> Result<Option<Vec<RecordType>>> {
+        self.with_zone(pubkey, |zone| zone.record_types(name)).await
+    }
+
+    /// Run `f` on the zone of `pubkey`, loading it from the store or the DHT if needed.
+    async fn with_zone<T>(
+        &self,
+        pubkey: &PublicKeyBytes,
+        f: impl FnOnce(&CachedZone) -> T,
+    ) -> Result<Option<T>> {
+        if let Some(zone) = self.cache.lock().await.get(pubkey) {
+            return Ok(Some(f(zone)));
         }
 
         if let Some(packet) = self.store.get(pubkey).await? {
-            return self
-                .cache
-                .lock()
-                .await
-                .insert_and_resolve(&packet, name, record_type);
+            let mut cache = self.cache.lock().await;
+            return Ok(cache.insert_and_get(&packet)?.map(f));
         };
 
         if let Some(pkarr) = self.pkarr.as_ref() {
@@ -119,11 +246,10 @@ impl ZoneStore {
             let packet_opt = pkarr.as_ref().clone().as_async().resolve(&key).await?;
             if let Some(packet) = packet_opt {
                 debug!("DHT resolve successful {:?}", packet.packet());
-                return self
-                    .cache
-                    .lock()
-                    .await
-                    .insert_and_resolve_dht(&packet, name, record_type);
+                let zone = CachedZone::from_signed_packet(&packet)?;
+                let res = f(&zone);
+                self.cache.lock().await.insert_dht(&packet, zone);
+                return Ok(Some(res));
             } else {
                 debug!("DHT resolve failed");
             }
@@ -141,26 +267,128 @@ impl ZoneStore {
     /// Insert a signed packet into the cache and the store.
     ///
     /// Returns whether this produced an update, i.e. whether the packet is the newest for its
-    /// pubkey.
+    /// pubkey.  Packets with a timestamp more than [`MAX_TIMESTAMP_DRIFT`] in the future are
+    /// rejected.
     // allow unused async: this will be async soon.
     #[allow(clippy::unused_async)]
-    pub async fn insert(&self, signed_packet: SignedPacket, _source: PacketSource) -> Result<bool> {
+    pub async fn insert(&self, signed_packet: SignedPacket, source: PacketSource) -> Result<bool> {
         let pubkey = PublicKeyBytes::from_signed_packet(&signed_packet);
-        if self.store.upsert(signed_packet).await? {
-            inc!(Metrics, pkarr_publish_update);
-            self.cache.lock().await.remove(&pubkey);
+        if is_too_new(&signed_packet) {
+            debug!("rejected packet for {} with a future timestamp", pubkey);
+            inc!(Metrics, store_packets_future);
+            return Ok(false);
+        }
+        let outcome = self.store.upsert(signed_packet.clone()).await?;
+        if outcome == UpsertOutcome::Blocked {
+            debug!("rejected packet for blocked key {}", pubkey);
+            inc!(Metrics, store_packets_blocked);
+            return Ok(false);
+        }
+        let mut cache = self.cache.lock().await;
+        for key in outcome.evicted() {
+            cache.remove(key);
+        }
+        if outcome.is_stored() {
+            match source {
+                PacketSource::PkarrPublish => inc!(Metrics, pkarr_publish_update),
+                PacketSource::Replication => inc!(Metrics, replication_update),
+                PacketSource::Import => {}
+            }
+            cache.remove(&pubkey);
+            drop(cache);
+            // there being no subscribers is not an error
+            self.updates.send((signed_packet, source)).ok();
             Ok(true)
         } else {
-            inc!(Metrics, pkarr_publish_noop);
+            match source {
+                PacketSource::PkarrPublish => inc!(Metrics, pkarr_publish_noop),
+                PacketSource::Replication => inc!(Metrics, replication_noop),
+                PacketSource::Import => {}
+            }
             Ok(false)
         }
     }
+
+    /// Remove the signed packet for a pubkey from the store and the cache.
+    ///
+    /// Returns whether a packet was removed.
+    pub async fn remove(&self, pubkey: &PublicKeyBytes) -> Result<bool> {
+        let removed = self.store.remove(pubkey).await?;
+        self.cache.lock().await.remove(pubkey);
+        Ok(removed)
+    }
+
+    /// Block a pubkey from being published and remove its signed packet.
+    ///
+    /// Packets for blocked pubkeys are rejected by [`Self::insert`], regardless of their
+    /// source.  Returns whether the pubkey was not blocked before.
+    pub async fn block(&self, pubkey: &PublicKeyBytes) -> Result<bool> {
+        let blocked = self.store.block(pubkey).await?;
+        self.cache.lock().await.remove(pubkey);
+        Ok(blocked)
+    }
+
+    /// Remove a pubkey from the blocklist.
+    ///
+    /// Returns whether the pubkey was blocked.
+    pub async fn unblock(&self, pubkey: &PublicKeyBytes) -> Result<bool> {
+        self.store.unblock(pubkey).await
+    }
+
+    /// Check whether a pubkey is blocked.
+    pub async fn is_blocked(&self, pubkey: &PublicKeyBytes) -> Result<bool> {
+        self.store.is_blocked(pubkey).await
+    }
+
+    /// Get all blocked pubkeys.
+    pub async fn blocked(&self) -> Result<Vec<PublicKeyBytes>> {
+        self.store.blocked().await
+    }
+
+    /// Export the blocklist and all signed packets as a portable archive.
+    ///
+    /// The archive is produced in chunks, reading the packets from the store page by page.  It
+    /// can be imported into any store with [`Self::import`].
+    pub fn export(&self) -> impl Stream<Item = Result<Bytes>> + Send + 'static {
+        archive::export(self.clone())
+    }
+
+    /// Import the blocklist and the signed packets of an archive created with [`Self::export`].
+    ///
+    /// Packets are inserted with [`PacketSource::Import`], so they only replace stored packets
+    /// which are older, and are not replicated to peers.
+    pub async fn import(&self, reader: impl AsyncRead + Unpin) -> Result<ImportStats> {
+        archive::import(self, reader).await
+    }
+}
+
+/// Create a pkarr client for the mainline DHT.
+pub(crate) fn mainline_client(bootstrap: BootstrapOption) -> Result<PkarrClient> {
+    let client = match bootstrap {
+        BootstrapOption::Default => PkarrClient::builder().build()?,
+        BootstrapOption::Custom(bootstrap) => PkarrClient::builder()
+            .dht_settings(DhtSettings {
+                bootstrap: Some(bootstrap),
+                ..Default::default()
+            })
+            .build()?,
+    };
+    Ok(client)
+}
+
+/// Returns whether the timestamp of the packet is more than [`MAX_TIMESTAMP_DRIFT`] in the
+/// future.
+pub(crate) fn is_too_new(signed_packet: &SignedPacket) -> bool {
+    let max = pkarr::system_time().saturating_add(MAX_TIMESTAMP_DRIFT.as_micros() as u64);
+    signed_packet.timestamp() > max
 }
 
 #[derive(derive_more::Debug)]
 struct ZoneCache {
     /// Cache for explicitly added entries
     cache: LruCache<PublicKeyBytes, CachedZone>,
+    /// How long explicitly added entries are valid, forever if `None`.
+    ttl: Option<Duration>,
     /// Cache for DHT entries, this must have a finite TTL
     /// so we don't cache stale entries indefinitely.
     #[debug("dht_cache")]
@@ -171,49 +399,48 @@ impl ZoneCache {
     fn new(cap: usize) -> Self {
         let cache = LruCache::new(NonZeroUsize::new(cap).expect("capacity must be larger than 0"));
         let dht_cache = TtlCache::new(cap);
-        Self { cache, dht_cache }
+        Self {
+            cache,
+            ttl: None,
+            dht_cache,
+        }
     }
 
-    fn resolve(
-        &mut self,
-        pubkey: &PublicKeyBytes,
-        name: &Name,
-        record_type: RecordType,
-    ) -> Option<Arc<RecordSet>> {
-        let zone = if let Some(zone) = self.cache.get(pubkey) {
+    fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    fn get(&mut self, pubkey: &PublicKeyBytes) -> Option<&CachedZone> {
+        let expired = self
+            .cache
+            .peek(pubkey)
+            .zip(self.ttl)
+            .is_some_and(|(zone, ttl)| zone.cached_at.elapsed() > ttl);
+        if expired {
+            trace!("cache entry expired {}", pubkey.to_z32());
+            self.cache.pop(pubkey);
+        }
+        if self.cache.contains(pubkey) {
             trace!("cache hit {}", pubkey.to_z32());
-            zone
+            self.cache.get(pubkey)
         } else if let Some(zone) = self.dht_cache.get(pubkey) {
             trace!("dht cache hit {}", pubkey.to_z32());
-            zone
+            Some(zone)
         } else {
-            return None;
-        };
-        zone.resolve(name, record_type)
+            None
+        }
     }
 
-    fn insert_and_resolve(
-        &mut self,
-        signed_packet: &SignedPacket,
-        name: &Name,
-        record_type: RecordType,
-    ) -> Result<Option<Arc<RecordSet>>> {
+    fn insert_and_get(&mut self, signed_packet: &SignedPacket) -> Result<Option<&CachedZone>> {
         let pubkey = PublicKeyBytes::from_signed_packet(signed_packet);
         self.insert(signed_packet)?;
-        Ok(self.resolve(&pubkey, name, record_type))
+        Ok(self.get(&pubkey))
     }
 
-    fn insert_and_resolve_dht(
-        &mut self,
-        signed_packet: &SignedPacket,
-        name: &Name,
-        record_type: RecordType,
-    ) -> Result<Option<Arc<RecordSet>>> {
+    fn insert_dht(&mut self, signed_packet: &SignedPacket, zone: CachedZone) {
         let pubkey = PublicKeyBytes::from_signed_packet(signed_packet);
-        let zone = CachedZone::from_signed_packet(signed_packet)?;
-        let res = zone.resolve(name, record_type);
         self.dht_cache.insert(pubkey, zone, DHT_CACHE_TTL);
-        Ok(res)
     }
 
     fn insert(&mut self, signed_packet: &SignedPacket) -> Result<()> {
@@ -240,6 +467,7 @@ impl ZoneCache {
 #[derive(Debug)]
 struct CachedZone {
     timestamp: u64,
+    cached_at: Instant,
     records: BTreeMap<RrKey, Arc<RecordSet>>,
 }
 
@@ -250,6 +478,7 @@ impl CachedZone {
         Ok(Self {
             records,
             timestamp: signed_packet.timestamp(),
+            cached_at: Instant::now(),
         })
     }
 
@@ -264,4 +493,13 @@ impl CachedZone {
         }
         self.records.get(&key).cloned()
     }
+
+    fn record_types(&self, name: &Name) -> Vec<RecordType> {
+        let name = LowerName::from(name);
+        self.records
+            .keys()
+            .filter(|key| key.name == name)
+            .map(|key| key.record_type)
+            .collect()
+    }
 }
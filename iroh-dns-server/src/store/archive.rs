@@ -0,0 +1,248 @@
+//! Export and import of a [`ZoneStore`] as a portable archive.
+//!
+//! An archive starts with the magic bytes `IRDNSAR` and a version byte, followed by a sequence
+//! of entries.  Each entry starts with a tag byte:
+//!
+//! * [`TAG_BLOCKED`] is followed by the 32 bytes of a blocked public key.
+//! * [`TAG_PACKET`] is followed by the length of a signed packet as big-endian `u16` and the
+//!   [`SignedPacket::as_bytes`] encoding of the packet.
+//!
+//! The blocked keys come first, so that importing an archive never stores a packet for a
+//! blocked key.  As signed packets carry their own signature, an archive can be imported into
+//! any server and storage backend.
+
+use anyhow::{bail, ensure, Context, Result};
+use bytes::{BufMut, Bytes, BytesMut};
+use n0_future::{stream, Stream};
+use pkarr::SignedPacket;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tracing::debug;
+
+use super::{PacketSource, ZoneStore};
+use crate::util::PublicKeyBytes;
+
+const MAGIC: &[u8; 7] = b"IRDNSAR";
+const VERSION: u8 = 1;
+
+/// Tag of an entry with a blocked public key.
+const TAG_BLOCKED: u8 = 1;
+/// Tag of an entry with a signed packet.
+const TAG_PACKET: u8 = 2;
+
+/// Number of packets read from the store for each chunk of the archive.
+const EXPORT_BATCH_SIZE: usize = 1024;
+
+/// The number of entries imported from an archive.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImportStats {
+    /// Number of signed packets in the archive.
+    pub packets: usize,
+    /// Number of signed packets which were stored, because no more recent packet was stored
+    /// for their public key.
+    pub stored: usize,
+    /// Number of blocked public keys in the archive.
+    pub blocked: usize,
+}
+
+pub(super) fn export(store: ZoneStore) -> impl Stream<Item = Result<Bytes>> + Send + 'static {
+    let export = Export {
+        store,
+        cursor: None,
+        done: false,
+    };
+    stream::unfold(export, |mut export| async move {
+        if export.done {
+            return None;
+        }
+        let chunk = export.next_chunk().await;
+        if chunk.is_err() {
+            export.done = true;
+        }
+        Some((chunk, export))
+    })
+}
+
+struct Export {
+    store: ZoneStore,
+    /// The timestamp and public key of the last exported packet, or `None` before the header.
+    cursor: Option<(u64, PublicKeyBytes)>,
+    done: bool,
+}
+
+impl Export {
+    async fn next_chunk(&mut self) -> Result<Bytes> {
+        let mut buf = BytesMut::new();
+        match self.cursor {
+            None => {
+                buf.put_slice(MAGIC);
+                buf.put_u8(VERSION);
+                let blocked = self.store.blocked().await?;
+                debug!("exporting {} blocked keys", blocked.len());
+                for key in blocked {
+                    buf.put_u8(TAG_BLOCKED);
+                    buf.put_slice(key.as_bytes());
+                }
+                self.cursor = Some((0, PublicKeyBytes::new([0; 32])));
+            }
+            Some(after) => {
+                let packets = self.store.packets_after(after, EXPORT_BATCH_SIZE).await?;
+                debug!("exporting {} packets", packets.len());
+                for packet in &packets {
+                    let bytes = packet.as_bytes();
+                    buf.put_u8(TAG_PACKET);
+                    buf.put_u16(bytes.len() as u16);
+                    buf.put_slice(bytes);
+                }
+                match packets.last() {
+                    Some(last) if packets.len() == EXPORT_BATCH_SIZE => {
+                        self.cursor =
+                            Some((last.timestamp(), PublicKeyBytes::from_signed_packet(last)))
+                    }
+                    _ => self.done = true,
+                }
+            }
+        }
+        Ok(buf.freeze())
+    }
+}
+
+pub(super) async fn import(
+    store: &ZoneStore,
+    mut reader: impl AsyncRead + Unpin,
+) -> Result<ImportStats> {
+    let mut header = [0u8; MAGIC.len() + 1];
+    reader
+        .read_exact(&mut header)
+        .await
+        .context("failed to read archive header")?;
+    ensure!(
+        &header[..MAGIC.len()] == MAGIC,
+        "not an iroh-dns-server archive"
+    );
+    let version = header[MAGIC.len()];
+    ensure!(
+        version == VERSION,
+        "unsupported archive version {version}, expected {VERSION}"
+    );
+
+    let mut stats = ImportStats::default();
+    let mut buf = Vec::new();
+    loop {
+        let tag = match reader.read_u8().await {
+            Ok(tag) => tag,
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err.into()),
+        };
+        match tag {
+            TAG_BLOCKED => {
+                let mut key = [0u8; 32];
+                reader
+                    .read_exact(&mut key)
+                    .await
+                    .context("truncated blocked key")?;
+                store.block(&PublicKeyBytes::new(key)).await?;
+                stats.blocked += 1;
+            }
+            TAG_PACKET => {
+                let len = reader.read_u16().await.context("truncated packet length")?;
+                buf.resize(len as usize, 0);
+                reader
+                    .read_exact(&mut buf)
+                    .await
+                    .context("truncated packet")?;
+                let packet = SignedPacket::from_bytes(&Bytes::copy_from_slice(&buf))
+                    .context("invalid signed packet")?;
+                if store.insert(packet, PacketSource::Import).await? {
+                    stats.stored += 1;
+                }
+                stats.packets += 1;
+            }
+            tag => bail!("invalid archive entry tag {tag}"),
+        }
+    }
+    debug!(?stats, "imported archive");
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use iroh::{dns::node_info::NodeInfo, SecretKey};
+    use n0_future::StreamExt;
+    use tracing_test::traced_test;
+
+    use super::ImportStats;
+    use crate::{
+        store::{PacketSource, ZoneStore},
+        util::PublicKeyBytes,
+    };
+
+    async fn export_to_vec(store: &ZoneStore) -> Result<Vec<u8>> {
+        let mut archive = Vec::new();
+        let mut chunks = std::pin::pin!(store.export());
+        while let Some(chunk) = chunks.next().await {
+            archive.extend_from_slice(&chunk?);
+        }
+        Ok(archive)
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn export_import() -> Result<()> {
+        let store = ZoneStore::in_memory(Default::default())?;
+        let mut keys = Vec::new();
+        for _ in 0..3 {
+            let secret_key = SecretKey::generate(rand::thread_rng());
+            let node_info = NodeInfo::new(secret_key.public(), None, Default::default());
+            let packet = node_info.to_pkarr_signed_packet(&secret_key, 30)?;
+            keys.push(PublicKeyBytes::from_signed_packet(&packet));
+            store.insert(packet, PacketSource::PkarrPublish).await?;
+        }
+        let blocked =
+            PublicKeyBytes::new(*SecretKey::generate(rand::thread_rng()).public().as_bytes());
+        store.block(&blocked).await?;
+
+        let archive = export_to_vec(&store).await?;
+
+        let imported = ZoneStore::in_memory(Default::default())?;
+        let stats = imported.import(&archive[..]).await?;
+        assert_eq!(
+            stats,
+            ImportStats {
+                packets: 3,
+                stored: 3,
+                blocked: 1
+            }
+        );
+        assert_eq!(imported.blocked().await?, vec![blocked]);
+        for key in &keys {
+            let packet = store.get_signed_packet(key).await?.unwrap();
+            let imported = imported.get_signed_packet(key).await?.unwrap();
+            assert!(imported.is_same_as(&packet));
+        }
+        // the archive of the imported store is the same
+        assert_eq!(export_to_vec(&imported).await?, archive);
+
+        // packets are not stored if a more recent packet is stored
+        let secret_key = SecretKey::generate(rand::thread_rng());
+        let node_info = NodeInfo::new(secret_key.public(), None, Default::default());
+        let older = node_info.to_pkarr_signed_packet(&secret_key, 30)?;
+        tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+        let newer = node_info.to_pkarr_signed_packet(&secret_key, 30)?;
+        store.insert(older, PacketSource::PkarrPublish).await?;
+        imported.insert(newer, PacketSource::PkarrPublish).await?;
+        let stats = imported.import(&export_to_vec(&store).await?[..]).await?;
+        assert_eq!(stats.packets, 4);
+        assert_eq!(stats.stored, 3);
+
+        // truncated and invalid archives are rejected
+        assert!(imported
+            .import(&archive[..archive.len() - 1])
+            .await
+            .is_err());
+        assert!(imported.import(&b"IRDNSAR\x02"[..]).await.is_err());
+        assert!(imported.import(&b"invalid!"[..]).await.is_err());
+        Ok(())
+    }
+}
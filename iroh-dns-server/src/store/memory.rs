@@ -0,0 +1,170 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    ops::Bound,
+    sync::Mutex,
+};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use iroh_metrics::inc;
+use pkarr::SignedPacket;
+use tracing::trace;
+
+use super::{PacketStorage, UpsertOutcome};
+use crate::{metrics::Metrics, util::PublicKeyBytes};
+
+/// A [`PacketStorage`] which keeps all packets in memory.
+///
+/// All packets are lost when the storage is dropped.
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    tables: Mutex<Tables>,
+}
+
+#[derive(Debug, Default)]
+struct Tables {
+    signed_packets: HashMap<PublicKeyBytes, SignedPacket>,
+    /// Index of the packets by their timestamp.
+    update_time: BTreeSet<(u64, PublicKeyBytes)>,
+    blocked: BTreeSet<PublicKeyBytes>,
+}
+
+impl Tables {
+    fn remove(&mut self, key: &PublicKeyBytes) -> Option<SignedPacket> {
+        let packet = self.signed_packets.remove(key)?;
+        self.update_time.remove(&(packet.timestamp(), *key));
+        Some(packet)
+    }
+}
+
+impl MemoryStorage {
+    /// Create a new, empty in-memory storage.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl PacketStorage for MemoryStorage {
+    async fn get(&self, key: &PublicKeyBytes) -> Result<Option<SignedPacket>> {
+        trace!("get {}", key);
+        let tables = self.tables.lock().expect("poisoned");
+        Ok(tables.signed_packets.get(key).cloned())
+    }
+
+    async fn upsert(&self, packet: SignedPacket) -> Result<UpsertOutcome> {
+        let key = PublicKeyBytes::from_signed_packet(&packet);
+        trace!("upsert {}", key);
+        let mut tables = self.tables.lock().expect("poisoned");
+        if tables.blocked.contains(&key) {
+            return Ok(UpsertOutcome::Blocked);
+        }
+        let replaced = match tables.signed_packets.get(&key) {
+            Some(existing) if existing.more_recent_than(&packet) => {
+                return Ok(UpsertOutcome::Outdated)
+            }
+            Some(_) => tables.remove(&key).is_some(),
+            None => false,
+        };
+        tables.update_time.insert((packet.timestamp(), key));
+        tables.signed_packets.insert(key, packet);
+        if replaced {
+            inc!(Metrics, store_packets_updated);
+        } else {
+            inc!(Metrics, store_packets_inserted);
+        }
+        Ok(UpsertOutcome::Stored { evicted: vec![] })
+    }
+
+    async fn remove(&self, key: &PublicKeyBytes) -> Result<bool> {
+        trace!("remove {}", key);
+        let mut tables = self.tables.lock().expect("poisoned");
+        let removed = tables.remove(key).is_some();
+        if removed {
+            inc!(Metrics, store_packets_removed);
+        }
+        Ok(removed)
+    }
+
+    async fn expired(&self, before: u64, limit: usize) -> Result<Vec<PublicKeyBytes>> {
+        trace!("expired before {}", before);
+        let tables = self.tables.lock().expect("poisoned");
+        let keys = tables
+            .update_time
+            .iter()
+            .take_while(|(time, _)| *time < before)
+            .take(limit)
+            .map(|(_, key)| *key)
+            .collect();
+        Ok(keys)
+    }
+
+    async fn remove_expired(&self, key: &PublicKeyBytes, before: u64) -> Result<bool> {
+        trace!("remove expired {} before {}", key, before);
+        let mut tables = self.tables.lock().expect("poisoned");
+        match tables.signed_packets.get(key) {
+            Some(packet) if packet.timestamp() < before => {
+                tables.remove(key);
+                inc!(Metrics, store_packets_expired);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn packets_after(
+        &self,
+        after: (u64, PublicKeyBytes),
+        limit: usize,
+    ) -> Result<Vec<SignedPacket>> {
+        trace!("packets after {} {}", after.0, after.1);
+        let tables = self.tables.lock().expect("poisoned");
+        let packets = tables
+            .update_time
+            .range((Bound::Excluded(after), Bound::Unbounded))
+            .take(limit)
+            .filter_map(|(_, key)| tables.signed_packets.get(key).cloned())
+            .collect();
+        Ok(packets)
+    }
+
+    async fn block(&self, key: &PublicKeyBytes) -> Result<bool> {
+        trace!("block {}", key);
+        let mut tables = self.tables.lock().expect("poisoned");
+        if tables.remove(key).is_some() {
+            inc!(Metrics, store_packets_removed);
+        }
+        Ok(tables.blocked.insert(*key))
+    }
+
+    async fn unblock(&self, key: &PublicKeyBytes) -> Result<bool> {
+        trace!("unblock {}", key);
+        let mut tables = self.tables.lock().expect("poisoned");
+        Ok(tables.blocked.remove(key))
+    }
+
+    async fn is_blocked(&self, key: &PublicKeyBytes) -> Result<bool> {
+        let tables = self.tables.lock().expect("poisoned");
+        Ok(tables.blocked.contains(key))
+    }
+
+    async fn blocked(&self) -> Result<Vec<PublicKeyBytes>> {
+        let tables = self.tables.lock().expect("poisoned");
+        Ok(tables.blocked.iter().copied().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use tracing_test::traced_test;
+
+    use super::MemoryStorage;
+    use crate::store::storage::conformance;
+
+    #[tokio::test]
+    #[traced_test]
+    async fn memory_conformance() -> Result<()> {
+        conformance::run(MemoryStorage::new()).await
+    }
+}
@@ -1,16 +1,19 @@
 use std::{future::Future, path::Path, result, time::Duration};
 
 use anyhow::{Context, Result};
+use async_trait::async_trait;
 use bytes::Bytes;
 use iroh_metrics::inc;
-use pkarr::{system_time, SignedPacket};
+use pkarr::SignedPacket;
 use redb::{
-    backends::InMemoryBackend, Database, MultimapTableDefinition, ReadableTable, TableDefinition,
+    backends::InMemoryBackend, Database, MultimapTableDefinition, ReadableMultimapTable,
+    ReadableTable, ReadableTableMetadata, TableDefinition,
 };
 use tokio::sync::{mpsc, oneshot};
 use tokio_util::sync::CancellationToken;
-use tracing::{debug, error, info, trace};
+use tracing::{debug, info, trace};
 
+use super::{PacketStorage, UpsertOutcome};
 use crate::{metrics::Metrics, util::PublicKeyBytes};
 
 pub type SignedPacketsKey = [u8; 32];
@@ -18,20 +21,26 @@ const SIGNED_PACKETS_TABLE: TableDefinition<&SignedPacketsKey, &[u8]> =
     TableDefinition::new("signed-packets-1");
 const UPDATE_TIME_TABLE: MultimapTableDefinition<[u8; 8], SignedPacketsKey> =
     MultimapTableDefinition::new("update-time-1");
+const BLOCKED_KEYS_TABLE: TableDefinition<&SignedPacketsKey, ()> =
+    TableDefinition::new("blocked-keys-1");
 
+/// The default [`PacketStorage`], backed by a [redb] database.
+///
+/// Writes are batched into transactions according to [`Options::max_batch_size`] and
+/// [`Options::max_batch_time`].  The number and total size of the stored packets can be bounded
+/// with [`Options::max_packets`] and [`Options::max_size`].
 #[derive(Debug)]
 pub struct SignedPacketStore {
     send: mpsc::Sender<Message>,
     cancel: CancellationToken,
     _write_thread: IoThread,
-    _evict_thread: IoThread,
 }
 
 impl Drop for SignedPacketStore {
     fn drop(&mut self) {
         // cancel the actor
         self.cancel.cancel();
-        // after cancellation, the two threads will be joined
+        // after cancellation, the thread will be joined
     }
 }
 
@@ -39,7 +48,7 @@ impl Drop for SignedPacketStore {
 enum Message {
     Upsert {
         packet: SignedPacket,
-        res: oneshot::Sender<bool>,
+        res: oneshot::Sender<UpsertOutcome>,
     },
     Get {
         key: PublicKeyBytes,
@@ -49,13 +58,36 @@ enum Message {
         key: PublicKeyBytes,
         res: oneshot::Sender<bool>,
     },
-    Snapshot {
+    Expired {
+        before: u64,
+        limit: usize,
+        res: oneshot::Sender<Vec<PublicKeyBytes>>,
+    },
+    RemoveExpired {
+        key: PublicKeyBytes,
+        before: u64,
+        res: oneshot::Sender<bool>,
+    },
+    PacketsAfter {
+        after: (u64, PublicKeyBytes),
+        limit: usize,
         #[debug(skip)]
-        res: oneshot::Sender<Snapshot>,
+        res: oneshot::Sender<Vec<SignedPacket>>,
     },
-    CheckExpired {
-        time: [u8; 8],
+    Block {
         key: PublicKeyBytes,
+        res: oneshot::Sender<bool>,
+    },
+    Unblock {
+        key: PublicKeyBytes,
+        res: oneshot::Sender<bool>,
+    },
+    IsBlocked {
+        key: PublicKeyBytes,
+        res: oneshot::Sender<bool>,
+    },
+    Blocked {
+        res: oneshot::Sender<Vec<PublicKeyBytes>>,
     },
 }
 
@@ -64,8 +96,13 @@ struct Actor {
     recv: PeekableReceiver<Message>,
     cancel: CancellationToken,
     options: Options,
+    /// Total size of the stored packets, only counted if [`Options::max_size`] is set.
+    stored_bytes: u64,
 }
 
+/// Options for the [`ZoneStore`] and its [`SignedPacketStore`].
+///
+/// [`ZoneStore`]: super::ZoneStore
 #[derive(Debug, Clone, Copy)]
 pub struct Options {
     /// Maximum number of packets to process in a single write transaction.
@@ -76,6 +113,18 @@ pub struct Options {
     pub eviction: Duration,
     /// Pause between eviction checks.
     pub eviction_interval: Duration,
+    /// Maximum number of packets to keep in the store.
+    ///
+    /// When the store is full, the packets with the oldest timestamps are evicted to make room
+    /// for new packets.  This is only supported by the [`SignedPacketStore`], the other
+    /// stores fail to open if it is set.
+    pub max_packets: Option<u64>,
+    /// Maximum total size of the stored signed packets, in bytes.
+    ///
+    /// When the size is exceeded, the packets with the oldest timestamps are evicted.  The
+    /// database file is larger than this because of the index and free pages.  This is only
+    /// supported by the [`SignedPacketStore`], the other stores fail to open if it is set.
+    pub max_size: Option<u64>,
 }
 
 impl Default for Options {
@@ -89,10 +138,19 @@ impl Default for Options {
             eviction: Duration::from_secs(3600 * 24 * 7),
             // eviction can run frequently since it does not do a full scan
             eviction_interval: Duration::from_secs(10),
+            max_packets: None,
+            max_size: None,
         }
     }
 }
 
+impl Options {
+    /// Returns whether [`Self::max_packets`] or [`Self::max_size`] is set.
+    pub fn is_bounded(&self) -> bool {
+        self.max_packets.is_some() || self.max_size.is_some()
+    }
+}
+
 impl Actor {
     async fn run(mut self) {
         match self.run0().await {
@@ -105,22 +163,12 @@ impl Actor {
     }
 
     async fn run0(&mut self) -> anyhow::Result<()> {
-        let expiry_us = self.options.eviction.as_micros() as u64;
         while let Some(msg) = self.recv.recv().await {
-            // if we get a snapshot message here we don't need to do a write transaction
-            let msg = if let Message::Snapshot { res } = msg {
-                let snapshot = Snapshot::new(&self.db)?;
-                res.send(snapshot).ok();
-                continue;
-            } else {
-                msg
-            };
             trace!("batch");
             self.recv.push_back(msg).unwrap();
             let transaction = self.db.begin_write()?;
             let mut tables = Tables::new(&transaction)?;
             let timeout = tokio::time::sleep(self.options.max_batch_time);
-            let expired = system_time() - expiry_us;
             tokio::pin!(timeout);
             for _ in 0..self.options.max_batch_size {
                 tokio::select! {
@@ -140,13 +188,18 @@ impl Actor {
                             Message::Upsert { packet, res } => {
                                 let key = PublicKeyBytes::from_signed_packet(&packet);
                                 trace!("upsert {}", key);
+                                if tables.blocked_keys.get(key.as_bytes())?.is_some() {
+                                    res.send(UpsertOutcome::Blocked).ok();
+                                    continue;
+                                }
                                 let replaced = if let Some(existing) = get_packet(&tables.signed_packets, &key)? {
                                     if existing.more_recent_than(&packet) {
-                                        res.send(false).ok();
+                                        res.send(UpsertOutcome::Outdated).ok();
                                         continue;
                                     } else {
                                         // remove the packet from the update time index
-                                        tables.update_time.remove(&packet.timestamp().to_be_bytes(), key.as_bytes())?;
+                                        tables.update_time.remove(&existing.timestamp().to_be_bytes(), key.as_bytes())?;
+                                        self.stored_bytes = self.stored_bytes.saturating_sub(existing.as_bytes().len() as u64);
                                         true
                                     }
                                 } else {
@@ -155,41 +208,99 @@ impl Actor {
                                 let value = packet.as_bytes();
                                 tables.signed_packets.insert(key.as_bytes(), &value[..])?;
                                 tables.update_time.insert(&packet.timestamp().to_be_bytes(), key.as_bytes())?;
+                                self.stored_bytes += value.len() as u64;
                                 if replaced {
                                     inc!(Metrics, store_packets_updated);
                                 } else {
                                     inc!(Metrics, store_packets_inserted);
                                 }
-                                res.send(true).ok();
+                                let evicted = self.evict_oldest(&mut tables)?;
+                                let outcome = if evicted.contains(&key) {
+                                    UpsertOutcome::Evicted { evicted }
+                                } else {
+                                    UpsertOutcome::Stored { evicted }
+                                };
+                                res.send(outcome).ok();
                             }
                             Message::Remove { key, res } => {
                                 trace!("remove {}", key);
-                                let updated = if let Some(row) = tables.signed_packets.remove(key.as_bytes())? {
-                                    let packet = SignedPacket::from_bytes(&Bytes::copy_from_slice(row.value()))?;
-                                    tables.update_time.remove(&packet.timestamp().to_be_bytes(), key.as_bytes())?;
-                                    inc!(Metrics, store_packets_removed);
-                                    true
-                                } else {
-                                    false
-                                };
+                                let updated = self.remove_packet(&mut tables, &key)?.is_some();
                                 if updated {
                                     inc!(Metrics, store_packets_removed);
                                 }
                                 res.send(updated).ok();
                             }
-                            Message::Snapshot { res } => {
-                                trace!("snapshot");
-                                res.send(Snapshot::new(&self.db)?).ok();
+                            Message::Expired { before, limit, res } => {
+                                trace!("expired before {}", before);
+                                let mut keys = Vec::new();
+                                'outer: for item in tables.update_time.range(..before.to_be_bytes())? {
+                                    let (_time, values) = item?;
+                                    for value in values {
+                                        if keys.len() >= limit {
+                                            break 'outer;
+                                        }
+                                        keys.push(PublicKeyBytes::new(value?.value()));
+                                    }
+                                }
+                                res.send(keys).ok();
                             }
-                            Message::CheckExpired { key, time } => {
-                                trace!("check expired {} at {}", key, u64::from_be_bytes(time));
-                                if let Some(packet) = get_packet(&tables.signed_packets, &key)? {
-                                    if packet.timestamp() < expired {
-                                        tables.update_time.remove(&time, key.as_bytes())?;
-                                        let _ = tables.signed_packets.remove(key.as_bytes())?;
+                            Message::RemoveExpired { key, before, res } => {
+                                trace!("remove expired {} before {}", key, before);
+                                let removed = match get_packet(&tables.signed_packets, &key)? {
+                                    Some(packet) if packet.timestamp() < before => {
+                                        self.remove_packet(&mut tables, &key)?;
                                         inc!(Metrics, store_packets_expired);
+                                        true
                                     }
+                                    _ => false,
+                                };
+                                res.send(removed).ok();
+                            }
+                            Message::PacketsAfter { after, limit, res } => {
+                                trace!("packets after {} {}", after.0, after.1);
+                                let mut packets = Vec::new();
+                                'outer: for item in tables.update_time.range(after.0.to_be_bytes()..)? {
+                                    let (time, values) = item?;
+                                    let time = u64::from_be_bytes(time.value());
+                                    for value in values {
+                                        if packets.len() >= limit {
+                                            break 'outer;
+                                        }
+                                        let key = PublicKeyBytes::new(value?.value());
+                                        if (time, key) <= after {
+                                            continue;
+                                        }
+                                        if let Some(packet) = get_packet(&tables.signed_packets, &key)? {
+                                            packets.push(packet);
+                                        }
+                                    }
+                                }
+                                res.send(packets).ok();
+                            }
+                            Message::Block { key, res } => {
+                                trace!("block {}", key);
+                                if self.remove_packet(&mut tables, &key)?.is_some() {
+                                    inc!(Metrics, store_packets_removed);
                                 }
+                                let inserted = tables.blocked_keys.insert(key.as_bytes(), ())?.is_none();
+                                res.send(inserted).ok();
+                            }
+                            Message::Unblock { key, res } => {
+                                trace!("unblock {}", key);
+                                let removed = tables.blocked_keys.remove(key.as_bytes())?.is_some();
+                                res.send(removed).ok();
+                            }
+                            Message::IsBlocked { key, res } => {
+                                let blocked = tables.blocked_keys.get(key.as_bytes())?.is_some();
+                                res.send(blocked).ok();
+                            }
+                            Message::Blocked { res } => {
+                                let keys = tables
+                                    .blocked_keys
+                                    .iter()?
+                                    .map(|item| Ok(PublicKeyBytes::new(*item?.0.value())))
+                                    .collect::<Result<_>>()?;
+                                res.send(keys).ok();
                             }
                         }
                     }
@@ -202,11 +313,77 @@ impl Actor {
     }
 }
 
+impl Actor {
+    /// Remove the packet for `key` from the signed packets table and the update time index.
+    fn remove_packet(
+        &mut self,
+        tables: &mut Tables,
+        key: &PublicKeyBytes,
+    ) -> Result<Option<SignedPacket>> {
+        let Some(row) = tables.signed_packets.remove(key.as_bytes())? else {
+            return Ok(None);
+        };
+        let packet = SignedPacket::from_bytes(&Bytes::copy_from_slice(row.value()))?;
+        drop(row);
+        tables
+            .update_time
+            .remove(&packet.timestamp().to_be_bytes(), key.as_bytes())?;
+        self.stored_bytes = self
+            .stored_bytes
+            .saturating_sub(packet.as_bytes().len() as u64);
+        Ok(Some(packet))
+    }
+
+    /// Evict the packets with the oldest timestamps while the store exceeds
+    /// [`Options::max_packets`] or [`Options::max_size`].
+    ///
+    /// Returns the keys of the evicted packets.
+    fn evict_oldest(&mut self, tables: &mut Tables) -> Result<Vec<PublicKeyBytes>> {
+        let mut evicted = Vec::new();
+        loop {
+            let too_many = match self.options.max_packets {
+                Some(max) => tables.signed_packets.len()? > max,
+                None => false,
+            };
+            let too_large = match self.options.max_size {
+                Some(max) => self.stored_bytes > max,
+                None => false,
+            };
+            if !too_many && !too_large {
+                return Ok(evicted);
+            }
+            let Some(key) = oldest_key(&tables.update_time)? else {
+                return Ok(evicted);
+            };
+            if self.remove_packet(tables, &key)?.is_some() {
+                debug!("evicted packet {} because the store is full", key);
+                inc!(Metrics, store_packets_evicted);
+                evicted.push(key);
+            }
+        }
+    }
+}
+
+/// Returns the key of the packet with the oldest timestamp.
+fn oldest_key(
+    update_time: &impl ReadableMultimapTable<[u8; 8], SignedPacketsKey>,
+) -> Result<Option<PublicKeyBytes>> {
+    let Some(item) = update_time.iter()?.next() else {
+        return Ok(None);
+    };
+    let (_time, mut values) = item?;
+    match values.next() {
+        Some(value) => Ok(Some(PublicKeyBytes::new(value?.value()))),
+        None => Ok(None),
+    }
+}
+
 /// A struct similar to [`redb::Table`] but for all tables that make up the
 /// signed packet store.
 pub(super) struct Tables<'a> {
     pub signed_packets: redb::Table<'a, &'static SignedPacketsKey, &'static [u8]>,
     pub update_time: redb::MultimapTable<'a, [u8; 8], SignedPacketsKey>,
+    pub blocked_keys: redb::Table<'a, &'static SignedPacketsKey, ()>,
 }
 
 impl<'txn> Tables<'txn> {
@@ -214,27 +391,13 @@ impl<'txn> Tables<'txn> {
         Ok(Self {
             signed_packets: tx.open_table(SIGNED_PACKETS_TABLE)?,
             update_time: tx.open_multimap_table(UPDATE_TIME_TABLE)?,
-        })
-    }
-}
-
-pub(super) struct Snapshot {
-    #[allow(dead_code)]
-    pub signed_packets: redb::ReadOnlyTable<&'static SignedPacketsKey, &'static [u8]>,
-    pub update_time: redb::ReadOnlyMultimapTable<[u8; 8], SignedPacketsKey>,
-}
-
-impl Snapshot {
-    pub fn new(db: &Database) -> Result<Self> {
-        let tx = db.begin_read()?;
-        Ok(Self {
-            signed_packets: tx.open_table(SIGNED_PACKETS_TABLE)?,
-            update_time: tx.open_multimap_table(UPDATE_TIME_TABLE)?,
+            blocked_keys: tx.open_table(BLOCKED_KEYS_TABLE)?,
         })
     }
 }
 
 impl SignedPacketStore {
+    /// Open or create a redb database at `path`.
     pub fn persistent(path: impl AsRef<Path>, options: Options) -> Result<Self> {
         let path = path.as_ref();
         info!("loading packet database from {}", path.to_string_lossy());
@@ -252,61 +415,143 @@ impl SignedPacketStore {
         Self::open(db, options)
     }
 
+    /// Create a redb database in memory.
     pub fn in_memory(options: Options) -> Result<Self> {
         info!("using in-memory packet database");
         let db = Database::builder().create_with_backend(InMemoryBackend::new())?;
         Self::open(db, options)
     }
 
+    /// Use an existing redb database, creating the tables if needed.
     pub fn open(db: Database, options: Options) -> Result<Self> {
         // create tables
         let write_tx = db.begin_write()?;
         let _ = Tables::new(&write_tx)?;
         write_tx.commit()?;
+        let stored_bytes = match options.max_size {
+            Some(_) => {
+                let read_tx = db.begin_read()?;
+                let table = read_tx.open_table(SIGNED_PACKETS_TABLE)?;
+                let mut stored_bytes = 0;
+                for item in table.iter()? {
+                    stored_bytes += item?.1.value().len() as u64;
+                }
+                stored_bytes
+            }
+            None => 0,
+        };
         let (send, recv) = mpsc::channel(1024);
-        let send2 = send.clone();
         let cancel = CancellationToken::new();
         let cancel2 = cancel.clone();
-        let cancel3 = cancel.clone();
         let actor = Actor {
             db,
             recv: PeekableReceiver::new(recv),
             cancel: cancel2,
             options,
+            stored_bytes,
         };
         // start an io thread and donate it to the tokio runtime so we can do blocking IO
         // inside the thread despite being in a tokio runtime
         let _write_thread = IoThread::new("packet-store-actor", move || actor.run())?;
-        let _evict_thread = IoThread::new("packet-store-evict", move || {
-            evict_task(send2, options, cancel3)
-        })?;
         Ok(Self {
             send,
             cancel,
             _write_thread,
-            _evict_thread,
         })
     }
+}
 
-    pub async fn upsert(&self, packet: SignedPacket) -> Result<bool> {
+#[async_trait]
+impl PacketStorage for SignedPacketStore {
+    async fn upsert(&self, packet: SignedPacket) -> Result<UpsertOutcome> {
         let (tx, rx) = oneshot::channel();
         self.send.send(Message::Upsert { packet, res: tx }).await?;
         Ok(rx.await?)
     }
 
-    pub async fn get(&self, key: &PublicKeyBytes) -> Result<Option<SignedPacket>> {
+    async fn get(&self, key: &PublicKeyBytes) -> Result<Option<SignedPacket>> {
         let (tx, rx) = oneshot::channel();
         self.send.send(Message::Get { key: *key, res: tx }).await?;
         Ok(rx.await?)
     }
 
-    pub async fn remove(&self, key: &PublicKeyBytes) -> Result<bool> {
+    async fn remove(&self, key: &PublicKeyBytes) -> Result<bool> {
         let (tx, rx) = oneshot::channel();
         self.send
             .send(Message::Remove { key: *key, res: tx })
             .await?;
         Ok(rx.await?)
     }
+
+    async fn expired(&self, before: u64, limit: usize) -> Result<Vec<PublicKeyBytes>> {
+        let (tx, rx) = oneshot::channel();
+        self.send
+            .send(Message::Expired {
+                before,
+                limit,
+                res: tx,
+            })
+            .await?;
+        Ok(rx.await?)
+    }
+
+    async fn remove_expired(&self, key: &PublicKeyBytes, before: u64) -> Result<bool> {
+        let (tx, rx) = oneshot::channel();
+        self.send
+            .send(Message::RemoveExpired {
+                key: *key,
+                before,
+                res: tx,
+            })
+            .await?;
+        Ok(rx.await?)
+    }
+
+    async fn packets_after(
+        &self,
+        after: (u64, PublicKeyBytes),
+        limit: usize,
+    ) -> Result<Vec<SignedPacket>> {
+        let (tx, rx) = oneshot::channel();
+        self.send
+            .send(Message::PacketsAfter {
+                after,
+                limit,
+                res: tx,
+            })
+            .await?;
+        Ok(rx.await?)
+    }
+
+    async fn block(&self, key: &PublicKeyBytes) -> Result<bool> {
+        let (tx, rx) = oneshot::channel();
+        self.send
+            .send(Message::Block { key: *key, res: tx })
+            .await?;
+        Ok(rx.await?)
+    }
+
+    async fn unblock(&self, key: &PublicKeyBytes) -> Result<bool> {
+        let (tx, rx) = oneshot::channel();
+        self.send
+            .send(Message::Unblock { key: *key, res: tx })
+            .await?;
+        Ok(rx.await?)
+    }
+
+    async fn is_blocked(&self, key: &PublicKeyBytes) -> Result<bool> {
+        let (tx, rx) = oneshot::channel();
+        self.send
+            .send(Message::IsBlocked { key: *key, res: tx })
+            .await?;
+        Ok(rx.await?)
+    }
+
+    async fn blocked(&self) -> Result<Vec<PublicKeyBytes>> {
+        let (tx, rx) = oneshot::channel();
+        self.send.send(Message::Blocked { res: tx }).await?;
+        Ok(rx.await?)
+    }
 }
 
 fn get_packet(
@@ -320,70 +565,6 @@ fn get_packet(
     Ok(Some(packet))
 }
 
-async fn evict_task(send: mpsc::Sender<Message>, options: Options, cancel: CancellationToken) {
-    let cancel2 = cancel.clone();
-    let _ = cancel2
-        .run_until_cancelled(async move {
-            info!("starting evict task");
-            if let Err(cause) = evict_task_inner(send, options).await {
-                error!("evict task failed: {:?}", cause);
-            }
-            // when we are done for whatever reason we want to shut down the actor
-            cancel.cancel();
-        })
-        .await;
-}
-
-/// Periodically check for expired packets and remove them.
-async fn evict_task_inner(send: mpsc::Sender<Message>, options: Options) -> anyhow::Result<()> {
-    let expiry_us = options.eviction.as_micros() as u64;
-    loop {
-        let (tx, rx) = oneshot::channel();
-        let _ = send.send(Message::Snapshot { res: tx }).await.ok();
-        // if we can't get the snapshot we exit the loop, main actor dead
-        let Ok(snapshot) = rx.await else {
-            anyhow::bail!("failed to get snapshot");
-        };
-        let expired = system_time() - expiry_us;
-        trace!("evicting packets older than {}", expired);
-        // if getting the range fails we exit the loop and shut down
-        // if individual reads fail we log the error and limp on
-        for item in snapshot.update_time.range(..expired.to_be_bytes())? {
-            let (time, keys) = match item {
-                Ok(v) => v,
-                Err(e) => {
-                    error!("failed to read update_time row {:?}", e);
-                    continue;
-                }
-            };
-            let time = time.value();
-            trace!("evicting expired packets at {}", u64::from_be_bytes(time));
-            for item in keys {
-                let key = match item {
-                    Ok(v) => v,
-                    Err(e) => {
-                        error!(
-                            "failed to read update_time item at {}: {:?}",
-                            u64::from_be_bytes(time),
-                            e
-                        );
-                        continue;
-                    }
-                };
-                let key = PublicKeyBytes::new(key.value());
-                debug!(
-                    "evicting expired packet {} {}",
-                    u64::from_be_bytes(time),
-                    key
-                );
-                send.send(Message::CheckExpired { time, key }).await?;
-            }
-        }
-        // sleep for the eviction interval so we don't constantly check
-        tokio::time::sleep(options.eviction_interval).await;
-    }
-}
-
 /// An io thread that drives a future to completion on the current tokio runtime
 ///
 /// Inside the future, blocking IO can be done without blocking one of the tokio
@@ -458,3 +639,90 @@ impl<T> PeekableReceiver<T> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use iroh::SecretKey;
+    use pkarr::SignedPacket;
+    use tracing_test::traced_test;
+
+    use super::{Options, SignedPacketStore};
+    use crate::{
+        store::{storage::conformance, PacketStorage},
+        util::PublicKeyBytes,
+    };
+
+    #[tokio::test]
+    #[traced_test]
+    async fn redb_conformance() -> Result<()> {
+        conformance::run(SignedPacketStore::in_memory(Options::default())?).await
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn redb_full_storage() -> Result<()> {
+        let options = Options {
+            max_packets: Some(3),
+            ..Default::default()
+        };
+        conformance::full_storage(SignedPacketStore::in_memory(options)?, 3).await
+    }
+
+    /// Returns the indices of the packets which are stored.
+    async fn stored(store: &SignedPacketStore, packets: &[SignedPacket]) -> Result<Vec<usize>> {
+        let mut stored = Vec::new();
+        for (i, packet) in packets.iter().enumerate() {
+            if store
+                .get(&PublicKeyBytes::from_signed_packet(packet))
+                .await?
+                .is_some()
+            {
+                stored.push(i);
+            }
+        }
+        Ok(stored)
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn bounded_eviction() -> Result<()> {
+        let mut packets = Vec::new();
+        for _ in 0..5 {
+            let secret_key = SecretKey::generate(rand::thread_rng());
+            packets.push(conformance::signed_packet(&secret_key, "relay").await?);
+        }
+        // the oldest packets are evicted when the store is full, regardless of insertion order
+        let options = Options {
+            max_packets: Some(3),
+            ..Default::default()
+        };
+        let store = SignedPacketStore::in_memory(options)?;
+        for i in [1, 0, 2, 3, 4] {
+            assert!(store.upsert(packets[i].clone()).await?.is_stored());
+        }
+        assert_eq!(stored(&store, &packets).await?, vec![2, 3, 4]);
+        drop(store);
+
+        // the size of the packets already in the database is counted on open
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("signed-packets.redb");
+        let store = SignedPacketStore::persistent(&path, Options::default())?;
+        for packet in &packets[..4] {
+            store.upsert(packet.clone()).await?;
+        }
+        drop(store);
+        let size = packets[..2]
+            .iter()
+            .map(|packet| packet.as_bytes().len() as u64)
+            .sum::<u64>();
+        let options = Options {
+            max_size: Some(size + 1),
+            ..Default::default()
+        };
+        let store = SignedPacketStore::persistent(&path, options)?;
+        store.upsert(packets[4].clone()).await?;
+        assert_eq!(stored(&store, &packets).await?, vec![3, 4]);
+        Ok(())
+    }
+}
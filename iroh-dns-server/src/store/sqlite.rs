@@ -0,0 +1,303 @@
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use iroh_metrics::inc;
+use pkarr::SignedPacket;
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+use tracing::{info, trace};
+
+use super::{PacketStorage, UpsertOutcome};
+use crate::{metrics::Metrics, util::PublicKeyBytes};
+
+/// How long to wait for a lock held by another connection to the database.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS signed_packets (
+        key BLOB PRIMARY KEY NOT NULL,
+        timestamp INTEGER NOT NULL,
+        packet BLOB NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS signed_packets_timestamp ON signed_packets (timestamp);
+    CREATE TABLE IF NOT EXISTS blocked_keys (
+        key BLOB PRIMARY KEY NOT NULL
+    );
+";
+
+/// A [`PacketStorage`] backed by a SQLite database.
+///
+/// Several servers can share a database file, as all writes happen in immediate transactions.
+/// Zones cached by a [`ZoneStore`] do not see the writes of other servers right away, see
+/// [`ZoneStore::sqlite`], and subscribers of [`ZoneStore::subscribe`] are only notified of
+/// packets inserted through the same store.
+///
+/// [`ZoneStore`]: super::ZoneStore
+/// [`ZoneStore::sqlite`]: super::ZoneStore::sqlite
+/// [`ZoneStore::subscribe`]: super::ZoneStore::subscribe
+#[derive(Debug, Clone)]
+pub struct SqliteStorage {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStorage {
+    /// Open or create a SQLite database at `path`.
+    pub fn persistent(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        info!("loading sqlite packet database from {}", path.display());
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).with_context(|| {
+                format!("failed to create database directory at {}", path.display())
+            })?;
+        }
+        let conn = Connection::open(path).context("failed to open packet database")?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        Self::open(conn)
+    }
+
+    /// Create a SQLite database in memory.
+    pub fn in_memory() -> Result<Self> {
+        info!("using in-memory sqlite packet database");
+        Self::open(Connection::open_in_memory()?)
+    }
+
+    /// Use an existing SQLite connection, creating the tables if needed.
+    pub fn open(conn: Connection) -> Result<Self> {
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// Run a blocking closure with the connection on the blocking thread pool.
+    async fn with_conn<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || f(&mut conn.lock().expect("poisoned"))).await?
+    }
+}
+
+#[async_trait]
+impl PacketStorage for SqliteStorage {
+    async fn get(&self, key: &PublicKeyBytes) -> Result<Option<SignedPacket>> {
+        trace!("get {}", key);
+        let key = *key;
+        self.with_conn(move |conn| get_packet(conn, &key)).await
+    }
+
+    async fn upsert(&self, packet: SignedPacket) -> Result<UpsertOutcome> {
+        let key = PublicKeyBytes::from_signed_packet(&packet);
+        trace!("upsert {}", key);
+        self.with_conn(move |conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let blocked = tx
+                .prepare_cached("SELECT 1 FROM blocked_keys WHERE key = ?1")?
+                .exists(params![key.as_bytes()])?;
+            if blocked {
+                return Ok(UpsertOutcome::Blocked);
+            }
+            let replaced = match get_packet(&tx, &key)? {
+                Some(existing) if existing.more_recent_than(&packet) => {
+                    return Ok(UpsertOutcome::Outdated)
+                }
+                Some(_) => true,
+                None => false,
+            };
+            tx.execute(
+                "INSERT OR REPLACE INTO signed_packets (key, timestamp, packet) VALUES (?1, ?2, ?3)",
+                params![
+                    key.as_bytes(),
+                    to_sql_time(packet.timestamp()),
+                    &packet.as_bytes()[..]
+                ],
+            )?;
+            tx.commit()?;
+            if replaced {
+                inc!(Metrics, store_packets_updated);
+            } else {
+                inc!(Metrics, store_packets_inserted);
+            }
+            Ok(UpsertOutcome::Stored { evicted: vec![] })
+        })
+        .await
+    }
+
+    async fn remove(&self, key: &PublicKeyBytes) -> Result<bool> {
+        trace!("remove {}", key);
+        let key = *key;
+        self.with_conn(move |conn| {
+            let removed = conn.execute(
+                "DELETE FROM signed_packets WHERE key = ?1",
+                params![key.as_bytes()],
+            )? > 0;
+            if removed {
+                inc!(Metrics, store_packets_removed);
+            }
+            Ok(removed)
+        })
+        .await
+    }
+
+    async fn expired(&self, before: u64, limit: usize) -> Result<Vec<PublicKeyBytes>> {
+        trace!("expired before {}", before);
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare_cached(
+                "SELECT key FROM signed_packets WHERE timestamp < ?1 ORDER BY timestamp, key LIMIT ?2",
+            )?;
+            let keys = stmt
+                .query_map(params![to_sql_time(before), to_sql_limit(limit)], |row| {
+                    row.get::<_, [u8; 32]>(0)
+                })?
+                .map(|key| Ok(PublicKeyBytes::new(key?)))
+                .collect::<Result<_>>()?;
+            Ok(keys)
+        })
+        .await
+    }
+
+    async fn remove_expired(&self, key: &PublicKeyBytes, before: u64) -> Result<bool> {
+        trace!("remove expired {} before {}", key, before);
+        let key = *key;
+        self.with_conn(move |conn| {
+            let removed = conn.execute(
+                "DELETE FROM signed_packets WHERE key = ?1 AND timestamp < ?2",
+                params![key.as_bytes(), to_sql_time(before)],
+            )? > 0;
+            if removed {
+                inc!(Metrics, store_packets_expired);
+            }
+            Ok(removed)
+        })
+        .await
+    }
+
+    async fn packets_after(
+        &self,
+        after: (u64, PublicKeyBytes),
+        limit: usize,
+    ) -> Result<Vec<SignedPacket>> {
+        trace!("packets after {} {}", after.0, after.1);
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare_cached(
+                "SELECT packet FROM signed_packets
+                 WHERE timestamp > ?1 OR (timestamp = ?1 AND key > ?2)
+                 ORDER BY timestamp, key LIMIT ?3",
+            )?;
+            let packets = stmt
+                .query_map(
+                    params![
+                        to_sql_time(after.0),
+                        after.1.as_bytes(),
+                        to_sql_limit(limit)
+                    ],
+                    |row| row.get::<_, Vec<u8>>(0),
+                )?
+                .map(|packet| Ok(SignedPacket::from_bytes(&Bytes::from(packet?))?))
+                .collect::<Result<_>>()?;
+            Ok(packets)
+        })
+        .await
+    }
+
+    async fn block(&self, key: &PublicKeyBytes) -> Result<bool> {
+        trace!("block {}", key);
+        let key = *key;
+        self.with_conn(move |conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let removed = tx.execute(
+                "DELETE FROM signed_packets WHERE key = ?1",
+                params![key.as_bytes()],
+            )? > 0;
+            let inserted = tx.execute(
+                "INSERT OR IGNORE INTO blocked_keys (key) VALUES (?1)",
+                params![key.as_bytes()],
+            )? > 0;
+            tx.commit()?;
+            if removed {
+                inc!(Metrics, store_packets_removed);
+            }
+            Ok(inserted)
+        })
+        .await
+    }
+
+    async fn unblock(&self, key: &PublicKeyBytes) -> Result<bool> {
+        trace!("unblock {}", key);
+        let key = *key;
+        self.with_conn(move |conn| {
+            let removed = conn.execute(
+                "DELETE FROM blocked_keys WHERE key = ?1",
+                params![key.as_bytes()],
+            )? > 0;
+            Ok(removed)
+        })
+        .await
+    }
+
+    async fn is_blocked(&self, key: &PublicKeyBytes) -> Result<bool> {
+        let key = *key;
+        self.with_conn(move |conn| {
+            let blocked = conn
+                .prepare_cached("SELECT 1 FROM blocked_keys WHERE key = ?1")?
+                .exists(params![key.as_bytes()])?;
+            Ok(blocked)
+        })
+        .await
+    }
+
+    async fn blocked(&self) -> Result<Vec<PublicKeyBytes>> {
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare_cached("SELECT key FROM blocked_keys ORDER BY key")?;
+            let keys = stmt
+                .query_map([], |row| row.get::<_, [u8; 32]>(0))?
+                .map(|key| Ok(PublicKeyBytes::new(key?)))
+                .collect::<Result<_>>()?;
+            Ok(keys)
+        })
+        .await
+    }
+}
+
+fn get_packet(conn: &Connection, key: &PublicKeyBytes) -> Result<Option<SignedPacket>> {
+    let packet = conn
+        .prepare_cached("SELECT packet FROM signed_packets WHERE key = ?1")?
+        .query_row(params![key.as_bytes()], |row| row.get::<_, Vec<u8>>(0))
+        .optional()?;
+    let Some(packet) = packet else {
+        return Ok(None);
+    };
+    Ok(Some(SignedPacket::from_bytes(&Bytes::from(packet))?))
+}
+
+/// SQLite integers are signed, timestamps in microseconds fit until the year 294247.
+fn to_sql_time(time: u64) -> i64 {
+    time.min(i64::MAX as u64) as i64
+}
+
+fn to_sql_limit(limit: usize) -> i64 {
+    limit.min(i64::MAX as usize) as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use tracing_test::traced_test;
+
+    use super::SqliteStorage;
+    use crate::store::storage::conformance;
+
+    #[tokio::test]
+    #[traced_test]
+    async fn sqlite_conformance() -> Result<()> {
+        conformance::run(SqliteStorage::in_memory()?).await
+    }
+}
@@ -0,0 +1,400 @@
+//! The [`PacketStorage`] trait implemented by the storage backends of the [`ZoneStore`].
+//!
+//! [`ZoneStore`]: super::ZoneStore
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use pkarr::{system_time, SignedPacket};
+use tracing::{debug, trace, warn};
+
+use super::signed_packets::Options;
+use crate::util::PublicKeyBytes;
+
+/// Maximum number of expired keys fetched from the storage at once.
+const EVICTION_BATCH_SIZE: usize = 1024;
+
+/// A storage backend for pkarr signed packets.
+///
+/// The storage keeps the most recent signed packet for each public key, and indexes the
+/// packets by their timestamp so that expired packets can be evicted.
+#[async_trait]
+pub trait PacketStorage: std::fmt::Debug + Send + Sync + 'static {
+    /// Get the signed packet for a public key.
+    async fn get(&self, key: &PublicKeyBytes) -> Result<Option<SignedPacket>>;
+
+    /// Insert a signed packet, replacing the packet stored for its public key.
+    ///
+    /// Keeps the stored packet if it is more recent than `packet`, as determined by
+    /// [`SignedPacket::more_recent_than`].  Packets for blocked public keys are not stored, the
+    /// blocklist is checked atomically with the insert.  A bounded storage evicts its oldest
+    /// packets to make room, which may be `packet` itself.
+    async fn upsert(&self, packet: SignedPacket) -> Result<UpsertOutcome>;
+
+    /// Remove the signed packet for a public key.
+    ///
+    /// Returns whether a packet was removed.
+    async fn remove(&self, key: &PublicKeyBytes) -> Result<bool>;
+
+    /// Get the public keys of at most `limit` packets with a timestamp before `before`.
+    ///
+    /// Timestamps are in microseconds since the unix epoch, as returned by
+    /// [`SignedPacket::timestamp`].  Keys are returned oldest first.
+    async fn expired(&self, before: u64, limit: usize) -> Result<Vec<PublicKeyBytes>>;
+
+    /// Remove the signed packet for a public key if its timestamp is before `before`.
+    ///
+    /// This is used to evict the keys returned by [`Self::expired`] without removing a packet
+    /// which was updated in the meantime.  Returns whether a packet was removed.
+    async fn remove_expired(&self, key: &PublicKeyBytes, before: u64) -> Result<bool>;
+
+    /// Get at most `limit` packets ordered by their timestamp and public key, starting after
+    /// the timestamp and public key in `after`.
+    ///
+    /// This allows to page through all packets, e.g. to synchronize with another server.
+    async fn packets_after(
+        &self,
+        after: (u64, PublicKeyBytes),
+        limit: usize,
+    ) -> Result<Vec<SignedPacket>>;
+
+    /// Add a public key to the blocklist and remove its signed packet.
+    ///
+    /// Returns whether the key was not blocked before.
+    async fn block(&self, key: &PublicKeyBytes) -> Result<bool>;
+
+    /// Remove a public key from the blocklist.
+    ///
+    /// Returns whether the key was blocked.
+    async fn unblock(&self, key: &PublicKeyBytes) -> Result<bool>;
+
+    /// Check whether a public key is on the blocklist.
+    async fn is_blocked(&self, key: &PublicKeyBytes) -> Result<bool>;
+
+    /// Get all public keys on the blocklist, ordered by key.
+    async fn blocked(&self) -> Result<Vec<PublicKeyBytes>>;
+}
+
+/// The outcome of [`PacketStorage::upsert`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UpsertOutcome {
+    /// The packet is stored.
+    Stored {
+        /// The keys of the packets evicted to make room for the packet.
+        evicted: Vec<PublicKeyBytes>,
+    },
+    /// The stored packet for the public key is more recent and was kept.
+    Outdated,
+    /// The public key is on the blocklist.
+    Blocked,
+    /// The packet was evicted right away, because it is the oldest packet of a full storage.
+    Evicted {
+        /// The keys of all evicted packets, including the key of the packet.
+        evicted: Vec<PublicKeyBytes>,
+    },
+}
+
+impl UpsertOutcome {
+    /// Returns whether the packet is stored.
+    pub fn is_stored(&self) -> bool {
+        matches!(self, Self::Stored { .. })
+    }
+
+    /// Returns the keys of the evicted packets.
+    pub fn evicted(&self) -> &[PublicKeyBytes] {
+        match self {
+            Self::Stored { evicted } | Self::Evicted { evicted } => evicted,
+            Self::Outdated | Self::Blocked => &[],
+        }
+    }
+}
+
+/// Periodically evict the packets which are older than [`Options::eviction`].
+pub(super) async fn evict_task(storage: Arc<dyn PacketStorage>, options: Options) {
+    let expiry_us = options.eviction.as_micros() as u64;
+    loop {
+        let before = system_time().saturating_sub(expiry_us);
+        trace!("evicting packets older than {}", before);
+        if let Err(err) = evict_before(storage.as_ref(), before).await {
+            warn!("failed to evict expired packets: {err:#}");
+        }
+        // sleep for the eviction interval so we don't constantly check
+        tokio::time::sleep(options.eviction_interval).await;
+    }
+}
+
+async fn evict_before(storage: &dyn PacketStorage, before: u64) -> Result<()> {
+    loop {
+        let keys = storage.expired(before, EVICTION_BATCH_SIZE).await?;
+        let mut removed = 0;
+        for key in &keys {
+            if storage.remove_expired(key, before).await? {
+                debug!("evicted expired packet {}", key);
+                removed += 1;
+            }
+        }
+        // stop when all expired packets were seen, or no progress is made
+        if keys.len() < EVICTION_BATCH_SIZE || removed == 0 {
+            return Ok(());
+        }
+    }
+}
+
+/// A conformance test suite for [`PacketStorage`] implementations.
+#[cfg(test)]
+pub(super) mod conformance {
+    use std::time::Duration;
+
+    use anyhow::{Context, Result};
+    use iroh::{dns::node_info::NodeInfo, SecretKey};
+    use pkarr::SignedPacket;
+    use url::Url;
+
+    use super::{PacketStorage, UpsertOutcome};
+    use crate::util::PublicKeyBytes;
+
+    /// Run all conformance tests against a storage which is empty initially.
+    pub(crate) async fn run(storage: impl PacketStorage) -> Result<()> {
+        upsert_get(&storage).await?;
+        remove(&storage).await?;
+        expired(&storage).await?;
+        packets_after(&storage).await?;
+        blocklist(&storage).await?;
+        Ok(())
+    }
+
+    /// Create a signed packet, sleeping first so that all packets have distinct timestamps.
+    pub(crate) async fn signed_packet(secret_key: &SecretKey, relay: &str) -> Result<SignedPacket> {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        let relay_url: Url = format!("https://{relay}.example.").parse()?;
+        let node_info = NodeInfo::new(secret_key.public(), Some(relay_url), Default::default());
+        node_info.to_pkarr_signed_packet(secret_key, 30)
+    }
+
+    async fn assert_stored(storage: &impl PacketStorage, packet: &SignedPacket) -> Result<()> {
+        let key = PublicKeyBytes::from_signed_packet(packet);
+        let stored = storage.get(&key).await?.context("packet not stored")?;
+        assert!(stored.is_same_as(packet));
+        Ok(())
+    }
+
+    fn random_key() -> SecretKey {
+        SecretKey::generate(rand::thread_rng())
+    }
+
+    async fn upsert_get(storage: &impl PacketStorage) -> Result<()> {
+        let secret_key = random_key();
+        let older = signed_packet(&secret_key, "older").await?;
+        let newer = signed_packet(&secret_key, "newer").await?;
+        let key = PublicKeyBytes::from_signed_packet(&older);
+
+        assert!(storage.get(&key).await?.is_none());
+
+        assert!(storage.upsert(older.clone()).await?.is_stored());
+        assert_stored(storage, &older).await?;
+
+        assert!(storage.upsert(newer.clone()).await?.is_stored());
+        assert_stored(storage, &newer).await?;
+
+        // an older packet does not replace a newer one
+        assert_eq!(storage.upsert(older).await?, UpsertOutcome::Outdated);
+        assert_stored(storage, &newer).await?;
+
+        // the same packet is accepted again
+        assert!(storage.upsert(newer.clone()).await?.is_stored());
+        assert_stored(storage, &newer).await?;
+
+        assert!(storage.remove(&key).await?);
+        Ok(())
+    }
+
+    async fn remove(storage: &impl PacketStorage) -> Result<()> {
+        let packet = signed_packet(&random_key(), "remove").await?;
+        let key = PublicKeyBytes::from_signed_packet(&packet);
+
+        assert!(!storage.remove(&key).await?);
+        storage.upsert(packet.clone()).await?;
+        assert!(storage.remove(&key).await?);
+        assert!(storage.get(&key).await?.is_none());
+        assert!(!storage.remove(&key).await?);
+
+        // a removed packet is not returned as expired
+        assert!(storage.expired(u64::MAX, usize::MAX).await?.is_empty());
+        Ok(())
+    }
+
+    async fn expired(storage: &impl PacketStorage) -> Result<()> {
+        let mut packets = Vec::new();
+        for i in 0..3 {
+            packets.push(signed_packet(&random_key(), &format!("expired{i}")).await?);
+        }
+        for packet in packets.iter().rev() {
+            storage.upsert(packet.clone()).await?;
+        }
+        let keys = packets
+            .iter()
+            .map(PublicKeyBytes::from_signed_packet)
+            .collect::<Vec<_>>();
+        let timestamps = packets.iter().map(|p| p.timestamp()).collect::<Vec<_>>();
+
+        // the timestamp bound is exclusive and keys are returned oldest first
+        assert!(storage.expired(timestamps[0], 10).await?.is_empty());
+        assert_eq!(storage.expired(timestamps[1], 10).await?, keys[..1]);
+        assert_eq!(storage.expired(u64::MAX, 10).await?, keys);
+        assert_eq!(storage.expired(u64::MAX, 2).await?, keys[..2]);
+
+        // updating a packet moves it in the index
+        let secret_key = random_key();
+        let old = signed_packet(&secret_key, "old").await?;
+        let key = PublicKeyBytes::from_signed_packet(&old);
+        storage.upsert(old.clone()).await?;
+        let new = signed_packet(&secret_key, "new").await?;
+        storage.upsert(new.clone()).await?;
+        assert_eq!(storage.expired(new.timestamp(), 10).await?, keys);
+
+        // a packet is only removed if it is still expired
+        assert!(!storage.remove_expired(&key, new.timestamp()).await?);
+        assert!(storage.get(&key).await?.is_some());
+        assert!(storage.remove_expired(&key, new.timestamp() + 1).await?);
+        assert!(storage.get(&key).await?.is_none());
+        assert!(!storage.remove_expired(&key, u64::MAX).await?);
+
+        for key in &keys {
+            assert!(storage.remove_expired(key, u64::MAX).await?);
+        }
+        assert!(storage.expired(u64::MAX, usize::MAX).await?.is_empty());
+        Ok(())
+    }
+
+    async fn packets_after(storage: &impl PacketStorage) -> Result<()> {
+        assert!(storage
+            .packets_after((0, PublicKeyBytes::new([0; 32])), 10)
+            .await?
+            .is_empty());
+
+        let mut packets = Vec::new();
+        for i in 0..3 {
+            packets.push(signed_packet(&random_key(), &format!("after{i}")).await?);
+        }
+        for packet in packets.iter().rev() {
+            storage.upsert(packet.clone()).await?;
+        }
+        let cursor = |packet: &SignedPacket| {
+            (
+                packet.timestamp(),
+                PublicKeyBytes::from_signed_packet(packet),
+            )
+        };
+        let keys = |packets: Vec<SignedPacket>| {
+            packets
+                .iter()
+                .map(PublicKeyBytes::from_signed_packet)
+                .collect::<Vec<_>>()
+        };
+        let all = keys(packets.clone());
+
+        let page = storage
+            .packets_after((0, PublicKeyBytes::new([0; 32])), 2)
+            .await?;
+        assert_eq!(keys(page), all[..2]);
+        let page = storage.packets_after(cursor(&packets[1]), 2).await?;
+        assert_eq!(keys(page), all[2..]);
+        let page = storage.packets_after(cursor(&packets[2]), 2).await?;
+        assert!(page.is_empty());
+        // a cursor in between two packets
+        let page = storage
+            .packets_after(
+                (packets[1].timestamp() - 1, PublicKeyBytes::new([0xff; 32])),
+                10,
+            )
+            .await?;
+        assert_eq!(keys(page), all[1..]);
+
+        for key in &all {
+            assert!(storage.remove(key).await?);
+        }
+        Ok(())
+    }
+
+    /// Run the eviction tests against an empty storage bounded to `capacity` packets.
+    pub(crate) async fn full_storage(storage: impl PacketStorage, capacity: usize) -> Result<()> {
+        let oldest = signed_packet(&random_key(), "oldest").await?;
+        let oldest_key = PublicKeyBytes::from_signed_packet(&oldest);
+        let mut packets = Vec::new();
+        for i in 0..capacity {
+            packets.push(signed_packet(&random_key(), &format!("full{i}")).await?);
+        }
+        for packet in &packets {
+            let outcome = storage.upsert(packet.clone()).await?;
+            assert_eq!(outcome, UpsertOutcome::Stored { evicted: vec![] });
+        }
+
+        // an older packet than all stored packets is evicted right away
+        let outcome = storage.upsert(oldest).await?;
+        assert_eq!(
+            outcome,
+            UpsertOutcome::Evicted {
+                evicted: vec![oldest_key]
+            }
+        );
+        assert!(storage.get(&oldest_key).await?.is_none());
+        for packet in &packets {
+            assert_stored(&storage, packet).await?;
+        }
+
+        // a newer packet evicts the oldest stored packet
+        let newest = signed_packet(&random_key(), "newest").await?;
+        let outcome = storage.upsert(newest.clone()).await?;
+        let evicted = PublicKeyBytes::from_signed_packet(&packets[0]);
+        assert_eq!(
+            outcome,
+            UpsertOutcome::Stored {
+                evicted: vec![evicted]
+            }
+        );
+        assert!(storage.get(&evicted).await?.is_none());
+        assert_stored(&storage, &newest).await?;
+        Ok(())
+    }
+
+    async fn blocklist(storage: &impl PacketStorage) -> Result<()> {
+        let packet = signed_packet(&random_key(), "blocked").await?;
+        let key = PublicKeyBytes::from_signed_packet(&packet);
+        let other =
+            PublicKeyBytes::from_signed_packet(&signed_packet(&random_key(), "other").await?);
+
+        assert!(!storage.is_blocked(&key).await?);
+        assert!(storage.blocked().await?.is_empty());
+
+        // blocking a key removes its packet
+        storage.upsert(packet.clone()).await?;
+        assert!(storage.block(&key).await?);
+        assert!(!storage.block(&key).await?);
+        assert!(storage.is_blocked(&key).await?);
+        assert!(storage.get(&key).await?.is_none());
+        assert!(storage.expired(u64::MAX, usize::MAX).await?.is_empty());
+
+        // packets for blocked keys are not stored
+        assert_eq!(
+            storage.upsert(packet.clone()).await?,
+            UpsertOutcome::Blocked
+        );
+        assert!(storage.get(&key).await?.is_none());
+
+        // keys are listed in order
+        assert!(storage.block(&other).await?);
+        let mut expected = vec![key, other];
+        expected.sort();
+        assert_eq!(storage.blocked().await?, expected);
+
+        assert!(storage.unblock(&key).await?);
+        assert!(!storage.unblock(&key).await?);
+        assert!(!storage.is_blocked(&key).await?);
+        assert!(storage.upsert(packet).await?.is_stored());
+        assert!(storage.remove(&key).await?);
+        assert!(storage.unblock(&other).await?);
+        assert!(storage.blocked().await?.is_empty());
+        Ok(())
+    }
+}
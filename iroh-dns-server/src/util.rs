@@ -16,34 +16,41 @@ use hickory_server::proto::{
 };
 use pkarr::SignedPacket;
 
+/// The bytes of a pkarr public key.
 #[derive(
     derive_more::From, derive_more::Into, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy,
 )]
 pub struct PublicKeyBytes([u8; 32]);
 
 impl PublicKeyBytes {
+    /// Create from the raw bytes of a public key.
     pub fn new(bytes: [u8; 32]) -> Self {
         Self(bytes)
     }
 
+    /// Parse from a z-base-32 encoded public key.
     pub fn from_z32(s: &str) -> Result<Self> {
         let bytes = z32::decode(s.as_bytes())?;
         let bytes: [u8; 32] = bytes.try_into().map_err(|_| anyhow!("invalid length"))?;
         Ok(Self(bytes))
     }
 
+    /// Encode as z-base-32.
     pub fn to_z32(self) -> String {
         z32::encode(&self.0)
     }
 
+    /// Get the raw bytes.
     pub fn to_bytes(self) -> [u8; 32] {
         self.0
     }
 
+    /// Get a reference to the raw bytes.
     pub fn as_bytes(&self) -> &[u8; 32] {
         &self.0
     }
 
+    /// Get the public key of a signed packet.
     pub fn from_signed_packet(packet: &SignedPacket) -> Self {
         Self(packet.public_key().to_bytes())
     }
@@ -1,18 +1,25 @@
 //! DNS resolver
 
 use std::{
+    collections::BTreeMap,
     fmt::{self, Write},
     future::Future,
     net::{IpAddr, Ipv6Addr, SocketAddr},
+    sync::Arc,
 };
 
 use anyhow::{bail, Context, Result};
-use hickory_resolver::{Resolver, TokioResolver};
+use hickory_resolver::{
+    config::{LookupIpStrategy, NameServerConfig, ResolverConfig, ResolverOpts},
+    proto::xfer::Protocol,
+    ResolveError, Resolver, TokioResolver,
+};
 use iroh_base::{NodeAddr, NodeId};
 use n0_future::{
     time::{self, Duration},
     StreamExt,
 };
+use tracing::debug;
 use url::Url;
 
 pub mod node_info;
@@ -22,9 +29,20 @@ pub const N0_DNS_NODE_ORIGIN_PROD: &str = "dns.iroh.link";
 /// The n0 testing DNS node origin, for testing.
 pub const N0_DNS_NODE_ORIGIN_STAGING: &str = "staging-dns.iroh.link";
 
+/// The default path of DNS-over-HTTPS queries, as served by `iroh-dns-server`.
+pub const DEFAULT_DOH_PATH: &str = "/dns-query";
+
 /// The DNS resolver used throughout `iroh`.
+///
+/// Use [`DnsResolver::builder`] to configure custom nameservers, including
+/// DNS-over-HTTPS and DNS-over-TLS nameservers.
 #[derive(Debug, Clone)]
-pub struct DnsResolver(TokioResolver);
+pub struct DnsResolver {
+    /// The resolvers of the configured nameservers, tried in order.
+    resolvers: Arc<[TokioResolver]>,
+    /// Static overrides for IP address lookups, keyed by normalized host name.
+    hosts: Arc<BTreeMap<String, Vec<IpAddr>>>,
+}
 
 impl DnsResolver {
     /// Create a new DNS resolver with sensible cross-platform defaults.
@@ -33,51 +51,59 @@ impl DnsResolver {
     /// This does not work at least on some Androids, therefore we fallback
     /// to the default `ResolverConfig` which uses eg. to google's `8.8.8.8` or `8.8.4.4`.
     pub fn new() -> Self {
-        let (system_config, mut options) =
-            hickory_resolver::system_conf::read_system_conf().unwrap_or_default();
-
-        // Copy all of the system config, but strip the bad windows nameservers.  Unfortunately
-        // there is no easy way to do this.
-        let mut config = hickory_resolver::config::ResolverConfig::new();
-        if let Some(name) = system_config.domain() {
-            config.set_domain(name.clone());
-        }
-        for name in system_config.search() {
-            config.add_search(name.clone());
-        }
-        for nameserver_cfg in system_config.name_servers() {
-            if !WINDOWS_BAD_SITE_LOCAL_DNS_SERVERS.contains(&nameserver_cfg.socket_addr.ip()) {
-                config.add_name_server(nameserver_cfg.clone());
-            }
-        }
-
-        // see [`DnsResolver::lookup_ipv4_ipv6`] for info on why we avoid `LookupIpStrategy::Ipv4AndIpv6`
-        options.ip_strategy = hickory_resolver::config::LookupIpStrategy::Ipv4thenIpv6;
-
-        let resolver = Resolver::tokio(config, options);
-        DnsResolver(resolver)
+        Self::builder().build()
     }
 
     /// Create a new DNS resolver configured with a single UDP DNS nameserver.
     pub fn with_nameserver(nameserver: SocketAddr) -> Self {
-        let mut config = hickory_resolver::config::ResolverConfig::new();
-        let nameserver_config = hickory_resolver::config::NameServerConfig::new(
-            nameserver,
-            hickory_resolver::proto::xfer::Protocol::Udp,
-        );
-        config.add_name_server(nameserver_config);
-        DnsResolver(Resolver::tokio(config, Default::default()))
+        Self::builder()
+            .nameserver(nameserver, DnsProtocol::Udp)
+            .build()
+    }
+
+    /// Creates a builder to configure a [`DnsResolver`].
+    pub fn builder() -> DnsResolverBuilder {
+        DnsResolverBuilder::default()
     }
 
     /// Removes all entries from the cache.
     pub fn clear_cache(&self) {
-        self.0.clear_cache();
+        for resolver in self.resolvers.iter() {
+            resolver.clear_cache();
+        }
+    }
+
+    /// Runs `lookup` on the resolvers in order, until one does not fail.
+    ///
+    /// Only errors move on to the next resolver, a negative answer is final.
+    async fn lookup<T, F, Fut>(&self, lookup: F) -> Result<T, ResolveError>
+    where
+        F: Fn(TokioResolver) -> Fut,
+        Fut: Future<Output = Result<T, ResolveError>>,
+    {
+        let mut resolvers = self.resolvers.iter();
+        let first = resolvers.next().expect("at least one resolver");
+        let mut res = lookup(first.clone()).await;
+        for resolver in resolvers {
+            match res {
+                Err(ref err) if !err.is_no_records_found() => {
+                    debug!("DNS lookup failed, trying next nameserver: {err}");
+                    res = lookup(resolver.clone()).await;
+                }
+                _ => break,
+            }
+        }
+        res
     }
 
     /// Lookup a TXT record.
     pub async fn lookup_txt(&self, host: impl ToString, timeout: Duration) -> Result<TxtLookup> {
         let host = host.to_string();
-        let res = time::timeout(timeout, self.0.txt_lookup(host)).await??;
+        let lookup = self.lookup(|resolver| {
+            let host = host.clone();
+            async move { resolver.txt_lookup(host).await }
+        });
+        let res = time::timeout(timeout, lookup).await??;
         Ok(TxtLookup(res))
     }
 
@@ -88,8 +114,19 @@ impl DnsResolver {
         timeout: Duration,
     ) -> Result<impl Iterator<Item = IpAddr>> {
         let host = host.to_string();
-        let addrs = time::timeout(timeout, self.0.ipv4_lookup(host)).await??;
-        Ok(addrs.into_iter().map(|ip| IpAddr::V4(ip.0)))
+        if let Some(addrs) = self.static_lookup(&host, IpAddr::is_ipv4)? {
+            return Ok(addrs);
+        }
+        let lookup = self.lookup(|resolver| {
+            let host = host.clone();
+            async move { resolver.ipv4_lookup(host).await }
+        });
+        let addrs = time::timeout(timeout, lookup).await??;
+        Ok(addrs
+            .into_iter()
+            .map(|ip| IpAddr::V4(ip.0))
+            .collect::<Vec<_>>()
+            .into_iter())
     }
 
     /// Perform an ipv6 lookup with a timeout.
@@ -99,8 +136,37 @@ impl DnsResolver {
         timeout: Duration,
     ) -> Result<impl Iterator<Item = IpAddr>> {
         let host = host.to_string();
-        let addrs = time::timeout(timeout, self.0.ipv6_lookup(host)).await??;
-        Ok(addrs.into_iter().map(|ip| IpAddr::V6(ip.0)))
+        if let Some(addrs) = self.static_lookup(&host, IpAddr::is_ipv6)? {
+            return Ok(addrs);
+        }
+        let lookup = self.lookup(|resolver| {
+            let host = host.clone();
+            async move { resolver.ipv6_lookup(host).await }
+        });
+        let addrs = time::timeout(timeout, lookup).await??;
+        Ok(addrs
+            .into_iter()
+            .map(|ip| IpAddr::V6(ip.0))
+            .collect::<Vec<_>>()
+            .into_iter())
+    }
+
+    /// Looks up `host` in the static hosts, returning the addresses matching `filter`.
+    ///
+    /// Returns `None` if the host has no static entry.
+    fn static_lookup(
+        &self,
+        host: &str,
+        filter: fn(&IpAddr) -> bool,
+    ) -> Result<Option<std::vec::IntoIter<IpAddr>>> {
+        let Some(addrs) = self.hosts.get(&normalize_host(host)) else {
+            return Ok(None);
+        };
+        let addrs = addrs.iter().copied().filter(filter).collect::<Vec<_>>();
+        if addrs.is_empty() {
+            bail!("no matching address for {host} in static hosts");
+        }
+        Ok(Some(addrs.into_iter()))
     }
 
     /// Resolve IPv4 and IPv6 in parallel with a timeout.
@@ -216,10 +282,41 @@ impl DnsResolver {
     /// To lookup nodes that published their node info to the DNS servers run by n0,
     /// pass [`N0_DNS_NODE_ORIGIN_PROD`] as `origin`.
     pub async fn lookup_node_by_id(&self, node_id: &NodeId, origin: &str) -> Result<NodeAddr> {
+        let info = self.lookup_node_info_by_id(node_id, origin).await?;
+        Ok(info.into())
+    }
+
+    /// Looks up the full [`NodeInfo`] by [`NodeId`] and origin domain name.
+    ///
+    /// Unlike [`Self::lookup_node_by_id`] this also returns the [`UserData`] published by
+    /// the node.
+    ///
+    /// [`NodeInfo`]: node_info::NodeInfo
+    /// [`UserData`]: node_info::UserData
+    pub async fn lookup_node_info_by_id(
+        &self,
+        node_id: &NodeId,
+        origin: &str,
+    ) -> Result<node_info::NodeInfo> {
         let attrs =
             node_info::TxtAttrs::<node_info::IrohAttr>::lookup_by_id(self, node_id, origin).await?;
-        let info: node_info::NodeInfo = attrs.into();
-        Ok(info.into())
+        Ok(attrs.into())
+    }
+
+    /// Looks up the full [`NodeInfo`] by [`NodeId`] and origin domain name, together with
+    /// how long the resolved records remain valid.
+    ///
+    /// [`NodeInfo`]: node_info::NodeInfo
+    pub async fn lookup_node_info_with_ttl_by_id(
+        &self,
+        node_id: &NodeId,
+        origin: &str,
+    ) -> Result<(node_info::NodeInfo, Duration)> {
+        let (attrs, ttl) = node_info::TxtAttrs::<node_info::IrohAttr>::lookup_by_id_with_ttl(
+            self, node_id, origin,
+        )
+        .await?;
+        Ok((attrs.into(), ttl))
     }
 
     /// Looks up node info by DNS name.
@@ -259,6 +356,38 @@ impl DnsResolver {
         let f = || self.lookup_node_by_id(node_id, origin);
         stagger_call(f, delays_ms).await
     }
+
+    /// Looks up the full [`NodeInfo`] by [`NodeId`] and origin domain name in a staggered
+    /// fashion.
+    ///
+    /// See [`Self::lookup_node_by_id_staggered`] for details about the staggering.
+    ///
+    /// [`NodeInfo`]: node_info::NodeInfo
+    pub async fn lookup_node_info_by_id_staggered(
+        &self,
+        node_id: &NodeId,
+        origin: &str,
+        delays_ms: &[u64],
+    ) -> Result<node_info::NodeInfo> {
+        let f = || self.lookup_node_info_by_id(node_id, origin);
+        stagger_call(f, delays_ms).await
+    }
+
+    /// Looks up the full [`NodeInfo`] by [`NodeId`] and origin domain name in a staggered
+    /// fashion, together with how long the resolved records remain valid.
+    ///
+    /// See [`Self::lookup_node_by_id_staggered`] for details about the staggering.
+    ///
+    /// [`NodeInfo`]: node_info::NodeInfo
+    pub async fn lookup_node_info_with_ttl_by_id_staggered(
+        &self,
+        node_id: &NodeId,
+        origin: &str,
+        delays_ms: &[u64],
+    ) -> Result<(node_info::NodeInfo, Duration)> {
+        let f = || self.lookup_node_info_with_ttl_by_id(node_id, origin);
+        stagger_call(f, delays_ms).await
+    }
 }
 
 impl Default for DnsResolver {
@@ -269,10 +398,217 @@ impl Default for DnsResolver {
 
 impl From<TokioResolver> for DnsResolver {
     fn from(resolver: TokioResolver) -> Self {
-        DnsResolver(resolver)
+        DnsResolver {
+            resolvers: Arc::from([resolver]),
+            hosts: Default::default(),
+        }
     }
 }
 
+/// The protocol used to talk to a nameserver of a [`DnsResolver`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DnsProtocol {
+    /// Plain DNS over UDP.
+    Udp,
+    /// Plain DNS over TCP.
+    Tcp,
+    /// DNS-over-TLS.
+    Tls {
+        /// The name to verify the certificate of the nameserver against.
+        server_name: String,
+    },
+    /// DNS-over-HTTPS.
+    Https {
+        /// The name to verify the certificate of the nameserver against.
+        server_name: String,
+        /// The HTTP path to send queries to, usually [`DEFAULT_DOH_PATH`].
+        path: String,
+    },
+}
+
+impl DnsProtocol {
+    /// DNS-over-TLS with the given server name.
+    pub fn tls(server_name: impl Into<String>) -> Self {
+        Self::Tls {
+            server_name: server_name.into(),
+        }
+    }
+
+    /// DNS-over-HTTPS with the given server name, querying [`DEFAULT_DOH_PATH`].
+    pub fn https(server_name: impl Into<String>) -> Self {
+        Self::Https {
+            server_name: server_name.into(),
+            path: DEFAULT_DOH_PATH.to_string(),
+        }
+    }
+}
+
+/// Builder for a [`DnsResolver`].
+///
+/// Created with [`DnsResolver::builder`].  Without any nameservers added, the resolver uses
+/// the system's configuration like [`DnsResolver::new`].
+///
+/// ```no_run
+/// use iroh_relay::dns::{DnsProtocol, DnsResolver};
+///
+/// // Query iroh-dns-server's DNS-over-HTTPS endpoint, falling back to plain DNS.
+/// let resolver = DnsResolver::builder()
+///     .nameserver("1.2.3.4:443".parse().unwrap(), DnsProtocol::https("dns.example"))
+///     .nameserver("1.2.3.4:53".parse().unwrap(), DnsProtocol::Udp)
+///     .build();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct DnsResolverBuilder {
+    nameservers: Vec<(SocketAddr, DnsProtocol)>,
+    timeout: Option<Duration>,
+    attempts: Option<usize>,
+    hosts: BTreeMap<String, Vec<IpAddr>>,
+    tls_config: Option<Arc<rustls::ClientConfig>>,
+}
+
+impl DnsResolverBuilder {
+    /// Adds a nameserver.
+    ///
+    /// Nameservers are tried in the order they were added: a query is only sent to the next
+    /// nameserver if the previous ones failed.
+    pub fn nameserver(mut self, addr: SocketAddr, protocol: DnsProtocol) -> Self {
+        self.nameservers.push((addr, protocol));
+        self
+    }
+
+    /// Sets the timeout of a single query to a nameserver.
+    ///
+    /// This needs to be shorter than the timeout passed to the lookup methods of the
+    /// [`DnsResolver`] for unresponsive nameservers to fall back to the next nameserver.
+    ///
+    /// Defaults to hickory's default of 5 seconds.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the number of attempts of a query before giving up.
+    pub fn attempts(mut self, attempts: usize) -> Self {
+        self.attempts = Some(attempts);
+        self
+    }
+
+    /// Statically resolves `host` to `addrs`, bypassing the nameservers.
+    ///
+    /// This only applies to IP address lookups, like [`DnsResolver::lookup_ipv4`] and
+    /// [`DnsResolver::resolve_host`].  Adding the same host again extends its addresses.
+    pub fn host(mut self, host: &str, addrs: impl IntoIterator<Item = IpAddr>) -> Self {
+        self.hosts
+            .entry(normalize_host(host))
+            .or_default()
+            .extend(addrs);
+        self
+    }
+
+    /// Sets the TLS configuration used for DNS-over-TLS and DNS-over-HTTPS nameservers.
+    ///
+    /// Defaults to verifying certificates against the [`webpki_roots`].
+    pub fn tls_client_config(mut self, config: Arc<rustls::ClientConfig>) -> Self {
+        self.tls_config = Some(config);
+        self
+    }
+
+    /// Builds the [`DnsResolver`].
+    pub fn build(self) -> DnsResolver {
+        let mut options = ResolverOpts::default();
+        // see [`DnsResolver::lookup_ipv4_ipv6`] for info on why we avoid `LookupIpStrategy::Ipv4AndIpv6`
+        options.ip_strategy = LookupIpStrategy::Ipv4thenIpv6;
+        let mut configs = Vec::new();
+        if self.nameservers.is_empty() {
+            let (config, system_options) = system_config();
+            options = system_options;
+            configs.push(config);
+        } else {
+            options.tls_config = self
+                .tls_config
+                .map(|tls_config| (*tls_config).clone())
+                .unwrap_or_else(default_tls_config);
+            // One resolver per nameserver, so that the fallback order is under our control.
+            for (addr, protocol) in self.nameservers {
+                let nameserver = match protocol {
+                    DnsProtocol::Udp => NameServerConfig::new(addr, Protocol::Udp),
+                    DnsProtocol::Tcp => NameServerConfig::new(addr, Protocol::Tcp),
+                    DnsProtocol::Tls { server_name } => {
+                        let mut nameserver = NameServerConfig::new(addr, Protocol::Tls);
+                        nameserver.tls_dns_name = Some(server_name);
+                        nameserver
+                    }
+                    DnsProtocol::Https { server_name, path } => {
+                        let mut nameserver = NameServerConfig::new(addr, Protocol::Https);
+                        nameserver.tls_dns_name = Some(server_name);
+                        nameserver.http_endpoint = Some(path);
+                        nameserver
+                    }
+                };
+                let mut config = ResolverConfig::new();
+                config.add_name_server(nameserver);
+                configs.push(config);
+            }
+        }
+        if let Some(timeout) = self.timeout {
+            options.timeout = timeout;
+        }
+        if let Some(attempts) = self.attempts {
+            options.attempts = attempts;
+        }
+        DnsResolver {
+            resolvers: configs
+                .into_iter()
+                .map(|config| Resolver::tokio(config, options.clone()))
+                .collect(),
+            hosts: Arc::new(self.hosts),
+        }
+    }
+}
+
+/// Reads the system's resolver configuration, see [`DnsResolver::new`].
+fn system_config() -> (ResolverConfig, ResolverOpts) {
+    let (system_config, mut options) =
+        hickory_resolver::system_conf::read_system_conf().unwrap_or_default();
+
+    // Copy all of the system config, but strip the bad windows nameservers.  Unfortunately
+    // there is no easy way to do this.
+    let mut config = ResolverConfig::new();
+    if let Some(name) = system_config.domain() {
+        config.set_domain(name.clone());
+    }
+    for name in system_config.search() {
+        config.add_search(name.clone());
+    }
+    for nameserver_cfg in system_config.name_servers() {
+        if !WINDOWS_BAD_SITE_LOCAL_DNS_SERVERS.contains(&nameserver_cfg.socket_addr.ip()) {
+            config.add_name_server(nameserver_cfg.clone());
+        }
+    }
+
+    // see [`DnsResolver::lookup_ipv4_ipv6`] for info on why we avoid `LookupIpStrategy::Ipv4AndIpv6`
+    options.ip_strategy = LookupIpStrategy::Ipv4thenIpv6;
+    (config, options)
+}
+
+/// The TLS configuration for DNS-over-TLS and DNS-over-HTTPS, verifying against the
+/// [`webpki_roots`].
+fn default_tls_config() -> rustls::ClientConfig {
+    let roots = rustls::RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+    rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .expect("protocols supported by ring")
+        .with_root_certificates(roots)
+        .with_no_client_auth()
+}
+
+/// Normalizes a host name to be used as a key of [`DnsResolverBuilder::host`].
+fn normalize_host(host: &str) -> String {
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
 /// TXT records returned from [`DnsResolver::lookup_txt`]
 #[derive(Debug, Clone)]
 pub struct TxtLookup(hickory_resolver::lookup::TxtLookup);
@@ -283,6 +619,15 @@ impl From<hickory_resolver::lookup::TxtLookup> for TxtLookup {
     }
 }
 
+impl TxtLookup {
+    /// Returns how long these records remain valid, based on their TTL.
+    pub fn ttl(&self) -> Duration {
+        self.0
+            .valid_until()
+            .saturating_duration_since(std::time::Instant::now())
+    }
+}
+
 impl IntoIterator for TxtLookup {
     type Item = TXT;
 
@@ -406,4 +751,46 @@ pub(crate) mod tests {
         let result = stagger_call(f, &delays).await.unwrap();
         assert_eq!(result, 5)
     }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn static_hosts() -> Result<()> {
+        let v4: IpAddr = "10.0.0.1".parse()?;
+        let v6: IpAddr = "fd00::1".parse()?;
+        // The nameserver is unreachable, static hosts must not query it.
+        let resolver = DnsResolver::builder()
+            .nameserver("127.0.0.1:1".parse()?, DnsProtocol::Udp)
+            .timeout(Duration::from_millis(100))
+            .attempts(1)
+            .host("Relay.Example.", [v4])
+            .host("relay.example", [v6])
+            .host("v4only.example", [v4])
+            .build();
+
+        let addrs = resolver
+            .lookup_ipv4_ipv6("relay.example", Duration::from_secs(1))
+            .await?
+            .collect::<Vec<_>>();
+        assert_eq!(addrs, vec![v4, v6]);
+
+        let url = "https://RELAY.example:443/".parse()?;
+        assert_eq!(
+            resolver
+                .resolve_host(&url, true, Duration::from_secs(1))
+                .await?,
+            v6
+        );
+        assert_eq!(
+            resolver
+                .resolve_host(&url, false, Duration::from_secs(1))
+                .await?,
+            v4
+        );
+
+        assert!(resolver
+            .lookup_ipv6("v4only.example", Duration::from_secs(1))
+            .await
+            .is_err());
+        Ok(())
+    }
 }
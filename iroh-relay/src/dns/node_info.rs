@@ -25,6 +25,9 @@
 //! - `addr=<addr> <addr>`: A space-separated list of sockets addresses for this iroh node.
 //!   Each address is an IPv4 or IPv6 address with a port.
 //!
+//! - `user-data=<string>`: Arbitrary application data published by the node, see
+//!   [`UserData`].
+//!
 //! [Pkarr]: https://app.pkarr.org
 //! [z-base-32]: https://philzimmermann.com/docs/human-oriented-base-32-encoding.txt
 //! [RFC1464]: https://www.rfc-editor.org/rfc/rfc1464
@@ -38,6 +41,7 @@ use std::{
     hash::Hash,
     net::SocketAddr,
     str::FromStr,
+    time::Duration,
 };
 
 use anyhow::{anyhow, Result};
@@ -63,6 +67,8 @@ pub(super) enum IrohAttr {
     Relay,
     /// Direct address.
     Addr,
+    /// User-defined data.
+    UserData,
 }
 
 /// Extension methods for [`NodeId`] to encode to and decode from [`z32`],
@@ -92,6 +98,82 @@ impl NodeIdExt for NodeId {
     }
 }
 
+/// Application-defined data published along with the addressing information of a node.
+///
+/// This can be used to announce small pieces of metadata, e.g. a service name, supported
+/// protocols or a version, through all discovery services publishing [`NodeInfo`].  Like
+/// the rest of the [`NodeInfo`] it is signed by the node's [`SecretKey`] when published
+/// to pkarr.
+///
+/// The data is a UTF-8 string of at most [`UserData::MAX_LENGTH`] bytes, so that it fits
+/// into a single character string of a TXT record.
+#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, serde::Serialize)]
+pub struct UserData(String);
+
+impl UserData {
+    /// The maximum length of [`UserData`] in bytes.
+    ///
+    /// A TXT character string can hold 255 bytes, of which `user-data=` takes 10.
+    pub const MAX_LENGTH: usize = 245;
+
+    /// Returns the user data as a string slice.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Error returned when creating [`UserData`] longer than [`UserData::MAX_LENGTH`].
+#[derive(Debug, Clone, thiserror::Error)]
+#[error(
+    "user data is {len} bytes long, but at most {} bytes are allowed",
+    UserData::MAX_LENGTH
+)]
+pub struct MaxLengthExceededError {
+    len: usize,
+}
+
+impl TryFrom<String> for UserData {
+    type Error = MaxLengthExceededError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        if value.len() > Self::MAX_LENGTH {
+            Err(MaxLengthExceededError { len: value.len() })
+        } else {
+            Ok(Self(value))
+        }
+    }
+}
+
+impl FromStr for UserData {
+    type Err = MaxLengthExceededError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::try_from(s.to_string())
+    }
+}
+
+impl Display for UserData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl AsRef<str> for UserData {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for UserData {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        Self::try_from(s).map_err(serde::de::Error::custom)
+    }
+}
+
 /// Information about the iroh node contained in an [`IROH_TXT_NAME`] TXT resource record.
 #[derive(derive_more::Debug, Clone, Eq, PartialEq)]
 pub struct NodeInfo {
@@ -102,6 +184,8 @@ pub struct NodeInfo {
     pub relay_url: Option<Url>,
     /// Any direct addresses.
     pub direct_addresses: BTreeSet<SocketAddr>,
+    /// Optional application-defined data.
+    pub user_data: Option<UserData>,
 }
 
 impl From<TxtAttrs<IrohAttr>> for NodeInfo {
@@ -126,10 +210,17 @@ impl From<&TxtAttrs<IrohAttr>> for NodeInfo {
             .flatten()
             .filter_map(|s| SocketAddr::from_str(s).ok())
             .collect();
+        let user_data = attrs
+            .get(&IrohAttr::UserData)
+            .into_iter()
+            .flatten()
+            .next()
+            .and_then(|s| UserData::from_str(s).ok());
         Self {
             node_id,
             relay_url,
             direct_addresses,
+            user_data,
         }
     }
 }
@@ -143,6 +234,9 @@ impl From<&NodeInfo> for TxtAttrs<IrohAttr> {
         for addr in &info.direct_addresses {
             attrs.push((IrohAttr::Addr, addr.to_string()));
         }
+        if let Some(user_data) = &info.user_data {
+            attrs.push((IrohAttr::UserData, user_data.to_string()));
+        }
         Self::from_parts(info.node_id, attrs.into_iter())
     }
 }
@@ -168,9 +262,16 @@ impl NodeInfo {
             node_id,
             relay_url,
             direct_addresses,
+            user_data: None,
         }
     }
 
+    /// Sets the [`UserData`] of this [`NodeInfo`].
+    pub fn with_user_data(mut self, user_data: Option<UserData>) -> Self {
+        self.user_data = user_data;
+        self
+    }
+
     fn to_attrs(&self) -> TxtAttrs<IrohAttr> {
         self.into()
     }
@@ -251,8 +352,7 @@ impl<T: FromStr + Display + Hash + Ord> TxtAttrs<T> {
     ) -> Result<Self> {
         let mut attrs: BTreeMap<T, Vec<String>> = BTreeMap::new();
         for s in strings {
-            let mut parts = s.split('=');
-            let (Some(key), Some(value)) = (parts.next(), parts.next()) else {
+            let Some((key, value)) = s.split_once('=') else {
                 continue;
             };
             let Ok(attr) = T::from_str(key) else {
@@ -264,10 +364,17 @@ impl<T: FromStr + Display + Hash + Ord> TxtAttrs<T> {
     }
 
     async fn lookup(resolver: &DnsResolver, name: Name) -> Result<Self> {
+        let (attrs, _ttl) = Self::lookup_with_ttl(resolver, name).await?;
+        Ok(attrs)
+    }
+
+    /// Looks up attributes from DNS, together with how long the records remain valid.
+    async fn lookup_with_ttl(resolver: &DnsResolver, name: Name) -> Result<(Self, Duration)> {
         let name = ensure_iroh_txt_label(name)?;
         let lookup = resolver.lookup_txt(name, DNS_TIMEOUT).await?;
+        let ttl = lookup.ttl();
         let attrs = Self::from_txt_lookup(lookup)?;
-        Ok(attrs)
+        Ok((attrs, ttl))
     }
 
     /// Looks up attributes by [`NodeId`] and origin domain.
@@ -280,6 +387,17 @@ impl<T: FromStr + Display + Hash + Ord> TxtAttrs<T> {
         TxtAttrs::lookup(resolver, name).await
     }
 
+    /// Looks up attributes by [`NodeId`] and origin domain, together with how long the
+    /// records remain valid.
+    pub(super) async fn lookup_by_id_with_ttl(
+        resolver: &DnsResolver,
+        node_id: &NodeId,
+        origin: &str,
+    ) -> Result<(Self, Duration)> {
+        let name = node_domain(node_id, origin)?;
+        TxtAttrs::lookup_with_ttl(resolver, name).await
+    }
+
     /// Looks up attributes by DNS name.
     pub(super) async fn lookup_by_name(resolver: &DnsResolver, name: &str) -> Result<Self> {
         let name = Name::from_str(name)?;
@@ -432,7 +550,7 @@ mod tests {
     use iroh_base::{NodeId, SecretKey};
     use testresult::TestResult;
 
-    use super::{NodeIdExt, NodeInfo};
+    use super::{NodeIdExt, NodeInfo, UserData};
 
     #[test]
     fn txt_attr_roundtrip() {
@@ -442,6 +560,7 @@ mod tests {
                 .unwrap(),
             relay_url: Some("https://example.com".parse().unwrap()),
             direct_addresses: ["127.0.0.1:1234".parse().unwrap()].into_iter().collect(),
+            user_data: Some("service=foo;version=1".parse().unwrap()),
         };
         let attrs = expected.to_attrs();
         let actual = NodeInfo::from(&attrs);
@@ -456,12 +575,28 @@ mod tests {
             node_id: secret_key.public(),
             relay_url: Some("https://example.com".parse().unwrap()),
             direct_addresses: ["127.0.0.1:1234".parse().unwrap()].into_iter().collect(),
+            user_data: Some("foobar".parse().unwrap()),
         };
         let packet = expected.to_pkarr_signed_packet(&secret_key, 30).unwrap();
         let actual = NodeInfo::from_pkarr_signed_packet(&packet).unwrap();
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    fn user_data_max_length() {
+        let max = "a".repeat(UserData::MAX_LENGTH);
+        assert!(UserData::try_from(max.clone()).is_ok());
+        assert!(UserData::try_from(format!("{max}a")).is_err());
+
+        // The longest user data still fits into a single TXT character string.
+        let secret_key = SecretKey::generate(rand::thread_rng());
+        let info = NodeInfo::new(secret_key.public(), None, Default::default())
+            .with_user_data(Some(max.parse().unwrap()));
+        let packet = info.to_pkarr_signed_packet(&secret_key, 30).unwrap();
+        let actual = NodeInfo::from_pkarr_signed_packet(&packet).unwrap();
+        assert_eq!(info, actual);
+    }
+
     /// There used to be a bug where uploading a NodeAddr with more than only exactly
     /// one relay URL or one publicly reachable IP addr would prevent connection
     /// establishment.
@@ -532,7 +667,8 @@ mod tests {
                 direct_addresses: BTreeSet::from([
                     "192.168.96.145:60165".parse()?,
                     "213.208.157.87:60165".parse()?,
-                ])
+                ]),
+                user_data: None,
             }
         );
 
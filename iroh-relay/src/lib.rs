@@ -34,6 +34,8 @@ pub mod client;
 pub mod defaults;
 pub mod http;
 pub mod protos;
+#[cfg(feature = "proxy-protocol")]
+pub mod proxy_protocol;
 pub mod quic;
 #[cfg(feature = "server")]
 pub mod server;
@@ -169,6 +169,12 @@ struct Config {
     /// This controls which nodes are allowed to relay connections, other endpoints, like STUN are not controlled by this.
     #[serde(default)]
     access: AccessConfig,
+    /// PROXY protocol support for running behind a TCP load balancer.
+    ///
+    /// When present, connections to the HTTP and HTTPS servers from one of the
+    /// `trusted_proxies` networks must start with a PROXY protocol v1 or v2 header carrying
+    /// the address of the client.  Disabled if not present.
+    proxy_protocol: Option<relay::ProxyProtocolConfig>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
@@ -250,6 +256,7 @@ impl Default for Config {
             metrics_bind_addr: None,
             key_cache_capacity: Default::default(),
             access: AccessConfig::Everyone,
+            proxy_protocol: None,
         }
     }
 }
@@ -381,10 +388,23 @@ impl TlsConfig {
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 struct Limits {
-    /// Rate limit for accepting new connection. Unlimited if not set.
+    /// Rate limit for accepting new connection, per second. Unlimited if not set.
     accept_conn_limit: Option<f64>,
     /// Burst limit for accepting new connection. Unlimited if not set.
     accept_conn_burst: Option<usize>,
+    /// Rate limit for accepting new connections from a single IP address, per second.
+    ///
+    /// IPv6 addresses are limited per /64 network.  Unlimited if not set.
+    accept_conn_limit_per_ip: Option<f64>,
+    /// Burst limit for accepting new connections from a single IP address. Unlimited if not
+    /// set.
+    accept_conn_burst_per_ip: Option<usize>,
+    /// Maximum number of concurrent relay connections from a single IP address.
+    ///
+    /// IPv6 addresses are limited per /64 network.  Unlimited if not set.
+    max_conns_per_ip: Option<usize>,
+    /// Maximum number of concurrently connected relay clients. Unlimited if not set.
+    max_clients: Option<usize>,
     /// Rate limiting configuration per client.
     client: Option<PerClientRateLimitConfig>,
 }
@@ -610,6 +630,10 @@ async fn build_relay_config(cfg: Config) -> Result<relay::ServerConfig<std::io::
             relay::Limits {
                 accept_conn_limit: limits.accept_conn_limit,
                 accept_conn_burst: limits.accept_conn_burst,
+                accept_conn_limit_per_ip: limits.accept_conn_limit_per_ip,
+                accept_conn_burst_per_ip: limits.accept_conn_burst_per_ip,
+                max_conns_per_ip: limits.max_conns_per_ip,
+                max_clients: limits.max_clients,
                 client_rx,
             }
         }
@@ -623,6 +647,7 @@ async fn build_relay_config(cfg: Config) -> Result<relay::ServerConfig<std::io::
         limits,
         key_cache_capacity: cfg.key_cache_capacity,
         access: cfg.access.clone().into(),
+        proxy_protocol: cfg.proxy_protocol.clone(),
     };
 
     let stun_config = relay::StunConfig {
@@ -719,6 +744,30 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_conn_limits_config() -> TestResult {
+        let config = "
+            [limits]
+            accept_conn_limit = 100.0
+            accept_conn_limit_per_ip = 2.5
+            accept_conn_burst_per_ip = 10
+            max_conns_per_ip = 8
+            max_clients = 10000
+        ";
+        let config = Config::from_str(config)?;
+        let relay_config = build_relay_config(config).await?;
+
+        let limits = relay_config.relay.expect("no relay config").limits;
+        assert_eq!(limits.accept_conn_limit, Some(100.0));
+        assert_eq!(limits.accept_conn_burst, None);
+        assert_eq!(limits.accept_conn_limit_per_ip, Some(2.5));
+        assert_eq!(limits.accept_conn_burst_per_ip, Some(10));
+        assert_eq!(limits.max_conns_per_ip, Some(8));
+        assert_eq!(limits.max_clients, Some(10000));
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_rate_limit_default() -> TestResult {
         let config = Config::from_str("")?;
@@ -753,4 +802,31 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_proxy_protocol_config() -> TestResult {
+        let config = "
+            [proxy_protocol]
+            trusted_proxies = [\"10.0.0.0/8\", \"fd00::/8\"]
+        ";
+        let config = Config::from_str(config)?;
+        let relay_config = build_relay_config(config).await?;
+
+        let relay = relay_config.relay.expect("no relay config");
+        let proxy_protocol = relay.proxy_protocol.expect("proxy protocol");
+        assert_eq!(
+            proxy_protocol.trusted_proxies,
+            vec!["10.0.0.0/8".parse()?, "fd00::/8".parse()?]
+        );
+
+        let config = Config::from_str("")?;
+        let relay_config = build_relay_config(config).await?;
+        assert!(relay_config
+            .relay
+            .expect("no relay config")
+            .proxy_protocol
+            .is_none());
+
+        Ok(())
+    }
 }
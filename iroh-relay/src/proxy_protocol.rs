@@ -0,0 +1,320 @@
+//! Support for the HAProxy PROXY protocol.
+//!
+//! When a server runs behind a TCP load balancer the peer address of each connection is the
+//! address of the load balancer.  Load balancers can pass on the address of the original
+//! client by sending a [PROXY protocol] header at the start of the connection.  Both the
+//! human-readable version 1 and the binary version 2 of the header are supported.
+//!
+//! Only connections from the configured trusted proxies are expected to start with a header,
+//! otherwise any client could claim to connect from any address.  Connections from other
+//! peers are used as they are.
+//!
+//! [PROXY protocol]: https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt
+
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Maximum time to wait for the PROXY protocol header of a connection.
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Signature starting a version 2 header.
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Signature starting a version 1 header.
+const V1_SIGNATURE: [u8; 5] = *b"PROXY";
+
+/// Maximum length of a version 1 header, including the final CRLF.
+const V1_MAX_LEN: usize = 107;
+
+/// Configuration for accepting the PROXY protocol on a listener.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProxyProtocolConfig {
+    /// Networks of the proxies allowed to send a PROXY protocol header.
+    ///
+    /// Connections from these networks must start with a header.  Connections from anywhere
+    /// else are served without reading a header.
+    pub trusted_proxies: Vec<IpNet>,
+}
+
+impl ProxyProtocolConfig {
+    /// Creates a config trusting the given proxy networks.
+    pub fn new(trusted_proxies: impl IntoIterator<Item = IpNet>) -> Self {
+        Self {
+            trusted_proxies: trusted_proxies.into_iter().collect(),
+        }
+    }
+
+    /// Whether connections from this address are expected to send a header.
+    pub fn is_trusted(&self, addr: IpAddr) -> bool {
+        let addr = addr.to_canonical();
+        self.trusted_proxies.iter().any(|net| net.contains(&addr))
+    }
+
+    /// Returns the address of the client of a newly accepted connection.
+    ///
+    /// If the peer is a trusted proxy, this reads the PROXY protocol header from the stream
+    /// and returns the client address from the header.  The stream is then positioned at the
+    /// first byte after the header.  For all other peers the stream is left untouched and
+    /// `peer_addr` is returned.
+    ///
+    /// Fails if a trusted proxy does not send a valid header in time.
+    pub async fn client_addr<S>(&self, stream: &mut S, peer_addr: SocketAddr) -> Result<SocketAddr>
+    where
+        S: AsyncRead + Unpin,
+    {
+        if !self.is_trusted(peer_addr.ip()) {
+            return Ok(peer_addr);
+        }
+        let header = tokio::time::timeout(HEADER_TIMEOUT, read_header(stream))
+            .await
+            .context("timeout reading PROXY protocol header")??;
+        Ok(header.map_or(peer_addr, |header| header.source))
+    }
+}
+
+/// The addresses of a proxied connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ProxyHeader {
+    /// Address of the client which connected to the proxy.
+    pub(crate) source: SocketAddr,
+    /// Address on the proxy the client connected to.
+    pub(crate) destination: SocketAddr,
+}
+
+/// Reads a version 1 or version 2 PROXY protocol header from the stream.
+///
+/// Does not read beyond the end of the header.  Returns `None` if the header does not carry
+/// addresses, e.g. for health checks of the proxy itself.
+pub(crate) async fn read_header<S>(stream: &mut S) -> Result<Option<ProxyHeader>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut start = [0u8; 5];
+    stream
+        .read_exact(&mut start)
+        .await
+        .context("reading PROXY protocol header")?;
+    if start == V1_SIGNATURE {
+        read_v1(stream).await
+    } else if start == V2_SIGNATURE[..5] {
+        read_v2(stream).await
+    } else {
+        bail!("missing PROXY protocol header");
+    }
+}
+
+/// Reads the rest of a version 1 header, after the `PROXY` signature.
+async fn read_v1<S>(stream: &mut S) -> Result<Option<ProxyHeader>>
+where
+    S: AsyncRead + Unpin,
+{
+    // The header has no length prefix, so it is read bytewise to not consume any data
+    // following it.
+    let mut line = Vec::with_capacity(V1_MAX_LEN);
+    line.extend_from_slice(&V1_SIGNATURE);
+    while !line.ends_with(b"\r\n") {
+        ensure!(line.len() < V1_MAX_LEN, "PROXY protocol v1 header too long");
+        line.push(stream.read_u8().await?);
+    }
+    let line =
+        std::str::from_utf8(&line[..line.len() - 2]).context("invalid PROXY protocol v1 header")?;
+    parse_v1(line)
+}
+
+fn parse_v1(line: &str) -> Result<Option<ProxyHeader>> {
+    let mut parts = line.split(' ');
+    ensure!(
+        parts.next() == Some("PROXY"),
+        "invalid PROXY protocol v1 header"
+    );
+    match parts.next() {
+        Some("TCP4") | Some("TCP6") => {}
+        Some("UNKNOWN") => return Ok(None),
+        _ => bail!("invalid PROXY protocol v1 protocol"),
+    }
+    let parts: Vec<&str> = parts.collect();
+    let [src_ip, dst_ip, src_port, dst_port] = parts[..] else {
+        bail!("invalid PROXY protocol v1 header");
+    };
+    let source = SocketAddr::new(src_ip.parse()?, src_port.parse()?);
+    let destination = SocketAddr::new(dst_ip.parse()?, dst_port.parse()?);
+    Ok(Some(ProxyHeader {
+        source,
+        destination,
+    }))
+}
+
+/// Reads the rest of a version 2 header, after the first 5 bytes of the signature.
+async fn read_v2<S>(stream: &mut S) -> Result<Option<ProxyHeader>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut header = [0u8; 11];
+    stream.read_exact(&mut header).await?;
+    ensure!(
+        header[..7] == V2_SIGNATURE[5..],
+        "invalid PROXY protocol v2 signature"
+    );
+    let version_command = header[7];
+    let family = header[8];
+    let len = u16::from_be_bytes([header[9], header[10]]);
+    let mut addresses = vec![0u8; usize::from(len)];
+    stream.read_exact(&mut addresses).await?;
+
+    ensure!(
+        version_command >> 4 == 2,
+        "unsupported PROXY protocol version"
+    );
+    match version_command & 0x0f {
+        // LOCAL: the connection was made by the proxy itself.
+        0x0 => return Ok(None),
+        // PROXY
+        0x1 => {}
+        _ => bail!("unsupported PROXY protocol v2 command"),
+    }
+    match family >> 4 {
+        // AF_INET
+        0x1 => {
+            ensure!(addresses.len() >= 12, "PROXY protocol v2 header too short");
+            let src_ip = Ipv4Addr::from(<[u8; 4]>::try_from(&addresses[0..4])?);
+            let dst_ip = Ipv4Addr::from(<[u8; 4]>::try_from(&addresses[4..8])?);
+            let src_port = u16::from_be_bytes([addresses[8], addresses[9]]);
+            let dst_port = u16::from_be_bytes([addresses[10], addresses[11]]);
+            Ok(Some(ProxyHeader {
+                source: SocketAddr::new(src_ip.into(), src_port),
+                destination: SocketAddr::new(dst_ip.into(), dst_port),
+            }))
+        }
+        // AF_INET6
+        0x2 => {
+            ensure!(addresses.len() >= 36, "PROXY protocol v2 header too short");
+            let src_ip = Ipv6Addr::from(<[u8; 16]>::try_from(&addresses[0..16])?);
+            let dst_ip = Ipv6Addr::from(<[u8; 16]>::try_from(&addresses[16..32])?);
+            let src_port = u16::from_be_bytes([addresses[32], addresses[33]]);
+            let dst_port = u16::from_be_bytes([addresses[34], addresses[35]]);
+            Ok(Some(ProxyHeader {
+                source: SocketAddr::new(src_ip.into(), src_port),
+                destination: SocketAddr::new(dst_ip.into(), dst_port),
+            }))
+        }
+        // AF_UNSPEC and AF_UNIX carry no usable address.
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v2_header(command: u8, family: u8, addresses: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.push(0x20 | command);
+        header.push(family);
+        header.extend_from_slice(&(addresses.len() as u16).to_be_bytes());
+        header.extend_from_slice(addresses);
+        header
+    }
+
+    #[tokio::test]
+    async fn test_v1() -> Result<()> {
+        let mut stream: &[u8] = b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\nGET /";
+        let header = read_header(&mut stream).await?;
+        assert_eq!(
+            header,
+            Some(ProxyHeader {
+                source: "192.0.2.1:56324".parse()?,
+                destination: "198.51.100.1:443".parse()?,
+            })
+        );
+        assert_eq!(stream, b"GET /");
+
+        let mut stream: &[u8] = b"PROXY TCP6 2001:db8::1 2001:db8::2 56324 443\r\n";
+        let header = read_header(&mut stream).await?.unwrap();
+        assert_eq!(header.source, "[2001:db8::1]:56324".parse()?);
+
+        let mut stream: &[u8] = b"PROXY UNKNOWN\r\n";
+        assert_eq!(read_header(&mut stream).await?, None);
+
+        let mut stream: &[u8] = b"PROXY TCP4 192.0.2.1\r\n";
+        assert!(read_header(&mut stream).await.is_err());
+
+        let long = format!("PROXY UNKNOWN {}\r\n", "x".repeat(200));
+        let mut stream = long.as_bytes();
+        assert!(read_header(&mut stream).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_v2() -> Result<()> {
+        let mut data = v2_header(
+            0x1,
+            0x11,
+            &[192, 0, 2, 1, 198, 51, 100, 1, 0xdc, 0x04, 0x01, 0xbb],
+        );
+        data.extend_from_slice(b"GET /");
+        let mut stream = &data[..];
+        let header = read_header(&mut stream).await?;
+        assert_eq!(
+            header,
+            Some(ProxyHeader {
+                source: "192.0.2.1:56324".parse()?,
+                destination: "198.51.100.1:443".parse()?,
+            })
+        );
+        assert_eq!(stream, b"GET /");
+
+        let src: Ipv6Addr = "2001:db8::1".parse()?;
+        let dst: Ipv6Addr = "2001:db8::2".parse()?;
+        let mut addresses = [src.octets(), dst.octets()].concat();
+        addresses.extend_from_slice(&[0xdc, 0x04, 0x01, 0xbb]);
+        // Trailing TLVs are ignored.
+        addresses.extend_from_slice(&[0x04, 0x00, 0x01, 0x00]);
+        let data = v2_header(0x1, 0x21, &addresses);
+        let header = read_header(&mut &data[..]).await?.unwrap();
+        assert_eq!(header.source, "[2001:db8::1]:56324".parse()?);
+
+        let data = v2_header(0x0, 0x00, &[]);
+        assert_eq!(read_header(&mut &data[..]).await?, None);
+
+        let data = v2_header(0x1, 0x11, &[192, 0, 2, 1]);
+        assert!(read_header(&mut &data[..]).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_trusted_proxies() -> Result<()> {
+        let config = ProxyProtocolConfig::new(["10.0.0.0/8".parse()?, "fd00::/8".parse()?]);
+        assert!(config.is_trusted("10.1.2.3".parse()?));
+        assert!(config.is_trusted("::ffff:10.1.2.3".parse()?));
+        assert!(config.is_trusted("fd00::1".parse()?));
+        assert!(!config.is_trusted("192.0.2.1".parse()?));
+
+        let data = b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\nGET /";
+
+        // Untrusted peers can not spoof their address.
+        let peer_addr = "192.0.2.7:1234".parse()?;
+        let mut stream = &data[..];
+        assert_eq!(config.client_addr(&mut stream, peer_addr).await?, peer_addr);
+        assert_eq!(stream, &data[..]);
+
+        let peer_addr = "10.0.0.1:1234".parse()?;
+        let mut stream = &data[..];
+        assert_eq!(
+            config.client_addr(&mut stream, peer_addr).await?,
+            "192.0.2.1:56324".parse()?
+        );
+        assert_eq!(stream, b"GET /");
+
+        // Trusted proxies must send a header.
+        let mut stream: &[u8] = b"GET / HTTP/1.1\r\n";
+        assert!(config.client_addr(&mut stream, peer_addr).await.is_err());
+        Ok(())
+    }
+}
@@ -15,6 +15,9 @@
 //! - HTTPS `/ping`: Used for net_report probes.
 //! - HTTPS `/generate_204`: Used for net_report probes.
 //! - STUN: UDP port for STUN requests/responses.
+//!
+//! The `/relay` and `/ping` endpoints can also be served from another HTTP server using the
+//! [`Server::relay_service`] of a running server, or a standalone [`RelayService`].
 
 use std::{fmt, future::Future, net::SocketAddr, num::NonZeroU32, pin::Pin, sync::Arc};
 
@@ -45,6 +48,7 @@ use crate::{
 
 mod client;
 mod clients;
+mod conn_limits;
 mod http_server;
 mod metrics;
 pub(crate) mod resolver;
@@ -53,9 +57,11 @@ pub(crate) mod streams;
 pub mod testing;
 
 pub use self::{
+    http_server::{ClientAddr, RelayService, RelayServiceBuilder},
     metrics::{Metrics, StunMetrics},
     resolver::{ReloadingResolver, DEFAULT_CERT_RELOAD_INTERVAL},
 };
+pub use crate::proxy_protocol::ProxyProtocolConfig;
 
 const NO_CONTENT_CHALLENGE_HEADER: &str = "X-Tailscale-Challenge";
 const NO_CONTENT_RESPONSE_HEADER: &str = "X-Tailscale-Response";
@@ -123,6 +129,11 @@ pub struct RelayConfig<EC: fmt::Debug, EA: fmt::Debug = EC> {
     pub key_cache_capacity: Option<usize>,
     /// Access configuration.
     pub access: AccessConfig,
+    /// PROXY protocol configuration for the HTTP and HTTPS listeners, disabled if `None`.
+    ///
+    /// Enable this when running behind a TCP load balancer, so that the addresses of the
+    /// clients instead of the load balancer are used.
+    pub proxy_protocol: Option<ProxyProtocolConfig>,
 }
 
 /// Controls which nodes are allowed to use the relay.
@@ -202,13 +213,26 @@ pub struct TlsConfig<EC: fmt::Debug, EA: fmt::Debug = EC> {
 }
 
 /// Rate limits.
-// TODO: accept_conn_limit and accept_conn_burst are not currently implemented.
+///
+/// The per-IP limits apply to IPv6 addresses per /64 network.  They use the client addresses
+/// from the PROXY protocol header if [`RelayConfig::proxy_protocol`] is enabled.
 #[derive(Debug, Default)]
 pub struct Limits {
-    /// Rate limit for accepting new connection. Unlimited if not set.
+    /// Rate limit for accepting new connection, per second. Unlimited if not set.
     pub accept_conn_limit: Option<f64>,
     /// Burst limit for accepting new connection. Unlimited if not set.
     pub accept_conn_burst: Option<usize>,
+    /// Rate limit for accepting new connections from a single IP address, per second.
+    /// Unlimited if not set.
+    pub accept_conn_limit_per_ip: Option<f64>,
+    /// Burst limit for accepting new connections from a single IP address. Unlimited if not
+    /// set.
+    pub accept_conn_burst_per_ip: Option<usize>,
+    /// Maximum number of concurrent relay connections from a single IP address. Unlimited if
+    /// not set.
+    pub max_conns_per_ip: Option<usize>,
+    /// Maximum number of concurrently connected relay clients. Unlimited if not set.
+    pub max_clients: Option<usize>,
     /// Rate limits for incoming traffic from a client connection.
     pub client_rx: Option<ClientRateLimit>,
 }
@@ -260,6 +284,8 @@ pub struct Server {
     quic_addr: Option<SocketAddr>,
     /// Handle to the relay server.
     relay_handle: Option<http_server::ServerHandle>,
+    /// The service of the relay server.
+    relay_service: Option<RelayService>,
     /// Handle to the quic server.
     quic_handle: Option<QuicServerHandle>,
     /// The main task running the server.
@@ -354,6 +380,7 @@ impl Server {
                     .headers(headers)
                     .key_cache_capacity(key_cache_capacity)
                     .access(relay_config.access)
+                    .proxy_protocol(relay_config.proxy_protocol.clone())
                     .request_handler(Method::GET, "/", Box::new(root_handler))
                     .request_handler(Method::GET, "/index.html", Box::new(root_handler))
                     .request_handler(Method::GET, RELAY_PROBE_PATH, Box::new(probe_handler))
@@ -361,6 +388,7 @@ impl Server {
                 if let Some(cfg) = relay_config.limits.client_rx {
                     builder = builder.client_rx_ratelimit(cfg);
                 }
+                builder = builder.limits(&relay_config.limits)?;
                 let http_addr = match relay_config.tls {
                     Some(tls_config) => {
                         let server_tls_config = match tls_config.cert {
@@ -404,7 +432,7 @@ impl Server {
                             .context("failed to bind http")?;
                         let http_addr = http_listener.local_addr()?;
                         tasks.spawn(
-                            run_captive_portal_service(http_listener)
+                            run_captive_portal_service(http_listener, relay_config.proxy_protocol)
                                 .instrument(info_span!("http-service", addr = %http_addr)),
                         );
                         Some(http_addr)
@@ -429,6 +457,7 @@ impl Server {
         // relay_server is serving HTTP, including the /generate_204 service.
         let relay_addr = relay_server.as_ref().map(|srv| srv.addr());
         let relay_handle = relay_server.as_ref().map(|srv| srv.handle());
+        let relay_service = relay_server.as_ref().map(|srv| srv.service().clone());
         let task = tokio::spawn(relay_supervisor(tasks, relay_server, quic_server));
 
         Ok(Self {
@@ -437,6 +466,7 @@ impl Server {
             https_addr: http_addr.and(relay_addr),
             quic_addr,
             relay_handle,
+            relay_service,
             quic_handle,
             supervisor: AbortOnDropHandle::new(task),
             certificates,
@@ -486,6 +516,15 @@ impl Server {
         self.stun_addr
     }
 
+    /// The [`RelayService`] of the relay server, if configured.
+    ///
+    /// This can be mounted into another HTTP server to serve the relay endpoints from there
+    /// too.  Clients connected via either server can talk to each other and count towards
+    /// the same connection limits.
+    pub fn relay_service(&self) -> Option<RelayService> {
+        self.relay_service.clone()
+    }
+
     /// The certificates chain if configured with manual TLS certificates.
     pub fn certificates(&self) -> Option<Vec<rustls::pki_types::CertificateDer<'static>>> {
         self.certificates.clone()
@@ -729,8 +768,12 @@ fn is_challenge_char(c: char) -> bool {
 }
 
 /// This is a future that never returns, drop it to cancel/abort.
-async fn run_captive_portal_service(http_listener: TcpListener) -> Result<()> {
+async fn run_captive_portal_service(
+    http_listener: TcpListener,
+    proxy_protocol: Option<ProxyProtocolConfig>,
+) -> Result<()> {
     info!("serving");
+    let proxy_protocol = proxy_protocol.map(Arc::new);
 
     // If this future is cancelled, this is dropped and all tasks are aborted.
     let mut tasks = JoinSet::new();
@@ -749,11 +792,18 @@ async fn run_captive_portal_service(http_listener: TcpListener) -> Result<()> {
 
             res = http_listener.accept() => {
                 match res {
-                    Ok((stream, peer_addr)) => {
+                    Ok((mut stream, peer_addr)) => {
                         debug!(%peer_addr, "Connection opened",);
                         let handler = CaptivePortalService;
+                        let proxy_protocol = proxy_protocol.clone();
 
                         tasks.spawn(async move {
+                            if let Some(config) = proxy_protocol {
+                                if let Err(err) = config.client_addr(&mut stream, peer_addr).await {
+                                    debug!(%peer_addr, "Dropping connection: {err:#}");
+                                    return;
+                                }
+                            }
                             let stream = crate::server::streams::MaybeTlsStream::Plain(stream);
                             let stream = hyper_util::rt::TokioIo::new(stream);
                             if let Err(err) = hyper::server::conn::http1::Builder::new()
@@ -829,6 +879,7 @@ mod tests {
                 limits: Default::default(),
                 key_cache_capacity: Some(1024),
                 access: AccessConfig::Everyone,
+                proxy_protocol: None,
             }),
             quic: None,
             stun: None,
@@ -884,6 +935,7 @@ mod tests {
                 limits: Default::default(),
                 key_cache_capacity: Some(1024),
                 access: AccessConfig::Everyone,
+                proxy_protocol: None,
             }),
             stun: None,
             quic: None,
@@ -931,6 +983,47 @@ mod tests {
         assert!(body.is_empty());
     }
 
+    #[tokio::test]
+    #[traced_test]
+    async fn test_proxy_protocol() -> TestResult<()> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let server = Server::spawn(ServerConfig::<(), ()> {
+            relay: Some(RelayConfig::<(), ()> {
+                http_bind_addr: (Ipv4Addr::LOCALHOST, 0).into(),
+                tls: None,
+                limits: Default::default(),
+                key_cache_capacity: Some(1024),
+                access: AccessConfig::Everyone,
+                proxy_protocol: Some(ProxyProtocolConfig::new(["127.0.0.0/8".parse()?])),
+            }),
+            quic: None,
+            stun: None,
+            metrics_addr: None,
+        })
+        .await?;
+        let addr = server.http_addr().unwrap();
+        let request = b"GET /ping HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await?;
+        stream
+            .write_all(b"PROXY TCP4 192.0.2.1 127.0.0.1 56324 80\r\n")
+            .await?;
+        stream.write_all(request).await?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+
+        // The trusted proxy must send a header.
+        let mut stream = tokio::net::TcpStream::connect(addr).await?;
+        stream.write_all(request).await?;
+        let mut response = String::new();
+        // The connection is either closed or reset, depending on the unread data.
+        let _ = stream.read_to_string(&mut response).await;
+        assert!(response.is_empty(), "{response}");
+        Ok(())
+    }
+
     #[tokio::test]
     #[traced_test]
     async fn test_relay_client_legacy_route() {
@@ -1001,6 +1094,60 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    #[traced_test]
+    async fn test_relay_service_shared_with_other_server() -> TestResult<()> {
+        let server = spawn_local_relay().await?;
+        let relay_url: RelayUrl = format!("http://{}", server.http_addr().unwrap()).parse()?;
+
+        // serve the relay service of the running server from another hyper server
+        let service = server.relay_service().context("relay not configured")?;
+        let listener = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        let other_url: RelayUrl = format!("http://{}", listener.local_addr()?).parse()?;
+        let other_server = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let service = service.clone();
+                let app = hyper::service::service_fn(move |req: Request<Incoming>| {
+                    let mut service = service.clone();
+                    async move { tower::Service::call(&mut service, req).await }
+                });
+                tokio::spawn(
+                    hyper::server::conn::http1::Builder::new()
+                        .serve_connection(hyper_util::rt::TokioIo::new(stream), app)
+                        .with_upgrades(),
+                );
+            }
+        });
+
+        // clients connected via either server can talk to each other
+        let a_secret_key = SecretKey::generate(rand::thread_rng());
+        let a_key = a_secret_key.public();
+        let resolver = dns_resolver();
+        let mut client_a = ClientBuilder::new(relay_url, a_secret_key, resolver.clone())
+            .connect()
+            .await?;
+        let b_secret_key = SecretKey::generate(rand::thread_rng());
+        let b_key = b_secret_key.public();
+        let mut client_b = ClientBuilder::new(other_url, b_secret_key, resolver)
+            .connect()
+            .await?;
+
+        let msg = Bytes::from("hello, b");
+        let res = try_send_recv(&mut client_a, &mut client_b, b_key, msg.clone()).await?;
+        let ReceivedMessage::ReceivedPacket {
+            remote_node_id,
+            data,
+        } = res
+        else {
+            panic!("client_b received unexpected message {res:?}");
+        };
+        assert_eq!(a_key, remote_node_id);
+        assert_eq!(msg, data);
+
+        other_server.abort();
+        Ok(())
+    }
+
     #[tokio::test]
     #[traced_test]
     async fn test_relay_clients_both_websockets() -> TestResult<()> {
@@ -1175,6 +1322,7 @@ mod tests {
                     }
                     .boxed()
                 })),
+                proxy_protocol: None,
             }),
             quic: None,
             stun: None,
@@ -1241,4 +1389,79 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_relay_conn_limits() -> TestResult<()> {
+        let server = Server::spawn(ServerConfig::<(), ()> {
+            relay: Some(RelayConfig::<(), ()> {
+                http_bind_addr: (Ipv4Addr::LOCALHOST, 0).into(),
+                tls: None,
+                limits: Limits {
+                    max_conns_per_ip: Some(1),
+                    ..Default::default()
+                },
+                key_cache_capacity: Some(1024),
+                access: AccessConfig::Everyone,
+                proxy_protocol: None,
+            }),
+            quic: None,
+            stun: None,
+            metrics_addr: None,
+        })
+        .await?;
+        let relay_url: RelayUrl = format!("http://{}", server.http_addr().unwrap()).parse()?;
+        let connect = || {
+            let secret_key = SecretKey::generate(rand::thread_rng());
+            let builder = ClientBuilder::new(relay_url.clone(), secret_key, dns_resolver());
+            async move { builder.connect().await }
+        };
+
+        let mut client_a = connect().await?;
+        client_a.send(SendMessage::Ping([0u8; 8])).await?;
+
+        // a second connection from the same IP address is rejected
+        assert!(connect().await.is_err());
+
+        // the limit is shared with other servers that serve the relay service and pass on
+        // the client address
+        let service = server.relay_service().context("relay not configured")?;
+        let listener = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        let other_url: RelayUrl = format!("http://{}", listener.local_addr()?).parse()?;
+        let other_server = tokio::spawn(async move {
+            while let Ok((stream, client_addr)) = listener.accept().await {
+                let service = service.clone();
+                let app = hyper::service::service_fn(move |mut req: Request<Incoming>| {
+                    let mut service = service.clone();
+                    req.extensions_mut().insert(ClientAddr(client_addr));
+                    async move { tower::Service::call(&mut service, req).await }
+                });
+                tokio::spawn(
+                    hyper::server::conn::http1::Builder::new()
+                        .serve_connection(hyper_util::rt::TokioIo::new(stream), app)
+                        .with_upgrades(),
+                );
+            }
+        });
+        let secret_key = SecretKey::generate(rand::thread_rng());
+        let res = ClientBuilder::new(other_url, secret_key, dns_resolver())
+            .connect()
+            .await;
+        assert!(res.is_err());
+        other_server.abort();
+
+        // once the first client disconnects, a new one can connect
+        client_a.close().await?;
+        drop(client_a);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                if connect().await.is_ok() {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await?;
+        Ok(())
+    }
 }
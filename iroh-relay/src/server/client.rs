@@ -20,7 +20,10 @@ use crate::{
         disco,
         relay::{write_frame, Frame, PING_INTERVAL},
     },
-    server::{clients::Clients, metrics::Metrics, streams::RelayedStream, ClientRateLimit},
+    server::{
+        clients::Clients, conn_limits::ConnPermit, metrics::Metrics, streams::RelayedStream,
+        ClientRateLimit,
+    },
     PingTracker,
 };
 
@@ -41,6 +44,8 @@ pub(super) struct Config {
     pub(super) write_timeout: Duration,
     pub(super) channel_capacity: usize,
     pub(super) rate_limit: Option<ClientRateLimit>,
+    /// Slot of the connection in the connection limits, released when the client is dropped.
+    pub(super) conn_permit: Option<ConnPermit>,
 }
 
 /// The [`Server`] side representation of a [`Client`]'s connection.
@@ -63,6 +68,8 @@ pub(super) struct Client {
     disco_send_queue: mpsc::Sender<Packet>,
     /// Channel to notify the client that a previous sender has disconnected.
     peer_gone: mpsc::Sender<NodeId>,
+    /// Slot of the connection in the connection limits.
+    _conn_permit: Option<ConnPermit>,
 }
 
 impl Client {
@@ -76,6 +83,7 @@ impl Client {
             write_timeout,
             channel_capacity,
             rate_limit,
+            conn_permit,
         } = config;
 
         let stream = match rate_limit {
@@ -124,6 +132,7 @@ impl Client {
             send_queue: send_queue_s,
             disco_send_queue: disco_send_queue_s,
             peer_gone: peer_gone_s,
+            _conn_permit: conn_permit,
         }
     }
 
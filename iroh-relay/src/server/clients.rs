@@ -196,6 +196,7 @@ mod tests {
                 write_timeout: Duration::from_secs(1),
                 channel_capacity: 10,
                 rate_limit: None,
+                conn_permit: None,
             },
             FramedRead::new(test_io, RelayCodec::test()),
         )
@@ -0,0 +1,293 @@
+//! Limits on the connections accepted by the relay server.
+//!
+//! The limits are applied per IP address, so a single abusive host can not use up the limits
+//! of all other clients.  IPv6 clients usually have a whole /64 network at their disposal, so
+//! IPv6 addresses are grouped by their /64 network.
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv6Addr},
+    num::NonZeroU32,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use anyhow::{ensure, Context, Result};
+use governor::{DefaultDirectRateLimiter, DefaultKeyedRateLimiter, Quota, RateLimiter};
+use http::StatusCode;
+
+use super::Limits;
+
+/// Number of accepted connections after which the state of idle IP addresses is removed from
+/// the per-IP rate limiter.
+const RETAIN_INTERVAL: u64 = 1024;
+
+/// Rate limits for accepting new TCP connections.
+#[derive(Debug, Default)]
+pub(super) struct AcceptLimiter {
+    global: Option<DefaultDirectRateLimiter>,
+    per_ip: Option<DefaultKeyedRateLimiter<IpAddr>>,
+    /// Number of connections checked, to periodically clean up the per-IP state.
+    checked: AtomicU64,
+}
+
+impl AcceptLimiter {
+    pub(super) fn new(limits: &Limits) -> Result<Self> {
+        let global = quota(limits.accept_conn_limit, limits.accept_conn_burst)
+            .context("invalid accept_conn_limit")?
+            .map(RateLimiter::direct);
+        let per_ip = quota(
+            limits.accept_conn_limit_per_ip,
+            limits.accept_conn_burst_per_ip,
+        )
+        .context("invalid accept_conn_limit_per_ip")?
+        .map(RateLimiter::keyed);
+        Ok(Self {
+            global,
+            per_ip,
+            checked: AtomicU64::new(0),
+        })
+    }
+
+    /// Checks whether a new connection from `ip` may be accepted.
+    pub(super) fn check(&self, ip: IpAddr) -> Result<(), AcceptRejection> {
+        if let Some(ref per_ip) = self.per_ip {
+            if self.checked.fetch_add(1, Ordering::Relaxed) % RETAIN_INTERVAL == 0 {
+                per_ip.retain_recent();
+            }
+            // Check the per-IP limit first, so rejected connections of a single IP do not
+            // use up the global limit.
+            if per_ip.check_key(&ip_key(ip)).is_err() {
+                return Err(AcceptRejection::PerIp);
+            }
+        }
+        if let Some(ref global) = self.global {
+            if global.check().is_err() {
+                return Err(AcceptRejection::Global);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The limit which rejected a new connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum AcceptRejection {
+    /// The accept rate limit of all connections.
+    Global,
+    /// The accept rate limit of the connections from the IP address.
+    PerIp,
+}
+
+/// Limits on the number of concurrent relay connections.
+#[derive(Debug, Default)]
+pub(super) struct ConnLimiter {
+    max_clients: Option<usize>,
+    max_conns_per_ip: Option<usize>,
+    counts: Arc<Mutex<ConnCounts>>,
+}
+
+#[derive(Debug, Default)]
+struct ConnCounts {
+    total: usize,
+    per_ip: HashMap<IpAddr, usize>,
+}
+
+impl ConnLimiter {
+    pub(super) fn new(limits: &Limits) -> Self {
+        Self {
+            max_clients: limits.max_clients,
+            max_conns_per_ip: limits.max_conns_per_ip,
+            counts: Default::default(),
+        }
+    }
+
+    /// Reserves a slot for a new relay connection from `ip`.
+    ///
+    /// The slot is released when the returned [`ConnPermit`] is dropped.  If the IP address
+    /// of the client is not known, only the maximum number of clients is enforced.
+    pub(super) fn acquire(&self, ip: Option<IpAddr>) -> Result<ConnPermit, ConnRejection> {
+        let key = ip.map(ip_key);
+        let mut counts = self.counts.lock().expect("poisoned");
+        if self.max_clients.is_some_and(|max| counts.total >= max) {
+            return Err(ConnRejection::MaxClients);
+        }
+        if let Some(key) = key {
+            let conns = counts.per_ip.entry(key).or_default();
+            if self.max_conns_per_ip.is_some_and(|max| *conns >= max) {
+                return Err(ConnRejection::MaxConnsPerIp);
+            }
+            *conns += 1;
+        }
+        counts.total += 1;
+        Ok(ConnPermit {
+            counts: self.counts.clone(),
+            key,
+        })
+    }
+}
+
+/// The limit which rejected a new relay connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum ConnRejection {
+    /// The maximum number of clients is connected.
+    MaxClients,
+    /// The maximum number of connections from the IP address is reached.
+    MaxConnsPerIp,
+}
+
+impl ConnRejection {
+    /// The status code of the response to the rejected upgrade request.
+    pub(super) fn status(&self) -> StatusCode {
+        match self {
+            Self::MaxClients => StatusCode::SERVICE_UNAVAILABLE,
+            Self::MaxConnsPerIp => StatusCode::TOO_MANY_REQUESTS,
+        }
+    }
+}
+
+/// A slot of a relay connection, released on drop.
+#[derive(Debug)]
+pub(super) struct ConnPermit {
+    counts: Arc<Mutex<ConnCounts>>,
+    key: Option<IpAddr>,
+}
+
+impl Drop for ConnPermit {
+    fn drop(&mut self) {
+        let mut counts = self.counts.lock().expect("poisoned");
+        counts.total -= 1;
+        if let Some(key) = self.key {
+            if let Some(conns) = counts.per_ip.get_mut(&key) {
+                *conns -= 1;
+                if *conns == 0 {
+                    counts.per_ip.remove(&key);
+                }
+            }
+        }
+    }
+}
+
+/// The key to limit the connections of `ip` by: the address, or its /64 network for IPv6.
+fn ip_key(ip: IpAddr) -> IpAddr {
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => IpAddr::V4(ip),
+        IpAddr::V6(ip) => IpAddr::V6(Ipv6Addr::from(u128::from(ip) & (u128::MAX << 64))),
+    }
+}
+
+/// Builds the quota for a rate limit of `limit` connections per second.
+///
+/// The burst defaults to the connections of one second.
+fn quota(limit: Option<f64>, burst: Option<usize>) -> Result<Option<Quota>> {
+    let Some(limit) = limit else {
+        ensure!(burst.is_none(), "a burst limit requires a rate limit");
+        return Ok(None);
+    };
+    ensure!(
+        limit.is_finite() && limit > 0.0,
+        "the rate limit must be positive"
+    );
+    let burst = burst.unwrap_or(limit.ceil() as usize);
+    let burst =
+        NonZeroU32::new(u32::try_from(burst)?).context("the burst limit must be positive")?;
+    let period = Duration::try_from_secs_f64(1.0 / limit).context("the rate limit is too low")?;
+    let quota = Quota::with_period(period)
+        .context("the rate limit is too high")?
+        .allow_burst(burst);
+    Ok(Some(quota))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ip_key() {
+        let key = |ip: &str| ip_key(ip.parse().unwrap());
+        assert_eq!(key("192.0.2.1"), key("::ffff:192.0.2.1"));
+        assert_ne!(key("192.0.2.1"), key("192.0.2.2"));
+        assert_eq!(key("2001:db8:1:2:3::1"), key("2001:db8:1:2:4::1"));
+        assert_ne!(key("2001:db8:1:2::1"), key("2001:db8:1:3::1"));
+    }
+
+    #[test]
+    fn test_accept_limiter() -> Result<()> {
+        let limiter = AcceptLimiter::new(&Limits {
+            accept_conn_limit: Some(0.1),
+            accept_conn_burst: Some(5),
+            accept_conn_limit_per_ip: Some(0.1),
+            accept_conn_burst_per_ip: Some(2),
+            ..Default::default()
+        })?;
+        let a = "192.0.2.1".parse()?;
+        assert_eq!(limiter.check(a), Ok(()));
+        assert_eq!(limiter.check(a), Ok(()));
+        assert_eq!(limiter.check(a), Err(AcceptRejection::PerIp));
+        // Addresses in the same /64 network share the limit, while the global limit is not
+        // used up yet.
+        assert_eq!(limiter.check("2001:db8::1".parse()?), Ok(()));
+        assert_eq!(limiter.check("2001:db8::2".parse()?), Ok(()));
+        assert_eq!(
+            limiter.check("2001:db8::3".parse()?),
+            Err(AcceptRejection::PerIp)
+        );
+        assert_eq!(limiter.check("2001:db8:0:1::1".parse()?), Ok(()));
+        assert_eq!(
+            limiter.check("2001:db8:0:2::1".parse()?),
+            Err(AcceptRejection::Global)
+        );
+
+        assert!(AcceptLimiter::new(&Limits {
+            accept_conn_burst: Some(3),
+            ..Default::default()
+        })
+        .is_err());
+        assert!(AcceptLimiter::new(&Limits {
+            accept_conn_limit_per_ip: Some(0.0),
+            ..Default::default()
+        })
+        .is_err());
+        assert!(AcceptLimiter::new(&Limits {
+            accept_conn_limit: Some(1e-20),
+            ..Default::default()
+        })
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_conn_limiter() -> Result<()> {
+        let limiter = ConnLimiter::new(&Limits {
+            max_clients: Some(3),
+            max_conns_per_ip: Some(2),
+            ..Default::default()
+        });
+        let a = "192.0.2.1".parse()?;
+        let b = "192.0.2.2".parse()?;
+
+        let a1 = limiter.acquire(Some(a)).unwrap();
+        let _a2 = limiter.acquire(Some(a)).unwrap();
+        assert_eq!(
+            limiter.acquire(Some(a)).unwrap_err(),
+            ConnRejection::MaxConnsPerIp
+        );
+        let _b1 = limiter.acquire(Some(b)).unwrap();
+        assert_eq!(
+            limiter.acquire(None).unwrap_err(),
+            ConnRejection::MaxClients
+        );
+
+        // Dropping a permit frees its slot.
+        drop(a1);
+        let _a3 = limiter.acquire(Some(a)).unwrap();
+        assert_eq!(
+            limiter.acquire(Some(b)).unwrap_err(),
+            ConnRejection::MaxClients
+        );
+        Ok(())
+    }
+}
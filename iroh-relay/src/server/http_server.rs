@@ -1,5 +1,11 @@
 use std::{
-    collections::HashMap, future::Future, net::SocketAddr, pin::Pin, sync::Arc, time::Duration,
+    collections::HashMap,
+    future::Future,
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
 };
 
 use anyhow::{bail, ensure, Context as _, Result};
@@ -24,10 +30,16 @@ use tokio_tungstenite::{
 use tokio_util::{codec::Framed, sync::CancellationToken, task::AbortOnDropHandle};
 use tracing::{debug, debug_span, error, info, info_span, trace, warn, Instrument};
 
-use super::{clients::Clients, AccessConfig};
+use super::{
+    clients::Clients,
+    conn_limits::{AcceptLimiter, AcceptRejection, ConnLimiter, ConnPermit, ConnRejection},
+    AccessConfig, Limits, ProxyProtocolConfig,
+};
 use crate::{
     defaults::{timeouts::SERVER_WRITE_TIMEOUT, DEFAULT_KEY_CACHE_CAPACITY},
-    http::{Protocol, LEGACY_RELAY_PATH, RELAY_PATH, SUPPORTED_WEBSOCKET_VERSION},
+    http::{
+        Protocol, LEGACY_RELAY_PATH, RELAY_PATH, RELAY_PROBE_PATH, SUPPORTED_WEBSOCKET_VERSION,
+    },
     protos::relay::{
         recv_client_key, Frame, RelayCodec, PER_CLIENT_SEND_QUEUE_DEPTH, PROTOCOL_VERSION,
     },
@@ -60,12 +72,16 @@ fn body_full(content: impl Into<hyper::body::Bytes>) -> BytesBody {
     http_body_util::Full::new(content.into())
 }
 
-fn downcast_upgrade(upgraded: Upgraded) -> Result<(MaybeTlsStream, Bytes)> {
+fn downcast_upgrade(upgraded: Upgraded) -> (MaybeTlsStream, Bytes) {
     match upgraded.downcast::<hyper_util::rt::TokioIo<MaybeTlsStream>>() {
-        Ok(parts) => Ok((parts.io.into_inner(), parts.read_buf)),
-        Err(_) => {
-            bail!("could not downcast the upgraded connection to MaybeTlsStream")
-        }
+        Ok(parts) => (parts.io.into_inner(), parts.read_buf),
+        // The connection is served by another HTTP server, which is the case when the
+        // `RelayService` is mounted into another application.  The upgraded connection
+        // then still contains any buffered data.
+        Err(upgraded) => (
+            MaybeTlsStream::Upgraded(hyper_util::rt::TokioIo::new(upgraded)),
+            Bytes::new(),
+        ),
     }
 }
 
@@ -79,6 +95,7 @@ fn downcast_upgrade(upgraded: Upgraded) -> Result<(MaybeTlsStream, Bytes)> {
 #[derive(Debug)]
 pub(super) struct Server {
     addr: SocketAddr,
+    service: RelayService,
     http_server_task: AbortOnDropHandle<()>,
     cancel_server_loop: CancellationToken,
 }
@@ -112,6 +129,11 @@ impl Server {
     pub(super) fn addr(&self) -> SocketAddr {
         self.addr
     }
+
+    /// Returns the [`RelayService`] serving the connections of this server.
+    pub(super) fn service(&self) -> &RelayService {
+        &self.service
+    }
 }
 
 /// A handle for the [`Server`].
@@ -166,6 +188,12 @@ pub(super) struct ServerBuilder {
     key_cache_capacity: usize,
     /// Access config for nodes.
     access: AccessConfig,
+    /// PROXY protocol configuration, disabled if `None`.
+    proxy_protocol: Option<Arc<ProxyProtocolConfig>>,
+    /// Rate limits for accepting connections.
+    accept_limiter: AcceptLimiter,
+    /// Limits on the number of relay connections.
+    conn_limiter: ConnLimiter,
 }
 
 impl ServerBuilder {
@@ -179,6 +207,9 @@ impl ServerBuilder {
             client_rx_ratelimit: None,
             key_cache_capacity: DEFAULT_KEY_CACHE_CAPACITY,
             access: AccessConfig::Everyone,
+            proxy_protocol: None,
+            accept_limiter: Default::default(),
+            conn_limiter: Default::default(),
         }
     }
 
@@ -194,6 +225,22 @@ impl ServerBuilder {
         self
     }
 
+    /// Reads the client address from a PROXY protocol header on connections from trusted
+    /// proxies.
+    pub(super) fn proxy_protocol(mut self, config: Option<ProxyProtocolConfig>) -> Self {
+        self.proxy_protocol = config.map(Arc::new);
+        self
+    }
+
+    /// Sets the limits for accepting connections and the number of relay connections.
+    ///
+    /// Fails if the rate limits are invalid.
+    pub(super) fn limits(mut self, limits: &Limits) -> Result<Self> {
+        self.accept_limiter = AcceptLimiter::new(limits)?;
+        self.conn_limiter = ConnLimiter::new(limits);
+        Ok(self)
+    }
+
     /// Sets the per-client rate-limit configuration for incoming data.
     ///
     /// On each client connection the incoming data is rate-limited.  By default
@@ -238,10 +285,13 @@ impl ServerBuilder {
             self.client_rx_ratelimit,
             KeyCache::new(self.key_cache_capacity),
             self.access,
+            self.conn_limiter,
         );
 
         let addr = self.addr;
         let tls_config = self.tls_config;
+        let proxy_protocol = self.proxy_protocol;
+        let accept_limiter = Arc::new(self.accept_limiter);
 
         // Bind a TCP listener on `addr` and handles content using HTTPS.
 
@@ -254,6 +304,7 @@ impl ServerBuilder {
         info!("[{http_str}] relay: serving on {addr}");
 
         let cancel = cancel_token.clone();
+        let server_service = service.clone();
         let task = tokio::task::spawn(
             async move {
                 // create a join set to track all our connection tasks
@@ -275,13 +326,17 @@ impl ServerBuilder {
                             Ok((stream, peer_addr)) => {
                                 debug!("connection opened from {peer_addr}");
                                 let tls_config = tls_config.clone();
+                                let proxy_protocol = proxy_protocol.clone();
+                                let accept_limiter = accept_limiter.clone();
                                 let service = service.clone();
                                 // spawn a task to handle the connection
-                                set.spawn(async move {
-                                    service
-                                        .handle_connection(stream, tls_config)
-                                        .await
-                                }.instrument(info_span!("conn", peer = %peer_addr)));
+                                set.spawn(service.accept_connection(
+                                    stream,
+                                    peer_addr,
+                                    proxy_protocol,
+                                    accept_limiter,
+                                    tls_config,
+                                ));
                             }
                             Err(err) => {
                                 error!("failed to accept connection: {err}");
@@ -298,15 +353,93 @@ impl ServerBuilder {
 
         Ok(Server {
             addr,
+            service: server_service,
             http_server_task: AbortOnDropHandle::new(task),
             cancel_server_loop: cancel_token,
         })
     }
 }
 
-/// The hyper Service that serves the actual relay endpoints.
+/// Builder for a [`RelayService`].
+///
+/// Created using [`RelayService::builder`].  The built service has its own connected clients
+/// and no connection limits, use [`crate::server::Server::relay_service`] to serve the same
+/// clients as a running relay server.
+#[derive(Debug)]
+pub struct RelayServiceBuilder {
+    headers: HeaderMap,
+    client_rx_ratelimit: Option<ClientRateLimit>,
+    key_cache_capacity: usize,
+    access: AccessConfig,
+}
+
+impl RelayServiceBuilder {
+    /// Set the access configuration.
+    pub fn access(mut self, access: AccessConfig) -> Self {
+        self.access = access;
+        self
+    }
+
+    /// Sets the per-client rate-limit configuration for incoming data.
+    ///
+    /// On each client connection the incoming data is rate-limited.  By default
+    /// no rate limit is enforced.
+    pub fn client_rx_ratelimit(mut self, config: ClientRateLimit) -> Self {
+        self.client_rx_ratelimit = Some(config);
+        self
+    }
+
+    /// Adds HTTP headers to responses.
+    pub fn headers(mut self, headers: HeaderMap) -> Self {
+        for (k, v) in headers.iter() {
+            self.headers.insert(k.clone(), v.clone());
+        }
+        self
+    }
+
+    /// Set the capacity of the cache for public keys.
+    pub fn key_cache_capacity(mut self, capacity: usize) -> Self {
+        self.key_cache_capacity = capacity;
+        self
+    }
+
+    /// Builds the [`RelayService`].
+    pub fn build(self) -> RelayService {
+        let mut handlers = Handlers::default();
+        handlers.insert(
+            (Method::GET, RELAY_PROBE_PATH),
+            Box::new(super::probe_handler),
+        );
+        RelayService::new(
+            handlers,
+            self.headers,
+            self.client_rx_ratelimit,
+            KeyCache::new(self.key_cache_capacity),
+            self.access,
+            ConnLimiter::default(),
+        )
+    }
+}
+
+/// The HTTP service that serves the relay endpoints.
+///
+/// This handles the upgrade of requests to [`RELAY_PATH`] to the relay protocol, for both
+/// [`Protocol::Relay`] and [`Protocol::Websocket`], and the latency probes at
+/// [`RELAY_PROBE_PATH`].  Requests to other paths get a `404 Not Found` response.
+///
+/// Besides being used by the relay server itself, this can be mounted into any hyper or
+/// tower based HTTP server, e.g. to serve the relay behind the same TLS terminator as other
+/// APIs.  Use [`RelayService::handles_request`] to decide which requests to route to this
+/// service.  The server must serve the connections with HTTP upgrades enabled.
+///
+/// All clones of the service share the same connected clients and connection limits, so
+/// clients connected via different HTTP servers can talk to each other.  The service of a
+/// running relay server is available from [`crate::server::Server::relay_service`].
+///
+/// The per-IP connection limit is only applied to requests with a [`ClientAddr`] extension,
+/// which the embedding server has to insert with the address of the client.
 #[derive(Clone, Debug)]
-struct RelayService(Arc<Inner>);
+pub struct RelayService(Arc<Inner>);
 
 #[derive(Debug)]
 struct Inner {
@@ -317,6 +450,33 @@ struct Inner {
     rate_limit: Option<ClientRateLimit>,
     key_cache: KeyCache,
     access: AccessConfig,
+    conn_limiter: ConnLimiter,
+}
+
+/// The address of the client of a connection, as a request extension for [`RelayService`].
+///
+/// The relay server adds this to the extensions of each request.  Servers embedding the
+/// [`RelayService`] need to add it as well, otherwise the per-IP connection limit is not
+/// applied to their clients.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientAddr(pub SocketAddr);
+
+/// The [`RelayService`] for a single connection, which adds the [`ClientAddr`] to requests.
+#[derive(Debug)]
+struct ConnService {
+    service: RelayService,
+    client_addr: SocketAddr,
+}
+
+impl Service<Request<Incoming>> for ConnService {
+    type Response = Response<BytesBody>;
+    type Error = HyperError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn call(&self, mut req: Request<Incoming>) -> Self::Future {
+        req.extensions_mut().insert(ClientAddr(self.client_addr));
+        self.service.call(req)
+    }
 }
 
 impl RelayService {
@@ -375,6 +535,26 @@ impl RelayService {
                     None
                 };
 
+                let client_ip = req.extensions().get::<ClientAddr>().map(|addr| addr.0.ip());
+                let conn_permit = match this.0.conn_limiter.acquire(client_ip) {
+                    Ok(permit) => permit,
+                    Err(rejection) => {
+                        match rejection {
+                            ConnRejection::MaxClients => {
+                                inc!(Metrics, relay_conns_rejected_max_clients)
+                            }
+                            ConnRejection::MaxConnsPerIp => {
+                                inc!(Metrics, relay_conns_rejected_per_ip)
+                            }
+                        }
+                        debug!(?client_ip, "rejecting relay connection: {rejection:?}");
+                        return Ok(builder
+                            .status(rejection.status())
+                            .body(body_empty())
+                            .expect("valid body"));
+                    }
+                };
+
                 debug!(?protocol, "upgrading connection");
 
                 // Setup a future that will eventually receive the upgraded
@@ -388,8 +568,10 @@ impl RelayService {
                     async move {
                         match hyper::upgrade::on(&mut req).await {
                             Ok(upgraded) => {
-                                if let Err(err) =
-                                    this.0.relay_connection_handler(protocol, upgraded).await
+                                if let Err(err) = this
+                                    .0
+                                    .relay_connection_handler(protocol, upgraded, conn_permit)
+                                    .await
                                 {
                                     warn!(
                                         ?protocol,
@@ -433,10 +615,7 @@ impl Service<Request<Incoming>> for RelayService {
 
     fn call(&self, req: Request<Incoming>) -> Self::Future {
         // Create a client if the request hits the relay endpoint.
-        if matches!(
-            (req.method(), req.uri().path()),
-            (&hyper::Method::GET, LEGACY_RELAY_PATH | RELAY_PATH)
-        ) {
+        if is_relay_request(&req) {
             let this = self.clone();
             return Box::pin(async move { this.call_client_conn(req).await.map_err(Into::into) });
         }
@@ -454,6 +633,27 @@ impl Service<Request<Incoming>> for RelayService {
     }
 }
 
+impl tower::Service<Request<Incoming>> for RelayService {
+    type Response = Response<BytesBody>;
+    type Error = HyperError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<Incoming>) -> Self::Future {
+        Service::call(self, req)
+    }
+}
+
+fn is_relay_request<B>(req: &Request<B>) -> bool {
+    matches!(
+        (req.method(), req.uri().path()),
+        (&hyper::Method::GET, LEGACY_RELAY_PATH | RELAY_PATH)
+    )
+}
+
 impl Inner {
     fn default_response(&self) -> ResponseBuilder {
         let mut response = Response::builder();
@@ -481,16 +681,21 @@ impl Inner {
     /// This handler runs while doing the connection upgrade handshake.  Once the connection
     /// is upgraded it sends the stream to the relay server which takes it over.  After
     /// having sent off the connection this handler returns.
-    async fn relay_connection_handler(&self, protocol: Protocol, upgraded: Upgraded) -> Result<()> {
+    async fn relay_connection_handler(
+        &self,
+        protocol: Protocol,
+        upgraded: Upgraded,
+        conn_permit: ConnPermit,
+    ) -> Result<()> {
         debug!(?protocol, "relay_connection upgraded");
-        let (io, read_buf) = downcast_upgrade(upgraded)?;
+        let (io, read_buf) = downcast_upgrade(upgraded);
         ensure!(
             read_buf.is_empty(),
             "can not deal with buffered data yet: {:?}",
             read_buf
         );
 
-        self.accept(protocol, io).await
+        self.accept(protocol, io, Some(conn_permit)).await
     }
 
     /// Adds a new connection to the server and serves it.
@@ -500,10 +705,16 @@ impl Inner {
     /// and is unable to verify this one, or if there is some issue communicating with the server.
     ///
     /// The provided [`AsyncRead`] and [`AsyncWrite`] must be already connected to the connection.
+    /// The `conn_permit` is held until the client disconnects.
     ///
     /// [`AsyncRead`]: tokio::io::AsyncRead
     /// [`AsyncWrite`]: tokio::io::AsyncWrite
-    async fn accept(&self, protocol: Protocol, io: MaybeTlsStream) -> Result<()> {
+    async fn accept(
+        &self,
+        protocol: Protocol,
+        io: MaybeTlsStream,
+        conn_permit: Option<ConnPermit>,
+    ) -> Result<()> {
         trace!(?protocol, "accept: start");
         let mut io = match protocol {
             Protocol::Relay => {
@@ -549,6 +760,7 @@ impl Inner {
             write_timeout: self.write_timeout,
             channel_capacity: PER_CLIENT_SEND_QUEUE_DEPTH,
             rate_limit: self.rate_limit,
+            conn_permit,
         };
         trace!("accept: create client");
         inc!(Metrics, accepts);
@@ -573,12 +785,39 @@ pub(super) enum TlsAcceptor {
 }
 
 impl RelayService {
+    /// Creates a [`RelayServiceBuilder`] to configure a new relay service.
+    pub fn builder() -> RelayServiceBuilder {
+        RelayServiceBuilder {
+            headers: HeaderMap::new(),
+            client_rx_ratelimit: None,
+            key_cache_capacity: DEFAULT_KEY_CACHE_CAPACITY,
+            access: AccessConfig::Everyone,
+        }
+    }
+
+    /// Returns whether the request is served by this service.
+    ///
+    /// These are the requests to the relay endpoints and the latency probes.
+    pub fn handles_request<B>(&self, req: &Request<B>) -> bool {
+        is_relay_request(req)
+            || self
+                .0
+                .handlers
+                .contains_key(&(req.method().clone(), req.uri().path()))
+    }
+
+    /// Disconnects all connected clients.
+    pub async fn shutdown(&self) {
+        self.0.clients.shutdown().await;
+    }
+
     fn new(
         handlers: Handlers,
         headers: HeaderMap,
         rate_limit: Option<ClientRateLimit>,
         key_cache: KeyCache,
         access: AccessConfig,
+        conn_limiter: ConnLimiter,
     ) -> Self {
         Self(Arc::new(Inner {
             handlers,
@@ -588,25 +827,63 @@ impl RelayService {
             rate_limit,
             key_cache,
             access,
+            conn_limiter,
         }))
     }
 
-    async fn shutdown(&self) {
-        self.0.clients.shutdown().await;
+    /// Checks the accept limits of a new connection and serves it.
+    ///
+    /// The client address is read from the PROXY protocol header first, if enabled.
+    async fn accept_connection(
+        self,
+        mut stream: TcpStream,
+        peer_addr: SocketAddr,
+        proxy_protocol: Option<Arc<ProxyProtocolConfig>>,
+        accept_limiter: Arc<AcceptLimiter>,
+        tls_config: Option<TlsConfig>,
+    ) {
+        let client_addr = match proxy_protocol {
+            Some(config) => match config.client_addr(&mut stream, peer_addr).await {
+                Ok(addr) => addr,
+                Err(err) => {
+                    debug!("dropping connection from {peer_addr}: {err:#}");
+                    return;
+                }
+            },
+            None => peer_addr,
+        };
+        if let Err(rejection) = accept_limiter.check(client_addr.ip()) {
+            match rejection {
+                AcceptRejection::Global => inc!(Metrics, accept_conns_ratelimited),
+                AcceptRejection::PerIp => inc!(Metrics, accept_conns_ratelimited_per_ip),
+            }
+            debug!("dropping connection from {client_addr}: {rejection:?} accept rate limit");
+            return;
+        }
+        self.handle_connection(stream, client_addr, tls_config)
+            .instrument(info_span!("conn", peer = %client_addr))
+            .await
     }
 
     /// Handle the incoming connection.
     ///
     /// If a `tls_config` is given, will serve the connection using HTTPS.
-    async fn handle_connection(self, stream: TcpStream, tls_config: Option<TlsConfig>) {
+    async fn handle_connection(
+        self,
+        stream: TcpStream,
+        client_addr: SocketAddr,
+        tls_config: Option<TlsConfig>,
+    ) {
         let res = match tls_config {
             Some(tls_config) => {
                 debug!("HTTPS: serve connection");
-                self.tls_serve_connection(stream, tls_config).await
+                self.tls_serve_connection(stream, client_addr, tls_config)
+                    .await
             }
             None => {
                 debug!("HTTP: serve connection");
-                self.serve_connection(MaybeTlsStream::Plain(stream)).await
+                self.serve_connection(MaybeTlsStream::Plain(stream), client_addr)
+                    .await
             }
         };
         match res {
@@ -623,7 +900,12 @@ impl RelayService {
     }
 
     /// Serve the tls connection
-    async fn tls_serve_connection(self, stream: TcpStream, tls_config: TlsConfig) -> Result<()> {
+    async fn tls_serve_connection(
+        self,
+        stream: TcpStream,
+        client_addr: SocketAddr,
+        tls_config: TlsConfig,
+    ) -> Result<()> {
         let TlsConfig { acceptor, config } = tls_config;
         match acceptor {
             TlsAcceptor::LetsEncrypt(a) => match a.accept(stream).await? {
@@ -636,7 +918,7 @@ impl RelayService {
                         .into_stream(config)
                         .await
                         .context("TLS[acme] handshake")?;
-                    self.serve_connection(MaybeTlsStream::Tls(tls_stream))
+                    self.serve_connection(MaybeTlsStream::Tls(tls_stream), client_addr)
                         .await
                         .context("TLS[acme] serve connection")?;
                 }
@@ -648,7 +930,7 @@ impl RelayService {
                     .context("TLS[manual] timeout")?
                     .context("TLS[manual] accept")?;
 
-                self.serve_connection(MaybeTlsStream::Tls(tls_stream))
+                self.serve_connection(MaybeTlsStream::Tls(tls_stream), client_addr)
                     .await
                     .context("TLS[manual] serve connection")?;
             }
@@ -657,12 +939,16 @@ impl RelayService {
     }
 
     /// Wrapper for the actual http connection (with upgrades)
-    async fn serve_connection<I>(self, io: I) -> Result<()>
+    async fn serve_connection<I>(self, io: I, client_addr: SocketAddr) -> Result<()>
     where
-        I: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + Sync + 'static,
+        I: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
     {
+        let service = ConnService {
+            service: self,
+            client_addr,
+        };
         hyper::server::conn::http1::Builder::new()
-            .serve_connection(hyper_util::rt::TokioIo::new(io), self)
+            .serve_connection(hyper_util::rt::TokioIo::new(io), service)
             .with_upgrades()
             .await?;
         Ok(())
@@ -922,6 +1208,62 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    #[traced_test]
+    async fn test_relay_service_in_other_server() -> Result<()> {
+        let relay = RelayService::builder().build();
+
+        // serve the relay next to another endpoint from a plain hyper server
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let service = relay.clone();
+        let server_task = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let relay = service.clone();
+                let app = hyper::service::service_fn(move |req: Request<Incoming>| {
+                    let mut relay = relay.clone();
+                    async move {
+                        if relay.handles_request(&req) {
+                            tower::Service::call(&mut relay, req).await
+                        } else {
+                            Ok(Response::new(body_full("hello")))
+                        }
+                    }
+                });
+                tokio::spawn(
+                    hyper::server::conn::http1::Builder::new()
+                        .serve_connection(hyper_util::rt::TokioIo::new(stream), app)
+                        .with_upgrades(),
+                );
+            }
+        });
+
+        let base_url: Url = format!("http://{addr}").parse()?;
+        let res = reqwest::get(base_url.join("/hello")?).await?;
+        assert_eq!(res.text().await?, "hello");
+        let res = reqwest::get(base_url.join(RELAY_PROBE_PATH)?).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let a_key = SecretKey::generate(rand::thread_rng());
+        let b_key = SecretKey::generate(rand::thread_rng());
+        let (a_key, mut client_a) = create_test_client(a_key, base_url.clone()).await?;
+        let (b_key, mut client_b) = create_test_client(b_key, base_url).await?;
+
+        info!("sending message from a to b");
+        let msg = Bytes::from_static(b"hi there, client b!");
+        client_a
+            .send(SendMessage::SendPacket(b_key, msg.clone()))
+            .await?;
+        let (got_key, got_msg) =
+            process_msg(client_b.next().await).expect("expected message from client_a");
+        assert_eq!(a_key, got_key);
+        assert_eq!(msg, got_msg);
+
+        relay.shutdown().await;
+        server_task.abort();
+        Ok(())
+    }
+
     async fn make_test_client(client: tokio::io::DuplexStream, key: &SecretKey) -> Result<Conn> {
         let client = MaybeTlsStreamChained::Mem(client);
         let client = Conn::new_relay(client, KeyCache::test(), key).await?;
@@ -938,6 +1280,7 @@ mod tests {
             None,
             KeyCache::test(),
             AccessConfig::Everyone,
+            ConnLimiter::default(),
         );
 
         info!("Create client A and connect it to the server.");
@@ -946,7 +1289,7 @@ mod tests {
         let (client_a, rw_a) = tokio::io::duplex(10);
         let s = service.clone();
         let handler_task = tokio::spawn(async move {
-            s.0.accept(Protocol::Relay, MaybeTlsStream::Test(rw_a))
+            s.0.accept(Protocol::Relay, MaybeTlsStream::Test(rw_a), None)
                 .await
         });
         let mut client_a = make_test_client(client_a, &key_a).await?;
@@ -958,7 +1301,7 @@ mod tests {
         let (client_b, rw_b) = tokio::io::duplex(10);
         let s = service.clone();
         let handler_task = tokio::spawn(async move {
-            s.0.accept(Protocol::Relay, MaybeTlsStream::Test(rw_b))
+            s.0.accept(Protocol::Relay, MaybeTlsStream::Test(rw_b), None)
                 .await
         });
         let mut client_b = make_test_client(client_b, &key_b).await?;
@@ -1025,6 +1368,7 @@ mod tests {
             None,
             KeyCache::test(),
             AccessConfig::Everyone,
+            ConnLimiter::default(),
         );
 
         info!("Create client A and connect it to the server.");
@@ -1033,7 +1377,7 @@ mod tests {
         let (client_a, rw_a) = tokio::io::duplex(10);
         let s = service.clone();
         let handler_task = tokio::spawn(async move {
-            s.0.accept(Protocol::Relay, MaybeTlsStream::Test(rw_a))
+            s.0.accept(Protocol::Relay, MaybeTlsStream::Test(rw_a), None)
                 .await
         });
         let mut client_a = make_test_client(client_a, &key_a).await?;
@@ -1045,7 +1389,7 @@ mod tests {
         let (client_b, rw_b) = tokio::io::duplex(10);
         let s = service.clone();
         let handler_task = tokio::spawn(async move {
-            s.0.accept(Protocol::Relay, MaybeTlsStream::Test(rw_b))
+            s.0.accept(Protocol::Relay, MaybeTlsStream::Test(rw_b), None)
                 .await
         });
         let mut client_b = make_test_client(client_b, &key_b).await?;
@@ -1091,7 +1435,7 @@ mod tests {
         let (new_client_b, new_rw_b) = tokio::io::duplex(10);
         let s = service.clone();
         let handler_task = tokio::spawn(async move {
-            s.0.accept(Protocol::Relay, MaybeTlsStream::Test(new_rw_b))
+            s.0.accept(Protocol::Relay, MaybeTlsStream::Test(new_rw_b), None)
                 .await
         });
         let mut new_client_b = make_test_client(new_client_b, &key_b).await?;
@@ -62,6 +62,15 @@ pub struct Metrics {
     pub websocket_accepts: Counter,
     /// Number of accepted 'iroh derp http' connection upgrades
     pub relay_accepts: Counter,
+
+    /// Number of connections dropped by the accept rate limit
+    pub accept_conns_ratelimited: Counter,
+    /// Number of connections dropped by the per-IP accept rate limit
+    pub accept_conns_ratelimited_per_ip: Counter,
+    /// Number of relay connections rejected because the maximum number of clients was reached
+    pub relay_conns_rejected_max_clients: Counter,
+    /// Number of relay connections rejected by the per-IP connection limit
+    pub relay_conns_rejected_per_ip: Counter,
     // TODO: enable when we can have multiple connections for one node id
     // pub duplicate_client_keys: Counter,
     // pub duplicate_client_conns: Counter,
@@ -113,6 +122,19 @@ impl Default for Metrics {
 
             websocket_accepts: Counter::new("Number of accepted websocket connections"),
             relay_accepts: Counter::new("Number of accepted 'iroh derp http' connection upgrades"),
+
+            accept_conns_ratelimited: Counter::new(
+                "Number of connections dropped by the accept rate limit.",
+            ),
+            accept_conns_ratelimited_per_ip: Counter::new(
+                "Number of connections dropped by the per-IP accept rate limit.",
+            ),
+            relay_conns_rejected_max_clients: Counter::new(
+                "Number of relay connections rejected because the maximum number of clients was reached.",
+            ),
+            relay_conns_rejected_per_ip: Counter::new(
+                "Number of relay connections rejected by the per-IP connection limit.",
+            ),
             // TODO: enable when we can have multiple connections for one node id
             // pub duplicate_client_keys: Counter::new("Number of duplicate client keys."),
             // pub duplicate_client_conns: Counter::new("Number of duplicate client connections."),
@@ -97,6 +97,12 @@ pub enum MaybeTlsStream {
     Plain(tokio::net::TcpStream),
     /// A Tls wrapped [`tokio::net::TcpStream`]
     Tls(tokio_rustls::server::TlsStream<tokio::net::TcpStream>),
+    /// A connection upgraded by an HTTP server which is not run by the relay.
+    ///
+    /// This is used when the [`RelayService`] is mounted into another HTTP server.
+    ///
+    /// [`RelayService`]: super::RelayService
+    Upgraded(hyper_util::rt::TokioIo<hyper::upgrade::Upgraded>),
     /// An in-memory bidirectional pipe.
     #[cfg(test)]
     Test(tokio::io::DuplexStream),
@@ -111,6 +117,7 @@ impl AsyncRead for MaybeTlsStream {
         match &mut *self {
             MaybeTlsStream::Plain(ref mut s) => Pin::new(s).poll_read(cx, buf),
             MaybeTlsStream::Tls(ref mut s) => Pin::new(s).poll_read(cx, buf),
+            MaybeTlsStream::Upgraded(ref mut s) => Pin::new(s).poll_read(cx, buf),
             #[cfg(test)]
             MaybeTlsStream::Test(ref mut s) => Pin::new(s).poll_read(cx, buf),
         }
@@ -125,6 +132,7 @@ impl AsyncWrite for MaybeTlsStream {
         match &mut *self {
             MaybeTlsStream::Plain(ref mut s) => Pin::new(s).poll_flush(cx),
             MaybeTlsStream::Tls(ref mut s) => Pin::new(s).poll_flush(cx),
+            MaybeTlsStream::Upgraded(ref mut s) => Pin::new(s).poll_flush(cx),
             #[cfg(test)]
             MaybeTlsStream::Test(ref mut s) => Pin::new(s).poll_flush(cx),
         }
@@ -137,6 +145,7 @@ impl AsyncWrite for MaybeTlsStream {
         match &mut *self {
             MaybeTlsStream::Plain(ref mut s) => Pin::new(s).poll_shutdown(cx),
             MaybeTlsStream::Tls(ref mut s) => Pin::new(s).poll_shutdown(cx),
+            MaybeTlsStream::Upgraded(ref mut s) => Pin::new(s).poll_shutdown(cx),
             #[cfg(test)]
             MaybeTlsStream::Test(ref mut s) => Pin::new(s).poll_shutdown(cx),
         }
@@ -150,6 +159,7 @@ impl AsyncWrite for MaybeTlsStream {
         match &mut *self {
             MaybeTlsStream::Plain(ref mut s) => Pin::new(s).poll_write(cx, buf),
             MaybeTlsStream::Tls(ref mut s) => Pin::new(s).poll_write(cx, buf),
+            MaybeTlsStream::Upgraded(ref mut s) => Pin::new(s).poll_write(cx, buf),
             #[cfg(test)]
             MaybeTlsStream::Test(ref mut s) => Pin::new(s).poll_write(cx, buf),
         }
@@ -163,6 +173,7 @@ impl AsyncWrite for MaybeTlsStream {
         match &mut *self {
             MaybeTlsStream::Plain(ref mut s) => Pin::new(s).poll_write_vectored(cx, bufs),
             MaybeTlsStream::Tls(ref mut s) => Pin::new(s).poll_write_vectored(cx, bufs),
+            MaybeTlsStream::Upgraded(ref mut s) => Pin::new(s).poll_write_vectored(cx, bufs),
             #[cfg(test)]
             MaybeTlsStream::Test(ref mut s) => Pin::new(s).poll_write_vectored(cx, bufs),
         }
@@ -40,8 +40,12 @@
 //! - The [`DhtDiscovery`] also uses the [`pkarr`] system but can also publish and lookup
 //!   records to/from the Mainline DHT.
 //!
+//! - The [`PeerExchangeDiscovery`] which asks already connected peers for the addresses of
+//!   other nodes, without relying on any external infrastructure.
+//!
 //! To use multiple discovery systems simultaneously use [`ConcurrentDiscovery`] which will
-//! perform lookups to all discovery systems at the same time.
+//! perform lookups to all discovery systems at the same time.  To avoid resolving the same
+//! node over the network again and again, wrap a discovery in a [`CachingDiscovery`].
 //!
 //! # Examples
 //!
@@ -103,11 +107,18 @@
 //! [pkarr relay servers]: https://pkarr.org/#servers
 //! [`LocalSwarmDiscovery`]: local_swarm_discovery::LocalSwarmDiscovery
 //! [`StaticProvider`]: static_provider::StaticProvider
+//! [`PeerExchangeDiscovery`]: peer_exchange::PeerExchangeDiscovery
+//! [`CachingDiscovery`]: caching::CachingDiscovery
 
-use std::{collections::BTreeSet, net::SocketAddr, sync::Arc};
+use std::{
+    collections::BTreeSet,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
 
 use anyhow::{anyhow, ensure, Result};
 use iroh_base::{NodeAddr, NodeId, RelayUrl};
+pub use iroh_relay::dns::node_info::UserData;
 use n0_future::{
     stream::{Boxed as BoxStream, StreamExt},
     task::{self, AbortOnDropHandle},
@@ -116,14 +127,24 @@ use n0_future::{
 use tokio::sync::oneshot;
 use tracing::{debug, error_span, warn, Instrument};
 
-use crate::Endpoint;
+use crate::{
+    endpoint::{DirectAddr, DirectAddrType},
+    Endpoint,
+};
 
+pub mod caching;
 pub mod dns;
 
 #[cfg(feature = "discovery-local-network")]
 pub mod local_swarm_discovery;
+pub mod peer_exchange;
 pub mod pkarr;
 pub mod static_provider;
+mod watch;
+
+pub use self::watch::{
+    DEFAULT_MAX_POLL_INTERVAL, DEFAULT_MAX_WATCHED_NODES, DEFAULT_MIN_POLL_INTERVAL,
+};
 
 /// Node discovery for [`super::Endpoint`].
 ///
@@ -134,7 +155,9 @@ pub mod static_provider;
 /// looked up by other nodes.
 ///
 /// The published addressing information can include both a [`RelayUrl`] and/or direct
-/// addresses.
+/// addresses.  Alongside the addressing information a node can publish a small amount of
+/// [`UserData`], e.g. to announce which services it provides, if the discovery mechanism
+/// supports it.
 ///
 /// To allow for discovery, the [`super::Endpoint`] will call `publish` whenever
 /// discovery information changes. If a discovery mechanism requires a periodic
@@ -144,12 +167,24 @@ pub mod static_provider;
 pub trait Discovery: std::fmt::Debug + Send + Sync {
     /// Publishes the given [`RelayUrl`] and direct addreesses to the discovery mechanism.
     ///
+    /// The direct addresses carry their [`DirectAddrType`], which implementations can use
+    /// to decide which addresses to publish, usually through an [`AddrPublishPolicy`].
+    ///
+    /// If set, the [`UserData`] is published as well.  Implementations which cannot
+    /// publish user data ignore it.
+    ///
     /// This is fire and forget, since the [`Endpoint`] can not wait for successful
     /// publishing. If publishing is async, the implementation should start it's own task.
     ///
     /// This will be called from a tokio task, so it is safe to spawn new tasks.
     /// These tasks will be run on the runtime of the [`super::Endpoint`].
-    fn publish(&self, _url: Option<&RelayUrl>, _addrs: &BTreeSet<SocketAddr>) {}
+    fn publish(
+        &self,
+        _url: Option<&RelayUrl>,
+        _addrs: &BTreeSet<DirectAddr>,
+        _user_data: Option<&UserData>,
+    ) {
+    }
 
     /// Resolves the [`DiscoveryItem`] for the given [`NodeId`].
     ///
@@ -206,6 +241,148 @@ pub struct DiscoveryItem {
     /// Must be microseconds since the unix epoch.
     // TODO(ramfox): this is currently unused. As we develop more `DiscoveryService`s, we may discover that we do not need this. It is only truly relevant when comparing `relay_urls`, since we can attempt to dial any number of socket addresses, but expect each node to have one "home relay" that we will attempt to contact them on. This means we would need some way to determine which relay url to choose between, if more than one relay url is reported.
     pub last_updated: Option<u64>,
+    /// The [`UserData`] published by the node, if any.
+    pub user_data: Option<UserData>,
+    /// How long the information is valid, if known.
+    ///
+    /// This is the TTL of the records the information was resolved from.
+    pub ttl: Option<Duration>,
+}
+
+/// Which addresses a discovery service publishes.
+///
+/// A node can be reachable via its home relay and via direct addresses.  Depending on the
+/// deployment only some of these should be published: nodes on a LAN or without relays need
+/// their direct addresses published, while privacy-sensitive nodes must never publish their
+/// IP addresses.
+///
+/// The policy first selects whether the relay URL and the direct addresses are published at
+/// all, and then filters the direct addresses by their [`DirectAddrType`] and IP range.
+///
+/// # Examples
+///
+/// ```
+/// use iroh::{discovery::AddrPublishPolicy, endpoint::DirectAddrType};
+///
+/// // Publish the relay URL as well as all publicly routable direct addresses.
+/// let policy = AddrPublishPolicy::relay_and_direct()
+///     .exclude_type(DirectAddrType::Local)
+///     .exclude_private_ranges();
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddrPublishPolicy {
+    mode: AddrPublishMode,
+    excluded_types: BTreeSet<DirectAddrType>,
+    exclude_private_ranges: bool,
+}
+
+/// Which kinds of addresses an [`AddrPublishPolicy`] publishes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AddrPublishMode {
+    RelayOnly,
+    DirectOnly,
+    RelayAndDirect,
+    RelayOrDirect,
+}
+
+impl Default for AddrPublishPolicy {
+    /// Returns [`AddrPublishPolicy::relay_or_direct`].
+    fn default() -> Self {
+        Self::relay_or_direct()
+    }
+}
+
+impl AddrPublishPolicy {
+    fn new(mode: AddrPublishMode) -> Self {
+        Self {
+            mode,
+            excluded_types: BTreeSet::new(),
+            exclude_private_ranges: false,
+        }
+    }
+
+    /// Publishes only the relay URL, never any direct addresses.
+    pub fn relay_only() -> Self {
+        Self::new(AddrPublishMode::RelayOnly)
+    }
+
+    /// Publishes only the direct addresses, never the relay URL.
+    pub fn direct_only() -> Self {
+        Self::new(AddrPublishMode::DirectOnly)
+    }
+
+    /// Publishes both the relay URL and the direct addresses.
+    pub fn relay_and_direct() -> Self {
+        Self::new(AddrPublishMode::RelayAndDirect)
+    }
+
+    /// Publishes the relay URL if there is one, and the direct addresses otherwise.
+    ///
+    /// This is the default.
+    pub fn relay_or_direct() -> Self {
+        Self::new(AddrPublishMode::RelayOrDirect)
+    }
+
+    /// Does not publish direct addresses of the given type.
+    pub fn exclude_type(mut self, typ: DirectAddrType) -> Self {
+        self.excluded_types.insert(typ);
+        self
+    }
+
+    /// Does not publish direct addresses in private, loopback or link-local ranges.
+    ///
+    /// These are the IPv4 private ranges of RFC 1918, IPv6 unique local addresses and the
+    /// loopback and link-local ranges of both address families.
+    pub fn exclude_private_ranges(mut self) -> Self {
+        self.exclude_private_ranges = true;
+        self
+    }
+
+    /// Returns whether the direct address passes the filters of this policy.
+    pub fn allows(&self, addr: &DirectAddr) -> bool {
+        if self.excluded_types.contains(&addr.typ) {
+            return false;
+        }
+        !(self.exclude_private_ranges && is_private_ip(addr.addr.ip()))
+    }
+
+    /// Applies the policy, returning the relay URL and direct addresses to publish.
+    pub fn apply(
+        &self,
+        url: Option<&RelayUrl>,
+        addrs: &BTreeSet<DirectAddr>,
+    ) -> (Option<RelayUrl>, BTreeSet<SocketAddr>) {
+        let (relay, direct) = match self.mode {
+            AddrPublishMode::RelayOnly => (true, false),
+            AddrPublishMode::DirectOnly => (false, true),
+            AddrPublishMode::RelayAndDirect => (true, true),
+            AddrPublishMode::RelayOrDirect => (true, url.is_none()),
+        };
+        let url = url.filter(|_| relay).cloned();
+        let addrs = match direct {
+            true => addrs
+                .iter()
+                .filter(|addr| self.allows(addr))
+                .map(|addr| addr.addr)
+                .collect(),
+            false => BTreeSet::new(),
+        };
+        (url, addrs)
+    }
+}
+
+/// Whether the IP address is in a private, loopback or link-local range.
+fn is_private_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_private() || ip.is_loopback() || ip.is_link_local(),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_private_ip(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                ip.is_loopback() || (first & 0xfe00) == 0xfc00 || (first & 0xffc0) == 0xfe80
+            }
+        },
+    }
 }
 
 /// A discovery service that combines multiple discovery sources.
@@ -244,9 +421,14 @@ where
 }
 
 impl Discovery for ConcurrentDiscovery {
-    fn publish(&self, url: Option<&RelayUrl>, addrs: &BTreeSet<SocketAddr>) {
+    fn publish(
+        &self,
+        url: Option<&RelayUrl>,
+        addrs: &BTreeSet<DirectAddr>,
+        user_data: Option<&UserData>,
+    ) {
         for service in &self.services {
-            service.publish(url, addrs);
+            service.publish(url, addrs, user_data);
         }
     }
 
@@ -407,7 +589,7 @@ impl DiscoveryTask {
                         continue;
                     }
                     debug!(provenance = %r.provenance, addr = ?r.node_addr, "discovery: new address found");
-                    ep.add_node_addr_with_source(r.node_addr, r.provenance).ok();
+                    ep.add_discovery_item(r).ok();
                     if let Some(tx) = on_first_tx.take() {
                         tx.send(Ok(())).ok();
                     }
@@ -451,7 +633,15 @@ mod tests {
     use super::*;
     use crate::RelayMode;
 
-    type InfoStore = HashMap<NodeId, (Option<RelayUrl>, BTreeSet<SocketAddr>, u64)>;
+    type InfoStore = HashMap<
+        NodeId,
+        (
+            Option<RelayUrl>,
+            BTreeSet<SocketAddr>,
+            Option<UserData>,
+            u64,
+        ),
+    >;
 
     #[derive(Debug, Clone, Default)]
     struct TestDiscoveryShared {
@@ -490,16 +680,22 @@ mod tests {
     }
 
     impl Discovery for TestDiscovery {
-        fn publish(&self, url: Option<&RelayUrl>, addrs: &BTreeSet<SocketAddr>) {
+        fn publish(
+            &self,
+            url: Option<&RelayUrl>,
+            addrs: &BTreeSet<DirectAddr>,
+            user_data: Option<&UserData>,
+        ) {
             if !self.publish {
                 return;
             }
             let now = system_time_now();
+            let addrs = addrs.iter().map(|addr| addr.addr).collect();
             self.shared
                 .nodes
                 .lock()
                 .unwrap()
-                .insert(self.node_id, (url.cloned(), addrs.clone(), now));
+                .insert(self.node_id, (url.cloned(), addrs, user_data.cloned(), now));
         }
 
         fn resolve(
@@ -514,11 +710,11 @@ mod tests {
                     let port: u16 = rand::thread_rng().gen_range(10_000..20_000);
                     // "240.0.0.0/4" is reserved and unreachable
                     let addr: SocketAddr = format!("240.0.0.1:{port}").parse().unwrap();
-                    Some((None, BTreeSet::from([addr]), ts))
+                    Some((None, BTreeSet::from([addr]), None, ts))
                 }
             };
             let stream = match addr_info {
-                Some((url, addrs, ts)) => {
+                Some((url, addrs, user_data, ts)) => {
                     let item = DiscoveryItem {
                         node_addr: NodeAddr {
                             node_id,
@@ -527,6 +723,8 @@ mod tests {
                         },
                         provenance: "test-disco",
                         last_updated: Some(ts),
+                        user_data,
+                        ttl: None,
                     };
                     let delay = self.delay;
                     let fut = async move {
@@ -549,7 +747,13 @@ mod tests {
     #[derive(Debug)]
     struct EmptyDiscovery;
     impl Discovery for EmptyDiscovery {
-        fn publish(&self, _url: Option<&RelayUrl>, _addrs: &BTreeSet<SocketAddr>) {}
+        fn publish(
+            &self,
+            _url: Option<&RelayUrl>,
+            _addrs: &BTreeSet<DirectAddr>,
+            _user_data: Option<&UserData>,
+        ) {
+        }
 
         fn resolve(
             &self,
@@ -694,6 +898,36 @@ mod tests {
         Ok(())
     }
 
+    /// The user data published by a node is available in the [`RemoteInfo`] of the
+    /// connecting node.
+    ///
+    /// [`RemoteInfo`]: crate::endpoint::RemoteInfo
+    #[tokio::test]
+    #[traced_test]
+    async fn endpoint_discovery_user_data() -> anyhow::Result<()> {
+        let disco_shared = TestDiscoveryShared::default();
+        let user_data: UserData = "services=foo,bar".parse()?;
+        let (ep1, _guard1) = {
+            let secret = SecretKey::generate(rand::thread_rng());
+            let disco = disco_shared.create_discovery(secret.public());
+            new_endpoint(secret, disco).await
+        };
+        let (ep2, _guard2) = {
+            let secret = SecretKey::generate(rand::thread_rng());
+            let disco = disco_shared.create_discovery(secret.public());
+            new_endpoint(secret, disco).await
+        };
+        ep1.set_user_data_for_discovery(Some(user_data.clone()));
+        // wait for our address to be updated and thus published at least once
+        ep1.node_addr().await?;
+        let _conn = ep2.connect(NodeAddr::new(ep1.node_id()), TEST_ALPN).await?;
+        let info = ep2
+            .remote_info(ep1.node_id())
+            .context("missing remote info")?;
+        assert_eq!(info.user_data, Some(user_data));
+        Ok(())
+    }
+
     async fn new_endpoint(
         secret: SecretKey,
         disco: impl Discovery + 'static,
@@ -747,6 +981,65 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_addr_publish_policy() {
+        let relay_url: RelayUrl = "https://relay.example".parse().unwrap();
+        let local = DirectAddr {
+            addr: "192.168.1.2:1234".parse().unwrap(),
+            typ: DirectAddrType::Local,
+        };
+        let local_public = DirectAddr {
+            addr: "1.2.3.4:1234".parse().unwrap(),
+            typ: DirectAddrType::Local,
+        };
+        let stun = DirectAddr {
+            addr: "5.6.7.8:1234".parse().unwrap(),
+            typ: DirectAddrType::Stun,
+        };
+        let ula = DirectAddr {
+            addr: "[fd00::1]:1234".parse().unwrap(),
+            typ: DirectAddrType::Local,
+        };
+        let addrs = BTreeSet::from([
+            local.clone(),
+            local_public.clone(),
+            stun.clone(),
+            ula.clone(),
+        ]);
+        let all: BTreeSet<SocketAddr> = addrs.iter().map(|addr| addr.addr).collect();
+
+        let policy = AddrPublishPolicy::default();
+        assert_eq!(
+            policy.apply(Some(&relay_url), &addrs),
+            (Some(relay_url.clone()), BTreeSet::new())
+        );
+        assert_eq!(policy.apply(None, &addrs), (None, all.clone()));
+
+        let policy = AddrPublishPolicy::relay_only();
+        assert_eq!(policy.apply(None, &addrs), (None, BTreeSet::new()));
+
+        let policy = AddrPublishPolicy::direct_only();
+        assert_eq!(policy.apply(Some(&relay_url), &addrs), (None, all.clone()));
+
+        let policy = AddrPublishPolicy::relay_and_direct();
+        assert_eq!(
+            policy.apply(Some(&relay_url), &addrs),
+            (Some(relay_url.clone()), all)
+        );
+
+        let policy = AddrPublishPolicy::relay_and_direct().exclude_private_ranges();
+        assert_eq!(
+            policy.apply(Some(&relay_url), &addrs).1,
+            BTreeSet::from([local_public.addr, stun.addr])
+        );
+
+        let policy = AddrPublishPolicy::direct_only().exclude_type(DirectAddrType::Local);
+        assert_eq!(
+            policy.apply(Some(&relay_url), &addrs),
+            (None, BTreeSet::from([stun.addr]))
+        );
+    }
 }
 
 /// This module contains end-to-end tests for DNS node discovery.
@@ -756,14 +1049,20 @@ mod tests {
 #[cfg(test)]
 mod test_dns_pkarr {
     use anyhow::Result;
-    use iroh_base::{NodeAddr, SecretKey};
+    use iroh_base::{NodeAddr, RelayUrl, SecretKey};
     use iroh_relay::RelayMap;
     use n0_future::time::Duration;
     use tokio_util::task::AbortOnDropHandle;
     use tracing_test::traced_test;
 
+    use n0_future::StreamExt;
+
     use crate::{
-        discovery::pkarr::PkarrPublisher,
+        discovery::{
+            dns::DnsDiscovery,
+            pkarr::{PkarrPublisher, PkarrRelayClient, PkarrResolver, PublishStatus},
+            Discovery, UserData,
+        },
         dns::{node_info::NodeInfo, DnsResolver},
         test_utils::{
             dns_server::run_dns_server, pkarr_dns_state::State, run_relay_server, DnsPkarrServer,
@@ -814,7 +1113,7 @@ mod test_dns_pkarr {
         let resolver = DnsResolver::with_nameserver(dns_pkarr_server.nameserver);
         let publisher = PkarrPublisher::new(secret_key, dns_pkarr_server.pkarr_url.clone());
         // does not block, update happens in background task
-        publisher.update_addr_info(relay_url.as_ref(), &Default::default());
+        publisher.update_addr_info(relay_url.as_ref(), &Default::default(), None);
         // wait until our shared state received the update from pkarr publishing
         dns_pkarr_server.on_node(&node_id, PUBLISH_TIMEOUT).await?;
         let resolved = resolver.lookup_node_by_id(&node_id, &origin).await?;
@@ -829,6 +1128,273 @@ mod test_dns_pkarr {
         Ok(())
     }
 
+    #[tokio::test]
+    #[traced_test]
+    async fn pkarr_publish_dns_resolve_user_data() -> Result<()> {
+        let origin = "testdns.example".to_string();
+
+        let dns_pkarr_server = DnsPkarrServer::run_with_origin(origin.clone()).await?;
+
+        let secret_key = SecretKey::generate(rand::thread_rng());
+        let node_id = secret_key.public();
+
+        let relay_url = Some("https://relay.example".parse().unwrap());
+        let user_data: UserData = "foobar".parse()?;
+
+        let resolver = DnsResolver::with_nameserver(dns_pkarr_server.nameserver);
+        let publisher = PkarrPublisher::new(secret_key, dns_pkarr_server.pkarr_url.clone());
+        publisher.update_addr_info(relay_url.as_ref(), &Default::default(), Some(&user_data));
+        dns_pkarr_server.on_node(&node_id, PUBLISH_TIMEOUT).await?;
+        let (resolved, ttl) = resolver
+            .lookup_node_info_with_ttl_by_id(&node_id, &origin)
+            .await?;
+
+        assert!(ttl > Duration::ZERO);
+        assert_eq!(resolved.user_data, Some(user_data));
+        assert_eq!(resolved.relay_url, relay_url.map(Into::into));
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn pkarr_publish_multiple_relays_status() -> Result<()> {
+        let dns_pkarr_server = DnsPkarrServer::run().await?;
+        let unreachable: url::Url = "http://127.0.0.1:1/pkarr".parse()?;
+
+        let secret_key = SecretKey::generate(rand::thread_rng());
+        let node_id = secret_key.public();
+        let publisher = PkarrPublisher::builder(secret_key)
+            .relay(unreachable.clone())
+            .relay(dns_pkarr_server.pkarr_url.clone())
+            .build()?;
+        let mut status = publisher.status();
+
+        let relay_url: RelayUrl = "https://relay.example".parse()?;
+        publisher.update_addr_info(Some(&relay_url), &Default::default(), None);
+        dns_pkarr_server.on_node(&node_id, PUBLISH_TIMEOUT).await?;
+
+        let status = tokio::time::timeout(PUBLISH_TIMEOUT, async {
+            loop {
+                let current = status.get()?;
+                if current.last_success.is_some() && current.last_error.is_some() {
+                    break anyhow::Ok(current);
+                }
+                status.updated().await?;
+            }
+        })
+        .await??;
+        assert_eq!(
+            status.info.and_then(|info| info.relay_url),
+            Some(relay_url.into())
+        );
+        assert!(status.last_error.unwrap().starts_with(unreachable.as_str()));
+        let [failed, succeeded] = &status.relays[..] else {
+            panic!("expected two relays");
+        };
+        assert_eq!(failed.url, unreachable);
+        assert!(failed.failed_attempts > 0);
+        assert!(failed.last_success.is_none());
+        assert_eq!(succeeded.url, dns_pkarr_server.pkarr_url);
+        assert_eq!(succeeded.failed_attempts, 0);
+        assert!(succeeded.last_success.is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn pkarr_publish_timeout() -> Result<()> {
+        let dns_pkarr_server = DnsPkarrServer::run().await?;
+        // Accepts connections but never responds.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let hanging: url::Url = format!("http://{}/pkarr", listener.local_addr()?).parse()?;
+
+        let secret_key = SecretKey::generate(rand::thread_rng());
+        let publisher = PkarrPublisher::builder(secret_key)
+            .relay(hanging.clone())
+            .relay(dns_pkarr_server.pkarr_url.clone())
+            .publish_timeout(Duration::from_millis(200))
+            .build()?;
+        let mut status = publisher.status();
+
+        let relay_url: RelayUrl = "https://relay.example".parse()?;
+        publisher.update_addr_info(Some(&relay_url), &Default::default(), None);
+
+        let status = tokio::time::timeout(PUBLISH_TIMEOUT, async {
+            loop {
+                let current = status.get()?;
+                if current.last_success.is_some() && current.last_error.is_some() {
+                    break anyhow::Ok(current);
+                }
+                status.updated().await?;
+            }
+        })
+        .await??;
+        let [failed, succeeded] = &status.relays[..] else {
+            panic!("expected two relays");
+        };
+        assert_eq!(failed.url, hanging);
+        assert!(failed.failed_attempts > 0);
+        assert!(failed.last_error.as_ref().unwrap().contains("timed out"));
+        assert_eq!(succeeded.failed_attempts, 0);
+        assert!(succeeded.last_success.is_some());
+        drop(listener);
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn pkarr_publish_backup_relay() -> Result<()> {
+        let dns_pkarr_server = DnsPkarrServer::run().await?;
+
+        let secret_key = SecretKey::generate(rand::thread_rng());
+        let node_id = secret_key.public();
+        // Backup relays alone are not enough.
+        assert!(PkarrPublisher::builder(secret_key.clone())
+            .backup_relay(dns_pkarr_server.pkarr_url.clone())
+            .build()
+            .is_err());
+
+        let relay_url: RelayUrl = "https://relay.example".parse()?;
+        let info = NodeInfo::new(node_id, Some(relay_url.clone().into()), Default::default());
+
+        // The backup relay is unused while publishing to the primary relay succeeds.
+        let backup_server = DnsPkarrServer::run().await?;
+        let publisher = PkarrPublisher::builder(secret_key.clone())
+            .relay(dns_pkarr_server.pkarr_url.clone())
+            .backup_relay(backup_server.pkarr_url.clone())
+            .build()?;
+        publisher.update_addr_info(Some(&relay_url), &Default::default(), None);
+        let status =
+            wait_for_status(&publisher, |status| status.relays[0].last_success.is_some()).await?;
+        assert_eq!(status.info, Some(info.clone()));
+        assert!(status.relays[1].backup);
+        assert!(status.relays[1].last_attempt.is_none());
+        assert!(backup_server
+            .on_node(&node_id, Duration::from_secs(1))
+            .await
+            .is_err());
+        drop(publisher);
+
+        // The node info ends up on the backup relay once the primary failed.
+        let publisher = PkarrPublisher::builder(secret_key)
+            .relay("http://127.0.0.1:1/pkarr".parse()?)
+            .backup_relay(backup_server.pkarr_url.clone())
+            .build()?;
+        publisher.update_addr_info(Some(&relay_url), &Default::default(), None);
+        let status =
+            wait_for_status(&publisher, |status| status.relays[1].last_success.is_some()).await?;
+        backup_server.on_node(&node_id, PUBLISH_TIMEOUT).await?;
+        let primary = &status.relays[0];
+        assert!(primary.failed_attempts > 0);
+        assert!(primary.last_error.is_some());
+        assert!(primary.last_success.is_none());
+        assert!(status.relays[1].backup);
+        assert_eq!(status.info, Some(info));
+        assert!(status.last_error.is_some());
+        Ok(())
+    }
+
+    /// Waits until the publish status of `publisher` satisfies `f`.
+    async fn wait_for_status(
+        publisher: &PkarrPublisher,
+        f: impl Fn(&PublishStatus) -> bool,
+    ) -> Result<PublishStatus> {
+        let mut stream = publisher.status().stream();
+        tokio::time::timeout(PUBLISH_TIMEOUT, async {
+            while let Some(status) = stream.next().await {
+                if f(&status) {
+                    return Ok(status);
+                }
+            }
+            anyhow::bail!("publisher stopped")
+        })
+        .await?
+    }
+
+    /// Publishes node info for `secret_key` with a relay URL on `port` to the pkarr relay.
+    async fn publish_relay_port(
+        server: &DnsPkarrServer,
+        secret_key: &SecretKey,
+        port: u16,
+    ) -> Result<RelayUrl> {
+        let relay_url: RelayUrl = format!("https://relay.example:{port}").parse()?;
+        let info = NodeInfo::new(
+            secret_key.public(),
+            Some(relay_url.clone().into()),
+            Default::default(),
+        );
+        let signed_packet = info.to_pkarr_signed_packet(secret_key, 1)?;
+        PkarrRelayClient::new(server.pkarr_url.clone())
+            .publish(&signed_packet)
+            .await?;
+        Ok(relay_url)
+    }
+
+    /// Resolves the node with `discovery`, then checks the subscription only emits changes.
+    async fn check_watch_resolved(
+        server: &DnsPkarrServer,
+        ep: Endpoint,
+        discovery: impl Discovery,
+    ) -> Result<()> {
+        let secret_key = SecretKey::generate(rand::thread_rng());
+        let node_id = secret_key.public();
+        let mut events = discovery.subscribe().expect("watching is enabled");
+        let resolver = ep.dns_resolver().clone();
+
+        let relay_url = publish_relay_port(server, &secret_key, 1).await?;
+        let item = discovery
+            .resolve(ep, node_id)
+            .unwrap()
+            .next()
+            .await
+            .unwrap()?;
+        assert_eq!(item.node_addr.relay_url, Some(relay_url));
+
+        // Unchanged records are not emitted.
+        assert!(tokio::time::timeout(Duration::from_secs(2), events.next())
+            .await
+            .is_err());
+
+        let relay_url = publish_relay_port(server, &secret_key, 2).await?;
+        // The test DNS server answers with a TTL of 30s, drop the cached records.
+        resolver.clear_cache();
+        let item = tokio::time::timeout(PUBLISH_TIMEOUT, events.next())
+            .await?
+            .unwrap();
+        assert_eq!(item.node_addr.node_id, node_id);
+        assert_eq!(item.node_addr.relay_url, Some(relay_url));
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn pkarr_resolver_watch() -> Result<()> {
+        let server = DnsPkarrServer::run().await?;
+        let ep = Endpoint::builder()
+            .relay_mode(RelayMode::Disabled)
+            .bind()
+            .await?;
+        let discovery = PkarrResolver::new(server.pkarr_url.clone())
+            .watch_resolved_nodes(true)
+            .poll_interval(Duration::from_millis(100), Duration::from_secs(1));
+        check_watch_resolved(&server, ep, discovery).await
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn dns_discovery_watch() -> Result<()> {
+        let server = DnsPkarrServer::run().await?;
+        let ep = Endpoint::builder()
+            .relay_mode(RelayMode::Disabled)
+            .dns_resolver(server.dns_resolver())
+            .bind()
+            .await?;
+        let discovery = DnsDiscovery::new(server.node_origin.clone())
+            .watch_resolved_nodes(true)
+            .poll_interval(Duration::from_millis(100), Duration::from_secs(1));
+        check_watch_resolved(&server, ep, discovery).await
+    }
+
     const TEST_ALPN: &[u8] = b"TEST";
 
     #[tokio::test]
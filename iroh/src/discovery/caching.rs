@@ -0,0 +1,583 @@
+//! A discovery wrapper caching the results of another discovery service.
+//!
+//! Every [`Endpoint::connect`] to a node without known addresses starts a node discovery,
+//! which for services like [`PkarrResolver`] or [`DnsDiscovery`] means a network round trip.
+//! The [`CachingDiscovery`] wraps any [`Discovery`] and remembers what it resolved:
+//!
+//! - Resolved [`DiscoveryItem`]s are cached for the [TTL] of their records, capped by a
+//!   maximum TTL which defaults to [`DEFAULT_PKARR_TTL`].  Items without a TTL are cached
+//!   for the maximum TTL.
+//!
+//! - Resolutions which did not produce any items are cached for a shorter time, so that
+//!   repeatedly connecting to an unreachable node does not hammer the network.  Failed
+//!   resolutions are not cached, so a temporary failure does not hide a node.
+//!
+//! - Concurrent resolutions of the same [`NodeId`] share a single resolution of the
+//!   wrapped service.
+//!
+//! Publishing and subscribing are passed through to the wrapped service unchanged.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use iroh::{
+//!     discovery::{caching::CachingDiscovery, dns::DnsDiscovery},
+//!     Endpoint,
+//! };
+//!
+//! # async fn wrapper() -> anyhow::Result<()> {
+//! let ep = Endpoint::builder()
+//!     .add_discovery(|_| Some(CachingDiscovery::new(DnsDiscovery::n0_dns())))
+//!     .bind()
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`Endpoint::connect`]: crate::Endpoint::connect
+//! [`PkarrResolver`]: super::pkarr::PkarrResolver
+//! [`DnsDiscovery`]: super::dns::DnsDiscovery
+//! [TTL]: DiscoveryItem::ttl
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, Result};
+use iroh_base::{NodeId, RelayUrl};
+use n0_future::{
+    boxed::BoxStream,
+    stream::{self, StreamExt},
+    task,
+    time::{Duration, Instant},
+};
+use tokio::sync::watch;
+use tracing::{debug, trace};
+
+use super::{pkarr::DEFAULT_PKARR_TTL, Discovery, DiscoveryItem, UserData};
+use crate::{endpoint::DirectAddr, Endpoint};
+
+/// The default time for which a resolution without results is cached.
+pub const DEFAULT_NEGATIVE_TTL: Duration = Duration::from_secs(5);
+
+/// The default maximum number of nodes for which results are cached.
+pub const DEFAULT_MAX_ENTRIES: usize = 1024;
+
+/// Statistics about the usage of a [`CachingDiscovery`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Number of resolutions answered from cached items.
+    pub hits: u64,
+    /// Number of resolutions answered from a cached empty result.
+    pub negative_hits: u64,
+    /// Number of resolutions which started a resolution of the wrapped service.
+    pub misses: u64,
+    /// Number of resolutions which joined an already running resolution.
+    pub coalesced: u64,
+    /// Number of cache entries removed to stay below the maximum number of entries.
+    pub evictions: u64,
+    /// Number of nodes currently cached, including cached empty results.
+    pub entries: usize,
+}
+
+/// A [`Discovery`] service caching the results of the wrapped service.
+///
+/// See the [module documentation](self) for details.
+#[derive(Debug)]
+pub struct CachingDiscovery<D> {
+    inner: Arc<D>,
+    max_ttl: Duration,
+    negative_ttl: Duration,
+    max_entries: usize,
+    state: Arc<Mutex<State>>,
+}
+
+impl<D> Clone for CachingDiscovery<D> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            max_ttl: self.max_ttl,
+            negative_ttl: self.negative_ttl,
+            max_entries: self.max_entries,
+            state: self.state.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    cache: HashMap<NodeId, CacheEntry>,
+    in_flight: HashMap<NodeId, Arc<watch::Sender<Resolution>>>,
+    stats: CacheStats,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    /// The cached items, empty for a negative entry.
+    items: Vec<DiscoveryItem>,
+    expires: Instant,
+}
+
+/// The progress of a running resolution, shared between all resolvers of a node.
+#[derive(Debug, Default)]
+struct Resolution {
+    /// The results produced so far.  Errors are stored as their formatted message.
+    results: Vec<Result<DiscoveryItem, String>>,
+    done: bool,
+}
+
+impl<D: Discovery + 'static> CachingDiscovery<D> {
+    /// Creates a new caching wrapper around the `inner` discovery service.
+    pub fn new(inner: D) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            max_ttl: Duration::from_secs(DEFAULT_PKARR_TTL as u64),
+            negative_ttl: DEFAULT_NEGATIVE_TTL,
+            max_entries: DEFAULT_MAX_ENTRIES,
+            state: Default::default(),
+        }
+    }
+
+    /// Sets the maximum time for which resolved items are cached.
+    ///
+    /// Items are cached for their [`DiscoveryItem::ttl`] if it is shorter, and for this time
+    /// if they have no TTL.  Defaults to [`DEFAULT_PKARR_TTL`].
+    pub fn max_ttl(mut self, ttl: Duration) -> Self {
+        self.max_ttl = ttl;
+        self
+    }
+
+    /// Sets the time for which a resolution without any results is cached.
+    ///
+    /// Defaults to [`DEFAULT_NEGATIVE_TTL`].  Set to [`Duration::ZERO`] to disable negative
+    /// caching.
+    pub fn negative_ttl(mut self, ttl: Duration) -> Self {
+        self.negative_ttl = ttl;
+        self
+    }
+
+    /// Sets the maximum number of nodes for which results are cached.
+    ///
+    /// Defaults to [`DEFAULT_MAX_ENTRIES`].
+    pub fn max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries;
+        self
+    }
+
+    /// Returns the wrapped discovery service.
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    /// Returns the current cache statistics.
+    pub fn stats(&self) -> CacheStats {
+        let state = self.state.lock().expect("poisoned");
+        CacheStats {
+            entries: state.cache.len(),
+            ..state.stats
+        }
+    }
+
+    /// Removes the cached results for the node.
+    pub fn invalidate(&self, node_id: &NodeId) {
+        self.state.lock().expect("poisoned").cache.remove(node_id);
+    }
+
+    /// Removes all cached results.
+    pub fn clear(&self) {
+        self.state.lock().expect("poisoned").cache.clear();
+    }
+
+    /// Runs the resolution of the wrapped service and caches its outcome.
+    ///
+    /// The resolution is abandoned once all resolvers dropped their streams.
+    fn spawn_resolution(
+        &self,
+        mut stream: BoxStream<Result<DiscoveryItem>>,
+        node_id: NodeId,
+        sender: Arc<watch::Sender<Resolution>>,
+    ) {
+        let this = self.clone();
+        task::spawn(async move {
+            loop {
+                let next = tokio::select! {
+                    next = stream.next() => next,
+                    _ = sender.closed() => {
+                        let mut state = this.state.lock().expect("poisoned");
+                        // A resolver might have joined while we were waking up.
+                        if sender.receiver_count() > 0 {
+                            continue;
+                        }
+                        trace!(node = %node_id.fmt_short(), "resolution abandoned");
+                        state.in_flight.remove(&node_id);
+                        return;
+                    }
+                };
+                match next {
+                    Some(result) => {
+                        let result = result.map_err(|err| format!("{err:#}"));
+                        sender.send_modify(|resolution| resolution.results.push(result));
+                    }
+                    None => break,
+                }
+            }
+            let (items, failed) = {
+                let resolution = sender.borrow();
+                let items = resolution
+                    .results
+                    .iter()
+                    .filter_map(|result| result.as_ref().ok().cloned())
+                    .collect::<Vec<_>>();
+                let failed = resolution.results.iter().any(Result::is_err);
+                (items, failed)
+            };
+            this.insert(node_id, items, failed);
+            sender.send_modify(|resolution| resolution.done = true);
+        });
+    }
+
+    /// Caches the outcome of a finished resolution.
+    ///
+    /// A resolution without items is only cached if none of its results was an error.
+    fn insert(&self, node_id: NodeId, items: Vec<DiscoveryItem>, failed: bool) {
+        let ttl = if items.is_empty() {
+            if failed {
+                Duration::ZERO
+            } else {
+                self.negative_ttl
+            }
+        } else {
+            items
+                .iter()
+                .map(|item| item.ttl.map_or(self.max_ttl, |ttl| ttl.min(self.max_ttl)))
+                .min()
+                .unwrap_or(self.max_ttl)
+        };
+        let mut state = self.state.lock().expect("poisoned");
+        state.in_flight.remove(&node_id);
+        if ttl.is_zero() || self.max_entries == 0 {
+            return;
+        }
+        let now = Instant::now();
+        if !state.cache.contains_key(&node_id) && state.cache.len() >= self.max_entries {
+            state.cache.retain(|_, entry| entry.expires > now);
+            if state.cache.len() >= self.max_entries {
+                let oldest = state
+                    .cache
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.expires)
+                    .map(|(node_id, _)| *node_id);
+                if let Some(oldest) = oldest {
+                    state.cache.remove(&oldest);
+                    state.stats.evictions += 1;
+                }
+            }
+        }
+        debug!(
+            node = %node_id.fmt_short(),
+            items = items.len(),
+            ?ttl,
+            "caching discovery result"
+        );
+        state.cache.insert(
+            node_id,
+            CacheEntry {
+                items,
+                expires: now + ttl,
+            },
+        );
+    }
+}
+
+/// Returns a stream yielding the results of a running resolution.
+fn follow(receiver: watch::Receiver<Resolution>) -> BoxStream<Result<DiscoveryItem>> {
+    stream::unfold((receiver, 0), |(mut receiver, idx)| async move {
+        loop {
+            let next = {
+                let resolution = receiver.borrow_and_update();
+                match resolution.results.get(idx) {
+                    Some(result) => Some(result.clone().map_err(|err| anyhow!(err))),
+                    None if resolution.done => return None,
+                    None => None,
+                }
+            };
+            if let Some(result) = next {
+                return Some((result, (receiver, idx + 1)));
+            }
+            if receiver.changed().await.is_err() {
+                return None;
+            }
+        }
+    })
+    .boxed()
+}
+
+impl<D: Discovery + 'static> Discovery for CachingDiscovery<D> {
+    fn publish(
+        &self,
+        url: Option<&RelayUrl>,
+        addrs: &BTreeSet<DirectAddr>,
+        user_data: Option<&UserData>,
+    ) {
+        self.inner.as_ref().publish(url, addrs, user_data);
+    }
+
+    fn resolve(
+        &self,
+        endpoint: Endpoint,
+        node_id: NodeId,
+    ) -> Option<BoxStream<Result<DiscoveryItem>>> {
+        let mut state = self.state.lock().expect("poisoned");
+        let now = Instant::now();
+        match state.cache.get(&node_id) {
+            Some(entry) if entry.expires > now => {
+                let items = entry.items.clone();
+                if items.is_empty() {
+                    state.stats.negative_hits += 1;
+                } else {
+                    state.stats.hits += 1;
+                }
+                trace!(node = %node_id.fmt_short(), items = items.len(), "discovery cache hit");
+                return Some(stream::iter(items.into_iter().map(Ok)).boxed());
+            }
+            Some(_) => {
+                state.cache.remove(&node_id);
+            }
+            None => {}
+        }
+        if let Some(sender) = state.in_flight.get(&node_id) {
+            let receiver = sender.subscribe();
+            state.stats.coalesced += 1;
+            return Some(follow(receiver));
+        }
+        let stream = self.inner.as_ref().resolve(endpoint, node_id)?;
+        state.stats.misses += 1;
+        let (sender, receiver) = watch::channel(Resolution::default());
+        let sender = Arc::new(sender);
+        state.in_flight.insert(node_id, sender.clone());
+        drop(state);
+        self.spawn_resolution(stream, node_id, sender);
+        Some(follow(receiver))
+    }
+
+    fn subscribe(&self) -> Option<BoxStream<DiscoveryItem>> {
+        self.inner.as_ref().subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use iroh_base::{NodeAddr, SecretKey};
+    use n0_future::time;
+    use testresult::TestResult;
+    use tracing_test::traced_test;
+
+    use super::*;
+    use crate::RelayMode;
+
+    /// A discovery service counting its resolutions, which only knows a single node.
+    #[derive(Debug, Default)]
+    struct CountingDiscovery {
+        known: Option<NodeId>,
+        /// The TTL of the resolved items.
+        ttl: Option<Duration>,
+        /// Whether resolutions fail.
+        fail: bool,
+        resolves: AtomicUsize,
+    }
+
+    impl Discovery for CountingDiscovery {
+        fn resolve(
+            &self,
+            _endpoint: Endpoint,
+            node_id: NodeId,
+        ) -> Option<BoxStream<Result<DiscoveryItem>>> {
+            self.resolves.fetch_add(1, Ordering::SeqCst);
+            let found = self.known == Some(node_id);
+            let ttl = self.ttl;
+            let fail = self.fail;
+            let fut = async move {
+                time::sleep(Duration::from_millis(100)).await;
+                if fail {
+                    return Some(Err(anyhow!("resolution failed")));
+                }
+                found.then(|| {
+                    Ok(DiscoveryItem {
+                        node_addr: NodeAddr::from_parts(
+                            node_id,
+                            None,
+                            ["240.0.0.1:1000".parse().unwrap()],
+                        ),
+                        provenance: "counting",
+                        last_updated: None,
+                        user_data: None,
+                        ttl,
+                    })
+                })
+            };
+            Some(stream::once_future(fut).filter_map(|item| item).boxed())
+        }
+    }
+
+    async fn endpoint() -> Result<Endpoint> {
+        Endpoint::builder()
+            .relay_mode(RelayMode::Disabled)
+            .bind()
+            .await
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn caching_discovery_hit_and_expiry() -> TestResult {
+        let ep = endpoint().await?;
+        let node_id = SecretKey::generate(rand::thread_rng()).public();
+        let disco = CachingDiscovery::new(CountingDiscovery {
+            known: Some(node_id),
+            ..Default::default()
+        })
+        .max_ttl(Duration::from_millis(500));
+
+        let items = disco
+            .resolve(ep.clone(), node_id)
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(items.len(), 1);
+        let items = disco
+            .resolve(ep.clone(), node_id)
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].as_ref().unwrap().node_addr.node_id, node_id);
+        assert_eq!(disco.inner().resolves.load(Ordering::SeqCst), 1);
+
+        time::sleep(Duration::from_millis(600)).await;
+        let items = disco
+            .resolve(ep.clone(), node_id)
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(items.len(), 1);
+        assert_eq!(disco.inner().resolves.load(Ordering::SeqCst), 2);
+
+        let stats = disco.stats();
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.misses, 2);
+        assert_eq!(stats.entries, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn caching_discovery_negative() -> TestResult {
+        let ep = endpoint().await?;
+        let node_id = SecretKey::generate(rand::thread_rng()).public();
+        let disco = CachingDiscovery::new(CountingDiscovery::default())
+            .negative_ttl(Duration::from_millis(300));
+
+        for _ in 0..3 {
+            let items = disco
+                .resolve(ep.clone(), node_id)
+                .unwrap()
+                .collect::<Vec<_>>()
+                .await;
+            assert!(items.is_empty());
+        }
+        assert_eq!(disco.inner().resolves.load(Ordering::SeqCst), 1);
+        assert_eq!(disco.stats().negative_hits, 2);
+
+        time::sleep(Duration::from_millis(400)).await;
+        disco
+            .resolve(ep.clone(), node_id)
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(disco.inner().resolves.load(Ordering::SeqCst), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn caching_discovery_coalesce() -> TestResult {
+        let ep = endpoint().await?;
+        let node_id = SecretKey::generate(rand::thread_rng()).public();
+        let disco = CachingDiscovery::new(CountingDiscovery {
+            known: Some(node_id),
+            ..Default::default()
+        });
+
+        let streams = (0..5)
+            .map(|_| disco.resolve(ep.clone(), node_id).unwrap())
+            .collect::<Vec<_>>();
+        for stream in streams {
+            let items = stream.collect::<Vec<_>>().await;
+            assert_eq!(items.len(), 1);
+        }
+        assert_eq!(disco.inner().resolves.load(Ordering::SeqCst), 1);
+        let stats = disco.stats();
+        assert_eq!(stats.misses, 1);
+        assert_eq!(stats.coalesced, 4);
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn caching_discovery_item_ttl() -> TestResult {
+        let ep = endpoint().await?;
+        let node_id = SecretKey::generate(rand::thread_rng()).public();
+        let disco = CachingDiscovery::new(CountingDiscovery {
+            known: Some(node_id),
+            ttl: Some(Duration::from_millis(200)),
+            ..Default::default()
+        })
+        .max_ttl(Duration::from_secs(60));
+
+        for _ in 0..2 {
+            disco
+                .resolve(ep.clone(), node_id)
+                .unwrap()
+                .collect::<Vec<_>>()
+                .await;
+        }
+        assert_eq!(disco.inner().resolves.load(Ordering::SeqCst), 1);
+
+        // The item expires after its own TTL, not the maximum TTL.
+        time::sleep(Duration::from_millis(300)).await;
+        disco
+            .resolve(ep.clone(), node_id)
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(disco.inner().resolves.load(Ordering::SeqCst), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn caching_discovery_errors_not_cached() -> TestResult {
+        let ep = endpoint().await?;
+        let node_id = SecretKey::generate(rand::thread_rng()).public();
+        let disco = CachingDiscovery::new(CountingDiscovery {
+            known: Some(node_id),
+            fail: true,
+            ..Default::default()
+        });
+
+        for _ in 0..2 {
+            let items = disco
+                .resolve(ep.clone(), node_id)
+                .unwrap()
+                .collect::<Vec<_>>()
+                .await;
+            assert_eq!(items.len(), 1);
+            assert!(items[0].is_err());
+        }
+        assert_eq!(disco.inner().resolves.load(Ordering::SeqCst), 2);
+        assert_eq!(disco.stats().entries, 0);
+        Ok(())
+    }
+}
@@ -1,12 +1,18 @@
 //! DNS node discovery for iroh
 
+use std::sync::Arc;
+
 use anyhow::Result;
 use iroh_base::NodeId;
 pub use iroh_relay::dns::{N0_DNS_NODE_ORIGIN_PROD, N0_DNS_NODE_ORIGIN_STAGING};
-use n0_future::boxed::BoxStream;
+use n0_future::{boxed::BoxStream, time::Duration};
 
 use crate::{
-    discovery::{Discovery, DiscoveryItem},
+    discovery::{
+        watch::{Lookup, NodeWatcher, Resolved, WatchOptions},
+        Discovery, DiscoveryItem,
+    },
+    dns::DnsResolver,
     endpoint::force_staging_infra,
     Endpoint,
 };
@@ -32,16 +38,33 @@ const DNS_STAGGERING_MS: &[u64] = &[200, 300];
 /// The DNS resolver defaults to using the nameservers configured on the host system, but can be changed
 /// with [`crate::endpoint::Builder::dns_resolver`].
 ///
+/// The discovery can keep watching the nodes it resolved, see
+/// [`DnsDiscovery::watch_resolved_nodes`].
+///
 /// [z-base-32]: https://philzimmermann.com/docs/human-oriented-base-32-encoding.txt
 #[derive(Debug)]
 pub struct DnsDiscovery {
     origin_domain: String,
+    watch: bool,
+    watch_options: WatchOptions,
+    watcher: NodeWatcher,
 }
 
 impl DnsDiscovery {
+    /// The provenance string for this discovery implementation.
+    ///
+    /// This is mostly used for debugging information and allows understanding the origin of
+    /// addressing information used by an iroh [`Endpoint`].
+    pub const PROVENANCE: &'static str = "dns";
+
     /// Creates a new DNS discovery.
     pub fn new(origin_domain: String) -> Self {
-        Self { origin_domain }
+        Self {
+            origin_domain,
+            watch: false,
+            watch_options: WatchOptions::default(),
+            watcher: NodeWatcher::default(),
+        }
     }
 
     /// Creates a new DNS discovery using the `iroh.link` domain.
@@ -60,23 +83,95 @@ impl DnsDiscovery {
             Self::new(N0_DNS_NODE_ORIGIN_PROD.to_string())
         }
     }
+
+    /// Sets whether to keep watching the nodes resolved by this discovery.
+    ///
+    /// When enabled, resolved nodes are looked up again periodically, and
+    /// [`Discovery::subscribe`] emits a [`DiscoveryItem`] whenever a node's records changed.
+    /// DNS records carry no timestamp, so unlike for pkarr any change is emitted.
+    ///
+    /// The nodes are looked up again once the TTL of their records elapsed, clamped to the
+    /// bounds set with [`DnsDiscovery::poll_interval`].
+    ///
+    /// Disabled by default.
+    pub fn watch_resolved_nodes(mut self, watch: bool) -> Self {
+        self.watch = watch;
+        self
+    }
+
+    /// Sets the bounds of the interval at which watched nodes are looked up again.
+    ///
+    /// Defaults to [`DEFAULT_MIN_POLL_INTERVAL`] and [`DEFAULT_MAX_POLL_INTERVAL`].
+    ///
+    /// [`DEFAULT_MIN_POLL_INTERVAL`]: super::DEFAULT_MIN_POLL_INTERVAL
+    /// [`DEFAULT_MAX_POLL_INTERVAL`]: super::DEFAULT_MAX_POLL_INTERVAL
+    pub fn poll_interval(mut self, min: Duration, max: Duration) -> Self {
+        self.watch_options.min_interval = min;
+        self.watch_options.max_interval = max;
+        self
+    }
+
+    /// Sets the maximum number of watched nodes.
+    ///
+    /// When exceeded, the node resolved least recently is no longer watched.  Defaults to
+    /// [`DEFAULT_MAX_WATCHED_NODES`].
+    ///
+    /// [`DEFAULT_MAX_WATCHED_NODES`]: super::DEFAULT_MAX_WATCHED_NODES
+    pub fn max_watched_nodes(mut self, max: usize) -> Self {
+        self.watch_options.max_nodes = max;
+        self
+    }
+
+    fn lookup(resolver: DnsResolver, origin_domain: String) -> Lookup {
+        Arc::new(move |node_id| {
+            let resolver = resolver.clone();
+            let origin_domain = origin_domain.clone();
+            Box::pin(async move { Self::resolve_node(&resolver, &origin_domain, node_id).await })
+        })
+    }
+
+    async fn resolve_node(
+        resolver: &DnsResolver,
+        origin_domain: &str,
+        node_id: NodeId,
+    ) -> Result<Resolved> {
+        let (node_info, ttl) = resolver
+            .lookup_node_info_with_ttl_by_id_staggered(&node_id, origin_domain, DNS_STAGGERING_MS)
+            .await?;
+        let user_data = node_info.user_data.clone();
+        let item = DiscoveryItem {
+            node_addr: node_info.into(),
+            provenance: Self::PROVENANCE,
+            last_updated: None,
+            user_data,
+            ttl: Some(ttl),
+        };
+        Ok(Resolved { item, ttl })
+    }
 }
 
 impl Discovery for DnsDiscovery {
     fn resolve(&self, ep: Endpoint, node_id: NodeId) -> Option<BoxStream<Result<DiscoveryItem>>> {
         let resolver = ep.dns_resolver().clone();
         let origin_domain = self.origin_domain.clone();
+        let watch = self
+            .watch
+            .then(|| (self.watcher.clone(), self.watch_options.clone()));
         let fut = async move {
-            let node_addr = resolver
-                .lookup_node_by_id_staggered(&node_id, &origin_domain, DNS_STAGGERING_MS)
-                .await?;
-            Ok(DiscoveryItem {
-                node_addr,
-                provenance: "dns",
-                last_updated: None,
-            })
+            let resolved = Self::resolve_node(&resolver, &origin_domain, node_id).await?;
+            let item = resolved.item.clone();
+            if let Some((watcher, options)) = watch {
+                let lookup = Self::lookup(resolver, origin_domain);
+                watcher.watch(resolved, lookup, &options);
+            }
+            Ok(item)
         };
         let stream = n0_future::stream::once_future(fut);
         Some(Box::pin(stream))
     }
+
+    fn subscribe(&self) -> Option<BoxStream<DiscoveryItem>> {
+        self.watch
+            .then(|| self.watcher.subscribe(&self.watch_options))
+    }
 }
@@ -48,7 +48,8 @@ use tokio::sync::mpsc::{self, error::TrySendError};
 use tracing::{debug, error, info_span, trace, warn, Instrument};
 
 use crate::{
-    discovery::{Discovery, DiscoveryItem},
+    discovery::{AddrPublishPolicy, Discovery, DiscoveryItem, UserData},
+    endpoint::DirectAddr,
     watchable::Watchable,
     Endpoint,
 };
@@ -66,14 +67,24 @@ pub const NAME: &str = "local.swarm.discovery";
 /// How long we will wait before we stop sending discovery items
 const DISCOVERY_DURATION: Duration = Duration::from_secs(10);
 
+/// The key of the TXT attribute carrying the [`UserData`].
+const USER_DATA_ATTRIBUTE: &str = "user-data";
+
+/// The relay URL, direct addresses and user data we announce.
+type LocalInfo = (Option<RelayUrl>, BTreeSet<SocketAddr>, Option<UserData>);
+
 /// Discovery using `swarm-discovery`, a variation on mdns
+///
+/// The [`UserData`] is announced as a TXT attribute of the swarm-discovery record.
 #[derive(Debug)]
 pub struct LocalSwarmDiscovery {
     #[allow(dead_code)]
     handle: AbortOnDropHandle<()>,
     sender: mpsc::Sender<Message>,
     /// When `local_addrs` changes, we re-publish our info.
-    local_addrs: Watchable<Option<(Option<RelayUrl>, BTreeSet<SocketAddr>)>>,
+    local_addrs: Watchable<Option<LocalInfo>>,
+    /// Which of our direct addresses we announce.
+    policy: AddrPublishPolicy,
 }
 
 #[derive(Debug)]
@@ -146,8 +157,7 @@ impl LocalSwarmDiscovery {
             &rt,
         )?;
 
-        let local_addrs: Watchable<Option<(Option<RelayUrl>, BTreeSet<SocketAddr>)>> =
-            Watchable::default();
+        let local_addrs: Watchable<Option<LocalInfo>> = Watchable::default();
         let mut addrs_change = local_addrs.watch();
         let discovery_fut = async move {
             let mut node_addrs: HashMap<PublicKey, Peer> = HashMap::default();
@@ -164,14 +174,25 @@ impl LocalSwarmDiscovery {
                     msg = recv.recv() => {
                         msg
                     }
-                    Ok(Some((_url, addrs))) = addrs_change.updated() => {
-                        tracing::trace!(?addrs, "LocalSwarmDiscovery address changed");
+                    Ok(Some((_url, addrs, user_data))) = addrs_change.updated() => {
+                        tracing::trace!(?addrs, ?user_data, "LocalSwarmDiscovery address changed");
                         discovery.remove_all();
                         let addrs =
                             LocalSwarmDiscovery::socketaddrs_to_addrs(addrs);
                         for addr in addrs {
                             discovery.add(addr.0, addr.1)
                         }
+                        match user_data {
+                            Some(user_data) => {
+                                if let Err(err) = discovery.set_txt_attribute(
+                                    USER_DATA_ATTRIBUTE.to_string(),
+                                    Some(user_data.to_string()),
+                                ) {
+                                    warn!("failed to set user data TXT attribute: {err:#}");
+                                }
+                            }
+                            None => discovery.remove_txt_attribute(USER_DATA_ATTRIBUTE.to_string()),
+                        }
                         continue;
                     }
                 };
@@ -294,9 +315,22 @@ impl LocalSwarmDiscovery {
             handle: AbortOnDropHandle::new(handle),
             sender: send,
             local_addrs,
+            policy: AddrPublishPolicy::direct_only(),
         })
     }
 
+    /// Sets which direct addresses are announced on the local network.
+    ///
+    /// Only the address filters of the policy are used: the relay URL is never announced, and
+    /// the direct addresses are announced even for [`AddrPublishPolicy::relay_only`] or
+    /// [`AddrPublishPolicy::relay_or_direct`], as they are all that is announced on the local
+    /// network.  Defaults to [`AddrPublishPolicy::direct_only`], announcing all direct
+    /// addresses.
+    pub fn addr_publish_policy(mut self, policy: AddrPublishPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
     fn spawn_discoverer(
         node_id: PublicKey,
         sender: mpsc::Sender<Message>,
@@ -349,6 +383,16 @@ fn peer_to_discovery_item(peer: &Peer, node_id: &NodeId) -> DiscoveryItem {
         .iter()
         .map(|(ip, port)| SocketAddr::new(*ip, *port))
         .collect();
+    let user_data = match peer.txt_attribute(USER_DATA_ATTRIBUTE) {
+        Some(Some(user_data)) => match user_data.parse() {
+            Ok(user_data) => Some(user_data),
+            Err(err) => {
+                debug!(?node_id, "ignoring invalid user data: {err}");
+                None
+            }
+        },
+        _ => None,
+    };
     DiscoveryItem {
         node_addr: NodeAddr {
             node_id: *node_id,
@@ -357,6 +401,8 @@ fn peer_to_discovery_item(peer: &Peer, node_id: &NodeId) -> DiscoveryItem {
         },
         provenance: NAME,
         last_updated: None,
+        user_data,
+        ttl: None,
     }
 }
 
@@ -376,9 +422,19 @@ impl Discovery for LocalSwarmDiscovery {
         Some(Box::pin(stream.flatten_stream()))
     }
 
-    fn publish(&self, url: Option<&RelayUrl>, addrs: &BTreeSet<SocketAddr>) {
+    fn publish(
+        &self,
+        url: Option<&RelayUrl>,
+        addrs: &BTreeSet<DirectAddr>,
+        user_data: Option<&UserData>,
+    ) {
+        let addrs = addrs
+            .iter()
+            .filter(|addr| self.policy.allows(addr))
+            .map(|addr| addr.addr)
+            .collect();
         self.local_addrs
-            .set(Some((url.cloned(), addrs.clone())))
+            .set(Some((url.cloned(), addrs, user_data.cloned())))
             .ok();
     }
 
@@ -426,7 +482,7 @@ mod tests {
 
             tracing::debug!(?node_id_b, "Discovering node id b");
             // publish discovery_b's address
-            discovery_b.publish(addr_info.0.as_ref(), &addr_info.1);
+            discovery_b.publish(addr_info.0.as_ref(), &direct_addrs(&addr_info.1), None);
             let s1_res = tokio::time::timeout(Duration::from_secs(5), s1.next())
                 .await?
                 .unwrap()?;
@@ -441,6 +497,29 @@ mod tests {
             Ok(())
         }
 
+        #[tokio::test]
+        #[traced_test]
+        async fn local_swarm_discovery_user_data() -> TestResult {
+            let (_, discovery_a) = make_discoverer()?;
+            let (node_id_b, discovery_b) = make_discoverer()?;
+
+            let addrs = BTreeSet::from(["0.0.0.0:11111".parse()?]);
+            let user_data: UserData = "services=foo,bar".parse()?;
+
+            // pass in endpoint, this is never used
+            let ep = crate::endpoint::Builder::default().bind().await?;
+
+            let mut stream = discovery_a.resolve(ep, node_id_b).unwrap();
+            discovery_b.publish(None, &direct_addrs(&addrs), Some(&user_data));
+            let item = tokio::time::timeout(Duration::from_secs(5), stream.next())
+                .await?
+                .unwrap()?;
+            assert_eq!(item.node_addr.direct_addresses, addrs);
+            assert_eq!(item.user_data, Some(user_data));
+
+            Ok(())
+        }
+
         #[tokio::test]
         #[traced_test]
         async fn local_swarm_discovery_subscribe() -> TestResult {
@@ -454,7 +533,7 @@ mod tests {
             for _ in 0..num_nodes {
                 let (node_id, discovery) = make_discoverer()?;
                 node_ids.insert(node_id);
-                discovery.publish(addr_info.0.as_ref(), &addr_info.1);
+                discovery.publish(addr_info.0.as_ref(), &direct_addrs(&addr_info.1), None);
                 discoverers.push(discovery);
             }
 
@@ -481,6 +560,38 @@ mod tests {
             Ok(())
         }
 
+        #[tokio::test]
+        #[traced_test]
+        async fn local_swarm_discovery_relay_policy_keeps_direct_addrs() -> TestResult {
+            let (_, discovery) = make_discoverer()?;
+            let discovery = discovery.addr_publish_policy(
+                AddrPublishPolicy::relay_only().exclude_type(crate::endpoint::DirectAddrType::Stun),
+            );
+            let relay_url: RelayUrl = "https://relay.example".parse()?;
+            let local: SocketAddr = "192.168.1.2:11111".parse()?;
+            let stun: SocketAddr = "1.2.3.4:11111".parse()?;
+            let mut addrs = direct_addrs(&BTreeSet::from([local]));
+            addrs.insert(DirectAddr {
+                addr: stun,
+                typ: crate::endpoint::DirectAddrType::Stun,
+            });
+
+            discovery.publish(Some(&relay_url), &addrs, None);
+            let (_, announced, _) = discovery.local_addrs.get().unwrap();
+            assert_eq!(announced, BTreeSet::from([local]));
+            Ok(())
+        }
+
+        fn direct_addrs(addrs: &BTreeSet<SocketAddr>) -> BTreeSet<DirectAddr> {
+            addrs
+                .iter()
+                .map(|addr| DirectAddr {
+                    addr: *addr,
+                    typ: crate::endpoint::DirectAddrType::Local,
+                })
+                .collect()
+        }
+
         fn make_discoverer() -> Result<(PublicKey, LocalSwarmDiscovery)> {
             let node_id = SecretKey::generate(rand::thread_rng()).public();
             Ok((node_id, LocalSwarmDiscovery::new(node_id)?))
@@ -0,0 +1,364 @@
+//! A node discovery asking already connected peers for addresses of other nodes.
+//!
+//! All other discovery services rely on some external infrastructure like a pkarr relay,
+//! DNS, the mainline DHT or multicast on the local network.  In a mesh where nodes are
+//! already connected to each other, they can instead tell each other about the addresses of
+//! third nodes: this is peer exchange.
+//!
+//! Peer exchange consists of two parts:
+//!
+//! - The [`PeerExchange`] protocol handler, which answers queries from other nodes on
+//!   [`ALPN`] with the addressing information the local [`Endpoint`] knows about.  Sharing
+//!   the addresses of other nodes is opt-in: by default it answers no queries, which nodes
+//!   are shared with whom is configured with [`PeerExchange::share`].
+//!
+//! - The [`PeerExchangeDiscovery`] service, which resolves a [`NodeId`] by asking the
+//!   peers the [`Endpoint`] is currently connected to.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use iroh::{
+//!     discovery::peer_exchange::{self, PeerExchange, PeerExchangeDiscovery},
+//!     protocol::Router,
+//!     Endpoint,
+//! };
+//!
+//! # async fn wrapper() -> anyhow::Result<()> {
+//! let ep = Endpoint::builder()
+//!     .add_discovery(|_| Some(PeerExchangeDiscovery::new()))
+//!     .bind()
+//!     .await?;
+//! // share the addresses of all connected nodes with everyone asking
+//! let pex = PeerExchange::new(ep.clone()).share(|_requester, _node_id| true);
+//! let router = Router::builder(ep.clone())
+//!     .accept(peer_exchange::ALPN, pex)
+//!     .spawn()
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::sync::Arc;
+
+use anyhow::{ensure, Result};
+use iroh_base::{NodeAddr, NodeId};
+use n0_future::{
+    boxed::BoxFuture,
+    stream::{Boxed as BoxStream, StreamExt},
+    time::{self, Duration},
+    FuturesUnordered,
+};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, trace};
+
+use super::{Discovery, DiscoveryItem};
+use crate::{
+    endpoint::{Connecting, ConnectionType},
+    protocol::ProtocolHandler,
+    Endpoint,
+};
+
+/// The ALPN used by the peer exchange protocol.
+pub const ALPN: &[u8] = b"/iroh/pex/0";
+
+/// The maximum number of connected peers asked concurrently by default.
+pub const DEFAULT_MAX_PEERS: usize = 8;
+
+/// The default time after which a query to a single peer is abandoned.
+pub const DEFAULT_QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The maximum size of an encoded request or response.
+const MAX_MESSAGE_SIZE: usize = 16 * 1024;
+
+/// A request asking a peer for the addresses of a node.
+#[derive(Debug, Serialize, Deserialize)]
+struct Request {
+    node_id: NodeId,
+}
+
+/// The answer to a [`Request`].
+#[derive(Debug, Serialize, Deserialize)]
+struct Response {
+    /// The addressing information the peer knows for the node, if any.
+    node_addr: Option<NodeAddr>,
+}
+
+type ShareFn = Arc<dyn Fn(NodeId, NodeId) -> bool + Send + Sync>;
+
+/// The [`ProtocolHandler`] answering peer exchange queries.
+///
+/// This answers queries for a node with the addressing information the [`Endpoint`]
+/// currently knows about, as returned by [`Endpoint::remote_info`].  Only nodes the endpoint
+/// is currently connected to are shared, and only if allowed by [`PeerExchange::share`].
+#[derive(derive_more::Debug, Clone)]
+pub struct PeerExchange {
+    endpoint: Endpoint,
+    #[debug("Option<ShareFn>")]
+    share: Option<ShareFn>,
+}
+
+impl PeerExchange {
+    /// Creates a new peer exchange protocol handler for the endpoint.
+    ///
+    /// The handler does not share the addresses of any node until allowed with
+    /// [`PeerExchange::share`].
+    pub fn new(endpoint: Endpoint) -> Self {
+        Self {
+            endpoint,
+            share: None,
+        }
+    }
+
+    /// Sets which nodes are shared with whom.
+    ///
+    /// The addresses of a connected node are shared with a requesting peer if
+    /// `share(requester, node_id)` returns `true`.  Sharing reveals the peers of the endpoint
+    /// to the requester, so this should only allow peers which may know about each other.
+    pub fn share<F>(mut self, share: F) -> Self
+    where
+        F: Fn(NodeId, NodeId) -> bool + Send + Sync + 'static,
+    {
+        self.share = Some(Arc::new(share));
+        self
+    }
+
+    /// Returns the addressing information we can share for the node with the requester.
+    fn lookup(&self, requester: NodeId, node_id: NodeId) -> Option<NodeAddr> {
+        let share = self.share.as_ref()?;
+        if !share(requester, node_id) {
+            return None;
+        }
+        let info = self.endpoint.remote_info(node_id)?;
+        if info.conn_type == ConnectionType::None || !info.has_send_address() {
+            return None;
+        }
+        let node_addr = NodeAddr::from(info);
+        (!node_addr.is_empty()).then_some(node_addr)
+    }
+}
+
+impl ProtocolHandler for PeerExchange {
+    fn accept(&self, connecting: Connecting) -> BoxFuture<Result<()>> {
+        let this = self.clone();
+        Box::pin(async move {
+            let connection = connecting.await?;
+            let remote = connection.remote_node_id()?;
+            while let Ok((mut send, mut recv)) = connection.accept_bi().await {
+                let request = recv.read_to_end(MAX_MESSAGE_SIZE).await?;
+                let request: Request = postcard::from_bytes(&request)?;
+                let node_addr = this.lookup(remote, request.node_id);
+                trace!(
+                    remote = %remote.fmt_short(),
+                    node_id = %request.node_id.fmt_short(),
+                    found = node_addr.is_some(),
+                    "peer exchange query"
+                );
+                let response = postcard::to_stdvec(&Response { node_addr })?;
+                send.write_all(&response).await?;
+                send.finish()?;
+            }
+            Ok(())
+        })
+    }
+}
+
+/// A discovery service asking connected peers for the addresses of a node.
+///
+/// On [`Discovery::resolve`] this asks up to [`PeerExchangeDiscovery::max_peers`] peers
+/// we are currently connected to, as listed by [`Endpoint::remote_info_iter`], whether they
+/// know the node.  The peers need to run the [`PeerExchange`] protocol handler.
+///
+/// This does not publish anything.
+#[derive(Debug, Clone)]
+pub struct PeerExchangeDiscovery {
+    max_peers: usize,
+    query_timeout: Duration,
+}
+
+impl Default for PeerExchangeDiscovery {
+    fn default() -> Self {
+        Self {
+            max_peers: DEFAULT_MAX_PEERS,
+            query_timeout: DEFAULT_QUERY_TIMEOUT,
+        }
+    }
+}
+
+impl PeerExchangeDiscovery {
+    /// The provenance string for this discovery implementation.
+    ///
+    /// This is mostly used for debugging information and allows understanding the origin of
+    /// addressing information used by an iroh [`Endpoint`].
+    pub const PROVENANCE: &'static str = "peer_exchange";
+
+    /// Creates a new peer exchange discovery with the default settings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the maximum number of connected peers asked when resolving a node.
+    ///
+    /// Defaults to [`DEFAULT_MAX_PEERS`].
+    pub fn max_peers(mut self, max_peers: usize) -> Self {
+        self.max_peers = max_peers;
+        self
+    }
+
+    /// Sets the time after which a query to a single peer is abandoned.
+    ///
+    /// Defaults to [`DEFAULT_QUERY_TIMEOUT`].
+    pub fn query_timeout(mut self, timeout: Duration) -> Self {
+        self.query_timeout = timeout;
+        self
+    }
+}
+
+impl Discovery for PeerExchangeDiscovery {
+    fn resolve(
+        &self,
+        endpoint: Endpoint,
+        node_id: NodeId,
+    ) -> Option<BoxStream<Result<DiscoveryItem>>> {
+        let peers = endpoint
+            .remote_info_iter()
+            .filter(|info| info.node_id != node_id && info.conn_type != ConnectionType::None)
+            .map(|info| info.node_id)
+            .take(self.max_peers)
+            .collect::<Vec<_>>();
+        if peers.is_empty() {
+            return None;
+        }
+        let query_timeout = self.query_timeout;
+        let queries = peers
+            .into_iter()
+            .map(|peer| {
+                let endpoint = endpoint.clone();
+                async move {
+                    match time::timeout(query_timeout, query(&endpoint, peer, node_id)).await {
+                        Ok(Ok(node_addr)) => node_addr,
+                        Ok(Err(err)) => {
+                            debug!(peer = %peer.fmt_short(), "peer exchange query failed: {err:#}");
+                            None
+                        }
+                        Err(_) => {
+                            debug!(peer = %peer.fmt_short(), "peer exchange query timed out");
+                            None
+                        }
+                    }
+                }
+            })
+            .collect::<FuturesUnordered<_>>();
+        let stream = queries.filter_map(|node_addr| {
+            node_addr.map(|node_addr| {
+                Ok(DiscoveryItem {
+                    node_addr,
+                    provenance: Self::PROVENANCE,
+                    last_updated: None,
+                    user_data: None,
+                    ttl: None,
+                })
+            })
+        });
+        Some(stream.boxed())
+    }
+}
+
+/// Asks the peer `peer` for the addressing information of `node_id`.
+async fn query(endpoint: &Endpoint, peer: NodeId, node_id: NodeId) -> Result<Option<NodeAddr>> {
+    let connection = endpoint.connect(peer, ALPN).await?;
+    let (mut send, mut recv) = connection.open_bi().await?;
+    send.write_all(&postcard::to_stdvec(&Request { node_id })?)
+        .await?;
+    send.finish()?;
+    let response = recv.read_to_end(MAX_MESSAGE_SIZE).await?;
+    connection.close(0u32.into(), b"done");
+    let response: Response = postcard::from_bytes(&response)?;
+    if let Some(ref node_addr) = response.node_addr {
+        ensure!(
+            node_addr.node_id == node_id,
+            "peer answered with addresses for the wrong node"
+        );
+    }
+    Ok(response.node_addr)
+}
+
+#[cfg(test)]
+mod tests {
+    use testresult::TestResult;
+    use tracing_test::traced_test;
+
+    use super::*;
+    use crate::{protocol::Router, RelayMode};
+
+    const TEST_ALPN: &[u8] = b"n0/iroh/test";
+
+    #[derive(Debug)]
+    struct Hold;
+
+    impl ProtocolHandler for Hold {
+        fn accept(&self, connecting: Connecting) -> BoxFuture<Result<()>> {
+            Box::pin(async move {
+                let connection = connecting.await?;
+                connection.closed().await;
+                Ok(())
+            })
+        }
+    }
+
+    async fn spawn_node(discovery: bool) -> Result<Router> {
+        let mut builder = Endpoint::builder().relay_mode(RelayMode::Disabled);
+        if discovery {
+            builder = builder.add_discovery(|_| Some(PeerExchangeDiscovery::new()));
+        }
+        let ep = builder.bind().await?;
+        let pex = PeerExchange::new(ep.clone()).share(|_, _| true);
+        Router::builder(ep.clone())
+            .accept(ALPN, pex)
+            .accept(TEST_ALPN, Hold)
+            .spawn()
+            .await
+    }
+
+    /// Node `a` learns the address of node `c` through their common peer `b`.
+    #[tokio::test]
+    #[traced_test]
+    async fn peer_exchange_resolve() -> TestResult {
+        let a = spawn_node(true).await?;
+        let b = spawn_node(false).await?;
+        let c = spawn_node(false).await?;
+
+        let b_addr = b.endpoint().node_addr().await?;
+        let c_addr = c.endpoint().node_addr().await?;
+
+        // b is connected to c, a is connected to b.
+        let _b_to_c = b.endpoint().connect(c_addr.clone(), TEST_ALPN).await?;
+        let _a_to_b = a.endpoint().connect(b_addr, TEST_ALPN).await?;
+
+        // a only knows the node id of c.
+        let _a_to_c = a
+            .endpoint()
+            .connect(NodeAddr::new(c_addr.node_id), TEST_ALPN)
+            .await?;
+
+        let info = a.endpoint().remote_info(c_addr.node_id).unwrap();
+        assert!(info.sources().iter().any(|(source, _)| matches!(
+            source,
+            crate::endpoint::Source::NamedApp { name } if name == PeerExchangeDiscovery::PROVENANCE
+        )));
+
+        // nothing is shared without opting in, or with requesters which are not allowed
+        let a_id = a.endpoint().node_id();
+        let pex = PeerExchange::new(b.endpoint().clone());
+        assert!(pex.lookup(a_id, c_addr.node_id).is_none());
+        let pex = pex.share(move |requester, _| requester != a_id);
+        assert!(pex.lookup(a_id, c_addr.node_id).is_none());
+        let other = crate::SecretKey::generate(rand::thread_rng()).public();
+        assert!(pex.lookup(other, c_addr.node_id).is_some());
+
+        a.shutdown().await?;
+        b.shutdown().await?;
+        c.shutdown().await?;
+        Ok(())
+    }
+}
@@ -26,7 +26,7 @@
 //! There are several node discovery services built on top of pkarr, which can be composed
 //! to the application's needs:
 //!
-//! - [`PkarrPublisher`], which publishes to a pkarr relay server using HTTP.
+//! - [`PkarrPublisher`], which publishes to one or more pkarr relay servers using HTTP.
 //!
 //! - [`PkarrResolver`], which resolves from a pkarr relay server using HTTP.
 //!
@@ -44,23 +44,27 @@
 //! [`DnsDiscovery`]: crate::discovery::dns::DnsDiscovery
 //! [`DhtDiscovery`]: dht::DhtDiscovery
 
-use std::{collections::BTreeSet, net::SocketAddr, sync::Arc};
+use std::{collections::BTreeSet, sync::Arc};
 
-use anyhow::{anyhow, bail, Result};
+use anyhow::{anyhow, bail, ensure, Result};
 use iroh_base::{NodeId, RelayUrl, SecretKey};
 use n0_future::{
     boxed::BoxStream,
+    join_all,
     task::{self, AbortOnDropHandle},
-    time::{self, Duration, Instant},
+    time::{self, Duration, Instant, SystemTime},
 };
 use pkarr::SignedPacket;
 use tracing::{debug, error_span, info, warn, Instrument};
 use url::Url;
 
 use crate::{
-    discovery::{Discovery, DiscoveryItem},
+    discovery::{
+        watch::{Lookup, NodeWatcher, Resolved, WatchOptions},
+        AddrPublishPolicy, Discovery, DiscoveryItem, UserData,
+    },
     dns::node_info::NodeInfo,
-    endpoint::force_staging_infra,
+    endpoint::{force_staging_infra, DirectAddr},
     watchable::{Disconnected, Watchable, Watcher},
     Endpoint,
 };
@@ -97,16 +101,26 @@ pub const DEFAULT_PKARR_TTL: u32 = 30;
 /// Interval in which to republish the node info even if unchanged: 5 minutes.
 pub const DEFAULT_REPUBLISH_INTERVAL: Duration = Duration::from_secs(60 * 5);
 
-/// Publisher of node discovery information to a [pkarr] relay.
+/// Timeout for publishing the node info to a single pkarr relay: 10 seconds.
+pub const DEFAULT_PUBLISH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Publisher of node discovery information to [pkarr] relays.
 ///
-/// This publisher uses HTTP to publish node discovery information to a pkarr relay
-/// server, see the [module docs] for details.
+/// This publisher uses HTTP to publish node discovery information to one or more pkarr
+/// relay servers, see the [module docs] for details.
 ///
 /// This implements the [`Discovery`] trait to be used as a node discovery service.  Note
 /// that it only publishes node discovery information, for the corresponding resolver use
 /// the [`PkarrResolver`] together with [`ConcurrentDiscovery`].
 ///
-/// This publisher will **only** publish the [`RelayUrl`] if it is set, otherwise the *direct addresses* are published instead.
+/// By default this publisher will **only** publish the [`RelayUrl`] if it is set, otherwise
+/// the *direct addresses* are published instead.  This can be changed with
+/// [`PkarrPublisher::addr_publish_policy`].
+///
+/// When publishing to several relays, created with [`PkarrPublisher::builder`], the node
+/// info is published to all relays concurrently and failed publishes are retried per relay.
+/// Backup relays are only published to while publishing to all other relays fails.  The
+/// progress is reported by [`PkarrPublisher::status`].
 ///
 /// [pkarr]: https://pkarr.org
 /// [module docs]: crate::discovery::pkarr
@@ -116,9 +130,135 @@ pub const DEFAULT_REPUBLISH_INTERVAL: Duration = Duration::from_secs(60 * 5);
 pub struct PkarrPublisher {
     node_id: NodeId,
     watchable: Watchable<Option<NodeInfo>>,
+    status: Watchable<PublishStatus>,
+    policy: AddrPublishPolicy,
     _drop_guard: Arc<AbortOnDropHandle<()>>,
 }
 
+/// The publishing state of a [`PkarrPublisher`].
+///
+/// See [`PkarrPublisher::status`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PublishStatus {
+    /// The node info last published successfully to at least one relay.
+    pub info: Option<NodeInfo>,
+    /// When the node info was last published successfully to any relay.
+    pub last_success: Option<SystemTime>,
+    /// The most recent error of the relays whose last publish attempt failed.
+    ///
+    /// This is `None` if the last publish attempt to each relay succeeded.
+    pub last_error: Option<String>,
+    /// The state of each relay.
+    pub relays: Vec<RelayPublishStatus>,
+}
+
+/// The publishing state of a single relay of a [`PkarrPublisher`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelayPublishStatus {
+    /// The URL of the pkarr relay.
+    pub url: Url,
+    /// Whether this is a backup relay.
+    pub backup: bool,
+    /// When publishing to this relay was last attempted.
+    pub last_attempt: Option<SystemTime>,
+    /// When the node info was last published successfully to this relay.
+    pub last_success: Option<SystemTime>,
+    /// The error of the last publish attempt, if it failed.
+    pub last_error: Option<String>,
+    /// The number of consecutive failed publish attempts.
+    pub failed_attempts: u32,
+}
+
+impl RelayPublishStatus {
+    fn new(url: Url, backup: bool) -> Self {
+        Self {
+            url,
+            backup,
+            last_attempt: None,
+            last_success: None,
+            last_error: None,
+            failed_attempts: 0,
+        }
+    }
+}
+
+/// Builder for a [`PkarrPublisher`] publishing to several relays.
+///
+/// Created with [`PkarrPublisher::builder`].
+#[derive(derive_more::Debug)]
+pub struct PkarrPublisherBuilder {
+    #[debug("SecretKey")]
+    secret_key: SecretKey,
+    relays: Vec<RelayPublishStatus>,
+    ttl: u32,
+    republish_interval: Duration,
+    publish_timeout: Duration,
+    policy: AddrPublishPolicy,
+}
+
+impl PkarrPublisherBuilder {
+    /// Adds a pkarr relay to publish to.
+    pub fn relay(mut self, pkarr_relay: Url) -> Self {
+        self.relays
+            .push(RelayPublishStatus::new(pkarr_relay, false));
+        self
+    }
+
+    /// Adds a backup pkarr relay.
+    ///
+    /// Backup relays are only published to while publishing to all other relays fails.
+    pub fn backup_relay(mut self, pkarr_relay: Url) -> Self {
+        self.relays.push(RelayPublishStatus::new(pkarr_relay, true));
+        self
+    }
+
+    /// Sets the time-to-live value of the published [`pkarr::SignedPacket`]s.
+    ///
+    /// Defaults to [`DEFAULT_PKARR_TTL`].
+    pub fn ttl(mut self, ttl: u32) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Sets the interval after which the node info is republished even if unchanged.
+    ///
+    /// Defaults to [`DEFAULT_REPUBLISH_INTERVAL`].
+    pub fn republish_interval(mut self, republish_interval: Duration) -> Self {
+        self.republish_interval = republish_interval;
+        self
+    }
+
+    /// Sets the timeout for publishing to a single relay.
+    ///
+    /// A publish which does not complete in time counts as a failed publish attempt and is
+    /// retried.  Defaults to [`DEFAULT_PUBLISH_TIMEOUT`].
+    pub fn publish_timeout(mut self, publish_timeout: Duration) -> Self {
+        self.publish_timeout = publish_timeout;
+        self
+    }
+
+    /// Sets which addresses are published.
+    ///
+    /// Defaults to [`AddrPublishPolicy::relay_or_direct`].
+    pub fn addr_publish_policy(mut self, policy: AddrPublishPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Builds the publisher and starts its publishing task.
+    ///
+    /// # Errors
+    ///
+    /// Fails if no relay other than backup relays was added.
+    pub fn build(self) -> Result<PkarrPublisher> {
+        ensure!(
+            self.relays.iter().any(|relay| !relay.backup),
+            "at least one pkarr relay is required"
+        );
+        Ok(PkarrPublisher::spawn(self))
+    }
+}
+
 impl PkarrPublisher {
     /// Creates a new publisher for the [`SecretKey`].
     ///
@@ -147,16 +287,61 @@ impl PkarrPublisher {
         ttl: u32,
         republish_interval: Duration,
     ) -> Self {
-        debug!("creating pkarr publisher that publishes to {pkarr_relay}");
+        Self::spawn(
+            Self::builder(secret_key)
+                .relay(pkarr_relay)
+                .ttl(ttl)
+                .republish_interval(republish_interval),
+        )
+    }
+
+    /// Creates a builder for a publisher publishing to several pkarr relays.
+    pub fn builder(secret_key: SecretKey) -> PkarrPublisherBuilder {
+        PkarrPublisherBuilder {
+            secret_key,
+            relays: Vec::new(),
+            ttl: DEFAULT_PKARR_TTL,
+            republish_interval: DEFAULT_REPUBLISH_INTERVAL,
+            publish_timeout: DEFAULT_PUBLISH_TIMEOUT,
+            policy: AddrPublishPolicy::default(),
+        }
+    }
+
+    fn spawn(builder: PkarrPublisherBuilder) -> Self {
+        let PkarrPublisherBuilder {
+            secret_key,
+            relays,
+            ttl,
+            republish_interval,
+            publish_timeout,
+            policy,
+        } = builder;
         let node_id = secret_key.public();
-        let pkarr_client = PkarrRelayClient::new(pkarr_relay);
+        let relays = relays
+            .into_iter()
+            .map(|status| {
+                debug!("creating pkarr publisher that publishes to {}", status.url);
+                RelayState {
+                    client: PkarrRelayClient::new(status.url.clone()),
+                    next_attempt: Instant::now(),
+                    status,
+                }
+            })
+            .collect::<Vec<_>>();
         let watchable = Watchable::default();
+        let status = Watchable::new(PublishStatus {
+            relays: relays.iter().map(|relay| relay.status.clone()).collect(),
+            ..Default::default()
+        });
         let service = PublisherService {
             ttl,
             watcher: watchable.watch(),
             secret_key,
-            pkarr_client,
+            relays,
+            status: status.clone(),
+            last_published: None,
             republish_interval,
+            publish_timeout,
         };
         let join_handle = task::spawn(
             service
@@ -166,6 +351,8 @@ impl PkarrPublisher {
         Self {
             watchable,
             node_id,
+            status,
+            policy,
             _drop_guard: Arc::new(AbortOnDropHandle::new(join_handle)),
         }
     }
@@ -190,93 +377,251 @@ impl PkarrPublisher {
         Self::new(secret_key, pkarr_relay)
     }
 
-    /// Publishes the addressing information about this node to a pkarr relay.
+    /// Sets which addresses are published.
+    ///
+    /// Defaults to [`AddrPublishPolicy::relay_or_direct`].
+    pub fn addr_publish_policy(mut self, policy: AddrPublishPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Returns a [`Watcher`] for the publishing state.
+    ///
+    /// This reports when the node info was last published successfully and the errors
+    /// encountered, both in total and per relay.  Health checks can use this to detect
+    /// stale discovery records.
+    pub fn status(&self) -> Watcher<PublishStatus> {
+        self.status.watch()
+    }
+
+    /// Publishes the addressing information and user data about this node to a pkarr relay.
+    ///
+    /// The addresses published are selected by the [`AddrPublishPolicy`] of the publisher.
     ///
     /// This is a nonblocking function, the actual update is performed in the background.
-    pub fn update_addr_info(&self, url: Option<&RelayUrl>, addrs: &BTreeSet<SocketAddr>) {
-        let (relay_url, direct_addresses) = if let Some(relay_url) = url {
-            // Only publish relay url, and no direct addrs
-            let url = relay_url.clone();
-            (Some(url.into()), Default::default())
-        } else {
-            (None, addrs.clone())
-        };
-        let info = NodeInfo::new(self.node_id, relay_url, direct_addresses);
+    pub fn update_addr_info(
+        &self,
+        url: Option<&RelayUrl>,
+        addrs: &BTreeSet<DirectAddr>,
+        user_data: Option<&UserData>,
+    ) {
+        let (relay_url, direct_addresses) = self.policy.apply(url, addrs);
+        let info = NodeInfo::new(self.node_id, relay_url.map(Into::into), direct_addresses)
+            .with_user_data(user_data.cloned());
         self.watchable.set(Some(info)).ok();
     }
 }
 
 impl Discovery for PkarrPublisher {
-    fn publish(&self, url: Option<&RelayUrl>, addrs: &BTreeSet<SocketAddr>) {
-        self.update_addr_info(url, addrs);
+    fn publish(
+        &self,
+        url: Option<&RelayUrl>,
+        addrs: &BTreeSet<DirectAddr>,
+        user_data: Option<&UserData>,
+    ) {
+        self.update_addr_info(url, addrs, user_data);
     }
 }
 
-/// Publish node info to a pkarr relay.
-#[derive(derive_more::Debug, Clone)]
+/// The state of a single relay of the [`PublisherService`].
+#[derive(Debug)]
+struct RelayState {
+    client: PkarrRelayClient,
+    status: RelayPublishStatus,
+    /// When to publish next, either to retry or to republish.
+    next_attempt: Instant,
+}
+
+/// Publish node info to pkarr relays.
+#[derive(derive_more::Debug)]
 struct PublisherService {
     #[debug("SecretKey")]
     secret_key: SecretKey,
-    #[debug("PkarrClient")]
-    pkarr_client: PkarrRelayClient,
+    relays: Vec<RelayState>,
     watcher: Watcher<Option<NodeInfo>>,
+    status: Watchable<PublishStatus>,
+    last_published: Option<NodeInfo>,
     ttl: u32,
     republish_interval: Duration,
+    publish_timeout: Duration,
 }
 
 impl PublisherService {
     async fn run(mut self) {
-        let mut failed_attempts = 0;
-        let republish = time::sleep(Duration::MAX);
-        tokio::pin!(republish);
+        let mut current = None;
         loop {
             let Ok(info) = self.watcher.get() else {
                 break; // disconnected
             };
-            if let Some(info) = info {
-                if let Err(err) = self.publish_current(info).await {
-                    failed_attempts += 1;
-                    // Retry after increasing timeout
-                    let retry_after = Duration::from_secs(failed_attempts);
-                    republish.as_mut().reset(Instant::now() + retry_after);
-                    warn!(
-                        err = %format!("{err:#}"),
-                        url = %self.pkarr_client.pkarr_relay_url ,
-                        ?retry_after,
-                        %failed_attempts,
-                        "Failed to publish to pkarr",
-                    );
-                } else {
-                    failed_attempts = 0;
-                    // Republish after fixed interval
-                    republish
-                        .as_mut()
-                        .reset(Instant::now() + self.republish_interval);
+            if info != current {
+                // Publish changed info to all relays right away.
+                let now = Instant::now();
+                for relay in self.relays.iter_mut() {
+                    relay.next_attempt = now;
                 }
+                current = info;
             }
+            let next_attempt = match current {
+                Some(ref info) => {
+                    self.publish_due(info).await;
+                    self.next_attempt()
+                }
+                None => None,
+            };
             // Wait until either the retry/republish timeout is reached, or the node info changed.
+            let sleep = async move {
+                match next_attempt {
+                    Some(next_attempt) => time::sleep_until(next_attempt).await,
+                    None => std::future::pending().await,
+                }
+            };
             tokio::select! {
                 res = self.watcher.updated() => match res {
                     Ok(_) => debug!("Publish node info to pkarr (info changed)"),
                     Err(Disconnected) => break,
                 },
-                _ = &mut republish => debug!("Publish node info to pkarr (interval elapsed)"),
+                _ = sleep => debug!("Publish node info to pkarr (interval elapsed)"),
             }
         }
     }
 
-    async fn publish_current(&self, info: NodeInfo) -> Result<()> {
+    /// Whether the backup relays are used, which is the case while publishing to all other
+    /// relays fails.
+    fn backups_active(&self) -> bool {
+        self.relays
+            .iter()
+            .filter(|relay| !relay.status.backup)
+            .all(|relay| relay.status.failed_attempts > 0)
+    }
+
+    /// Returns the relays which are currently published to.
+    fn active_relays(&self) -> impl Iterator<Item = &RelayState> {
+        let backups_active = self.backups_active();
+        self.relays
+            .iter()
+            .filter(move |relay| backups_active || !relay.status.backup)
+    }
+
+    /// Returns when to publish next.
+    fn next_attempt(&self) -> Option<Instant> {
+        self.active_relays().map(|relay| relay.next_attempt).min()
+    }
+
+    /// Publishes the node info to all active relays which are due.
+    async fn publish_due(&mut self, info: &NodeInfo) {
+        let now = Instant::now();
+        let due = self
+            .active_relays()
+            .filter(|relay| relay.next_attempt <= now)
+            .map(|relay| relay.status.url.clone())
+            .collect::<Vec<_>>();
+        if due.is_empty() {
+            return;
+        }
+        let results = match info.to_pkarr_signed_packet(&self.secret_key, self.ttl) {
+            Ok(signed_packet) => {
+                let signed_packet = &signed_packet;
+                let publish_timeout = self.publish_timeout;
+                join_all(
+                    self.relays
+                        .iter()
+                        .filter(|relay| due.contains(&relay.status.url))
+                        .map(|relay| async move {
+                            let res = time::timeout(
+                                publish_timeout,
+                                Self::publish_to(relay, info, signed_packet),
+                            )
+                            .await
+                            .unwrap_or_else(|_| {
+                                Err(anyhow!("publish timed out after {publish_timeout:?}"))
+                            });
+                            (relay.status.url.clone(), res)
+                        }),
+                )
+                .await
+            }
+            Err(err) => {
+                let err = format!("{err:#}");
+                due.into_iter()
+                    .map(|url| (url, Err(anyhow!("{err}"))))
+                    .collect()
+            }
+        };
+
+        let now = Instant::now();
+        let now_system = SystemTime::now();
+        for (url, res) in results {
+            let Some(relay) = self.relays.iter_mut().find(|relay| relay.status.url == url) else {
+                continue;
+            };
+            relay.status.last_attempt = Some(now_system);
+            match res {
+                Ok(()) => {
+                    relay.status.failed_attempts = 0;
+                    relay.status.last_error = None;
+                    relay.status.last_success = Some(now_system);
+                    // Republish after fixed interval
+                    relay.next_attempt = now + self.republish_interval;
+                    self.last_published = Some(info.clone());
+                }
+                Err(err) => {
+                    relay.status.failed_attempts += 1;
+                    relay.status.last_error = Some(format!("{err:#}"));
+                    // Retry after increasing timeout
+                    let retry_after = Duration::from_secs(relay.status.failed_attempts.into())
+                        .min(self.republish_interval);
+                    relay.next_attempt = now + retry_after;
+                    warn!(
+                        err = %format!("{err:#}"),
+                        %url,
+                        ?retry_after,
+                        failed_attempts = %relay.status.failed_attempts,
+                        "Failed to publish to pkarr",
+                    );
+                }
+            }
+        }
+        self.status.set(self.current_status()).ok();
+    }
+
+    async fn publish_to(
+        relay: &RelayState,
+        info: &NodeInfo,
+        signed_packet: &SignedPacket,
+    ) -> Result<()> {
         info!(
             relay_url = ?info
                 .relay_url
                 .as_ref()
                 .map(|s| s.as_str()),
-            pkarr_relay = %self.pkarr_client.pkarr_relay_url,
+            pkarr_relay = %relay.client.pkarr_relay_url,
             "Publish node info to pkarr"
         );
-        let signed_packet = info.to_pkarr_signed_packet(&self.secret_key, self.ttl)?;
-        self.pkarr_client.publish(&signed_packet).await?;
-        Ok(())
+        relay.client.publish(signed_packet).await
+    }
+
+    /// Computes the [`PublishStatus`] from the state of the relays.
+    fn current_status(&self) -> PublishStatus {
+        let relays = self
+            .relays
+            .iter()
+            .map(|relay| relay.status.clone())
+            .collect::<Vec<_>>();
+        let last_success = relays.iter().filter_map(|relay| relay.last_success).max();
+        let last_error = relays
+            .iter()
+            .filter(|relay| relay.last_error.is_some())
+            .max_by_key(|relay| relay.last_attempt)
+            .and_then(|relay| {
+                let err = relay.last_error.as_ref()?;
+                Some(format!("{}: {err}", relay.url))
+            });
+        PublishStatus {
+            info: self.last_published.clone(),
+            last_success,
+            last_error,
+            relays,
+        }
     }
 }
 
@@ -289,19 +634,34 @@ impl PublisherService {
 /// that it only resolves node discovery information, for the corresponding publisher use
 /// the [`PkarrPublisher`] together with [`ConcurrentDiscovery`].
 ///
+/// The resolver can keep watching the nodes it resolved, see
+/// [`PkarrResolver::watch_resolved_nodes`].
+///
 /// [pkarr]: https://pkarr.org
 /// [module docs]: crate::discovery::pkarr
 /// [`ConcurrentDiscovery`]: super::ConcurrentDiscovery
 #[derive(derive_more::Debug, Clone)]
 pub struct PkarrResolver {
     pkarr_client: PkarrRelayClient,
+    watch: bool,
+    watch_options: WatchOptions,
+    watcher: NodeWatcher,
 }
 
 impl PkarrResolver {
+    /// The provenance string for this discovery implementation.
+    ///
+    /// This is mostly used for debugging information and allows understanding the origin of
+    /// addressing information used by an iroh [`Endpoint`].
+    pub const PROVENANCE: &'static str = "pkarr";
+
     /// Creates a new publisher using the pkarr relay server at the URL.
     pub fn new(pkarr_relay: Url) -> Self {
         Self {
             pkarr_client: PkarrRelayClient::new(pkarr_relay),
+            watch: false,
+            watch_options: WatchOptions::default(),
+            watcher: NodeWatcher::default(),
         }
     }
 
@@ -324,24 +684,94 @@ impl PkarrResolver {
         let pkarr_relay: Url = pkarr_relay.parse().expect("url is valid");
         Self::new(pkarr_relay)
     }
+
+    /// Sets whether to keep watching the nodes resolved by this resolver.
+    ///
+    /// When enabled, the signed packets of resolved nodes are fetched again once their TTL
+    /// elapsed, and [`Discovery::subscribe`] emits a [`DiscoveryItem`] whenever the
+    /// timestamp of a node's signed packet advanced.  This lets the [`Endpoint`] learn about
+    /// changed addresses of a node before connections to the old addresses fail.
+    ///
+    /// Disabled by default.
+    pub fn watch_resolved_nodes(mut self, watch: bool) -> Self {
+        self.watch = watch;
+        self
+    }
+
+    /// Sets the bounds of the interval at which watched nodes are fetched again.
+    ///
+    /// The TTL of a node's signed packet is clamped to these bounds.  Defaults to
+    /// [`DEFAULT_MIN_POLL_INTERVAL`] and [`DEFAULT_MAX_POLL_INTERVAL`].
+    ///
+    /// [`DEFAULT_MIN_POLL_INTERVAL`]: super::DEFAULT_MIN_POLL_INTERVAL
+    /// [`DEFAULT_MAX_POLL_INTERVAL`]: super::DEFAULT_MAX_POLL_INTERVAL
+    pub fn poll_interval(mut self, min: Duration, max: Duration) -> Self {
+        self.watch_options.min_interval = min;
+        self.watch_options.max_interval = max;
+        self
+    }
+
+    /// Sets the maximum number of watched nodes.
+    ///
+    /// When exceeded, the node resolved least recently is no longer watched.  Defaults to
+    /// [`DEFAULT_MAX_WATCHED_NODES`].
+    ///
+    /// [`DEFAULT_MAX_WATCHED_NODES`]: super::DEFAULT_MAX_WATCHED_NODES
+    pub fn max_watched_nodes(mut self, max: usize) -> Self {
+        self.watch_options.max_nodes = max;
+        self
+    }
+
+    fn lookup(&self) -> Lookup {
+        let pkarr_client = self.pkarr_client.clone();
+        Arc::new(move |node_id| {
+            let pkarr_client = pkarr_client.clone();
+            Box::pin(async move { Self::resolve_node(&pkarr_client, node_id).await })
+        })
+    }
+
+    async fn resolve_node(pkarr_client: &PkarrRelayClient, node_id: NodeId) -> Result<Resolved> {
+        let signed_packet = pkarr_client.resolve(node_id).await?;
+        let info = NodeInfo::from_pkarr_signed_packet(&signed_packet)?;
+        let ttl = Duration::from_secs(signed_packet.ttl(0, u32::MAX).into());
+        let user_data = info.user_data.clone();
+        let item = DiscoveryItem {
+            node_addr: info.into(),
+            provenance: Self::PROVENANCE,
+            last_updated: Some(signed_packet.timestamp()),
+            user_data,
+            ttl: Some(ttl),
+        };
+        Ok(Resolved { item, ttl })
+    }
 }
 
 impl Discovery for PkarrResolver {
     fn resolve(&self, _ep: Endpoint, node_id: NodeId) -> Option<BoxStream<Result<DiscoveryItem>>> {
         let pkarr_client = self.pkarr_client.clone();
+        let watch = self.watch.then(|| {
+            (
+                self.watcher.clone(),
+                self.lookup(),
+                self.watch_options.clone(),
+            )
+        });
         let fut = async move {
-            let signed_packet = pkarr_client.resolve(node_id).await?;
-            let info = NodeInfo::from_pkarr_signed_packet(&signed_packet)?;
-            let item = DiscoveryItem {
-                node_addr: info.into(),
-                provenance: "pkarr",
-                last_updated: None,
-            };
+            let resolved = Self::resolve_node(&pkarr_client, node_id).await?;
+            let item = resolved.item.clone();
+            if let Some((watcher, lookup, options)) = watch {
+                watcher.watch(resolved, lookup, &options);
+            }
             Ok(item)
         };
         let stream = n0_future::stream::once_future(fut);
         Some(Box::pin(stream))
     }
+
+    fn subscribe(&self) -> Option<BoxStream<DiscoveryItem>> {
+        self.watch
+            .then(|| self.watcher.subscribe(&self.watch_options))
+    }
 }
 
 /// A [pkarr] client to publish [`pkarr::SignedPacket`]s to a pkarr relay.
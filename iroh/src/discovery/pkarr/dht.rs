@@ -7,7 +7,6 @@
 //! [pkarr module]: super
 use std::{
     collections::BTreeSet,
-    net::SocketAddr,
     sync::{Arc, Mutex},
 };
 
@@ -29,9 +28,10 @@ use url::Url;
 use crate::{
     discovery::{
         pkarr::{DEFAULT_PKARR_TTL, N0_DNS_PKARR_RELAY_PROD},
-        Discovery, DiscoveryItem,
+        AddrPublishPolicy, Discovery, DiscoveryItem, UserData,
     },
     dns::node_info::NodeInfo,
+    endpoint::DirectAddr,
     Endpoint,
 };
 
@@ -85,8 +85,8 @@ struct Inner {
     dht: bool,
     /// Time-to-live value for the DNS packets.
     ttl: u32,
-    /// True to include the direct addresses in the DNS packet.
-    include_direct_addresses: bool,
+    /// Which addresses to include in the DNS packet.
+    policy: AddrPublishPolicy,
     /// Initial delay before the first publish.
     initial_publish_delay: Duration,
     /// Republish delay for the DHT.
@@ -106,6 +106,7 @@ impl Inner {
         match maybe_packet {
             Ok(Some(signed_packet)) => match NodeInfo::from_pkarr_signed_packet(&signed_packet) {
                 Ok(node_info) => {
+                    let user_data = node_info.user_data.clone();
                     let node_addr: NodeAddr = node_info.into();
 
                     tracing::info!("discovered node info from relay {:?}", node_addr);
@@ -113,6 +114,8 @@ impl Inner {
                         node_addr,
                         provenance: "relay",
                         last_updated: None,
+                        user_data,
+                        ttl: Some(Duration::from_secs(signed_packet.ttl(0, u32::MAX).into())),
                     }))
                 }
                 Err(_err) => {
@@ -137,12 +140,15 @@ impl Inner {
         match maybe_packet {
             Ok(Some(signed_packet)) => match NodeInfo::from_pkarr_signed_packet(&signed_packet) {
                 Ok(node_info) => {
+                    let user_data = node_info.user_data.clone();
                     let node_addr: NodeAddr = node_info.into();
                     tracing::info!("discovered node info from DHT {:?}", node_addr);
                     Some(Ok(DiscoveryItem {
                         node_addr,
                         provenance: "mainline",
                         last_updated: None,
+                        user_data,
+                        ttl: Some(Duration::from_secs(signed_packet.ttl(0, u32::MAX).into())),
                     }))
                 }
                 Err(_err) => {
@@ -171,6 +177,7 @@ pub struct Builder {
     pkarr_relay: Option<Url>,
     dht: bool,
     include_direct_addresses: bool,
+    policy: Option<AddrPublishPolicy>,
     initial_publish_delay: Duration,
     republish_delay: Duration,
 }
@@ -184,6 +191,7 @@ impl Default for Builder {
             pkarr_relay: None,
             dht: true,
             include_direct_addresses: false,
+            policy: None,
             initial_publish_delay: INITIAL_PUBLISH_DELAY,
             republish_delay: REPUBLISH_DELAY,
         }
@@ -232,11 +240,24 @@ impl Builder {
     }
 
     /// Sets whether to include the direct addresses in the DNS packet.
+    ///
+    /// This is ignored if an [`AddrPublishPolicy`] is set using
+    /// [`Builder::addr_publish_policy`].
     pub fn include_direct_addresses(mut self, include_direct_addresses: bool) -> Self {
         self.include_direct_addresses = include_direct_addresses;
         self
     }
 
+    /// Sets which addresses to include in the DNS packet.
+    ///
+    /// Defaults to [`AddrPublishPolicy::relay_only`], or to
+    /// [`AddrPublishPolicy::relay_and_direct`] if [`Builder::include_direct_addresses`] is
+    /// enabled.
+    pub fn addr_publish_policy(mut self, policy: AddrPublishPolicy) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
     /// Sets the initial delay before the first publish.
     pub fn initial_publish_delay(mut self, initial_publish_delay: Duration) -> Self {
         self.initial_publish_delay = initial_publish_delay;
@@ -259,7 +280,12 @@ impl Builder {
         let ttl = self.ttl.unwrap_or(DEFAULT_PKARR_TTL);
         let relay_url = self.pkarr_relay;
         let dht = self.dht;
-        let include_direct_addresses = self.include_direct_addresses;
+        let policy = self
+            .policy
+            .unwrap_or_else(|| match self.include_direct_addresses {
+                true => AddrPublishPolicy::relay_and_direct(),
+                false => AddrPublishPolicy::relay_only(),
+            });
         anyhow::ensure!(
             dht || relay_url.is_some(),
             "at least one of DHT or relay must be enabled"
@@ -282,7 +308,7 @@ impl Builder {
             ttl,
             relay_url,
             dht,
-            include_direct_addresses,
+            policy,
             secret_key: self.secret_key,
             initial_publish_delay: self.initial_publish_delay,
             republish_delay: self.republish_delay,
@@ -352,21 +378,20 @@ impl DhtDiscovery {
 }
 
 impl Discovery for DhtDiscovery {
-    fn publish(&self, url: Option<&RelayUrl>, addrs: &BTreeSet<SocketAddr>) {
+    fn publish(
+        &self,
+        url: Option<&RelayUrl>,
+        addrs: &BTreeSet<DirectAddr>,
+        user_data: Option<&UserData>,
+    ) {
         let Some(keypair) = &self.0.secret_key else {
             tracing::debug!("no keypair set, not publishing");
             return;
         };
         tracing::debug!("publishing {:?}, {:?}", url, addrs);
-        let info = NodeInfo {
-            node_id: keypair.public(),
-            relay_url: url.cloned().map(Url::from),
-            direct_addresses: if self.0.include_direct_addresses {
-                addrs.clone()
-            } else {
-                Default::default()
-            },
-        };
+        let (relay_url, direct_addresses) = self.0.policy.apply(url, addrs);
+        let info = NodeInfo::new(keypair.public(), relay_url.map(Url::from), direct_addresses)
+            .with_user_data(user_data.cloned());
         let Ok(signed_packet) = info.to_pkarr_signed_packet(keypair, self.0.ttl) else {
             tracing::warn!("failed to create signed packet");
             return;
@@ -436,7 +461,7 @@ mod tests {
             .build()?;
         let relay_url: RelayUrl = Url::parse("https://example.com")?.into();
 
-        discovery.publish(Some(&relay_url), &Default::default());
+        discovery.publish(Some(&relay_url), &Default::default(), None);
 
         // publish is fire and forget, so we have no way to wait until it is done.
         tokio::time::timeout(Duration::from_secs(30), async move {
@@ -22,7 +22,8 @@ use n0_future::{
     time::SystemTime,
 };
 
-use super::{Discovery, DiscoveryItem};
+use super::{Discovery, DiscoveryItem, UserData};
+use crate::endpoint::DirectAddr;
 
 /// A static node discovery to manually add node addressing information.
 ///
@@ -202,7 +203,13 @@ impl StaticProvider {
 }
 
 impl Discovery for StaticProvider {
-    fn publish(&self, _url: Option<&RelayUrl>, _addrs: &BTreeSet<SocketAddr>) {}
+    fn publish(
+        &self,
+        _url: Option<&RelayUrl>,
+        _addrs: &BTreeSet<DirectAddr>,
+        _user_data: Option<&UserData>,
+    ) {
+    }
 
     fn resolve(
         &self,
@@ -227,6 +234,8 @@ impl Discovery for StaticProvider {
                             .expect("time drift")
                             .as_micros() as u64,
                     ),
+                    user_data: None,
+                    ttl: None,
                 };
                 Some(stream::iter(Some(Ok(item))).boxed())
             }
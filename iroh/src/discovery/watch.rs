@@ -0,0 +1,267 @@
+//! Watching of resolved nodes for discovery services which can only resolve.
+//!
+//! Discovery services like pkarr and DNS have no way to be notified when a node publishes
+//! new addressing information.  The [`NodeWatcher`] implements [`Discovery::subscribe`] for
+//! them by polling the nodes which were resolved before, and emitting a [`DiscoveryItem`]
+//! whenever a node's information changed.
+//!
+//! [`Discovery::subscribe`]: super::Discovery::subscribe
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+use iroh_base::NodeId;
+use n0_future::{
+    boxed::{BoxFuture, BoxStream},
+    join_all,
+    task::{self, AbortOnDropHandle},
+    time::{self, Duration, Instant},
+};
+use tokio::sync::{mpsc, Notify};
+use tracing::{debug, error_span, trace, warn, Instrument};
+
+use super::DiscoveryItem;
+
+/// The default minimum interval between two lookups of a watched node.
+pub const DEFAULT_MIN_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The default maximum interval between two lookups of a watched node.
+pub const DEFAULT_MAX_POLL_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// The default maximum number of nodes watched.
+pub const DEFAULT_MAX_WATCHED_NODES: usize = 256;
+
+/// The capacity of the channel to each subscriber.
+const SUBSCRIBER_CAPACITY: usize = 32;
+
+/// Looks up the current information of a node.
+pub(super) type Lookup = Arc<dyn Fn(NodeId) -> BoxFuture<Result<Resolved>> + Send + Sync>;
+
+/// A resolved node.
+#[derive(Debug, Clone)]
+pub(super) struct Resolved {
+    /// The discovered information.
+    pub(super) item: DiscoveryItem,
+    /// How long the information is valid, which determines when to poll next.
+    pub(super) ttl: Duration,
+}
+
+/// Options for watching resolved nodes.
+#[derive(Debug, Clone)]
+pub(super) struct WatchOptions {
+    pub(super) min_interval: Duration,
+    pub(super) max_interval: Duration,
+    pub(super) max_nodes: usize,
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        Self {
+            min_interval: DEFAULT_MIN_POLL_INTERVAL,
+            max_interval: DEFAULT_MAX_POLL_INTERVAL,
+            max_nodes: DEFAULT_MAX_WATCHED_NODES,
+        }
+    }
+}
+
+impl WatchOptions {
+    fn interval(&self, ttl: Duration) -> Duration {
+        ttl.clamp(self.min_interval, self.max_interval.max(self.min_interval))
+    }
+}
+
+/// Polls watched nodes and sends updated information to the subscribers.
+///
+/// The polling task is started by the first call to [`NodeWatcher::subscribe`] and stops when
+/// the last clone of the [`NodeWatcher`] is dropped.
+#[derive(Debug, Clone, Default)]
+pub(super) struct NodeWatcher {
+    inner: Arc<Inner>,
+    /// The polling task, not part of [`Inner`] as the task holds on to it.
+    task: Arc<Mutex<Option<AbortOnDropHandle<()>>>>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    state: Mutex<State>,
+    /// Wakes the polling task when a node was added.
+    notify: Notify,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    nodes: HashMap<NodeId, Watched>,
+    subscribers: Vec<mpsc::Sender<DiscoveryItem>>,
+}
+
+#[derive(derive_more::Debug)]
+struct Watched {
+    #[debug("Lookup")]
+    lookup: Lookup,
+    /// The most recent information about the node.
+    item: DiscoveryItem,
+    next_poll: Instant,
+    /// When the node was last resolved, to evict the least recently resolved node.
+    last_resolved: Instant,
+}
+
+impl NodeWatcher {
+    /// Watches a node which was just resolved.
+    ///
+    /// The node is polled with `lookup`, the first time after the TTL of `resolved`.  As
+    /// required by [`Discovery::subscribe`], the resolved information itself is not sent to
+    /// the subscribers.
+    ///
+    /// [`Discovery::subscribe`]: super::Discovery::subscribe
+    pub(super) fn watch(&self, resolved: Resolved, lookup: Lookup, options: &WatchOptions) {
+        if options.max_nodes == 0 {
+            return;
+        }
+        let node_id = resolved.item.node_addr.node_id;
+        let now = Instant::now();
+        let mut state = self.inner.state.lock().expect("poisoned");
+        match state.nodes.get_mut(&node_id) {
+            Some(watched) => {
+                if is_newer(&watched.item, &resolved.item) {
+                    watched.item = resolved.item;
+                }
+                watched.last_resolved = now;
+                watched.lookup = lookup;
+            }
+            None => {
+                if state.nodes.len() >= options.max_nodes {
+                    let oldest = state
+                        .nodes
+                        .iter()
+                        .min_by_key(|(_, watched)| watched.last_resolved)
+                        .map(|(node_id, _)| *node_id);
+                    if let Some(oldest) = oldest {
+                        trace!(node_id = %oldest.fmt_short(), "stop watching node");
+                        state.nodes.remove(&oldest);
+                    }
+                }
+                state.nodes.insert(
+                    node_id,
+                    Watched {
+                        lookup,
+                        item: resolved.item,
+                        next_poll: now + options.interval(resolved.ttl),
+                        last_resolved: now,
+                    },
+                );
+                self.inner.notify.notify_one();
+            }
+        }
+    }
+
+    /// Returns a stream of the changed information of the watched nodes.
+    pub(super) fn subscribe(&self, options: &WatchOptions) -> BoxStream<DiscoveryItem> {
+        let (sender, receiver) = mpsc::channel(SUBSCRIBER_CAPACITY);
+        self.inner
+            .state
+            .lock()
+            .expect("poisoned")
+            .subscribers
+            .push(sender);
+        let mut task = self.task.lock().expect("poisoned");
+        if task.is_none() {
+            let inner = self.inner.clone();
+            let handle =
+                task::spawn(run(inner, options.clone()).instrument(error_span!("discovery-watch")));
+            *task = Some(AbortOnDropHandle::new(handle));
+        }
+        Box::pin(tokio_stream::wrappers::ReceiverStream::new(receiver))
+    }
+}
+
+/// Polls the nodes which are due until the [`NodeWatcher`] is dropped.
+async fn run(inner: Arc<Inner>, options: WatchOptions) {
+    loop {
+        let notified = inner.notify.notified();
+        let next_poll = {
+            let state = inner.state.lock().expect("poisoned");
+            state.nodes.values().map(|watched| watched.next_poll).min()
+        };
+        match next_poll {
+            Some(next_poll) => {
+                tokio::select! {
+                    _ = time::sleep_until(next_poll) => {}
+                    _ = notified => {}
+                }
+            }
+            None => notified.await,
+        }
+        poll_due(&inner, &options).await;
+    }
+}
+
+/// Looks up all nodes which are due and sends the changed information to the subscribers.
+async fn poll_due(inner: &Inner, options: &WatchOptions) {
+    let now = Instant::now();
+    let due = {
+        let state = inner.state.lock().expect("poisoned");
+        state
+            .nodes
+            .iter()
+            .filter(|(_, watched)| watched.next_poll <= now)
+            .map(|(node_id, watched)| (*node_id, watched.lookup.clone()))
+            .collect::<Vec<_>>()
+    };
+    if due.is_empty() {
+        return;
+    }
+    let results = join_all(
+        due.into_iter()
+            .map(|(node_id, lookup)| async move { (node_id, lookup(node_id).await) }),
+    )
+    .await;
+
+    let now = Instant::now();
+    let mut state = inner.state.lock().expect("poisoned");
+    let State {
+        nodes, subscribers, ..
+    } = &mut *state;
+    for (node_id, res) in results {
+        let Some(watched) = nodes.get_mut(&node_id) else {
+            continue;
+        };
+        match res {
+            Ok(resolved) => {
+                watched.next_poll = now + options.interval(resolved.ttl);
+                if !is_newer(&watched.item, &resolved.item) {
+                    continue;
+                }
+                debug!(node_id = %node_id.fmt_short(), "node info changed");
+                watched.item = resolved.item;
+                subscribers.retain(
+                    |subscriber| match subscriber.try_send(watched.item.clone()) {
+                        Ok(()) => true,
+                        Err(mpsc::error::TrySendError::Full(_)) => {
+                            warn!("discovery watch subscriber is blocked, dropping item");
+                            true
+                        }
+                        Err(mpsc::error::TrySendError::Closed(_)) => false,
+                    },
+                );
+            }
+            Err(err) => {
+                debug!(node_id = %node_id.fmt_short(), "failed to poll node: {err:#}");
+                watched.next_poll = now + options.min_interval;
+            }
+        }
+    }
+}
+
+/// Whether `new` is more recent information than `old`.
+///
+/// If both carry a timestamp, the timestamp has to advance.  Otherwise, the information has
+/// to differ.
+fn is_newer(old: &DiscoveryItem, new: &DiscoveryItem) -> bool {
+    match (old.last_updated, new.last_updated) {
+        (Some(old), Some(new)) => new > old,
+        _ => old.node_addr != new.node_addr || old.user_data != new.user_data,
+    }
+}
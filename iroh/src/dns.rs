@@ -8,7 +8,8 @@
 //! iroh node records are structured.
 
 pub use iroh_relay::dns::{
-    node_info, DnsResolver, N0_DNS_NODE_ORIGIN_PROD, N0_DNS_NODE_ORIGIN_STAGING,
+    node_info, DnsProtocol, DnsResolver, DnsResolverBuilder, DEFAULT_DOH_PATH,
+    N0_DNS_NODE_ORIGIN_PROD, N0_DNS_NODE_ORIGIN_STAGING,
 };
 
 #[cfg(test)]
@@ -31,7 +31,8 @@ use url::Url;
 
 use crate::{
     discovery::{
-        dns::DnsDiscovery, pkarr::PkarrPublisher, ConcurrentDiscovery, Discovery, DiscoveryTask,
+        dns::DnsDiscovery, pkarr::PkarrPublisher, ConcurrentDiscovery, Discovery, DiscoveryItem,
+        DiscoveryTask, UserData,
     },
     dns::DnsResolver,
     magicsock::{self, Handle, NodeIdMappedAddr},
@@ -99,6 +100,7 @@ pub struct Builder {
     keylog: bool,
     #[debug(skip)]
     discovery: Vec<DiscoveryBuilder>,
+    discovery_user_data: Option<UserData>,
     proxy_url: Option<Url>,
     /// List of known nodes. See [`Builder::known_nodes`].
     node_map: Option<Vec<NodeAddr>>,
@@ -122,6 +124,7 @@ impl Default for Builder {
             transport_config,
             keylog: Default::default(),
             discovery: Default::default(),
+            discovery_user_data: Default::default(),
             proxy_url: None,
             node_map: None,
             dns_resolver: None,
@@ -172,6 +175,7 @@ impl Builder {
             relay_map,
             node_map: self.node_map,
             discovery,
+            discovery_user_data: self.discovery_user_data,
             proxy_url: self.proxy_url,
             dns_resolver,
             server_config,
@@ -367,6 +371,16 @@ impl Builder {
         self
     }
 
+    /// Sets the initial user data to publish via discovery.
+    ///
+    /// The [`UserData`] is published along with the addressing information of this node
+    /// by all discovery services that support it.  It can be changed later using
+    /// [`Endpoint::set_user_data_for_discovery`].
+    pub fn user_data_for_discovery(mut self, user_data: UserData) -> Self {
+        self.discovery_user_data = Some(user_data);
+        self
+    }
+
     /// Optionally set a list of known nodes.
     pub fn known_nodes(mut self, nodes: Vec<NodeAddr>) -> Self {
         self.node_map = Some(nodes);
@@ -772,7 +786,11 @@ impl Endpoint {
         )
     }
 
-    fn add_node_addr_inner(&self, node_addr: NodeAddr, source: magicsock::Source) -> Result<()> {
+    pub(crate) fn add_node_addr_inner(
+        &self,
+        node_addr: NodeAddr,
+        source: magicsock::Source,
+    ) -> Result<()> {
         // Connecting to ourselves is not supported.
         if node_addr.node_id == self.node_id() {
             bail!(
@@ -783,6 +801,33 @@ impl Endpoint {
         self.msock.add_node_addr(node_addr, source)
     }
 
+    /// Adds the addressing information and user data of a [`DiscoveryItem`].
+    pub(crate) fn add_discovery_item(&self, item: DiscoveryItem) -> Result<()> {
+        // Connecting to ourselves is not supported.
+        if item.node_addr.node_id == self.node_id() {
+            bail!(
+                "Adding our own address is not supported ({} is the node id of this node)",
+                item.node_addr.node_id.fmt_short()
+            );
+        }
+        let source = magicsock::Source::NamedApp {
+            name: item.provenance.into(),
+        };
+        self.msock.add_discovery_item(item, source)
+    }
+
+    /// Sets the user data to publish via discovery.
+    ///
+    /// The [`UserData`] is published along with the addressing information of this node
+    /// by all discovery services that support it, replacing any previously published user
+    /// data.  Setting it to `None` stops publishing user data.
+    ///
+    /// Other nodes find the user data in [`RemoteInfo::user_data`] after discovering this
+    /// node.
+    pub fn set_user_data_for_discovery(&self, user_data: Option<UserData>) {
+        self.msock.set_discovery_user_data(user_data);
+    }
+
     // # Getter methods for properties of this Endpoint itself.
 
     /// Returns the secret_key of this endpoint.
@@ -813,6 +858,41 @@ impl Endpoint {
         ))
     }
 
+    /// Returns a [`Watcher`] for the current [`NodeAddr`] of this endpoint.
+    ///
+    /// This combines [`Endpoint::home_relay`] and [`Endpoint::direct_addresses`] and is
+    /// updated whenever the addressing information we would publish for this node changes.
+    ///
+    /// The watcher stores `None` until the first set of direct addresses was discovered,
+    /// use [`Watcher::initialized`] to wait for it.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use futures_lite::StreamExt;
+    /// use iroh::Endpoint;
+    ///
+    /// # let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    /// # rt.block_on(async move {
+    /// let ep = Endpoint::builder().bind().await.unwrap();
+    /// let mut addrs = ep.node_addr_watcher().stream();
+    /// while let Some(Some(addr)) = addrs.next().await {
+    ///     println!("our address changed: {addr:?}");
+    /// }
+    /// # });
+    /// ```
+    pub fn node_addr_watcher(&self) -> Watcher<Option<NodeAddr>> {
+        let node_id = self.node_id();
+        self.home_relay()
+            .join(self.direct_addresses())
+            .map(move |(relay, addrs)| {
+                addrs.map(|addrs| {
+                    NodeAddr::from_parts(node_id, relay, addrs.into_iter().map(|x| x.addr))
+                })
+            })
+            .dedup()
+    }
+
     /// Returns a [`Watcher`] for the [`RelayUrl`] of the Relay server used as home relay.
     ///
     /// Every endpoint has a home Relay server which it chooses as the server with the
@@ -2001,6 +2081,19 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    #[traced_test]
+    async fn endpoint_node_addr_watcher() {
+        let ep = Endpoint::builder()
+            .relay_mode(RelayMode::Disabled)
+            .bind()
+            .await
+            .unwrap();
+        let addr = ep.node_addr_watcher().initialized().await.unwrap();
+        assert_eq!(addr, ep.node_addr().await.unwrap());
+        assert_eq!(ep.node_addr_watcher().get().unwrap(), Some(addr));
+    }
+
     #[tokio::test]
     #[traced_test]
     async fn endpoint_bidi_send_recv() {
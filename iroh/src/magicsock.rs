@@ -69,7 +69,7 @@ use crate::endpoint::PathSelection;
 use crate::{
     defaults::timeouts::NET_REPORT_TIMEOUT,
     disco::{self, CallMeMaybe, SendAddr},
-    discovery::{Discovery, DiscoveryItem},
+    discovery::{Discovery, DiscoveryItem, UserData},
     dns::DnsResolver,
     key::{public_ed_box, secret_ed_box, DecryptionError, SharedSecret},
     watchable::{Watchable, Watcher},
@@ -117,6 +117,9 @@ pub(crate) struct Options {
     /// Optional node discovery mechanism.
     pub(crate) discovery: Option<Box<dyn Discovery>>,
 
+    /// Optional user data to publish via discovery.
+    pub(crate) discovery_user_data: Option<UserData>,
+
     /// A DNS resolver to use for resolving relay URLs.
     ///
     /// You can use [`crate::dns::DnsResolver::new`] for a resolver
@@ -152,6 +155,7 @@ impl Default for Options {
             relay_map: RelayMap::empty(),
             node_map: None,
             discovery: None,
+            discovery_user_data: None,
             proxy_url: None,
             dns_resolver: DnsResolver::new(),
             server_config,
@@ -262,6 +266,9 @@ pub(crate) struct MagicSock {
     /// Optional discovery service
     discovery: Option<Box<dyn Discovery>>,
 
+    /// Optional user data to publish via discovery.
+    discovery_user_data: std::sync::RwLock<Option<UserData>>,
+
     /// Our discovered direct addresses.
     direct_addrs: DiscoveredDirectAddrs,
 
@@ -403,6 +410,26 @@ impl MagicSock {
         }
     }
 
+    /// Adds the addressing information and [`UserData`] of a [`DiscoveryItem`].
+    pub(crate) fn add_discovery_item(&self, item: DiscoveryItem, source: Source) -> Result<()> {
+        let node_id = item.node_addr.node_id;
+        self.add_node_addr(item.node_addr, source)?;
+        self.node_map
+            .update_user_data(node_id, item.provenance, item.user_data);
+        Ok(())
+    }
+
+    /// Sets the [`UserData`] published via discovery and republishes our address.
+    pub(crate) fn set_discovery_user_data(&self, user_data: Option<UserData>) {
+        let mut current = self.discovery_user_data.write().expect("poisoned");
+        if *current == user_data {
+            return;
+        }
+        *current = user_data;
+        drop(current);
+        self.publish_my_addr();
+    }
+
     /// Stores a new set of direct addresses.
     ///
     /// If the direct addresses have changed from the previous set, they are published to
@@ -1479,7 +1506,12 @@ impl MagicSock {
     /// Called whenever our addresses or home relay node changes.
     fn publish_my_addr(&self) {
         if let Some(ref discovery) = self.discovery {
-            discovery.publish(self.my_relay().as_ref(), &self.direct_addrs.sockaddrs());
+            let user_data = self.discovery_user_data.read().expect("poisoned");
+            discovery.publish(
+                self.my_relay().as_ref(),
+                &self.direct_addrs.addrs.get().unwrap_or_default(),
+                user_data.as_ref(),
+            );
         }
     }
 }
@@ -1621,6 +1653,7 @@ impl Handle {
             relay_map,
             node_map,
             discovery,
+            discovery_user_data,
             dns_resolver,
             proxy_url,
             server_config,
@@ -1694,6 +1727,7 @@ impl Handle {
             ip_mapped_addrs,
             udp_disco_sender,
             discovery,
+            discovery_user_data: std::sync::RwLock::new(discovery_user_data),
             direct_addrs: Default::default(),
             pending_call_me_maybes: Default::default(),
             direct_addr_update_state: DirectAddrUpdateState::new(),
@@ -2323,12 +2357,12 @@ impl Actor {
                 // forever like we do with the other branches that yield `Option`s
                 Some(discovery_item) = discovery_events.next() => {
                     trace!("tick: discovery event, address discovered: {discovery_item:?}");
-                    if let Err(e) = self.msock.add_node_addr(
-                        discovery_item.node_addr.clone(),
-                        Source::Discovery {
-                            name: discovery_item.provenance.into()
-                        }) {
-                        warn!(?discovery_item.node_addr, "unable to add discovered node address to the node map: {e:?}");
+                    let node_addr = discovery_item.node_addr.clone();
+                    let source = Source::Discovery {
+                        name: discovery_item.provenance.into()
+                    };
+                    if let Err(e) = self.msock.add_discovery_item(discovery_item, source) {
+                        warn!(?node_addr, "unable to add discovered node address to the node map: {e:?}");
                     }
                 }
             }
@@ -4040,6 +4074,7 @@ mod tests {
             relay_map: RelayMap::empty(),
             node_map: None,
             discovery: None,
+            discovery_user_data: None,
             dns_resolver,
             proxy_url: None,
             server_config,
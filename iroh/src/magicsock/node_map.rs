@@ -23,6 +23,7 @@ use super::{
 use crate::endpoint::PathSelection;
 use crate::{
     disco::{CallMeMaybe, Pong, SendAddr},
+    discovery::UserData,
     watchable::Watcher,
 };
 
@@ -124,6 +125,10 @@ pub enum Source {
         /// The name of the application that added the node
         name: String,
     },
+    /// The node told us its addresses using the [identify protocol].
+    ///
+    /// [identify protocol]: crate::protocol::identify
+    Identify,
 }
 
 impl NodeMap {
@@ -153,6 +158,25 @@ impl NodeMap {
             .add_node_addr(node_addr, source)
     }
 
+    /// Updates the [`UserData`] a node published via discovery, as received from `provenance`.
+    ///
+    /// Does nothing if the node is not known.
+    pub(super) fn update_user_data(
+        &self,
+        node_id: NodeId,
+        provenance: &'static str,
+        user_data: Option<UserData>,
+    ) {
+        if let Some(node_state) = self
+            .inner
+            .lock()
+            .expect("poisoned")
+            .get_mut(NodeStateKey::NodeId(node_id))
+        {
+            node_state.update_user_data(provenance, user_data);
+        }
+    }
+
     /// Number of nodes currently listed.
     pub(super) fn node_count(&self) -> usize {
         self.inner.lock().expect("poisoned").node_count()
@@ -27,6 +27,7 @@ use super::{
 use crate::endpoint::PathSelection;
 use crate::{
     disco::{self, SendAddr},
+    discovery::UserData,
     magicsock::{ActorMessage, MagicsockMetrics, NodeIdMappedAddr, HEARTBEAT_INTERVAL},
     watchable::{Watchable, Watcher},
 };
@@ -140,6 +141,9 @@ pub(super) struct NodeState {
     ///
     /// Used for metric reporting.
     has_been_direct: bool,
+    /// The user data the node published via discovery, if any, with the name of the
+    /// discovery service it was received from.
+    user_data: Option<(&'static str, UserData)>,
     /// Configuration for what path selection to use
     #[cfg(any(test, feature = "test-utils"))]
     path_selection: PathSelection,
@@ -185,6 +189,7 @@ impl NodeState {
             last_call_me_maybe: None,
             conn_type: Watchable::new(ConnectionType::None),
             has_been_direct: false,
+            user_data: None,
             #[cfg(any(test, feature = "test-utils"))]
             path_selection: options.path_selection,
         }
@@ -266,6 +271,30 @@ impl NodeState {
             conn_type,
             latency,
             last_used: self.last_used.map(|instant| now.duration_since(instant)),
+            user_data: self.user_data.as_ref().map(|(_, data)| data.clone()),
+        }
+    }
+
+    /// Updates the user data the node published via discovery, as received from `provenance`.
+    ///
+    /// Not all discovery services support user data, so a result without user data only
+    /// clears the user data previously received from the same discovery service.
+    pub(super) fn update_user_data(
+        &mut self,
+        provenance: &'static str,
+        user_data: Option<UserData>,
+    ) {
+        match user_data {
+            Some(user_data) => self.user_data = Some((provenance, user_data)),
+            None => {
+                if self
+                    .user_data
+                    .as_ref()
+                    .is_some_and(|(p, _)| *p == provenance)
+                {
+                    self.user_data = None;
+                }
+            }
         }
     }
 
@@ -1371,6 +1400,12 @@ pub struct RemoteInfo {
     /// from the remote node. Note that sending to the remote node does not imply
     /// the remote node received anything.
     pub last_used: Option<Duration>,
+    /// The user data the node published via discovery, if any.
+    ///
+    /// This is the most recent [`UserData`] received from a discovery service which
+    /// supports publishing user data.  It is cleared once that discovery service finds the
+    /// node without user data.
+    pub user_data: Option<UserData>,
 }
 
 impl RemoteInfo {
@@ -1507,6 +1542,7 @@ mod tests {
                     last_call_me_maybe: None,
                     conn_type: Watchable::new(ConnectionType::Direct(ip_port.into())),
                     has_been_direct: true,
+                    user_data: None,
                     #[cfg(any(test, feature = "test-utils"))]
                     path_selection: PathSelection::default(),
                 },
@@ -1529,6 +1565,7 @@ mod tests {
                 last_call_me_maybe: None,
                 conn_type: Watchable::new(ConnectionType::Relay(send_addr.clone())),
                 has_been_direct: false,
+                user_data: None,
                 #[cfg(any(test, feature = "test-utils"))]
                 path_selection: PathSelection::default(),
             }
@@ -1558,6 +1595,7 @@ mod tests {
                 last_call_me_maybe: None,
                 conn_type: Watchable::new(ConnectionType::Relay(send_addr.clone())),
                 has_been_direct: false,
+                user_data: None,
                 #[cfg(any(test, feature = "test-utils"))]
                 path_selection: PathSelection::default(),
             }
@@ -1600,6 +1638,7 @@ mod tests {
                         send_addr.clone(),
                     )),
                     has_been_direct: false,
+                    user_data: None,
                     #[cfg(any(test, feature = "test-utils"))]
                     path_selection: PathSelection::default(),
                 },
@@ -1622,6 +1661,7 @@ mod tests {
                 conn_type: ConnectionType::Direct(a_socket_addr),
                 latency: Some(latency),
                 last_used: Some(elapsed),
+                user_data: None,
             },
             RemoteInfo {
                 node_id: b_endpoint.node_id,
@@ -1634,6 +1674,7 @@ mod tests {
                 conn_type: ConnectionType::Relay(send_addr.clone()),
                 latency: Some(latency),
                 last_used: Some(elapsed),
+                user_data: None,
             },
             RemoteInfo {
                 node_id: c_endpoint.node_id,
@@ -1646,6 +1687,7 @@ mod tests {
                 conn_type: ConnectionType::Relay(send_addr.clone()),
                 latency: None,
                 last_used: Some(elapsed),
+                user_data: None,
             },
             RemoteInfo {
                 node_id: d_endpoint.node_id,
@@ -1665,6 +1707,7 @@ mod tests {
                 conn_type: ConnectionType::Mixed(d_socket_addr, send_addr.clone()),
                 latency: Some(Duration::from_millis(50)),
                 last_used: Some(elapsed),
+                user_data: None,
             },
         ]);
 
@@ -1738,4 +1781,32 @@ mod tests {
         // number of pings as direct addresses in the call-me-maybe.
         assert_eq!(ping_messages.len(), my_numbers_count as usize);
     }
+
+    #[test]
+    fn test_update_user_data() {
+        let key = SecretKey::generate(rand::thread_rng());
+        let opts = Options {
+            node_id: key.public(),
+            relay_url: None,
+            active: true,
+            source: crate::magicsock::Source::NamedApp {
+                name: "test".into(),
+            },
+            path_selection: PathSelection::default(),
+        };
+        let mut ep = NodeState::new(0, opts);
+        let user_data = |ep: &NodeState| ep.info(Instant::now()).user_data;
+        let data: UserData = "foo".parse().unwrap();
+
+        ep.update_user_data("pkarr", Some(data.clone()));
+        assert_eq!(user_data(&ep), Some(data.clone()));
+
+        // Discovery services without user data do not clear it.
+        ep.update_user_data("local.swarm.discovery", None);
+        assert_eq!(user_data(&ep), Some(data));
+
+        // The service which published the user data clears it once it is gone.
+        ep.update_user_data("pkarr", None);
+        assert_eq!(user_data(&ep), None);
+    }
 }
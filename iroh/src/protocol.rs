@@ -52,6 +52,8 @@ use tracing::{error, info_span, trace, warn, Instrument};
 
 use crate::{endpoint::Connecting, Endpoint};
 
+pub mod identify;
+
 /// The built router.
 ///
 /// Construct this using [`Router::builder`].
@@ -97,6 +99,7 @@ pub struct Router {
 pub struct RouterBuilder {
     endpoint: Endpoint,
     protocols: ProtocolMap,
+    identify_agent: Option<String>,
 }
 
 /// Handler for incoming connections.
@@ -217,6 +220,7 @@ impl RouterBuilder {
         Self {
             endpoint,
             protocols: ProtocolMap::default(),
+            identify_agent: None,
         }
     }
 
@@ -228,13 +232,34 @@ impl RouterBuilder {
         self
     }
 
+    /// Configures the router to answer requests of the [`identify`] protocol.
+    ///
+    /// Remote nodes requesting [`identify::identify`] will learn about all ALPNs registered
+    /// on this router, our current [`NodeAddr`](crate::NodeAddr) and the given `agent`
+    /// string, which usually names the software and version the node runs.
+    pub fn identify(mut self, agent: impl Into<String>) -> Self {
+        self.identify_agent = Some(agent.into());
+        self
+    }
+
     /// Returns the [`Endpoint`] of the node.
     pub fn endpoint(&self) -> &Endpoint {
         &self.endpoint
     }
 
     /// Spawns an accept loop and returns a handle to it encapsulated as the [`Router`].
-    pub async fn spawn(self) -> Result<Router> {
+    pub async fn spawn(mut self) -> Result<Router> {
+        if let Some(agent) = self.identify_agent.take() {
+            let mut alpns = self.protocols.alpns().cloned().collect::<Vec<_>>();
+            if !alpns.iter().any(|alpn| alpn == identify::ALPN) {
+                alpns.push(identify::ALPN.to_vec());
+                alpns.sort();
+            }
+            let handler = identify::Identify::new(self.endpoint.clone(), agent, alpns);
+            self.protocols
+                .insert(identify::ALPN.to_vec(), Box::new(handler));
+        }
+
         // Update the endpoint with our alpns.
         let alpns = self
             .protocols
@@ -0,0 +1,215 @@
+//! An identify protocol to learn about the capabilities of a remote node.
+//!
+//! When connecting to an unknown node there is no way to know which protocols it serves,
+//! which software it runs or under which addresses it is reachable without out-of-band
+//! information.  The identify protocol answers these questions: a node accepting
+//! connections on [`ALPN`] responds with an [`IdentifyInfo`] describing itself.
+//!
+//! To serve the protocol, enable it on the [`Router`] with [`RouterBuilder::identify`].
+//! To query a remote node use [`identify`], which also adds the addresses learned from the
+//! remote node to the [`Endpoint`] with [`Source::Identify`].
+//!
+//! ## Example
+//!
+//! ```no_run
+//! # use anyhow::Result;
+//! # use iroh::{protocol::{identify, Router}, Endpoint, NodeAddr};
+//! #
+//! # async fn test_compile(remote: NodeAddr) -> Result<()> {
+//! let endpoint = Endpoint::builder().discovery_n0().bind().await?;
+//!
+//! let router = Router::builder(endpoint.clone())
+//!     .identify("my-app/1.0.0")
+//!     .spawn()
+//!     .await?;
+//!
+//! let info = identify::identify(&endpoint, remote).await?;
+//! println!("remote runs {} and serves {} ALPNs", info.agent, info.alpns.len());
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`Router`]: crate::protocol::Router
+//! [`RouterBuilder::identify`]: crate::protocol::RouterBuilder::identify
+//! [`Source::Identify`]: crate::endpoint::Source::Identify
+
+use std::{net::SocketAddr, sync::Arc};
+
+use anyhow::{ensure, Result};
+use n0_future::boxed::BoxFuture;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::{
+    endpoint::{Connecting, ConnectionType, Source},
+    protocol::ProtocolHandler,
+    Endpoint, NodeAddr, NodeId,
+};
+
+/// The ALPN used by the identify protocol.
+pub const ALPN: &[u8] = b"/iroh/identify/0";
+
+/// The maximum size of an encoded [`IdentifyInfo`] we are willing to receive.
+const MAX_INFO_SIZE: usize = 16 * 1024;
+
+/// The information a node sends about itself in response to an identify request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdentifyInfo {
+    /// A free-form string identifying the software the node runs, e.g. `my-app/1.0.0`.
+    pub agent: String,
+    /// The ALPN protocol identifiers the node accepts connections for.
+    pub alpns: Vec<Vec<u8>>,
+    /// The current addressing information of the node.
+    pub node_addr: NodeAddr,
+    /// The address from which the node observed the requesting node, if the connection
+    /// uses a direct path.
+    pub observed_addr: Option<SocketAddr>,
+}
+
+/// The [`ProtocolHandler`] answering identify requests.
+///
+/// This is registered on a [`Router`] using [`RouterBuilder::identify`].
+///
+/// [`Router`]: crate::protocol::Router
+/// [`RouterBuilder::identify`]: crate::protocol::RouterBuilder::identify
+#[derive(Debug, Clone)]
+pub struct Identify {
+    endpoint: Endpoint,
+    agent: String,
+    alpns: Arc<Vec<Vec<u8>>>,
+}
+
+impl Identify {
+    /// Creates a new identify handler announcing the given agent and ALPNs.
+    pub(crate) fn new(endpoint: Endpoint, agent: String, alpns: Vec<Vec<u8>>) -> Self {
+        Self {
+            endpoint,
+            agent,
+            alpns: Arc::new(alpns),
+        }
+    }
+
+    /// Builds the [`IdentifyInfo`] sent to the node `remote`.
+    async fn info(&self, remote: NodeId) -> Result<IdentifyInfo> {
+        let node_addr = self.endpoint.node_addr().await?;
+        let observed_addr = self
+            .endpoint
+            .conn_type(remote)
+            .ok()
+            .and_then(|watcher| watcher.get().ok())
+            .and_then(|conn_type| match conn_type {
+                ConnectionType::Direct(addr) | ConnectionType::Mixed(addr, _) => Some(addr),
+                ConnectionType::Relay(_) | ConnectionType::None => None,
+            });
+        Ok(IdentifyInfo {
+            agent: self.agent.clone(),
+            alpns: self.alpns.as_ref().clone(),
+            node_addr,
+            observed_addr,
+        })
+    }
+}
+
+impl ProtocolHandler for Identify {
+    fn accept(&self, connecting: Connecting) -> BoxFuture<Result<()>> {
+        let this = self.clone();
+        Box::pin(async move {
+            let connection = connecting.await?;
+            let remote = connection.remote_node_id()?;
+            let info = this.info(remote).await?;
+            let bytes = postcard::to_stdvec(&info)?;
+            let mut send = connection.open_uni().await?;
+            send.write_all(&bytes).await?;
+            send.finish()?;
+            // Wait for the requesting node to close the connection once it read the info.
+            connection.closed().await;
+            Ok(())
+        })
+    }
+}
+
+/// Requests the [`IdentifyInfo`] of a remote node.
+///
+/// The addressing information received from the remote node is added to the
+/// [`Endpoint`] with [`Source::Identify`].
+///
+/// # Errors
+///
+/// Fails if the node cannot be reached, does not serve the identify protocol, or
+/// responds with addressing information for a different node.
+pub async fn identify(endpoint: &Endpoint, node_addr: impl Into<NodeAddr>) -> Result<IdentifyInfo> {
+    let node_addr = node_addr.into();
+    let node_id = node_addr.node_id;
+    let connection = endpoint.connect(node_addr, ALPN).await?;
+    let mut recv = connection.accept_uni().await?;
+    let bytes = recv.read_to_end(MAX_INFO_SIZE).await?;
+    connection.close(0u32.into(), b"done");
+
+    let info: IdentifyInfo = postcard::from_bytes(&bytes)?;
+    ensure!(
+        info.node_addr.node_id == node_id,
+        "identify response is for node {}, expected {}",
+        info.node_addr.node_id.fmt_short(),
+        node_id.fmt_short()
+    );
+    if !info.node_addr.is_empty() {
+        if let Err(err) = endpoint.add_node_addr_inner(info.node_addr.clone(), Source::Identify) {
+            debug!(node_id = %node_id.fmt_short(), "failed to add identified addresses: {err:#}");
+        }
+    }
+    Ok(info)
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_test::traced_test;
+
+    use super::*;
+    use crate::{protocol::Router, RelayMode};
+
+    #[derive(Debug)]
+    struct Noop;
+
+    impl ProtocolHandler for Noop {
+        fn accept(&self, _conn: Connecting) -> BoxFuture<Result<()>> {
+            Box::pin(async move { Ok(()) })
+        }
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_identify() -> Result<()> {
+        const OTHER_ALPN: &[u8] = b"n0/iroh/test";
+
+        let server = Endpoint::builder()
+            .relay_mode(RelayMode::Disabled)
+            .bind()
+            .await?;
+        let router = Router::builder(server.clone())
+            .accept(OTHER_ALPN, Noop)
+            .identify("test-agent/0.1.0")
+            .spawn()
+            .await?;
+        let server_addr = server.node_addr().await?;
+
+        let client = Endpoint::builder()
+            .relay_mode(RelayMode::Disabled)
+            .bind()
+            .await?;
+        let info = identify(&client, server_addr.clone()).await?;
+
+        assert_eq!(info.agent, "test-agent/0.1.0");
+        assert_eq!(info.alpns, vec![ALPN.to_vec(), OTHER_ALPN.to_vec()]);
+        assert_eq!(info.node_addr, server_addr);
+
+        let remote_info = client.remote_info(server.node_id()).unwrap();
+        assert!(remote_info
+            .sources()
+            .iter()
+            .any(|(source, _)| *source == Source::Identify));
+
+        router.shutdown().await?;
+        client.close().await;
+        Ok(())
+    }
+}
@@ -88,6 +88,7 @@ pub async fn run_relay_server_with(
             limits: Default::default(),
             key_cache_capacity: Some(1024),
             access: AccessConfig::Everyone,
+            proxy_protocol: None,
         }),
         quic,
         stun,
@@ -299,6 +300,7 @@ pub(crate) mod pkarr_relay {
         Router,
     };
     use bytes::Bytes;
+    use iroh_base::NodeId;
     use tokio::sync::oneshot;
     use tracing::{debug, error, warn};
     use url::Url;
@@ -309,7 +311,7 @@ pub(crate) mod pkarr_relay {
     pub async fn run_pkarr_relay(state: AppState) -> Result<(Url, CleanupDropGuard)> {
         let bind_addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
         let app = Router::new()
-            .route("/pkarr/:key", put(pkarr_put))
+            .route("/pkarr/:key", put(pkarr_put).get(pkarr_get))
             .with_state(state);
         let listener = tokio::net::TcpListener::bind(bind_addr).await?;
         let bound_addr = listener.local_addr()?;
@@ -345,6 +347,19 @@ pub(crate) mod pkarr_relay {
         Ok(http::StatusCode::NO_CONTENT)
     }
 
+    async fn pkarr_get(
+        State(state): State<AppState>,
+        Path(key): Path<String>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let key = pkarr::PublicKey::try_from(key.as_str())?;
+        let node_id = NodeId::from_bytes(&key.to_bytes())?;
+        let payload = state.get(&node_id, |packet| packet.map(|p| p.to_relay_payload()));
+        match payload {
+            Some(payload) => Ok((http::StatusCode::OK, payload).into_response()),
+            None => Ok(http::StatusCode::NOT_FOUND.into_response()),
+        }
+    }
+
     #[derive(Debug)]
     struct AppError(anyhow::Error);
     impl<T: Into<anyhow::Error>> From<T> for AppError {
@@ -12,6 +12,7 @@
 use std::sync;
 use std::{
     collections::VecDeque,
+    fmt,
     future::Future,
     pin::Pin,
     sync::{Arc, Weak},
@@ -91,7 +92,7 @@ impl<T: Clone + Eq> Watchable<T> {
     pub fn watch(&self) -> Watcher<T> {
         Watcher {
             epoch: self.shared.state.read().expect("poisoned").epoch,
-            shared: Arc::downgrade(&self.shared),
+            source: WatcherSource::Direct(Direct(Arc::downgrade(&self.shared))),
         }
     }
 
@@ -109,10 +110,22 @@ impl<T: Clone + Eq> Watchable<T> {
 /// it'll miss in-between values.
 /// When the thread changing the [`Watchable`] pauses updating, the [`Watcher`] will always
 /// end up reporting the most recent state eventually.
-#[derive(Debug, Clone)]
+///
+/// Watchers can be combined into derived watchers using [`Watcher::map`],
+/// [`Watcher::filter`], [`Watcher::join`] and [`Watcher::dedup`].  Derived watchers do not
+/// keep the original [`Watchable`]s alive either.
+#[derive(Clone)]
 pub struct Watcher<T> {
     epoch: u64,
-    shared: Weak<Shared<T>>,
+    source: WatcherSource<T>,
+}
+
+impl<T> fmt::Debug for Watcher<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Watcher")
+            .field("epoch", &self.epoch)
+            .finish_non_exhaustive()
+    }
 }
 
 impl<T: Clone + Eq> Watcher<T> {
@@ -121,8 +134,7 @@ impl<T: Clone + Eq> Watcher<T> {
     /// Returns [`Err(Disconnected)`](Disconnected) if the original
     /// [`Watchable`] was dropped.
     pub fn get(&self) -> Result<T, Disconnected> {
-        let shared = self.shared.upgrade().ok_or(Disconnected)?;
-        Ok(shared.get())
+        self.source.current().map(|(_epoch, value)| value)
     }
 
     /// Returns a future completing with `Ok(value)` once a new value is set, or with
@@ -173,6 +185,74 @@ impl<T: Clone + Eq> Watcher<T> {
     }
 }
 
+impl<T: Clone + Eq + Send + Sync + 'static> Watcher<T> {
+    /// Returns a [`Watcher`] for the value of this watcher transformed by `map`.
+    ///
+    /// The returned watcher is updated every time this watcher is updated, even if the
+    /// mapped value did not change.  Use [`Watcher::dedup`] to only observe changes of the
+    /// mapped value.
+    pub fn map<U, F>(self, map: F) -> Watcher<U>
+    where
+        U: Clone + Eq + Send + Sync + 'static,
+        F: Fn(T) -> U + Send + Sync + 'static,
+    {
+        Watcher {
+            epoch: self.epoch,
+            source: WatcherSource::Derived(Arc::new(Map {
+                inner: self.source.into_dyn(),
+                map: Box::new(map),
+            })),
+        }
+    }
+
+    /// Returns a [`Watcher`] holding the most recent value for which `filter` returns `true`.
+    ///
+    /// The returned watcher stores `None` until a value matching the filter is observed,
+    /// which can be awaited using [`Watcher::initialized`].  Updates to values not matching
+    /// the filter are skipped.
+    pub fn filter<F>(self, filter: F) -> Watcher<Option<T>>
+    where
+        F: Fn(&T) -> bool + Send + Sync + 'static,
+    {
+        let filtered = Filtered::new(self.source.into_dyn(), Box::new(filter));
+        Watcher {
+            epoch: INITIAL_EPOCH,
+            source: WatcherSource::Derived(Arc::new(filtered)),
+        }
+    }
+
+    /// Returns a [`Watcher`] combining the values of this and the `other` watcher.
+    ///
+    /// The returned watcher holds a tuple of the current values of both watchers and is
+    /// updated whenever either of them is updated.  It is disconnected as soon as either
+    /// of the underlying [`Watchable`]s is dropped.
+    pub fn join<U>(self, other: Watcher<U>) -> Watcher<(T, U)>
+    where
+        U: Clone + Eq + Send + Sync + 'static,
+    {
+        Watcher {
+            epoch: self.epoch + other.epoch,
+            source: WatcherSource::Derived(Arc::new(Join {
+                left: self.source.into_dyn(),
+                right: other.source.into_dyn(),
+            })),
+        }
+    }
+
+    /// Returns a [`Watcher`] which is only updated when the value actually changes.
+    ///
+    /// A [`Watchable`] only notifies its watchers if the value changed, but derived watchers
+    /// such as those returned by [`Watcher::map`] can be updated with an equal value.  This
+    /// removes such consecutive duplicates.
+    pub fn dedup(self) -> Watcher<T> {
+        let deduped = Dedup::new(self.source.into_dyn());
+        Watcher {
+            epoch: INITIAL_EPOCH,
+            source: WatcherSource::Derived(Arc::new(deduped)),
+        }
+    }
+}
+
 impl<T: Clone + Eq> Watcher<Option<T>> {
     /// Returns a future completing once the value is set to [`Some`] value.
     ///
@@ -202,12 +282,10 @@ impl<T: Clone + Eq> Future for WatchNextFut<'_, T> {
     type Output = Result<T, Disconnected>;
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<Self::Output> {
-        let Some(shared) = self.watcher.shared.upgrade() else {
-            return Poll::Ready(Err(Disconnected));
-        };
-        match shared.poll_next(cx, self.watcher.epoch) {
+        match self.watcher.source.poll_next(cx, self.watcher.epoch) {
             Poll::Pending => Poll::Pending,
-            Poll::Ready((current_epoch, value)) => {
+            Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+            Poll::Ready(Ok((current_epoch, value))) => {
                 self.watcher.epoch = current_epoch;
                 Poll::Ready(Ok(value))
             }
@@ -233,10 +311,8 @@ impl<T: Clone + Eq> Future for WatchInitializedFut<'_, T> {
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<Self::Output> {
         loop {
-            let Some(shared) = self.watcher.shared.upgrade() else {
-                return Poll::Ready(Err(Disconnected));
-            };
-            let (epoch, value) = n0_future::ready!(shared.poll_next(cx, self.watcher.epoch));
+            let (epoch, value) =
+                n0_future::ready!(self.watcher.source.poll_next(cx, self.watcher.epoch))?;
             self.watcher.epoch = epoch;
 
             if let Some(value) = value {
@@ -262,12 +338,10 @@ impl<T: Clone + Eq> Stream for WatcherStream<T> {
     type Item = T;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<Option<Self::Item>> {
-        let Some(shared) = self.watcher.shared.upgrade() else {
-            return Poll::Ready(None);
-        };
-        match shared.poll_next(cx, self.watcher.epoch) {
+        match self.watcher.source.poll_next(cx, self.watcher.epoch) {
             Poll::Pending => Poll::Pending,
-            Poll::Ready((epoch, value)) => {
+            Poll::Ready(Err(Disconnected)) => Poll::Ready(None),
+            Poll::Ready(Ok((epoch, value))) => {
                 self.watcher.epoch = epoch;
                 Poll::Ready(Some(value))
             }
@@ -315,6 +389,12 @@ impl<T: Clone> Shared<T> {
         self.state.read().expect("poisoned").value.clone()
     }
 
+    /// Returns the current epoch together with the value.
+    fn current(&self) -> (u64, T) {
+        let state = self.state.read().expect("poisoned");
+        (state.epoch, state.value.clone())
+    }
+
     fn poll_next(&self, cx: &mut task::Context<'_>, last_epoch: u64) -> Poll<(u64, T)> {
         {
             let state = self.state.read().expect("poisoned");
@@ -350,6 +430,301 @@ impl<T: Clone> Shared<T> {
     }
 }
 
+/// The source of the values observed by a [`Watcher`].
+///
+/// Every source has an epoch which increases every time its value changes.  A [`Watcher`]
+/// remembers the last epoch it observed to know whether it has seen the current value.
+trait Source<T>: Send + Sync {
+    /// Returns the current epoch and value.
+    fn current(&self) -> Result<(u64, T), Disconnected>;
+
+    /// Polls for a value with an epoch newer than `last_epoch`.
+    fn poll_next(
+        &self,
+        cx: &mut task::Context<'_>,
+        last_epoch: u64,
+    ) -> Poll<Result<(u64, T), Disconnected>>;
+}
+
+/// Where a [`Watcher`] gets its values from.
+///
+/// Watchers of a [`Watchable`] observe it directly, so that they do not require the value
+/// to be `Send + Sync + 'static` like the type-erased derived sources do.
+enum WatcherSource<T> {
+    /// Observing a [`Watchable`], see [`Watchable::watch`].
+    Direct(Direct<T>),
+    /// Derived from other watchers, see [`Watcher::map`] and the other combinators.
+    Derived(Arc<dyn Source<T>>),
+}
+
+impl<T> Clone for WatcherSource<T> {
+    fn clone(&self) -> Self {
+        match self {
+            Self::Direct(direct) => Self::Direct(Direct(direct.0.clone())),
+            Self::Derived(source) => Self::Derived(source.clone()),
+        }
+    }
+}
+
+impl<T: Clone> WatcherSource<T> {
+    fn current(&self) -> Result<(u64, T), Disconnected> {
+        match self {
+            Self::Direct(direct) => direct.current(),
+            Self::Derived(source) => source.current(),
+        }
+    }
+
+    fn poll_next(
+        &self,
+        cx: &mut task::Context<'_>,
+        last_epoch: u64,
+    ) -> Poll<Result<(u64, T), Disconnected>> {
+        match self {
+            Self::Direct(direct) => direct.poll_next(cx, last_epoch),
+            Self::Derived(source) => source.poll_next(cx, last_epoch),
+        }
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static> WatcherSource<T> {
+    /// Returns the type-erased [`Source`], to derive other sources from.
+    fn into_dyn(self) -> Arc<dyn Source<T>> {
+        match self {
+            Self::Direct(direct) => Arc::new(direct),
+            Self::Derived(source) => source,
+        }
+    }
+}
+
+/// A [`Source`] directly observing a [`Watchable`].
+struct Direct<T>(Weak<Shared<T>>);
+
+impl<T: Clone> Direct<T> {
+    fn current(&self) -> Result<(u64, T), Disconnected> {
+        let shared = self.0.upgrade().ok_or(Disconnected)?;
+        Ok(shared.current())
+    }
+
+    fn poll_next(
+        &self,
+        cx: &mut task::Context<'_>,
+        last_epoch: u64,
+    ) -> Poll<Result<(u64, T), Disconnected>> {
+        let Some(shared) = self.0.upgrade() else {
+            return Poll::Ready(Err(Disconnected));
+        };
+        shared.poll_next(cx, last_epoch).map(Ok)
+    }
+}
+
+impl<T: Clone + Send + Sync> Source<T> for Direct<T> {
+    fn current(&self) -> Result<(u64, T), Disconnected> {
+        Direct::current(self)
+    }
+
+    fn poll_next(
+        &self,
+        cx: &mut task::Context<'_>,
+        last_epoch: u64,
+    ) -> Poll<Result<(u64, T), Disconnected>> {
+        Direct::poll_next(self, cx, last_epoch)
+    }
+}
+
+type MapFn<T, U> = Box<dyn Fn(T) -> U + Send + Sync>;
+
+/// A [`Source`] transforming the values of another source, see [`Watcher::map`].
+struct Map<T, U> {
+    inner: Arc<dyn Source<T>>,
+    map: MapFn<T, U>,
+}
+
+impl<T, U> Source<U> for Map<T, U> {
+    fn current(&self) -> Result<(u64, U), Disconnected> {
+        let (epoch, value) = self.inner.current()?;
+        Ok((epoch, (self.map)(value)))
+    }
+
+    fn poll_next(
+        &self,
+        cx: &mut task::Context<'_>,
+        last_epoch: u64,
+    ) -> Poll<Result<(u64, U), Disconnected>> {
+        self.inner
+            .poll_next(cx, last_epoch)
+            .map_ok(|(epoch, value)| (epoch, (self.map)(value)))
+    }
+}
+
+/// A [`Source`] combining the values of two sources, see [`Watcher::join`].
+///
+/// The epoch of the combined source is the sum of the epochs of both sources, which
+/// increases whenever either of them changes.
+struct Join<T, U> {
+    left: Arc<dyn Source<T>>,
+    right: Arc<dyn Source<U>>,
+}
+
+impl<T, U> Source<(T, U)> for Join<T, U> {
+    fn current(&self) -> Result<(u64, (T, U)), Disconnected> {
+        let (left_epoch, left) = self.left.current()?;
+        let (right_epoch, right) = self.right.current()?;
+        Ok((left_epoch + right_epoch, (left, right)))
+    }
+
+    fn poll_next(
+        &self,
+        cx: &mut task::Context<'_>,
+        last_epoch: u64,
+    ) -> Poll<Result<(u64, (T, U)), Disconnected>> {
+        let (left_epoch, _) = self.left.current()?;
+        let (right_epoch, _) = self.right.current()?;
+        if last_epoch < left_epoch + right_epoch {
+            return Poll::Ready(self.current());
+        }
+        // Register our waker with both sources, any of them changing is an update.
+        let left = self.left.poll_next(cx, left_epoch);
+        let right = self.right.poll_next(cx, right_epoch);
+        if left.is_ready() || right.is_ready() {
+            return Poll::Ready(self.current());
+        }
+        Poll::Pending
+    }
+}
+
+type FilterFn<T> = Box<dyn Fn(&T) -> bool + Send + Sync>;
+
+/// A [`Source`] only taking over values of another source matching a filter, see
+/// [`Watcher::filter`].
+struct Filtered<T> {
+    inner: Arc<dyn Source<T>>,
+    filter: FilterFn<T>,
+    state: Mutex<DerivedState<Option<T>>>,
+}
+
+impl<T: Clone> Filtered<T> {
+    fn new(inner: Arc<dyn Source<T>>, filter: FilterFn<T>) -> Self {
+        let (inner_epoch, value) = match inner.current() {
+            Ok((epoch, value)) => (epoch, filter(&value).then_some(value)),
+            Err(Disconnected) => (PRE_INITIAL_EPOCH, None),
+        };
+        Self {
+            inner,
+            filter,
+            state: Mutex::new(DerivedState {
+                inner_epoch,
+                epoch: INITIAL_EPOCH,
+                value,
+            }),
+        }
+    }
+
+    fn apply(&self, state: &mut DerivedState<Option<T>>, inner_epoch: u64, value: T) {
+        state.inner_epoch = inner_epoch;
+        if (self.filter)(&value) {
+            state.value = Some(value);
+            state.epoch += 1;
+        }
+    }
+}
+
+impl<T: Clone + Send> Source<Option<T>> for Filtered<T> {
+    fn current(&self) -> Result<(u64, Option<T>), Disconnected> {
+        let (inner_epoch, value) = self.inner.current()?;
+        let mut state = self.state.lock().expect("poisoned");
+        if state.inner_epoch < inner_epoch {
+            self.apply(&mut state, inner_epoch, value);
+        }
+        Ok((state.epoch, state.value.clone()))
+    }
+
+    fn poll_next(
+        &self,
+        cx: &mut task::Context<'_>,
+        last_epoch: u64,
+    ) -> Poll<Result<(u64, Option<T>), Disconnected>> {
+        let mut state = self.state.lock().expect("poisoned");
+        loop {
+            if last_epoch < state.epoch {
+                return Poll::Ready(Ok((state.epoch, state.value.clone())));
+            }
+            let (inner_epoch, value) =
+                n0_future::ready!(self.inner.poll_next(cx, state.inner_epoch))?;
+            self.apply(&mut state, inner_epoch, value);
+        }
+    }
+}
+
+/// A [`Source`] skipping consecutive equal values of another source, see
+/// [`Watcher::dedup`].
+struct Dedup<T> {
+    inner: Arc<dyn Source<T>>,
+    state: Mutex<DerivedState<Option<T>>>,
+}
+
+impl<T: Clone + Eq> Dedup<T> {
+    fn new(inner: Arc<dyn Source<T>>) -> Self {
+        let (inner_epoch, value) = match inner.current() {
+            Ok((epoch, value)) => (epoch, Some(value)),
+            Err(Disconnected) => (PRE_INITIAL_EPOCH, None),
+        };
+        Self {
+            inner,
+            state: Mutex::new(DerivedState {
+                inner_epoch,
+                epoch: INITIAL_EPOCH,
+                value,
+            }),
+        }
+    }
+
+    fn apply(state: &mut DerivedState<Option<T>>, inner_epoch: u64, value: T) {
+        state.inner_epoch = inner_epoch;
+        if state.value.as_ref() != Some(&value) {
+            state.value = Some(value);
+            state.epoch += 1;
+        }
+    }
+}
+
+impl<T: Clone + Eq + Send> Source<T> for Dedup<T> {
+    fn current(&self) -> Result<(u64, T), Disconnected> {
+        let (inner_epoch, value) = self.inner.current()?;
+        let mut state = self.state.lock().expect("poisoned");
+        if state.inner_epoch < inner_epoch {
+            Self::apply(&mut state, inner_epoch, value);
+        }
+        let value = state.value.clone().ok_or(Disconnected)?;
+        Ok((state.epoch, value))
+    }
+
+    fn poll_next(
+        &self,
+        cx: &mut task::Context<'_>,
+        last_epoch: u64,
+    ) -> Poll<Result<(u64, T), Disconnected>> {
+        let mut state = self.state.lock().expect("poisoned");
+        loop {
+            if let Some(value) = state.value.as_ref().filter(|_| last_epoch < state.epoch) {
+                return Poll::Ready(Ok((state.epoch, value.clone())));
+            }
+            let (inner_epoch, value) =
+                n0_future::ready!(self.inner.poll_next(cx, state.inner_epoch))?;
+            Self::apply(&mut state, inner_epoch, value);
+        }
+    }
+}
+
+/// The state of a derived [`Source`] which keeps its own epoch.
+#[derive(Debug)]
+struct DerivedState<T> {
+    /// The epoch of the inner source which was last applied.
+    inner_epoch: u64,
+    /// The epoch of the derived source itself.
+    epoch: u64,
+    value: T,
+}
+
 #[cfg(test)]
 mod tests {
     use std::time::{Duration, Instant};
@@ -451,6 +826,15 @@ mod tests {
         assert_eq!(watchable.get(), Some(1u8));
     }
 
+    #[test]
+    fn test_non_send_value() {
+        // watching a value directly does not require it to be `Send + Sync + 'static`
+        let watchable = Watchable::new(std::rc::Rc::new(1u8));
+        let watcher = watchable.watch();
+        watchable.set(std::rc::Rc::new(2u8)).ok();
+        assert_eq!(*watcher.get().unwrap(), 2u8);
+    }
+
     #[tokio::test]
     async fn test_initialize() {
         let watchable = Watchable::new(None);
@@ -507,6 +891,72 @@ mod tests {
         test_case();
     }
 
+    #[tokio::test]
+    async fn test_map() {
+        let watchable = Watchable::new(1u8);
+        let mut watcher = watchable.watch().map(|x| x * 2);
+        assert_eq!(watcher.get().unwrap(), 2);
+
+        watchable.set(2).ok();
+        assert_eq!(watcher.updated().await.unwrap(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_filter() {
+        let watchable = Watchable::new(1u8);
+        let mut watcher = watchable.watch().filter(|x| x % 2 == 0);
+        assert_eq!(watcher.get().unwrap(), None);
+
+        let mut initialized = watcher.initialized();
+        let poll = n0_future::future::poll_once(&mut initialized).await;
+        assert!(poll.is_none());
+
+        watchable.set(3).ok();
+        let poll = n0_future::future::poll_once(&mut initialized).await;
+        assert!(poll.is_none());
+
+        watchable.set(4).ok();
+        let poll = n0_future::future::poll_once(&mut initialized).await;
+        assert_eq!(poll.unwrap().unwrap(), 4);
+
+        // Values not matching the filter keep the last matching value.
+        watchable.set(5).ok();
+        assert_eq!(watcher.get().unwrap(), Some(4));
+    }
+
+    #[tokio::test]
+    async fn test_join() {
+        let a = Watchable::new(1u8);
+        let b = Watchable::new("one");
+        let mut stream = a.watch().join(b.watch()).stream();
+        assert_eq!(stream.next().await.unwrap(), (1, "one"));
+
+        a.set(2).ok();
+        assert_eq!(stream.next().await.unwrap(), (2, "one"));
+
+        b.set("two").ok();
+        assert_eq!(stream.next().await.unwrap(), (2, "two"));
+
+        drop(a);
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_dedup() {
+        let watchable = Watchable::new(1u8);
+        let mut watcher = watchable.watch().map(|x| x / 10).dedup();
+        assert_eq!(watcher.get().unwrap(), 0);
+
+        let mut updated = watcher.updated();
+        watchable.set(2).ok();
+        let poll = n0_future::future::poll_once(&mut updated).await;
+        assert!(poll.is_none());
+
+        watchable.set(12).ok();
+        let poll = n0_future::future::poll_once(&mut updated).await;
+        assert_eq!(poll.unwrap().unwrap(), 1);
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn test_update_cancel_safety() {
         let watchable = Watchable::new(0);